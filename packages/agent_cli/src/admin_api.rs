@@ -0,0 +1,297 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use playit_agent_core::agent_control::health::ControlHealth;
+use playit_agent_core::network::connection_stats::{ActiveConnectionCounts, ServerConnectionCounts};
+use playit_agent_core::network::tcp_clients::SetupLimit;
+use playit_api_client::api::AgentTunnel;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+use crate::local_http::{header, request_target, serve, HttpResponse};
+
+/*
+ * Optional local admin API (--admin_listen), HTTP + JSON for automation.
+ *
+ *   GET  /tunnels   tunnels as last loaded from the api
+ *   GET  /stats     open connections per tunnel, connections per tunnel server,
+ *                   TCP clients rejected by --tcp_setup_limit
+ *   GET  /control   control channel state, tunnel server / data center and latency
+ *   POST /reload    load tunnels from the api now instead of at the next refresh
+ *   POST /stop      stops the agent, the process exits once it has shut down
+ *
+ * Auth: a bare port binds 127.0.0.1 so only local processes can connect.
+ * With --admin_token every request needs "Authorization: Bearer <token>",
+ * binding a non-loopback address without one is refused.
+ *
+ * Browsers are local processes too. Requests with an Origin header are
+ * refused so a web page can't POST /stop, and without a token the Host has
+ * to be loopback so DNS rebinding can't read the responses.
+ */
+
+#[derive(Clone)]
+pub struct AdminServer {
+    inner: Arc<AdminState>,
+}
+
+struct AdminState {
+    token: Option<String>,
+    agent: Mutex<Option<AdminAgent>>,
+    tunnels: Mutex<Vec<AgentTunnel>>,
+    reload: Notify,
+    reloadable: AtomicBool,
+}
+
+/* handles of the running agent, replaced when the agent is recreated */
+pub struct AdminAgent {
+    pub control: ControlHealth,
+    pub active_counts: ActiveConnectionCounts,
+    pub server_counts: ServerConnectionCounts,
+    pub keep_running: Arc<AtomicBool>,
+    pub setup_limit: SetupLimit,
+}
+
+impl AdminServer {
+    pub async fn start(addr: SocketAddr, token: Option<String>) -> std::io::Result<Self> {
+        /* anyone who can reach the port could stop the agent */
+        if !addr.ip().is_loopback() && token.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--admin_token is required when the admin api listens on a non-loopback address",
+            ));
+        }
+
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!(addr = %listener.local_addr()?, "admin api started");
+
+        let server = AdminServer {
+            inner: Arc::new(AdminState {
+                token,
+                agent: Mutex::new(None),
+                tunnels: Mutex::new(Vec::new()),
+                reload: Notify::new(),
+                reloadable: AtomicBool::new(false),
+            }),
+        };
+
+        let handler = server.clone();
+        serve(listener, "admin api", move |request| handler.handle(request));
+        Ok(server)
+    }
+
+    pub fn attach(&self, agent: AdminAgent) {
+        self.inner.agent.lock().unwrap().replace(agent);
+    }
+
+    pub fn set_tunnels(&self, tunnels: Vec<AgentTunnel>) {
+        *self.inner.tunnels.lock().unwrap() = tunnels;
+    }
+
+    /* POST /reload is rejected until something waits for reloads */
+    pub fn reload_requests(&self) -> &Notify {
+        self.inner.reloadable.store(true, Ordering::SeqCst);
+        &self.inner.reload
+    }
+
+    fn authorized(&self, request: &str) -> bool {
+        let Some(token) = &self.inner.token else {
+            return header(request, "host").is_none_or(is_loopback_host);
+        };
+
+        header(request, "authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_bytes()))
+    }
+
+    fn handle(&self, request: &str) -> HttpResponse {
+        let (status, body) = self.route(request);
+        HttpResponse::json(status, body)
+    }
+
+    fn route(&self, request: &str) -> (&'static str, serde_json::Value) {
+        let Some((method, path)) = request_target(request) else {
+            return ("400 Bad Request", json!({ "error": "bad request" }));
+        };
+
+        if header(request, "origin").is_some() {
+            return ("403 Forbidden", json!({ "error": "browser requests are not allowed" }));
+        }
+
+        if !self.authorized(request) {
+            return ("401 Unauthorized", json!({ "error": "missing or invalid token" }));
+        }
+
+        let agent = self.inner.agent.lock().unwrap();
+
+        match (method, path) {
+            ("GET", "/tunnels") => ("200 OK", json!(*self.inner.tunnels.lock().unwrap())),
+            ("GET", "/stats") => match agent.as_ref() {
+                Some(agent) => ("200 OK", json!({
+                    "active": agent.active_counts.snapshot(),
+                    "total": agent.active_counts.total(),
+                    "servers": agent.server_counts.snapshot().into_iter()
+                        .map(|(server, connections)| json!({ "server": server, "connections": connections }))
+                        .collect::<Vec<_>>(),
+                    "tcp_setup_rejected": agent.setup_limit.rejected(),
+                })),
+                None => ("503 Service Unavailable", json!({ "error": "agent not running" })),
+            },
+            ("GET", "/control") => match agent.as_ref() {
+                Some(agent) => ("200 OK", json!({
+                    "registered": agent.control.is_registered(),
+                    "ready": agent.control.is_ready(),
+                    "last_pong": agent.control.last_pong(),
+                })),
+                None => ("503 Service Unavailable", json!({ "error": "agent not running" })),
+            },
+            ("POST", "/reload") if self.inner.reloadable.load(Ordering::SeqCst) => {
+                tracing::info!("reload requested through admin api");
+                self.inner.reload.notify_one();
+                ("202 Accepted", json!({ "status": "reloading" }))
+            }
+            ("POST", "/reload") => ("409 Conflict", json!({ "error": "reload not supported by this command" })),
+            ("POST", "/stop") => match agent.as_ref() {
+                Some(agent) => {
+                    tracing::info!("stop requested through admin api");
+                    agent.keep_running.store(false, Ordering::SeqCst);
+                    ("202 Accepted", json!({ "status": "stopping" }))
+                }
+                None => ("503 Service Unavailable", json!({ "error": "agent not running" })),
+            },
+            (_, "/tunnels" | "/stats" | "/control" | "/reload" | "/stop") => ("405 Method Not Allowed", json!({ "error": "method not allowed" })),
+            _ => ("404 Not Found", json!({ "error": "not found" })),
+        }
+    }
+}
+
+/* host header value, with or without port */
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => host.split(':').next().unwrap_or(""),
+    };
+
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/* token checks shouldn't reveal how much of a guess matched, only the length may differ early */
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use playit_agent_core::agent_control::health::ControlHealth;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::{constant_time_eq, is_loopback_host, AdminAgent, AdminServer};
+
+    async fn request(addr: std::net::SocketAddr, method: &str, path: &str, token: Option<&str>) -> (String, serde_json::Value) {
+        let auth = token.map(|token| format!("Authorization: Bearer {}\r\n", token)).unwrap_or_default();
+        request_with_headers(addr, method, path, &format!("Host: localhost\r\n{}", auth)).await
+    }
+
+    async fn request_with_headers(addr: std::net::SocketAddr, method: &str, path: &str, headers: &str) -> (String, serde_json::Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("{} {} HTTP/1.1\r\n{}\r\n", method, path, headers).as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), serde_json::from_str(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = AdminServer::start(addr, Some("secret".to_string())).await.unwrap();
+
+        assert_eq!(request(addr, "GET", "/tunnels", None).await.0, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(request(addr, "GET", "/tunnels", Some("wrong")).await.0, "HTTP/1.1 401 Unauthorized");
+
+        let (status, body) = request(addr, "GET", "/tunnels", Some("secret")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, serde_json::json!([]));
+
+        assert_eq!(request(addr, "GET", "/stats", Some("secret")).await.0, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(request(addr, "POST", "/reload", Some("secret")).await.0, "HTTP/1.1 409 Conflict");
+        assert_eq!(request(addr, "GET", "/stop", Some("secret")).await.0, "HTTP/1.1 405 Method Not Allowed");
+
+        let keep_running = Arc::new(AtomicBool::new(true));
+        server.attach(AdminAgent {
+            control: ControlHealth::default(),
+            active_counts: Default::default(),
+            server_counts: Default::default(),
+            keep_running: keep_running.clone(),
+            setup_limit: Default::default(),
+        });
+
+        let (status, body) = request(addr, "GET", "/control", Some("secret")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["registered"], false);
+        assert!(body["last_pong"].is_null());
+
+        let (_, body) = request(addr, "GET", "/stats", Some("secret")).await;
+        assert_eq!(body["total"], serde_json::json!({ "tcp": 0, "udp": 0 }));
+        assert_eq!(body["tcp_setup_rejected"], 0);
+
+        let reload = server.reload_requests();
+        assert_eq!(request(addr, "POST", "/reload", Some("secret")).await.0, "HTTP/1.1 202 Accepted");
+        reload.notified().await;
+
+        assert_eq!(request(addr, "POST", "/stop", Some("secret")).await.0, "HTTP/1.1 202 Accepted");
+        assert!(!keep_running.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_non_loopback_requires_token() {
+        let error = AdminServer::start("0.0.0.0:0".parse().unwrap(), None).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(AdminServer::start("0.0.0.0:0".parse().unwrap(), Some("secret".to_string())).await.is_ok());
+
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+
+    #[tokio::test]
+    async fn test_browser_requests_rejected() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = AdminServer::start(addr, None).await.unwrap();
+
+        let keep_running = Arc::new(AtomicBool::new(true));
+        server.attach(AdminAgent {
+            control: ControlHealth::default(),
+            active_counts: Default::default(),
+            server_counts: Default::default(),
+            keep_running: keep_running.clone(),
+            setup_limit: Default::default(),
+        });
+
+        /* fetch(..., { method: "POST", mode: "no-cors" }) from a web page */
+        let (status, _) = request_with_headers(addr, "POST", "/stop", "Host: 127.0.0.1\r\nOrigin: https://example.com\r\n").await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+
+        /* dns rebinding, same origin for the page but the attacker's name in Host */
+        let (status, _) = request_with_headers(addr, "GET", "/tunnels", "Host: attacker.example:8080\r\n").await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        assert!(keep_running.load(Ordering::SeqCst));
+
+        assert_eq!(request(addr, "GET", "/tunnels", None).await.0, "HTTP/1.1 200 OK");
+        assert_eq!(request_with_headers(addr, "GET", "/tunnels", "Host: [::1]:80\r\n").await.0, "HTTP/1.1 200 OK");
+
+        assert!(is_loopback_host("127.0.0.1:9000"));
+        assert!(is_loopback_host("LOCALHOST"));
+        assert!(!is_loopback_host("192.168.1.4"));
+        assert!(!is_loopback_host("[2001:db8::1]:80"));
+    }
+}
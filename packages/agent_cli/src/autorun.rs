@@ -1,23 +1,139 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Write,
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
     sync::{Arc, atomic::Ordering, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use playit_agent_core::{
-    network::address_lookup::{AddressLookup, AddressValue, HostOrigin},
+    network::{access_log::AccessLog, address_lookup::{check_self_loop, AddressLookup, AddressValue, HostOrigin}, client_filter::ClientFilter, client_geo::ClientGeo, connection_hooks::ConnectionHooks, host_routing::HostRouting, idle_sleep::IDLE_TUNNEL_REFRESH_INTERVAL, lan_address::SourcePorts, tunnel_quota::{QuotaExceeded, QuotaUsage, TunnelQuotas}, udp::clients::UdpProxyResend},
+    agent_control::{errors::{register_error_is_terminal, SetupError}, server_override::ControlServerOverride, AuthApi, AuthResource},
     playit_agent::PlayitAgent,
-    utils::now_milli,
+    utils::{now_milli, now_sec, reconnect::reconnect_coordinator, supervise::join_error_message},
 };
 use playit_api_client::api::*;
 use playit_ping_monitor::PingMonitor;
 use rand::random;
 use uuid::Uuid;
 
-use crate::{API_BASE, CliError, match_ip::MatchIp, playit_secret::PlayitSecret, ui::UI};
+use crate::{API_BASE, CliError, admin_api::{AdminAgent, AdminServer}, backoff::RetryBackoff, guest_account_notice, health::HealthServer, local_addr_check::{find_local_addr_conflicts, report_local_addr_conflicts, LocalBinding}, match_ip::MatchIp, playit_secret::PlayitSecret, tunnel_filter::TunnelFilter, tunnel_state::TunnelStates, ui::UI, webhook::{WebhookEvent, WebhookNotifier, WebhookSettings}};
+
+#[derive(Default)]
+pub struct AutorunSettings {
+    pub tunnel_filter: TunnelFilter,
+    pub connection_hooks: ConnectionHooks,
+    pub client_filter: ClientFilter,
+    pub client_geo: ClientGeo,
+    pub access_log: AccessLog,
+    pub tcp_buffer_size: Option<usize>,
+    pub local_connect_timeout: Option<Duration>,
+    pub tcp_setup_limit: Option<usize>,
+    pub source_ports: Option<SourcePorts>,
+    pub udp_recv_batch_size: Option<usize>,
+    pub udp_proxy_resend: UdpProxyResend,
+    pub host_routing: HostRouting,
+    pub max_connection_lifetimes: HashMap<Uuid, Duration>,
+    pub unix_targets: HashMap<Uuid, PathBuf>,
+    pub reconnect_on_auth_error: bool,
+    pub match_client_family: bool,
+    pub local_ipv6_first: bool,
+    pub webhook: WebhookSettings,
+    pub health: Option<HealthServer>,
+    pub admin: Option<AdminServer>,
+    pub tunnel_refresh_interval: Option<Duration>,
+    pub idle_sleep: Option<Duration>,
+    pub deny_local_addr_conflicts: bool,
+    pub exit_on_no_tunnels: bool,
+    pub quotas: TunnelQuotas,
+    pub control_server: Option<ControlServerOverride>,
+    pub display_family: Option<AddressFamily>,
+}
+
+impl AutorunSettings {
+    /* shared by autorun and run, webhook events are attached by the caller since the notifier needs the agent id */
+    pub fn apply<L: AddressLookup + Sync + Send>(&self, agent: &mut PlayitAgent<L>) where L::Value: Into<HostOrigin> + Into<SocketAddr> {
+        agent.set_connection_hooks(self.connection_hooks.clone());
+        agent.set_client_filter(self.client_filter.clone());
+        agent.set_client_geo(self.client_geo.clone());
+        agent.set_access_log(self.access_log.clone());
+        if let Some(size) = self.tcp_buffer_size {
+            agent.set_tcp_buffer_size(size);
+        }
+        if let Some(timeout) = self.local_connect_timeout {
+            agent.set_local_connect_timeout(timeout);
+        }
+        if let Some(limit) = self.tcp_setup_limit {
+            agent.set_tcp_setup_limit(limit);
+        }
+        agent.set_source_ports(self.source_ports);
+        if let Some(size) = self.udp_recv_batch_size {
+            agent.set_udp_recv_batch_size(size);
+        }
+        agent.set_udp_proxy_resend(self.udp_proxy_resend);
+        agent.set_max_connection_lifetimes(self.max_connection_lifetimes.clone());
+        agent.set_unix_targets(self.unix_targets.clone());
+        agent.set_tunnel_quotas(self.quotas.clone());
+        agent.set_host_routing(self.host_routing.clone());
+        agent.set_reconnect_on_auth_error(self.reconnect_on_auth_error);
+        agent.set_match_client_family(self.match_client_family);
+        agent.set_local_ipv6_first(self.local_ipv6_first);
+        if let Some(after) = self.idle_sleep {
+            agent.set_idle_sleep(after);
+        }
+        if let Some(health) = &self.health {
+            health.attach(agent.control_health());
+        }
+        if let Some(admin) = &self.admin {
+            admin.attach(AdminAgent {
+                control: agent.control_health(),
+                active_counts: agent.active_connection_counts(),
+                server_counts: agent.server_connection_counts(),
+                keep_running: agent.keep_running(),
+                setup_limit: agent.tcp_setup_limit(),
+            });
+        }
+        #[cfg(target_os = "linux")]
+        crate::systemd::notify_ready_when_registered(agent.control_health());
+        crate::signal_handle::dump_udp_flows_on_signal(agent.udp_flow_dump_trigger());
+        crate::signal_handle::reset_stats_on_signal(agent.stats_reset());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
 
-pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliError> {
+impl AddressFamily {
+    fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::Ipv4 => ip.is_ipv4(),
+            AddressFamily::Ipv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+pub const DEFAULT_TUNNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+/* every refresh is an api call, don't let a typo hammer the api */
+pub const MIN_TUNNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/* shorter would flap between sleeping and awake between connections */
+pub const MIN_IDLE_SLEEP: Duration = Duration::from_secs(10);
+
+fn register_rejected_message(error: ProtoRegisterError) -> &'static str {
+    match error {
+        ProtoRegisterError::AccountBanned => "Agent stopped, account banned: https://playit.gg/account",
+        ProtoRegisterError::DisabledByUser => "Agent stopped, agent was disabled: https://playit.gg/account/agents",
+        ProtoRegisterError::UnknownPlayitVersion => "Agent stopped, this version is no longer supported, please update: https://playit.gg/download",
+        /* not terminal (see register_error_is_terminal), the agent keeps retrying */
+        ProtoRegisterError::AgentDisabledOverLimit => "Too many agents, retrying until one is removed: https://playit.gg/account/agents",
+        ProtoRegisterError::Unknown => "Registration rejected, retrying",
+    }
+}
+
+pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret, settings: AutorunSettings) -> Result<(), CliError> {
     let secret_code = secret
         .ensure_valid(ui)
         .await?
@@ -25,13 +141,33 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
         .await?;
 
     let api = secret.create_api().await?;
-    let mut ping_monitor = PingMonitor::new(api.clone()).await.unwrap();
+    let mut ping_monitor = {
+        let secret_path = secret.get_path().map(|v| v.to_string());
+        let secret_format = secret.get_format();
+        let secret_code = secret_code.clone();
+
+        PingMonitor::new(api.clone()).await.unwrap().with_secret_loader(move || {
+            let secret_path = secret_path.clone();
+            let secret_code = secret_code.clone();
+
+            async move {
+                if let Some(path) = secret_path {
+                    match PlayitSecret::read_secret_file(&path, secret_format).await {
+                        Ok(secret) => return Some(secret),
+                        Err(error) => tracing::warn!(?error, "failed to reload secret for ping monitor"),
+                    }
+                }
+                Some(secret_code)
+            }
+        })
+    };
 
     /* start ping monitor */
     tokio::spawn(async move {
         loop {
             if let Err(error) = ping_monitor.refresh().await {
                 tracing::error!(?error, "error running ping monitor");
+                reconnect_coordinator().wait_turn("ping_monitor").await;
             }
             tokio::time::sleep(Duration::from_millis(3_000 + (random::<u64>() % 5_000))).await;
         }
@@ -39,22 +175,92 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
 
     tokio::time::sleep(Duration::from_secs(2)).await;
 
-    let lookup = {
+    let auth = AuthApi::new(API_BASE.to_string(), secret_code.clone()).with_server_override(settings.control_server);
+
+    let (lookup, webhook, mut disabled_tunnels, mut account_banned, mut tunnel_states) = {
         let data = api.agents_rundata().await?;
+        check_has_tunnels(&data, settings.exit_on_no_tunnels)?;
+
+        /* only notify webhook of changes after startup */
+        let disabled_tunnels = data.tunnels.iter()
+            .filter(|tunnel| tunnel.disabled.is_some())
+            .map(|tunnel| tunnel.id)
+            .collect::<HashSet<_>>();
+        let account_banned = data.account_status == AgentAccountStatus::Banned;
+        let tunnel_states = TunnelStates::new(&data);
+
+        if data.account_status == AgentAccountStatus::Guest {
+            let notice = guest_account_notice(&api).await;
+            tracing::warn!("{}", notice);
+            ui.write_screen(notice).await;
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        }
+
+        let webhook = settings.webhook.url.clone().map(|url| {
+            WebhookNotifier::start(url, settings.webhook.secret.clone(), Some(data.agent_id))
+        });
+
+        if !settings.tunnel_filter.is_empty() {
+            for tunnel in &data.tunnels {
+                if settings.tunnel_filter.matches(tunnel.id, tunnel.name.as_deref()) {
+                    tracing::info!(tunnel_id = %tunnel.id, name = ?tunnel.name, "tunnel included by filter");
+                } else {
+                    tracing::info!(tunnel_id = %tunnel.id, name = ?tunnel.name, "tunnel excluded by filter");
+                }
+            }
+        }
+
+        let server_ips = control_server_ips(&auth).await;
+        for (tunnel, public_addr) in find_self_loops(&data.tunnels, &settings.tunnel_filter, &server_ips).await {
+            let local_addr = SocketAddr::new(tunnel.local_ip, tunnel.local_port);
+            tracing::error!(tunnel_id = %tunnel.id, %local_addr, %public_addr, "tunnel local address points to its own public address");
+
+            ui.write_error(
+                format!("Tunnel {} has local address {} which is its own public address, connections will be refused. Update the local address to your server", tunnel.id, local_addr),
+                public_addr,
+            ).await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+
+        let bindings: Vec<LocalBinding> = data.tunnels.iter()
+            .filter(|tunnel| tunnel.disabled.is_none() && settings.tunnel_filter.matches(tunnel.id, tunnel.name.as_deref()))
+            .map(|tunnel| LocalBinding {
+                tunnel_id: tunnel.id,
+                proto: tunnel.proto,
+                local_addr: SocketAddr::new(tunnel.local_ip, tunnel.local_port),
+                port_count: tunnel.port.to - tunnel.port.from,
+            })
+            .collect();
+
+        let conflicts = find_local_addr_conflicts(&bindings);
+        for conflict in &conflicts {
+            ui.write_error(
+                format!("Tunnels {} and {} both forward to {}, their clients will reach the same server", conflict.tunnel_ids.0, conflict.tunnel_ids.1, conflict.local_addr),
+                conflict.local_addr,
+            ).await;
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        }
+        report_local_addr_conflicts(&conflicts, settings.deny_local_addr_conflicts)?;
+
         let lookup = Arc::new(LocalLookup {
             data: Mutex::new(vec![]),
+            filter: settings.tunnel_filter.clone(),
         });
         lookup.update(data.tunnels).await;
 
-        lookup
+        (lookup, webhook, disabled_tunnels, account_banned, tunnel_states)
     };
 
     let mut error_count = 0;
     ui.write_screen("starting up tunnel connection").await;
 
-    let runner = loop {
-        match PlayitAgent::new(API_BASE.to_string(), secret_code.clone(), lookup.clone()).await {
+    let mut runner = loop {
+        match PlayitAgent::with_auth(auth.clone(), lookup.clone()).await {
             Ok(res) => break res,
+            Err(SetupError::RegisterRejected(error)) if register_error_is_terminal(error) && !settings.reconnect_on_auth_error => {
+                ui.write_error(register_rejected_message(error), error).await;
+                return Err(CliError::TunnelSetupError(SetupError::RegisterRejected(error)));
+            }
             Err(error) => {
                 error_count += 1;
                 if error_count > 5 {
@@ -63,38 +269,145 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
                     return Err(CliError::TunnelSetupError(error));
                 };
 
-                ui.write_error("Failed to setup tunnel client", error).await;
+                let msg = match error {
+                    SetupError::RegisterRejected(reason) if !register_error_is_terminal(reason) => register_rejected_message(reason),
+                    _ => "Failed to setup tunnel client",
+                };
+                ui.write_error(msg, error).await;
                 tokio::time::sleep(Duration::from_secs(5)).await;
+                reconnect_coordinator().wait_turn("control").await;
             }
         }
     };
 
+    settings.apply(&mut runner);
+    if let Some(webhook) = &webhook {
+        runner.set_event_sender(webhook.agent_event_sender());
+    }
+
+    let server_counts = runner.server_connection_counts();
+    let active_counts = runner.active_connection_counts();
     let signal = runner.keep_running();
+    let idle_sleep = runner.idle_sleep();
+    let control_health = runner.control_health();
     let runner = tokio::spawn(runner.run());
 
     ui.write_screen("tunnel running").await;
 
+    let refresh_interval = settings.tunnel_refresh_interval.unwrap_or(DEFAULT_TUNNEL_REFRESH_INTERVAL);
+    tracing::info!(?refresh_interval, "polling tunnel data");
+
     let mut guest_login_link: Option<(String, u64)> = None;
+    /* only filled with --display_family, domains are resolved once */
+    let mut resolved_domains = HashMap::<String, Vec<IpAddr>>::new();
+    let mut backoff = RetryBackoff::default();
+    let mut last_refresh = Instant::now();
+    let reload_requests = settings.admin.as_ref().map(|admin| admin.reload_requests());
 
     loop {
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        /* POST /reload on the admin api skips the wait */
+        let reload = tokio::select! {
+            _ = tokio::time::sleep(refresh_interval) => false,
+            _ = async {
+                match reload_requests {
+                    Some(reload) => reload.notified().await,
+                    None => std::future::pending().await,
+                }
+            } => true,
+        };
+
+        /* runner only stops by itself when registration is rejected for good */
+        if runner.is_finished() {
+            return match runner.await {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(SetupError::RegisterRejected(error))) => {
+                    ui.write_error(register_rejected_message(error), error).await;
+                    Err(CliError::TunnelSetupError(SetupError::RegisterRejected(error)))
+                }
+                Ok(Err(error)) => {
+                    ui.write_error("Tunnel client stopped", &error).await;
+                    Err(CliError::TunnelSetupError(error))
+                }
+                Err(error) => {
+                    let error = SetupError::TaskPanicked(join_error_message(error).unwrap_or_else(|| "task cancelled".to_string()));
+                    ui.write_error("Tunnel client stopped", &error).await;
+                    Err(CliError::TunnelSetupError(error))
+                }
+            };
+        }
+
+        /* checked every refresh_interval so a new connection brings back full cadence quickly */
+        if !reload && idle_sleep.is_asleep() && last_refresh.elapsed() < IDLE_TUNNEL_REFRESH_INTERVAL {
+            continue;
+        }
+        last_refresh = Instant::now();
 
         let account_tunnels_res = api.agents_rundata().await;
-        let agent_data = match account_tunnels_res {
+        let mut agent_data = match account_tunnels_res {
             Ok(v) => v,
             Err(error) => {
                 ui.write_error("Failed to load latest tunnels", error).await;
-                tokio::time::sleep(Duration::from_secs(3)).await;
+                tokio::time::sleep(backoff.failed()).await;
+                reconnect_coordinator().wait_turn("api").await;
                 continue;
             }
         };
+        backoff.succeeded();
 
-        let mut msg = format!(
-            "playit (v{}): {} tunnel running, {} tunnels registered\n\n",
-            env!("CARGO_PKG_VERSION"),
-            now_milli(),
-            agent_data.tunnels.len()
-        );
+        for change in tunnel_states.update(&agent_data) {
+            tracing::info!(tunnel_id = %change.tunnel_id, from = ?change.from, to = ?change.to, "tunnel state changed");
+            if let Some(webhook) = &webhook {
+                webhook.notify(WebhookEvent::tunnel_state_changed(change));
+            }
+        }
+
+        if let Some(webhook) = &webhook {
+            let banned = agent_data.account_status == AgentAccountStatus::Banned;
+            if banned && !account_banned {
+                webhook.notify(WebhookEvent::AccountBanned);
+            }
+            account_banned = banned;
+
+            for tunnel in &agent_data.tunnels {
+                match tunnel.disabled {
+                    Some(disabled) => {
+                        if disabled_tunnels.insert(tunnel.id) {
+                            webhook.notify(WebhookEvent::tunnel_disabled(tunnel.id, disabled));
+                        }
+                    }
+                    None => {
+                        disabled_tunnels.remove(&tunnel.id);
+                    }
+                }
+            }
+        }
+
+        sort_tunnels(&mut agent_data.tunnels, &mut agent_data.pending);
+
+        if let Some(admin) = &settings.admin {
+            admin.set_tunnels(agent_data.tunnels.clone());
+        }
+
+        /*
+         * quiet mode logs the whole screen when it changes, so it leaves out
+         * what changes on every refresh: the timestamp, client counts, quota
+         * usage and connections per tunnel server
+         */
+        let quiet = ui.is_quiet();
+        let mut msg = if quiet {
+            format!(
+                "playit (v{}): tunnel running, {} tunnels registered\n\n",
+                env!("CARGO_PKG_VERSION"),
+                agent_data.tunnels.len()
+            )
+        } else {
+            format!(
+                "playit (v{}): {} tunnel running, {} tunnels registered\n\n",
+                env!("CARGO_PKG_VERSION"),
+                now_milli(),
+                agent_data.tunnels.len()
+            )
+        };
 
         match agent_data.account_status {
             AgentAccountStatus::Guest => {
@@ -119,25 +432,15 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
                     }
                 }
             }
-            AgentAccountStatus::EmailNotVerified => {
-                writeln!(msg, "Email not verified https://playit.gg/account/settings/account/verify-email").unwrap();
-            }
-            AgentAccountStatus::AccountDeleteScheduled => {
-                writeln!(msg, "Account scheduled for delete: https://playit.gg/account/settings/account/delete-account").unwrap();
-            }
-            AgentAccountStatus::Banned => {
-                writeln!(msg, "Account banned: https://playit.gg/account").unwrap();
-            }
-            AgentAccountStatus::HasMessage => {
-                writeln!(msg, "You have a message: https://playit.gg/account").unwrap();
-            }
-            AgentAccountStatus::AgentOverLimit => {
-                writeln!(msg, "Too many agents: https://playit.gg/account/agents").unwrap();
-            }
-            AgentAccountStatus::AgentDisabled => {
-                writeln!(msg, "Account disabled: https://playit.gg/account/agents/{}", agent_data.agent_id).unwrap();
+            status => {
+                if let Some(notice) = account_status_notice(status, agent_data.agent_id) {
+                    writeln!(msg, "{}", notice).unwrap();
+                }
             }
-            AgentAccountStatus::Ready => {}
+        }
+
+        if control_health.duplicate_suspected() {
+            writeln!(msg, "{}", duplicate_agent_notice(agent_data.agent_id)).unwrap();
         }
 
         writeln!(msg, "\nTUNNELS").unwrap();
@@ -153,14 +456,33 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
         } else {
             for tunnel in &agent_data.tunnels {
                 let addr = tunnel.custom_domain.as_ref().unwrap_or(&tunnel.assigned_domain);
-                let src = match tunnel.tunnel_type.as_ref().map(|v| v.as_str()) {
+                let domain_src = match tunnel.tunnel_type.as_ref().map(|v| v.as_str()) {
                     Some("minecraft-java") => addr.clone(),
                     _ => format!("{}:{}", addr, tunnel.port.from),
                 };
 
+                let (src, also) = match settings.display_family {
+                    Some(family) => {
+                        if !resolved_domains.contains_key(addr) {
+                            let resolved = match tokio::net::lookup_host((addr.as_str(), 0)).await {
+                                Ok(resolved) => resolved.map(|v| v.ip()).collect(),
+                                Err(error) => {
+                                    tracing::warn!(?error, domain = %addr, "failed to resolve tunnel address for display");
+                                    vec![]
+                                }
+                            };
+                            resolved_domains.insert(addr.clone(), resolved);
+                        }
+                        public_addr_display(domain_src, tunnel.port.from, &resolved_domains[addr], family)
+                    }
+                    None => (domain_src, None),
+                };
+
                 let dst = format!("{}:{}", tunnel.local_ip, tunnel.local_port);
 
-                if let Some(disabled) = tunnel.disabled {
+                if !settings.tunnel_filter.matches(tunnel.id, tunnel.name.as_deref()) {
+                    writeln!(msg, "{} => {} (excluded by filter)", src, dst).unwrap();
+                } else if let Some(disabled) = tunnel.disabled {
                     writeln!(msg, "{} => {} (disabled)", src, dst).unwrap();
                     if disabled == AgentTunnelDisabled::BySystem {
                         writeln!(msg, "\tsee: https://playit.gg/account/tunnels/{}", tunnel.id).unwrap();
@@ -170,14 +492,35 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
                 } else {
                     writeln!(msg, "{} => {} (proto: {:?}, port count: {})", src, dst, tunnel.proto, tunnel.port.to - tunnel.port.from).unwrap();
                 }
+
+                if let Some(also) = also {
+                    writeln!(msg, "\talso: {}", also).unwrap();
+                }
+
+                if quiet {
+                    continue;
+                }
+
+                let active = active_counts.get(tunnel.id);
+                if active.total() != 0 {
+                    writeln!(msg, "\tclients: {} tcp, {} udp", active.tcp, active.udp).unwrap();
+                }
+
+                if let Some(usage) = settings.quotas.usage(tunnel.id) {
+                    writeln!(msg, "\t{}", quota_usage_line(&usage, now_sec() as u64)).unwrap();
+                }
             }
 
             for tunnel in &agent_data.pending {
-                if tunnel.is_disabled {
-                    writeln!(msg, "tunnel pending (disabled): https://playit.gg/account/tunnels/{}", tunnel.id).unwrap();
-                } else {
-                    writeln!(msg, "tunnel pending: https://playit.gg/account/tunnels/{}", tunnel.id).unwrap();
-                }
+                writeln!(msg, "{}", pending_tunnel_line(tunnel)).unwrap();
+            }
+        }
+
+        let counts = if quiet { vec![] } else { server_counts.snapshot() };
+        if !counts.is_empty() {
+            writeln!(msg, "\nCONNECTIONS BY TUNNEL SERVER").unwrap();
+            for (server, count) in counts {
+                writeln!(msg, "dc {} server {}: {}", server.data_center_id, server.tunnel_server_id, count).unwrap();
             }
         }
 
@@ -191,8 +534,224 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
     Ok(())
 }
 
+/* the tunnel's address of the preferred family first, the domain and other family as secondary */
+fn public_addr_display(domain_src: String, port: u16, resolved: &[IpAddr], family: AddressFamily) -> (String, Option<String>) {
+    let Some(primary) = resolved.iter().find(|ip| family.matches(ip)) else {
+        return (domain_src, None);
+    };
+
+    let mut also = vec![domain_src];
+    if let Some(other) = resolved.iter().find(|ip| !family.matches(ip)) {
+        also.push(SocketAddr::new(*other, port).to_string());
+    }
+
+    (SocketAddr::new(*primary, port).to_string(), Some(also.join(", ")))
+}
+
+fn quota_usage_line(usage: &QuotaUsage, now: u64) -> String {
+    let mut parts = Vec::new();
+    if let Some(limit) = usage.limits.bytes_per_day {
+        parts.push(format!("{} / {} today", format_bytes(usage.bytes_today), format_bytes(limit)));
+    }
+    if let Some(limit) = usage.limits.connections_per_hour {
+        parts.push(format!("{} / {} connections this hour", usage.connections_this_hour, limit));
+    }
+
+    let mut line = format!("quota: {}", parts.join(", "));
+    if let Some(exceeded) = usage.exceeded {
+        let resets_at = match exceeded {
+            QuotaExceeded::BytesPerDay { resets_at, .. } => resets_at,
+            QuotaExceeded::ConnectionsPerHour { resets_at, .. } => resets_at,
+        };
+        let mins = resets_at.saturating_sub(now).div_ceil(60);
+        line.push_str(&format!(" (PAUSED, new clients rejected for {}h {}m)", mins / 60, mins % 60));
+    }
+    line
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    let bytes = bytes as f64;
+    if 1024.0 * MIB <= bytes {
+        format!("{:.2} GiB", bytes / (1024.0 * MIB))
+    } else {
+        format!("{:.1} MiB", bytes / MIB)
+    }
+}
+
+const SELF_LOOP_LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/* ips of the control servers, a local address pointing at one of them loops back through playit */
+pub async fn control_server_ips<A: AuthResource>(auth: &A) -> Vec<IpAddr> {
+    match auth.get_control_addresses().await {
+        Ok(addresses) => addresses.iter().map(|addr| addr.ip()).collect(),
+        Err(error) => {
+            tracing::warn!(?error, "failed to load control addresses for self loop check");
+            vec![]
+        }
+    }
+}
+
+/* resolves each tunnel's public address (concurrently) to catch local addresses that loop back through playit */
+pub async fn find_self_loops<'a>(tunnels: &'a [AgentTunnel], filter: &TunnelFilter, server_ips: &[IpAddr]) -> Vec<(&'a AgentTunnel, SocketAddr)> {
+    let tunnels: Vec<&AgentTunnel> = tunnels.iter()
+        .filter(|tunnel| tunnel.disabled.is_none() && filter.matches(tunnel.id, tunnel.name.as_deref()))
+        .collect();
+
+    let mut lookups = tokio::task::JoinSet::new();
+    for (idx, tunnel) in tunnels.iter().enumerate() {
+        let (domain, port) = tunnel_public_host(tunnel);
+        lookups.spawn(async move { (idx, resolve_public_addrs(domain, port).await) });
+    }
+
+    let mut loops = vec![];
+    while let Some(result) = lookups.join_next().await {
+        let Ok((idx, resolved)) = result else { continue };
+        let tunnel = tunnels[idx];
+        let local_addr = SocketAddr::new(tunnel.local_ip, tunnel.local_port);
+
+        if let Some(public_addr) = resolved.into_iter().find(|public_addr| check_self_loop(local_addr, *public_addr, server_ips).is_some()) {
+            loops.push((idx, tunnel, public_addr));
+        }
+    }
+
+    loops.sort_by_key(|(idx, _, _)| *idx);
+    loops.into_iter().map(|(_, tunnel, public_addr)| (tunnel, public_addr)).collect()
+}
+
+/* public address of the tunnel that local_addr points back to */
+pub async fn find_self_loop(tunnel: &AgentTunnel, local_addr: SocketAddr, server_ips: &[IpAddr]) -> Option<SocketAddr> {
+    let (domain, port) = tunnel_public_host(tunnel);
+    resolve_public_addrs(domain, port).await
+        .into_iter()
+        .find(|public_addr| check_self_loop(local_addr, *public_addr, server_ips).is_some())
+}
+
+fn tunnel_public_host(tunnel: &AgentTunnel) -> (String, u16) {
+    (tunnel.custom_domain.as_ref().unwrap_or(&tunnel.assigned_domain).clone(), tunnel.port.from)
+}
+
+/* empty when the lookup fails or takes longer than SELF_LOOP_LOOKUP_TIMEOUT */
+async fn resolve_public_addrs(domain: String, port: u16) -> Vec<SocketAddr> {
+    match tokio::time::timeout(SELF_LOOP_LOOKUP_TIMEOUT, tokio::net::lookup_host((domain.as_str(), port))).await {
+        Ok(Ok(resolved)) => resolved.collect(),
+        Ok(Err(error)) => {
+            tracing::warn!(?error, %domain, "failed to resolve tunnel address for self loop check");
+            vec![]
+        }
+        Err(_) => {
+            tracing::warn!(%domain, "timed out resolving tunnel address for self loop check");
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NoticePriority {
+    Info,
+    Warning,
+    Urgent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoticeInfo {
+    pub priority: NoticePriority,
+    pub message: &'static str,
+    pub resolve_link: String,
+}
+
+impl std::fmt::Display for NoticeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self.priority {
+            NoticePriority::Info => "NOTICE",
+            NoticePriority::Warning => "WARNING",
+            NoticePriority::Urgent => "URGENT",
+        };
+        write!(f, "{}: {}\n\t{}", label, self.message, self.resolve_link)
+    }
+}
+
+/* guest accounts get a login link instead and ready accounts have nothing to resolve */
+fn account_status_notice(status: AgentAccountStatus, agent_id: Uuid) -> Option<NoticeInfo> {
+    let (priority, message, resolve_link) = match status {
+        AgentAccountStatus::Guest | AgentAccountStatus::Ready => return None,
+        AgentAccountStatus::AccountDeleteScheduled => (
+            NoticePriority::Urgent,
+            "Account is scheduled for deletion, tunnels stop working once it is deleted. Cancel the deletion to keep them",
+            "https://playit.gg/account/settings/account/delete-account".to_string(),
+        ),
+        AgentAccountStatus::Banned => (
+            NoticePriority::Urgent,
+            "Account banned, tunnels will not accept connections",
+            "https://playit.gg/account".to_string(),
+        ),
+        AgentAccountStatus::AgentDisabled => (
+            NoticePriority::Urgent,
+            "This agent is disabled, its tunnels will not accept connections until it is enabled",
+            format!("https://playit.gg/account/agents/{}", agent_id),
+        ),
+        AgentAccountStatus::AgentOverLimit => (
+            NoticePriority::Warning,
+            "Account has more agents than its plan allows, agents over the limit don't get tunnel ports. Remove an unused agent or upgrade the plan",
+            "https://playit.gg/account/agents".to_string(),
+        ),
+        AgentAccountStatus::EmailNotVerified => (
+            NoticePriority::Warning,
+            "Email not verified, some features are limited until it is",
+            "https://playit.gg/account/settings/account/verify-email".to_string(),
+        ),
+        AgentAccountStatus::HasMessage => (
+            NoticePriority::Info,
+            "You have a message from playit",
+            "https://playit.gg/account".to_string(),
+        ),
+    };
+
+    Some(NoticeInfo { priority, message, resolve_link })
+}
+
+/* --exit_on_no_tunnels, an agent without any tunnel is most likely a provisioning mistake */
+pub fn check_has_tunnels(data: &AgentRunData, exit_on_no_tunnels: bool) -> Result<(), CliError> {
+    if exit_on_no_tunnels && data.tunnels.is_empty() && data.pending.is_empty() {
+        tracing::error!(agent_id = %data.agent_id, "no tunnels configured for agent, exiting (--exit_on_no_tunnels)");
+        return Err(CliError::NoTunnelsConfigured);
+    }
+    Ok(())
+}
+
+/* control session keeps being replaced, see DuplicateAgentDetector */
+fn duplicate_agent_notice(agent_id: Uuid) -> NoticeInfo {
+    NoticeInfo {
+        priority: NoticePriority::Warning,
+        message: "Another agent seems to be running with the same secret, they keep replacing each other's connection. Stop the other agent or give each machine its own agent",
+        resolve_link: format!("https://playit.gg/account/agents/{}", agent_id),
+    }
+}
+
+/* api order can change between refreshes, sort by the displayed address so the status screen doesn't jump around */
+pub fn sort_tunnels(tunnels: &mut [AgentTunnel], pending: &mut [AgentPendingTunnel]) {
+    let key = |tunnel: &AgentTunnel| (tunnel.custom_domain.clone().unwrap_or_else(|| tunnel.assigned_domain.clone()), tunnel.port.from, tunnel.id);
+    tunnels.sort_by_cached_key(key);
+    pending.sort_by_key(|tunnel| tunnel.id);
+}
+
+/* pending tunnels have no address yet, they get served once a refresh lists them as allocated */
+fn pending_tunnel_line(tunnel: &AgentPendingTunnel) -> String {
+    let name = match &tunnel.name {
+        Some(name) => name.clone(),
+        None => format!("{:?} tunnel", tunnel.proto).to_lowercase(),
+    };
+
+    if tunnel.is_disabled {
+        format!("{} => pending allocation (disabled): https://playit.gg/account/tunnels/{}", name, tunnel.id)
+    } else {
+        format!("{} => pending allocation: https://playit.gg/account/tunnels/{}", name, tunnel.id)
+    }
+}
+
 pub struct LocalLookup {
     data: Mutex<Vec<TunnelEntry>>,
+    filter: TunnelFilter,
 }
 
 impl AddressLookup for LocalLookup {
@@ -215,6 +774,7 @@ impl AddressLookup for LocalLookup {
                     value: HostOrigin {
                         tunnel_id: tunnel.tunnel_id,
                         host_addr: tunnel.local_start_address,
+                        alt_host_addr: None,
                         use_special_lan: None,
                         proxy_protocol: tunnel.proxy_protocol,
                     },
@@ -233,20 +793,16 @@ impl LocalLookup {
         let mut entries: Vec<TunnelEntry> = vec![];
 
         for tunnel in tunnels {
-            entries.push(TunnelEntry {
-                tunnel_id: tunnel.id,
-                pub_address: if tunnel.tunnel_type.as_ref().map(|v| v.eq("minecraft-java")).unwrap_or(false) {
-                    tunnel.custom_domain.unwrap_or(tunnel.assigned_domain)
-                } else {
-                    format!("{}:{}", tunnel.custom_domain.unwrap_or(tunnel.assigned_domain), tunnel.port.from)
-                },
-                match_ip: MatchIp { ip_number: tunnel.ip_num, region_id: if tunnel.region_num == 0 { None } else { Some(tunnel.region_num) } },
-                port_type: tunnel.proto,
-                from_port: tunnel.port.from,
-                to_port: tunnel.port.to,
-                local_start_address: SocketAddr::new(tunnel.local_ip, tunnel.local_port),
-                proxy_protocol: tunnel.proxy_protocol,
-            });
+            if !self.filter.matches(tunnel.id, tunnel.name.as_deref()) {
+                continue;
+            }
+
+            /* stays listed so it's picked up again once re-enabled */
+            if tunnel.disabled.is_some() {
+                continue;
+            }
+
+            entries.push(TunnelEntry::new(tunnel));
         }
 
         let mut value = self.data.lock().unwrap();
@@ -264,3 +820,168 @@ pub struct TunnelEntry {
     pub local_start_address: SocketAddr,
     pub proxy_protocol: Option<ProxyProtocol>,
 }
+
+impl TunnelEntry {
+    pub fn new(tunnel: AgentTunnel) -> Self {
+        TunnelEntry {
+            tunnel_id: tunnel.id,
+            pub_address: if tunnel.tunnel_type.as_ref().map(|v| v.eq("minecraft-java")).unwrap_or(false) {
+                tunnel.custom_domain.unwrap_or(tunnel.assigned_domain)
+            } else {
+                format!("{}:{}", tunnel.custom_domain.unwrap_or(tunnel.assigned_domain), tunnel.port.from)
+            },
+            match_ip: MatchIp { ip_number: tunnel.ip_num, region_id: if tunnel.region_num == 0 { None } else { Some(tunnel.region_num) } },
+            port_type: tunnel.proto,
+            from_port: tunnel.port.from,
+            to_port: tunnel.port.to,
+            local_start_address: SocketAddr::new(tunnel.local_ip, tunnel.local_port),
+            proxy_protocol: tunnel.proxy_protocol,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use playit_agent_core::network::address_lookup::AddressLookup;
+    use playit_api_client::api::{AgentAccountStatus, AgentPendingTunnel, AgentRunData, AgentTunnel, AgentTunnelDisabled, AgentType, PortRange, PortType};
+    use uuid::Uuid;
+
+    use crate::tunnel_filter::TunnelFilter;
+
+    use crate::CliError;
+
+    use super::{account_status_notice, check_has_tunnels, pending_tunnel_line, public_addr_display, sort_tunnels, AddressFamily, LocalLookup, NoticePriority};
+
+    fn allocated(id: Uuid) -> AgentTunnel {
+        AgentTunnel {
+            id,
+            name: Some("survival".to_string()),
+            ip_num: 5,
+            region_num: 0,
+            port: PortRange { from: 25565, to: 25566 },
+            proto: PortType::Tcp,
+            local_ip: "127.0.0.1".parse().unwrap(),
+            local_port: 25565,
+            tunnel_type: None,
+            assigned_domain: "abc.gl.joinmc.link".to_string(),
+            custom_domain: None,
+            disabled: None,
+            proxy_protocol: None,
+        }
+    }
+
+    #[test]
+    fn test_check_has_tunnels() {
+        let mut data = AgentRunData {
+            agent_id: Uuid::from_u128(1),
+            agent_type: AgentType::SelfManaged,
+            account_status: AgentAccountStatus::Ready,
+            tunnels: vec![],
+            pending: vec![],
+        };
+
+        assert!(check_has_tunnels(&data, false).is_ok());
+        assert!(matches!(check_has_tunnels(&data, true), Err(CliError::NoTunnelsConfigured)));
+
+        data.tunnels.push(allocated(Uuid::from_u128(2)));
+        assert!(check_has_tunnels(&data, true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pending_tunnel_served_once_allocated() {
+        let id = Uuid::from_u128(7);
+        let pending = AgentPendingTunnel {
+            id,
+            name: Some("survival".to_string()),
+            proto: PortType::Tcp,
+            port_count: 1,
+            tunnel_type: None,
+            is_disabled: false,
+        };
+        assert_eq!(pending_tunnel_line(&pending), format!("survival => pending allocation: https://playit.gg/account/tunnels/{}", id));
+
+        let lookup = LocalLookup {
+            data: Mutex::new(vec![]),
+            filter: TunnelFilter::default(),
+        };
+        let public_ip = "::5".parse().unwrap();
+
+        /* while pending the tunnel is only in the pending list */
+        lookup.update(vec![]).await;
+        assert!(lookup.lookup(public_ip, 25565, PortType::Tcp).is_none());
+
+        lookup.update(vec![allocated(id)]).await;
+        let found = lookup.lookup(public_ip, 25565, PortType::Tcp).unwrap();
+        assert_eq!(found.value.tunnel_id, id);
+
+        let mut disabled = allocated(id);
+        disabled.disabled = Some(AgentTunnelDisabled::ByUser);
+        lookup.update(vec![disabled]).await;
+        assert!(lookup.lookup(public_ip, 25565, PortType::Tcp).is_none());
+    }
+
+    #[test]
+    fn test_sort_tunnels() {
+        let mut tunnels = vec![allocated(Uuid::from_u128(3)), allocated(Uuid::from_u128(1)), allocated(Uuid::from_u128(2))];
+        tunnels[0].assigned_domain = "aaa.gl.joinmc.link".to_string();
+        tunnels[2].port = PortRange { from: 25000, to: 25001 };
+        /* shown as its custom domain, sorted by it too */
+        tunnels[1].custom_domain = Some("aa.example.com".to_string());
+
+        let pending = |id: u128| AgentPendingTunnel {
+            id: Uuid::from_u128(id),
+            name: None,
+            proto: PortType::Udp,
+            port_count: 1,
+            tunnel_type: None,
+            is_disabled: false,
+        };
+        let mut pending_tunnels = vec![pending(9), pending(8)];
+
+        sort_tunnels(&mut tunnels, &mut pending_tunnels);
+        let ids: Vec<u128> = tunnels.iter().map(|tunnel| tunnel.id.as_u128()).collect();
+        assert_eq!(ids, vec![1, 3, 2]);
+        let ids: Vec<u128> = pending_tunnels.iter().map(|tunnel| tunnel.id.as_u128()).collect();
+        assert_eq!(ids, vec![8, 9]);
+    }
+
+    #[test]
+    fn test_account_status_notices() {
+        let agent_id = Uuid::from_u128(3);
+
+        assert_eq!(account_status_notice(AgentAccountStatus::Ready, agent_id), None);
+        assert_eq!(account_status_notice(AgentAccountStatus::Guest, agent_id), None);
+
+        let deleting = account_status_notice(AgentAccountStatus::AccountDeleteScheduled, agent_id).unwrap();
+        assert_eq!(deleting.priority, NoticePriority::Urgent);
+        assert!(deleting.to_string().starts_with("URGENT: Account is scheduled for deletion"));
+
+        let over_limit = account_status_notice(AgentAccountStatus::AgentOverLimit, agent_id).unwrap();
+        assert_eq!(over_limit.priority, NoticePriority::Warning);
+        assert!(over_limit.message.contains("ports"));
+
+        let disabled = account_status_notice(AgentAccountStatus::AgentDisabled, agent_id).unwrap();
+        assert_eq!(disabled.resolve_link, format!("https://playit.gg/account/agents/{}", agent_id));
+        assert!(NoticePriority::Info < NoticePriority::Warning && NoticePriority::Warning < disabled.priority);
+    }
+
+    #[test]
+    fn test_public_addr_display() {
+        let resolved = ["147.185.221.1".parse().unwrap(), "2602:fbaf:0:1::1".parse().unwrap()];
+        let domain = || "abc.gl.joinmc.link:5000".to_string();
+
+        assert_eq!(
+            public_addr_display(domain(), 5000, &resolved, AddressFamily::Ipv6),
+            ("[2602:fbaf:0:1::1]:5000".to_string(), Some("abc.gl.joinmc.link:5000, 147.185.221.1:5000".to_string()))
+        );
+        assert_eq!(
+            public_addr_display(domain(), 5000, &resolved, AddressFamily::Ipv4),
+            ("147.185.221.1:5000".to_string(), Some("abc.gl.joinmc.link:5000, [2602:fbaf:0:1::1]:5000".to_string()))
+        );
+
+        /* no address of the preferred family keeps the domain */
+        assert_eq!(public_addr_display(domain(), 5000, &resolved[..1], AddressFamily::Ipv6), (domain(), None));
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+/*
+ * Delay between retries of the autorun loop when the api keeps failing.
+ * Doubles from MIN_DELAY up to MAX_DELAY with up to 25% jitter so agents
+ * behind the same outage spread out. Only a sustained healthy period resets
+ * it, a single success during a flapping outage does not.
+ */
+
+pub const MIN_DELAY: Duration = Duration::from_secs(3);
+pub const MAX_DELAY: Duration = Duration::from_secs(120);
+pub const HEALTHY_RESET: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+pub struct RetryBackoff {
+    failures: u32,
+    healthy_since: Option<Instant>,
+}
+
+impl RetryBackoff {
+    /* delay before the next retry without jitter */
+    fn base_delay(failures: u32) -> Duration {
+        MIN_DELAY.saturating_mul(1u32 << failures.min(16)).min(MAX_DELAY)
+    }
+
+    pub fn failed(&mut self) -> Duration {
+        let base = Self::base_delay(self.failures);
+        self.failures = self.failures.saturating_add(1);
+        self.healthy_since = None;
+
+        let jitter = Duration::from_millis(rand::random::<u64>() % (base.as_millis() as u64 / 4 + 1));
+        let delay = (base + jitter).min(MAX_DELAY);
+
+        tracing::warn!(failures = self.failures, ?delay, "backing off before retrying");
+        delay
+    }
+
+    pub fn succeeded(&mut self) {
+        self.succeeded_at(Instant::now());
+    }
+
+    fn succeeded_at(&mut self, now: Instant) {
+        if self.failures == 0 {
+            return;
+        }
+
+        let healthy_since = *self.healthy_since.get_or_insert(now);
+        if HEALTHY_RESET <= now.saturating_duration_since(healthy_since) {
+            tracing::info!(failures = self.failures, "healthy again, reset retry backoff");
+            self.failures = 0;
+            self.healthy_since = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{RetryBackoff, HEALTHY_RESET, MAX_DELAY, MIN_DELAY};
+
+    #[test]
+    fn test_backoff_grows_caps_and_resets() {
+        assert_eq!(RetryBackoff::base_delay(0), MIN_DELAY);
+        assert_eq!(RetryBackoff::base_delay(1), MIN_DELAY * 2);
+        assert_eq!(RetryBackoff::base_delay(3), MIN_DELAY * 8);
+        assert_eq!(RetryBackoff::base_delay(10), MAX_DELAY);
+        assert_eq!(RetryBackoff::base_delay(u32::MAX), MAX_DELAY);
+
+        let mut backoff = RetryBackoff::default();
+        for failures in 0..8 {
+            let delay = backoff.failed();
+            let base = RetryBackoff::base_delay(failures);
+            assert!(base <= delay && delay <= (base + base / 4).min(MAX_DELAY));
+        }
+
+        /* a short healthy period keeps the backoff */
+        let start = Instant::now();
+        backoff.succeeded_at(start);
+        backoff.succeeded_at(start + Duration::from_secs(10));
+        assert_eq!(backoff.failures, 8);
+
+        backoff.succeeded_at(start + HEALTHY_RESET);
+        assert_eq!(backoff.failures, 0);
+        assert!(backoff.failed() < MIN_DELAY * 2);
+    }
+}
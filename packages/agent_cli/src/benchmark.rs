@@ -0,0 +1,409 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use playit_agent_core::agent_control::{DualStackUdpSocket, PacketIO};
+use playit_agent_core::network::tcp_pipe::{pipe_with_buffer, DEFAULT_PIPE_BUFFER_SIZE};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/*
+ * `playit benchmark`, measures what the agent's forwarding adds on localhost.
+ *
+ * A built-in server runs in sink mode (counts bytes / packets) and echo mode
+ * (round trips). Each measurement runs once directly against the server and
+ * once through a relay that forwards like the agent does: TCP is copied with
+ * pipe_with_buffer (--tcp_buffer_size applies) and UDP goes through a
+ * DualStackUdpSocket towards the origin. No playit servers are involved so
+ * the result is the agent's overhead, not internet performance.
+ */
+
+const MODE_SINK: u8 = b'S';
+const MODE_ECHO: u8 = b'E';
+const MODE_DONE: u8 = b'D';
+
+const TCP_CHUNK_SIZE: usize = 64 * 1024;
+const UDP_RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkSettings {
+    pub tcp_bytes: usize,
+    pub tcp_buffer_size: usize,
+    pub udp_packets: usize,
+    pub udp_packet_size: usize,
+    pub round_trips: usize,
+}
+
+impl Default for BenchmarkSettings {
+    fn default() -> Self {
+        BenchmarkSettings {
+            tcp_bytes: 256 * 1024 * 1024,
+            tcp_buffer_size: DEFAULT_PIPE_BUFFER_SIZE,
+            udp_packets: 50_000,
+            udp_packet_size: 1200,
+            round_trips: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /* UDP only, packets that did not arrive */
+    pub lost: u64,
+}
+
+impl Throughput {
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(0.000_001)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RoundTrip {
+    pub average: Duration,
+    pub lost: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Comparison<T> {
+    pub direct: T,
+    pub relayed: T,
+}
+
+#[derive(Debug)]
+pub struct BenchmarkReport {
+    pub tcp_buffer_size: usize,
+    pub tcp_throughput: Comparison<Throughput>,
+    pub tcp_round_trip: Comparison<RoundTrip>,
+    pub udp_throughput: Comparison<Throughput>,
+    pub udp_round_trip: Comparison<RoundTrip>,
+}
+
+pub async fn run_benchmark(settings: &BenchmarkSettings) -> std::io::Result<BenchmarkReport> {
+    let tcp_server = start_tcp_server().await?;
+    let tcp_relay = start_tcp_relay(tcp_server, settings.tcp_buffer_size).await?;
+    let udp_server = start_udp_server().await?;
+    let udp_relay = start_udp_relay(udp_server).await?;
+
+    Ok(BenchmarkReport {
+        tcp_buffer_size: settings.tcp_buffer_size,
+        tcp_throughput: Comparison {
+            direct: tcp_throughput(tcp_server, settings.tcp_bytes).await?,
+            relayed: tcp_throughput(tcp_relay, settings.tcp_bytes).await?,
+        },
+        tcp_round_trip: Comparison {
+            direct: tcp_round_trip(tcp_server, settings.round_trips).await?,
+            relayed: tcp_round_trip(tcp_relay, settings.round_trips).await?,
+        },
+        udp_throughput: Comparison {
+            direct: udp_throughput(udp_server, settings.udp_packets, settings.udp_packet_size).await?,
+            relayed: udp_throughput(udp_relay, settings.udp_packets, settings.udp_packet_size).await?,
+        },
+        udp_round_trip: Comparison {
+            direct: udp_round_trip(udp_server, settings.round_trips).await?,
+            relayed: udp_round_trip(udp_relay, settings.round_trips).await?,
+        },
+    })
+}
+
+impl BenchmarkReport {
+    pub fn format(&self) -> String {
+        let throughput = |t: &Throughput| {
+            if t.lost == 0 {
+                format!("{:>10}", format_rate(t.bytes_per_sec()))
+            } else {
+                format!("{:>10} ({} lost)", format_rate(t.bytes_per_sec()), t.lost)
+            }
+        };
+        let round_trip = |r: &RoundTrip| {
+            if r.lost == 0 {
+                format!("{:>10}", format!("{:?}", r.average))
+            } else {
+                format!("{:>10} ({} lost)", format!("{:?}", r.average), r.lost)
+            }
+        };
+        let added = self.tcp_round_trip.relayed.average.saturating_sub(self.tcp_round_trip.direct.average);
+        let udp_added = self.udp_round_trip.relayed.average.saturating_sub(self.udp_round_trip.direct.average);
+
+        let mut out = String::new();
+        out.push_str(&format!("{:<16} {:<24} {:<24}\n", "", "direct", "through agent"));
+        out.push_str(&format!("{:<16} {:<24} {:<24}\n", "TCP throughput", throughput(&self.tcp_throughput.direct), throughput(&self.tcp_throughput.relayed)));
+        out.push_str(&format!("{:<16} {:<24} {:<24} +{:?}\n", "TCP round trip", round_trip(&self.tcp_round_trip.direct), round_trip(&self.tcp_round_trip.relayed), added));
+        out.push_str(&format!("{:<16} {:<24} {:<24}\n", "UDP throughput", throughput(&self.udp_throughput.direct), throughput(&self.udp_throughput.relayed)));
+        out.push_str(&format!("{:<16} {:<24} {:<24} +{:?}\n", "UDP round trip", round_trip(&self.udp_round_trip.direct), round_trip(&self.udp_round_trip.relayed), udp_added));
+        out.push_str(&format!("TCP buffer size: {} bytes (--tcp_buffer_size)\n", self.tcp_buffer_size));
+        out
+    }
+}
+
+fn format_rate(bytes_per_sec: f64) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    if 1024.0 * MIB <= bytes_per_sec {
+        format!("{:.2} GiB/s", bytes_per_sec / (1024.0 * MIB))
+    } else {
+        format!("{:.1} MiB/s", bytes_per_sec / MIB)
+    }
+}
+
+fn localhost() -> SocketAddr {
+    SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+}
+
+/* first byte picks the mode, sink replies with the byte count after EOF */
+async fn start_tcp_server() -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(localhost()).await?;
+    let addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.set_nodelay(true);
+
+            tokio::spawn(async move {
+                let mut buffer = vec![0u8; TCP_CHUNK_SIZE];
+                let mode = stream.read_u8().await?;
+                let mut total = 0u64;
+
+                loop {
+                    let read = stream.read(&mut buffer).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    total += read as u64;
+
+                    if mode == MODE_ECHO {
+                        stream.write_all(&buffer[..read]).await?;
+                    }
+                }
+
+                if mode == MODE_SINK {
+                    stream.write_u64(total).await?;
+                }
+                stream.shutdown().await
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/* forwards every connection to target with the same copy loop the agent uses */
+async fn start_tcp_relay(target: SocketAddr, buffer_size: usize) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(localhost()).await?;
+    let addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let origin = TcpStream::connect(target).await?;
+                let (client_read, client_write) = client.into_split();
+                let (origin_read, origin_write) = origin.into_split();
+
+                let to_origin = tokio::spawn(pipe_with_buffer(client_read, origin_write, buffer_size));
+                let _ = pipe_with_buffer(origin_read, client_write, buffer_size).await;
+                let _ = to_origin.await;
+                Ok::<_, std::io::Error>(())
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn tcp_throughput(addr: SocketAddr, total: usize) -> std::io::Result<Throughput> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let chunk = vec![7u8; TCP_CHUNK_SIZE];
+    let start = Instant::now();
+
+    stream.write_u8(MODE_SINK).await?;
+    let mut sent = 0;
+    while sent < total {
+        let len = chunk.len().min(total - sent);
+        stream.write_all(&chunk[..len]).await?;
+        sent += len;
+    }
+    stream.shutdown().await?;
+
+    let received = stream.read_u64().await?;
+    Ok(Throughput {
+        bytes: received,
+        elapsed: start.elapsed(),
+        lost: 0,
+    })
+}
+
+async fn tcp_round_trip(addr: SocketAddr, count: usize) -> std::io::Result<RoundTrip> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    stream.write_u8(MODE_ECHO).await?;
+
+    let message = [1u8; 32];
+    let mut reply = [0u8; 32];
+    let start = Instant::now();
+
+    for _ in 0..count {
+        stream.write_all(&message).await?;
+        stream.read_exact(&mut reply).await?;
+    }
+
+    Ok(RoundTrip {
+        average: start.elapsed() / count.max(1) as u32,
+        lost: 0,
+    })
+}
+
+/*
+ * UDP sink counts packets between the first one and MODE_DONE, then replies
+ * with the count and the microseconds between first and last packet.
+ */
+async fn start_udp_server() -> std::io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(localhost()).await?;
+    let addr = socket.local_addr()?;
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 2048];
+        let mut count = 0u64;
+        let mut first: Option<Instant> = None;
+        let mut last = Instant::now();
+
+        while let Ok((len, from)) = socket.recv_from(&mut buffer).await {
+            match buffer.first() {
+                Some(&MODE_ECHO) => {
+                    let _ = socket.send_to(&buffer[..len], from).await;
+                }
+                Some(&MODE_SINK) => {
+                    count += 1;
+                    last = Instant::now();
+                    first.get_or_insert(last);
+                }
+                Some(&MODE_DONE) => {
+                    let micros = first.take().map(|first| (last - first).as_micros() as u64).unwrap_or(0);
+                    let mut reply = [0u8; 16];
+                    reply[..8].copy_from_slice(&count.to_be_bytes());
+                    reply[8..].copy_from_slice(&micros.to_be_bytes());
+                    let _ = socket.send_to(&reply, from).await;
+                    count = 0;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    Ok(addr)
+}
+
+/* single client UDP forwarder, the origin side uses the agent's DualStackUdpSocket */
+async fn start_udp_relay(target: SocketAddr) -> std::io::Result<SocketAddr> {
+    let front = Arc::new(UdpSocket::bind(localhost()).await?);
+    let origin = Arc::new(DualStackUdpSocket::new().await?);
+    let addr = front.local_addr()?;
+    let client = Arc::new(Mutex::new(None::<SocketAddr>));
+
+    {
+        let (front, origin, client) = (front.clone(), origin.clone(), client.clone());
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 2048];
+            while let Ok((len, from)) = front.recv_from(&mut buffer).await {
+                client.lock().unwrap().replace(from);
+                let _ = origin.send_to(&buffer[..len], target).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 2048];
+        while let Ok((len, _)) = PacketIO::recv_from(&*origin, &mut buffer).await {
+            let Some(client) = *client.lock().unwrap() else { continue };
+            let _ = front.send_to(&buffer[..len], client).await;
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn udp_throughput(addr: SocketAddr, packets: usize, packet_size: usize) -> std::io::Result<Throughput> {
+    let socket = UdpSocket::bind(localhost()).await?;
+    let mut packet = vec![7u8; packet_size.clamp(1, 1400)];
+    packet[0] = MODE_SINK;
+
+    for i in 0..packets {
+        socket.send_to(&packet, addr).await?;
+        /* let the receivers keep up, loss should come from forwarding not from one task hogging the runtime */
+        if i % 32 == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    let mut reply = [0u8; 16];
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        socket.send_to(&[MODE_DONE], addr).await?;
+
+        if let Ok(Ok((16, _))) = tokio::time::timeout(UDP_RECV_TIMEOUT, socket.recv_from(&mut reply)).await {
+            let received = u64::from_be_bytes(reply[..8].try_into().unwrap());
+            let micros = u64::from_be_bytes(reply[8..].try_into().unwrap());
+
+            return Ok(Throughput {
+                bytes: received * packet.len() as u64,
+                elapsed: Duration::from_micros(micros),
+                lost: (packets as u64).saturating_sub(received),
+            });
+        }
+    }
+
+    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no reply from UDP sink"))
+}
+
+async fn udp_round_trip(addr: SocketAddr, count: usize) -> std::io::Result<RoundTrip> {
+    let socket = UdpSocket::bind(localhost()).await?;
+    let mut message = [1u8; 32];
+    let mut reply = [0u8; 64];
+    let mut total = Duration::ZERO;
+    let mut answered = 0u64;
+
+    for i in 0..count {
+        message[0] = MODE_ECHO;
+        message[1..9].copy_from_slice(&(i as u64).to_be_bytes());
+
+        let start = Instant::now();
+        socket.send_to(&message, addr).await?;
+
+        /* drop late replies from earlier lost round trips */
+        let deadline = tokio::time::Instant::now() + UDP_RECV_TIMEOUT;
+        while let Ok(Ok((len, _))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut reply)).await {
+            if reply[..len] == message[..] {
+                total += start.elapsed();
+                answered += 1;
+                break;
+            }
+        }
+    }
+
+    Ok(RoundTrip {
+        average: total / answered.max(1) as u32,
+        lost: count as u64 - answered,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_benchmark, BenchmarkSettings};
+
+    #[tokio::test]
+    async fn test_benchmark_small() {
+        let report = run_benchmark(&BenchmarkSettings {
+            tcp_bytes: 1024 * 1024,
+            tcp_buffer_size: 16 * 1024,
+            udp_packets: 100,
+            udp_packet_size: 500,
+            round_trips: 10,
+        }).await.unwrap();
+
+        assert_eq!(report.tcp_throughput.direct.bytes, 1024 * 1024);
+        assert_eq!(report.tcp_throughput.relayed.bytes, 1024 * 1024);
+        assert!(0 < report.udp_throughput.relayed.bytes);
+        assert_eq!(report.udp_round_trip.relayed.lost, 0);
+        assert!(report.format().contains("TCP throughput"));
+    }
+}
@@ -0,0 +1,196 @@
+use std::net::{IpAddr, SocketAddr};
+
+use playit_api_client::api::{AgentTunnel, PortType};
+
+use crate::autorun::{find_self_loop, find_self_loops};
+use crate::tunnel_filter::TunnelFilter;
+use crate::flags::{parse_mapping_overrides, LocalTarget, MappingOverrideArg};
+use crate::CliError;
+
+/*
+ * playit config check: preflight for deployment configs, meant for CI. Every
+ * problem found is reported instead of stopping at the first one.
+ *
+ *   - the config (secret) file parses and holds a valid secret
+ *   - run mappings parse, same format as `playit run`
+ *   - unless --offline, mapped tunnel ids exist on the account and carry the
+ *     mapped protocol, and no local target (mapped or the tunnel's own)
+ *     points back at the tunnel's public address
+ */
+
+#[derive(Debug, Default)]
+pub struct ConfigReport {
+    pub problems: Vec<String>,
+}
+
+impl ConfigReport {
+    fn problem<S: Into<String>>(&mut self, problem: S) {
+        self.problems.push(problem.into());
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn format(&self) -> String {
+        if self.is_ok() {
+            return "config ok\n".to_string();
+        }
+
+        let mut out = String::new();
+        for problem in &self.problems {
+            out.push_str(&format!("error: {}\n", problem));
+        }
+        out.push_str(&format!("{} problem(s) found\n", self.problems.len()));
+        out
+    }
+}
+
+/* tunnels is None with --offline or when there is no secret to load them with, server_ips are the control servers' */
+pub async fn check_config(secret: Result<(), CliError>, mappings: &[&str], tunnels: Option<Result<Vec<AgentTunnel>, CliError>>, server_ips: &[IpAddr]) -> ConfigReport {
+    let mut report = ConfigReport::default();
+
+    if let Err(error) = &secret {
+        report.problem(format!("failed to load secret from config: {}", error));
+    }
+
+    let mappings = check_mappings(mappings, &mut report);
+
+    let tunnels = match tunnels {
+        Some(Ok(tunnels)) => tunnels,
+        Some(Err(error)) => {
+            report.problem(format!("failed to load tunnels from the api: {}", error));
+            return report;
+        }
+        None => return report,
+    };
+
+    check_mapped_tunnels(&mappings, &tunnels, server_ips, &mut report).await;
+
+    for (tunnel, public_addr) in find_self_loops(&tunnels, &TunnelFilter::default(), server_ips).await {
+        report.problem(format!(
+            "tunnel {} has local address {} which is its own public address {}",
+            tunnel.id, SocketAddr::new(tunnel.local_ip, tunnel.local_port), public_addr,
+        ));
+    }
+
+    report
+}
+
+/* values continuing a tunnel ("udp:7778") are checked together with the value that names it */
+fn check_mappings(values: &[&str], report: &mut ConfigReport) -> Vec<MappingOverrideArg> {
+    let mut groups: Vec<Vec<&str>> = Vec::new();
+
+    for value in values.iter().map(|value| value.trim()).filter(|value| !value.is_empty()) {
+        let names_tunnel = value.split_once("/proxy=").map_or(value, |(mapping, _)| mapping).contains('=');
+        match groups.last_mut() {
+            Some(group) if !names_tunnel => group.push(value),
+            _ => groups.push(vec![value]),
+        }
+    }
+
+    let mut mappings = Vec::new();
+    for group in groups {
+        match parse_mapping_overrides(group.iter().copied()) {
+            Ok(parsed) => mappings.extend(parsed),
+            Err(_) => report.problem(format!(
+                "invalid mapping \"{}\", expected \"<tunnel-id>=[tcp:|udp:][<local-ip>:]<local-port>[/proxy=none|v1|v2]\" or \"<tunnel-id>=[tcp:]unix:<path>\"",
+                group.join(","),
+            )),
+        }
+    }
+
+    mappings
+}
+
+async fn check_mapped_tunnels(mappings: &[MappingOverrideArg], tunnels: &[AgentTunnel], server_ips: &[IpAddr], report: &mut ConfigReport) {
+    for mapping in mappings {
+        let Some(tunnel) = tunnels.iter().find(|tunnel| tunnel.id == mapping.tunnel_id) else {
+            report.problem(format!("mapping for tunnel {} which is not on this agent's account", mapping.tunnel_id));
+            continue;
+        };
+
+        if let Some(proto) = mapping.proto {
+            if !tunnel.proto.matches(proto) {
+                report.problem(format!("mapping for tunnel {} targets {:?} but the tunnel is {:?}", tunnel.id, proto, tunnel.proto));
+            }
+        }
+
+        let local_addr = match &mapping.target {
+            LocalTarget::Addr(addr) => *addr,
+            LocalTarget::Unix(path) => {
+                if !tunnel.proto.matches(PortType::Tcp) {
+                    report.problem(format!("mapping for tunnel {} targets UNIX socket {} but the tunnel is {:?}", tunnel.id, path.display(), tunnel.proto));
+                }
+                continue;
+            }
+        };
+
+        if let Some(public_addr) = find_self_loop(tunnel, local_addr, server_ips).await {
+            report.problem(format!(
+                "mapping for tunnel {} points to {} which is its own public address {}",
+                tunnel.id, local_addr, public_addr,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::{AgentTunnel, PortRange, PortType};
+    use uuid::Uuid;
+
+    use crate::CliError;
+
+    use super::check_config;
+
+    fn tunnel(id: u128, proto: PortType, local_port: u16) -> AgentTunnel {
+        AgentTunnel {
+            id: Uuid::from_u128(id),
+            name: None,
+            ip_num: 0,
+            region_num: 0,
+            port: PortRange { from: 25565, to: 25566 },
+            proto,
+            local_ip: "127.0.0.1".parse().unwrap(),
+            local_port,
+            tunnel_type: None,
+            /* resolves without dns so the self loop check can run in tests */
+            assigned_domain: "127.0.0.1".to_string(),
+            custom_domain: None,
+            disabled: None,
+            proxy_protocol: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reports_all_problems() {
+        let tcp = Uuid::from_u128(1).to_string();
+        let missing = Uuid::from_u128(3).to_string();
+
+        let mappings = [
+            format!("{}=udp:7777", tcp),
+            "not-a-tunnel=25565".to_string(),
+            format!("{}=tcp:25565", missing),
+            format!("{}=127.0.0.1:25565", tcp),
+            format!("{}=tcp:8080", tcp),
+        ];
+        let mappings: Vec<&str> = mappings.iter().map(|v| v.as_str()).collect();
+
+        let tunnels = vec![tunnel(1, PortType::Tcp, 8080), tunnel(2, PortType::Udp, 25565)];
+        let report = check_config(Ok(()), &mappings, Some(Ok(tunnels)), &[]).await;
+
+        assert_eq!(report.problems.len(), 5, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("invalid mapping \"not-a-tunnel=25565\""));
+        assert!(report.problems[1].contains("targets Udp but the tunnel is Tcp"));
+        assert!(report.problems[2].contains("not on this agent's account"));
+        assert!(report.problems[3].contains("points to 127.0.0.1:25565 which is its own public address"));
+        assert!(report.problems[4].starts_with("tunnel 00000000-0000-0000-0000-000000000002 has local address 127.0.0.1:25565"));
+
+        /* offline only checks what doesn't need the account */
+        let report = check_config(Err(CliError::MalformedSecret), &mappings, None, &[]).await;
+        assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("failed to load secret"));
+        assert!(!report.is_ok());
+    }
+}
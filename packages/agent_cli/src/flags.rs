@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use playit_agent_core::network::access_log::AccessLog;
+use playit_agent_core::network::client_filter::{ClientFilter, ClientIpFilter};
+use playit_agent_core::network::client_geo::ClientGeo;
+use playit_agent_core::network::connection_hooks::{ConnectionHooks, TunnelHooks};
+use playit_agent_core::network::host_routing::{normalize_hostname, HostRoute, HostRouting};
+use playit_agent_core::network::lan_address::{parse_local_addr, SourcePorts};
+use playit_agent_core::network::tcp_pipe::is_valid_buffer_size;
+use playit_agent_core::network::tunnel_quota::{QuotaLimits, TunnelQuotas};
+use playit_agent_core::network::udp::clients::UdpProxyResend;
+use playit_agent_core::network::udp::receive_task::MAX_RECV_BATCH_SIZE;
+use playit_agent_core::agent_control::server_override::ControlServerOverride;
+use playit_api_client::api::{PortType, ProxyProtocol};
+use uuid::Uuid;
+
+use crate::autorun::{AddressFamily, AutorunSettings, MIN_IDLE_SLEEP, MIN_TUNNEL_REFRESH_INTERVAL};
+use crate::tunnel_filter::TunnelFilter;
+use crate::util::parse_byte_size;
+use crate::webhook::WebhookSettings;
+use crate::{ClaimPollIntervals, CliError};
+
+/*
+ * Parsing of command line flags into settings. Every parser rejects bad
+ * values up front so the agent never starts with a half applied config.
+ */
+
+/* health, admin and access_log are set up by the caller, they open listeners and files */
+pub fn autorun_settings(matches: &ArgMatches) -> Result<AutorunSettings, CliError> {
+    Ok(AutorunSettings {
+        tunnel_filter: TunnelFilter::new(matches.get_many::<String>("tunnel_filter").into_iter().flatten()),
+        connection_hooks: parse_connection_hooks(
+            matches.get_many::<String>("on_connect").into_iter().flatten(),
+            matches.get_many::<String>("on_disconnect").into_iter().flatten(),
+        )?,
+        client_filter: ClientFilter::new(if matches.get_flag("ip6_only") {
+            ClientIpFilter::Ip6Only
+        } else if matches.get_flag("ip4_only") {
+            ClientIpFilter::Ip4Only
+        } else {
+            ClientIpFilter::Any
+        }),
+        tcp_buffer_size: match matches.get_one::<String>("tcp_buffer_size") {
+            Some(size) => {
+                let size = size.parse::<usize>().map_err(|_| CliError::InvalidTcpBufferSize)?;
+                if !is_valid_buffer_size(size) {
+                    return Err(CliError::InvalidTcpBufferSize);
+                }
+                Some(size)
+            }
+            None => None,
+        },
+        local_connect_timeout: match matches.get_one::<String>("connect_timeout") {
+            Some(secs) => match secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+                Some(timeout) if !timeout.is_zero() => Some(timeout),
+                _ => return Err(CliError::InvalidConnectTimeout),
+            },
+            None => None,
+        },
+        tcp_setup_limit: match matches.get_one::<String>("tcp_setup_limit") {
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) if 0 < limit => Some(limit),
+                _ => return Err(CliError::InvalidTcpSetupLimit),
+            },
+            None => None,
+        },
+        source_ports: match matches.get_one::<String>("source_port") {
+            Some(ports) => Some(SourcePorts::parse(ports).ok_or(CliError::InvalidSourcePort)?),
+            None => None,
+        },
+        #[cfg(feature = "client-geo")]
+        client_geo: match matches.get_one::<String>("client_geo_db") {
+            Some(path) => ClientGeo::load(std::path::Path::new(path))
+                .map_err(|error| CliError::InvalidClientGeoDb(format!("{:?}", error)))?,
+            None => ClientGeo::default(),
+        },
+        #[cfg(not(feature = "client-geo"))]
+        client_geo: ClientGeo::default(),
+        udp_recv_batch_size: match matches.get_one::<String>("udp_recv_batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if (1..=MAX_RECV_BATCH_SIZE).contains(&size) => Some(size),
+                _ => return Err(CliError::InvalidUdpRecvBatchSize),
+            },
+            None => None,
+        },
+        udp_proxy_resend: parse_udp_proxy_resend(
+            matches.get_one::<String>("udp_proxy_host_idle"),
+            matches.get_one::<String>("udp_proxy_min_resend"),
+        )?,
+        host_routing: parse_host_routes(matches.get_many::<String>("host_route").into_iter().flatten())?,
+        max_connection_lifetimes: parse_max_connection_lifetimes(
+            matches.get_many::<String>("max_connection_lifetime").into_iter().flatten(),
+        )?,
+        unix_targets: parse_unix_targets(matches.get_many::<String>("unix_target").into_iter().flatten())?,
+        quotas: TunnelQuotas::new(parse_tunnel_quotas(
+            matches.get_many::<String>("quota_bytes_per_day").into_iter().flatten(),
+            matches.get_many::<String>("quota_connections_per_hour").into_iter().flatten(),
+        )?),
+        reconnect_on_auth_error: matches.get_flag("reconnect_on_auth_error"),
+        match_client_family: matches.get_flag("match_client_family"),
+        local_ipv6_first: matches.get_flag("local_ipv6_first"),
+        display_family: match matches.get_one::<String>("display_family").map(|v| v.as_str()) {
+            Some("ipv4") => Some(AddressFamily::Ipv4),
+            Some("ipv6") => Some(AddressFamily::Ipv6),
+            _ => None,
+        },
+        control_server: match matches.get_one::<String>("control_server") {
+            Some(server) => Some(server.parse::<ControlServerOverride>().map_err(|_| CliError::InvalidControlServer)?),
+            None => None,
+        },
+        webhook: WebhookSettings {
+            url: matches.get_one::<String>("webhook_url").cloned(),
+            secret: matches.get_one::<String>("webhook_secret").cloned(),
+        },
+        health: None,
+        admin: None,
+        access_log: AccessLog::default(),
+        deny_local_addr_conflicts: matches.get_flag("deny_local_addr_conflicts"),
+        exit_on_no_tunnels: matches.get_flag("exit_on_no_tunnels"),
+        tunnel_refresh_interval: match matches.get_one::<String>("tunnel_refresh_interval") {
+            Some(secs) => match secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+                Some(interval) if MIN_TUNNEL_REFRESH_INTERVAL <= interval => Some(interval),
+                _ => return Err(CliError::InvalidTunnelRefreshInterval),
+            },
+            None => None,
+        },
+        idle_sleep: match matches.get_one::<String>("idle_sleep") {
+            Some(secs) => match secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+                Some(after) if MIN_IDLE_SLEEP <= after => Some(after),
+                _ => return Err(CliError::InvalidIdleSleep),
+            },
+            None => None,
+        },
+    })
+}
+
+const MIN_CLAIM_SETUP_INTERVAL: Duration = Duration::from_millis(100);
+const MIN_CLAIM_EXCHANGE_INTERVAL: Duration = Duration::from_millis(500);
+
+/* values in seconds */
+pub fn parse_claim_poll_intervals(setup: Option<&String>, exchange: Option<&String>) -> Result<ClaimPollIntervals, CliError> {
+    let parse = |value: Option<&String>, default: Duration, min: Duration| match value {
+        Some(secs) => match secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+            Some(interval) if min <= interval => Ok(interval),
+            _ => Err(CliError::InvalidClaimPollInterval { min }),
+        },
+        None => Ok(default),
+    };
+
+    let defaults = ClaimPollIntervals::default();
+    Ok(ClaimPollIntervals {
+        setup: parse(setup, defaults.setup, MIN_CLAIM_SETUP_INTERVAL)?,
+        exchange: parse(exchange, defaults.exchange, MIN_CLAIM_EXCHANGE_INTERVAL)?,
+    })
+}
+
+/* format "<tunnel-id>=<command>" */
+pub fn parse_connection_hooks<'a, C, D>(on_connect: C, on_disconnect: D) -> Result<ConnectionHooks, CliError>
+    where C: IntoIterator<Item = &'a String>, D: IntoIterator<Item = &'a String>
+{
+    let mut hooks = HashMap::<Uuid, TunnelHooks>::new();
+
+    let parse = |value: &String| -> Result<(Uuid, String), CliError> {
+        let (tunnel_id, command) = value.split_once('=').ok_or(CliError::InvalidConnectionHook)?;
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidConnectionHook)?;
+        Ok((tunnel_id, command.to_string()))
+    };
+
+    for value in on_connect {
+        let (tunnel_id, command) = parse(value)?;
+        hooks.entry(tunnel_id).or_default().on_connect = Some(command);
+    }
+
+    for value in on_disconnect {
+        let (tunnel_id, command) = parse(value)?;
+        hooks.entry(tunnel_id).or_default().on_disconnect = Some(command);
+    }
+
+    Ok(ConnectionHooks::new(hooks, Duration::from_secs(5)))
+}
+
+/* format "<tunnel-id>=<hostname>=[<local-ip>:]<local-port>", hostname may start with "*." */
+pub fn parse_host_routes<'a, I: IntoIterator<Item = &'a String>>(values: I) -> Result<HostRouting, CliError> {
+    let mut routes = HashMap::<Uuid, Vec<HostRoute>>::new();
+
+    for value in values {
+        let mut parts = value.splitn(3, '=');
+        let (Some(tunnel_id), Some(hostname), Some(target)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(CliError::InvalidHostRoute);
+        };
+
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidHostRoute)?;
+        let hostname = normalize_hostname(hostname);
+        if hostname.is_empty() || hostname[1..].contains('*') || (hostname.starts_with('*') && !hostname.starts_with("*.")) {
+            return Err(CliError::InvalidHostRoute);
+        }
+
+        let target = target.trim();
+        let local_addr = match parse_local_addr(target) {
+            Some(addr) => addr,
+            None => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), u16::from_str(target).map_err(|_| CliError::InvalidHostRoute)?),
+        };
+
+        routes.entry(tunnel_id).or_default().push(HostRoute { hostname, local_addr });
+    }
+
+    Ok(HostRouting::new(routes))
+}
+
+/* a bare port binds loopback, see admin_api.rs */
+pub fn parse_admin_listen(listen: &str) -> Result<SocketAddr, CliError> {
+    if let Ok(port) = listen.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+    listen.parse::<SocketAddr>().map_err(|_| CliError::InvalidAdminListenAddr)
+}
+
+/* values in seconds, unset values keep the defaults */
+pub fn parse_udp_proxy_resend(host_idle: Option<&String>, min_resend: Option<&String>) -> Result<UdpProxyResend, CliError> {
+    let parse = |value: Option<&String>, default: Duration| match value {
+        Some(secs) => match secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+            Some(interval) if !interval.is_zero() => Ok(interval),
+            _ => Err(CliError::InvalidUdpProxyResend),
+        },
+        None => Ok(default),
+    };
+
+    let defaults = UdpProxyResend::default();
+    Ok(UdpProxyResend {
+        host_idle: parse(host_idle, defaults.host_idle)?,
+        min_resend: parse(min_resend, defaults.min_resend)?,
+    })
+}
+
+/* format "<tunnel-id>=<seconds>" */
+pub fn parse_max_connection_lifetimes<'a, I: IntoIterator<Item = &'a String>>(values: I) -> Result<HashMap<Uuid, Duration>, CliError> {
+    let mut lifetimes = HashMap::new();
+
+    for value in values {
+        let (tunnel_id, secs) = value.split_once('=').ok_or(CliError::InvalidConnectionLifetime)?;
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidConnectionLifetime)?;
+
+        let lifetime = match secs.trim().parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+            Some(lifetime) if !lifetime.is_zero() => lifetime,
+            _ => return Err(CliError::InvalidConnectionLifetime),
+        };
+
+        lifetimes.insert(tunnel_id, lifetime);
+    }
+
+    Ok(lifetimes)
+}
+
+/* format "<tunnel-id>=unix:<path>" ("unix:" optional), the socket must exist once connections arrive, not at startup */
+pub fn parse_unix_targets<'a, I: IntoIterator<Item = &'a String>>(values: I) -> Result<HashMap<Uuid, PathBuf>, CliError> {
+    let mut targets = HashMap::new();
+
+    for value in values {
+        if !cfg!(unix) {
+            return Err(CliError::UnixTargetUnsupported);
+        }
+
+        let (tunnel_id, path) = value.split_once('=').ok_or(CliError::InvalidUnixTarget)?;
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidUnixTarget)?;
+
+        let path = path.trim();
+        let path = path.strip_prefix("unix:").unwrap_or(path).trim();
+        if path.is_empty() {
+            return Err(CliError::InvalidUnixTarget);
+        }
+
+        if targets.insert(tunnel_id, PathBuf::from(path)).is_some() {
+            return Err(CliError::InvalidUnixTarget);
+        }
+    }
+
+    Ok(targets)
+}
+
+/* format "<tunnel-id>=<bytes, ex. 10G>" and "<tunnel-id>=<count>", a tunnel may have both */
+pub fn parse_tunnel_quotas<'a, B: IntoIterator<Item = &'a String>, C: IntoIterator<Item = &'a String>>(bytes_per_day: B, connections_per_hour: C) -> Result<HashMap<Uuid, QuotaLimits>, CliError> {
+    let mut quotas = HashMap::<Uuid, QuotaLimits>::new();
+
+    for value in bytes_per_day {
+        let (tunnel_id, size) = value.split_once('=').ok_or(CliError::InvalidTunnelQuota)?;
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidTunnelQuota)?;
+        let size = parse_byte_size(size).filter(|size| *size != 0).ok_or(CliError::InvalidTunnelQuota)?;
+        quotas.entry(tunnel_id).or_default().bytes_per_day = Some(size);
+    }
+
+    for value in connections_per_hour {
+        let (tunnel_id, count) = value.split_once('=').ok_or(CliError::InvalidTunnelQuota)?;
+        let tunnel_id = Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidTunnelQuota)?;
+        let count = count.trim().parse::<u64>().ok().filter(|count| *count != 0).ok_or(CliError::InvalidTunnelQuota)?;
+        quotas.entry(tunnel_id).or_default().connections_per_hour = Some(count);
+    }
+
+    Ok(quotas)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalTarget {
+    Addr(SocketAddr),
+    /* "unix:<path>", TCP only */
+    Unix(PathBuf),
+}
+
+pub struct MappingOverrideArg {
+    pub tunnel_id: Uuid,
+    pub proto: Option<PortType>,
+    pub target: LocalTarget,
+    /* None uses the tunnel's setting from the api, Some(None) disables the header */
+    pub proxy_protocol: Option<Option<ProxyProtocol>>,
+}
+
+/* format "<tunnel-id>=[tcp:|udp:][<local-ip>:]<local-port>[/proxy=none|v1|v2]" or "<tunnel-id>=[tcp:]unix:<path>", values without a
+ * tunnel id continue the previous tunnel so "<tunnel-id>=tcp:7777,udp:7778" maps tcp and udp of a "both" tunnel independently */
+pub fn parse_mapping_overrides<'a, I: IntoIterator<Item = &'a str>>(values: I) -> Result<Vec<MappingOverrideArg>, CliError> {
+    let mut overrides = Vec::new();
+    let mut last_tunnel_id = None;
+
+    for value in values {
+        let (value, proxy_protocol) = match value.split_once("/proxy=") {
+            Some((value, proxy)) => (value, Some(match proxy.trim() {
+                "none" => None,
+                "v1" => Some(ProxyProtocol::ProxyProtocolV1),
+                "v2" => Some(ProxyProtocol::ProxyProtocolV2),
+                _ => return Err(CliError::InvalidMappingOverride),
+            })),
+            None => (value, None),
+        };
+
+        let (tunnel_id, target) = match value.split_once('=') {
+            Some((tunnel_id, target)) => (
+                Uuid::from_str(tunnel_id.trim()).map_err(|_| CliError::InvalidMappingOverride)?,
+                target.trim(),
+            ),
+            None => (
+                last_tunnel_id.ok_or(CliError::InvalidMappingOverride)?,
+                value.trim(),
+            ),
+        };
+
+        let (proto, local_addr_str) = if let Some(rest) = target.strip_prefix("tcp:") {
+            (Some(PortType::Tcp), rest)
+        } else if let Some(rest) = target.strip_prefix("udp:") {
+            (Some(PortType::Udp), rest)
+        } else {
+            (None, target)
+        };
+
+        /* continuation values must say which protocol they target */
+        if proto.is_none() && !value.contains('=') {
+            return Err(CliError::InvalidMappingOverride);
+        }
+
+        let target = if let Some(path) = local_addr_str.strip_prefix("unix:") {
+            if !cfg!(unix) {
+                return Err(CliError::UnixTargetUnsupported);
+            }
+            if path.trim().is_empty() || proto == Some(PortType::Udp) {
+                return Err(CliError::InvalidMappingOverride);
+            }
+            LocalTarget::Unix(PathBuf::from(path.trim()))
+        } else {
+            LocalTarget::Addr(match parse_local_addr(local_addr_str) {
+                Some(addr) => addr,
+                _ => match u16::from_str(local_addr_str) {
+                    Ok(port) => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+                    _ => return Err(CliError::InvalidMappingOverride),
+                }
+            })
+        };
+
+        last_tunnel_id = Some(tunnel_id);
+        overrides.push(MappingOverrideArg {
+            tunnel_id,
+            proto,
+            target,
+            proxy_protocol,
+        });
+    }
+
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use playit_agent_core::network::udp::clients::UdpProxyResend;
+    use playit_api_client::api::{PortType, ProxyProtocol};
+    use uuid::Uuid;
+
+    use crate::{ClaimPollIntervals, CliError};
+
+    use super::{parse_admin_listen, parse_claim_poll_intervals, parse_host_routes, parse_mapping_overrides, parse_max_connection_lifetimes, parse_tunnel_quotas, parse_udp_proxy_resend, parse_unix_targets, LocalTarget};
+
+    #[test]
+    fn test_parse_claim_poll_intervals() {
+        assert_eq!(parse_claim_poll_intervals(None, None).unwrap(), ClaimPollIntervals::default());
+
+        let intervals = parse_claim_poll_intervals(Some(&"1".to_string()), Some(&"10".to_string())).unwrap();
+        assert_eq!(intervals.setup, Duration::from_secs(1));
+        assert_eq!(intervals.exchange, Duration::from_secs(10));
+
+        assert!(parse_claim_poll_intervals(Some(&"0.01".to_string()), None).is_err());
+        assert!(parse_claim_poll_intervals(None, Some(&"0".to_string())).is_err());
+        assert!(parse_claim_poll_intervals(None, Some(&"soon".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_parse_udp_proxy_resend() {
+        assert_eq!(parse_udp_proxy_resend(None, None).unwrap(), UdpProxyResend::default());
+
+        let resend = parse_udp_proxy_resend(Some(&"60".to_string()), Some(&"0.5".to_string())).unwrap();
+        assert_eq!(resend.host_idle, Duration::from_secs(60));
+        assert_eq!(resend.min_resend, Duration::from_millis(500));
+
+        assert!(parse_udp_proxy_resend(Some(&"0".to_string()), None).is_err());
+        assert!(parse_udp_proxy_resend(None, Some(&"-1".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_parse_admin_listen() {
+        assert_eq!(parse_admin_listen("9200").unwrap(), "127.0.0.1:9200".parse::<SocketAddr>().unwrap());
+        assert_eq!(parse_admin_listen("[::1]:9200").unwrap(), "[::1]:9200".parse::<SocketAddr>().unwrap());
+        assert!(matches!(parse_admin_listen("localhost"), Err(CliError::InvalidAdminListenAddr)));
+    }
+
+    #[test]
+    fn test_parse_host_routes() {
+        let tunnel_id = Uuid::from_u128(4);
+        let values = [
+            format!("{}=Web.Example.com=8080", tunnel_id),
+            format!("{}=*.example.com=192.168.1.5:443", tunnel_id),
+        ];
+        let routing = parse_host_routes(values.iter()).unwrap();
+
+        assert_eq!(routing.route(tunnel_id, "web.example.com"), Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(routing.route(tunnel_id, "app.example.com"), Some("192.168.1.5:443".parse().unwrap()));
+
+        for invalid in ["web.example.com=8080", "not-a-uuid=a.com=1", "00000000-0000-0000-0000-000000000004=a*.com=1", "00000000-0000-0000-0000-000000000004=a.com=x"] {
+            assert!(matches!(parse_host_routes([invalid.to_string()].iter()), Err(CliError::InvalidHostRoute)), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_split_mapping_override() {
+        let id = Uuid::from_u128(1);
+        let input = format!("{}=tcp:7777", id);
+        let parsed = parse_mapping_overrides([input.as_str(), "udp:192.168.1.2:7778"]).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].tunnel_id, id);
+        assert_eq!(parsed[0].proto, Some(PortType::Tcp));
+        assert_eq!(parsed[0].target, LocalTarget::Addr("127.0.0.1:7777".parse().unwrap()));
+        assert_eq!(parsed[1].tunnel_id, id);
+        assert_eq!(parsed[1].proto, Some(PortType::Udp));
+        assert_eq!(parsed[1].target, LocalTarget::Addr("192.168.1.2:7778".parse().unwrap()));
+
+        let input = format!("{}=25565", id);
+        let parsed = parse_mapping_overrides([input.as_str()]).unwrap();
+        assert_eq!(parsed[0].proto, None);
+
+        let input = format!("{}=udp:[fe80::1%3]:7778", id);
+        let parsed = parse_mapping_overrides([input.as_str()]).unwrap();
+        assert_eq!(parsed[0].target, LocalTarget::Addr("[fe80::1%3]:7778".parse().unwrap()));
+
+        assert!(parse_mapping_overrides(["udp:7778"]).is_err());
+        assert!(parse_mapping_overrides([input.as_str(), "7778"]).is_err());
+        assert_eq!(parsed[0].proxy_protocol, None);
+
+        let input = format!("{}=tcp:25565/proxy=v2", id);
+        let parsed = parse_mapping_overrides([input.as_str(), "udp:[::1]:19132/proxy=none"]).unwrap();
+        assert_eq!(parsed[0].target, LocalTarget::Addr("127.0.0.1:25565".parse().unwrap()));
+        assert_eq!(parsed[0].proxy_protocol, Some(Some(ProxyProtocol::ProxyProtocolV2)));
+        assert_eq!(parsed[1].target, LocalTarget::Addr("[::1]:19132".parse().unwrap()));
+        assert_eq!(parsed[1].proxy_protocol, Some(None));
+
+        assert!(parse_mapping_overrides([format!("{}=25565/proxy=v3", id).as_str()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_tunnel_quotas() {
+        let bytes = [format!("{}=10G", Uuid::from_u128(1))];
+        let connections = [format!("{}=100", Uuid::from_u128(1)), format!("{}=5", Uuid::from_u128(2))];
+        let quotas = parse_tunnel_quotas(&bytes, &connections).unwrap();
+
+        assert_eq!(quotas[&Uuid::from_u128(1)].bytes_per_day, Some(10 * 1024 * 1024 * 1024));
+        assert_eq!(quotas[&Uuid::from_u128(1)].connections_per_hour, Some(100));
+        assert_eq!(quotas[&Uuid::from_u128(2)].bytes_per_day, None);
+
+        assert!(parse_tunnel_quotas(&[format!("{}=0", Uuid::from_u128(1))], &[]).is_err());
+        assert!(parse_tunnel_quotas(&[], &["100".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_max_connection_lifetimes() {
+        let values = [format!("{}=3600", Uuid::from_u128(1)), format!("{} = 0.5", Uuid::from_u128(2))];
+        let lifetimes = parse_max_connection_lifetimes(&values).unwrap();
+
+        assert_eq!(lifetimes.get(&Uuid::from_u128(1)), Some(&Duration::from_secs(3600)));
+        assert_eq!(lifetimes.get(&Uuid::from_u128(2)), Some(&Duration::from_millis(500)));
+
+        assert!(parse_max_connection_lifetimes(&[format!("{}=0", Uuid::from_u128(1))]).is_err());
+        assert!(parse_max_connection_lifetimes(&["3600".to_string()]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_unix_targets() {
+        let values = [format!("{}=unix:/run/app.sock", Uuid::from_u128(1)), format!("{} = ./app.sock", Uuid::from_u128(2))];
+        let targets = parse_unix_targets(&values).unwrap();
+
+        assert_eq!(targets.get(&Uuid::from_u128(1)), Some(&PathBuf::from("/run/app.sock")));
+        assert_eq!(targets.get(&Uuid::from_u128(2)), Some(&PathBuf::from("./app.sock")));
+
+        assert!(parse_unix_targets(&[format!("{}=", Uuid::from_u128(1))]).is_err());
+        assert!(parse_unix_targets(&[format!("{}=unix:", Uuid::from_u128(1))]).is_err());
+        assert!(parse_unix_targets(&["/run/app.sock".to_string()]).is_err());
+        assert!(parse_unix_targets(&[values[0].clone(), values[0].clone()]).is_err());
+
+        let input = format!("{}=unix:/run/game.sock", Uuid::from_u128(1));
+        let parsed = parse_mapping_overrides([input.as_str(), "udp:7778"]).unwrap();
+        assert_eq!(parsed[0].target, LocalTarget::Unix(PathBuf::from("/run/game.sock")));
+        assert_eq!(parsed[0].proto, None);
+        assert_eq!(parsed[1].target, LocalTarget::Addr("127.0.0.1:7778".parse().unwrap()));
+
+        let input = format!("{}=tcp:unix:/run/game.sock/proxy=v2", Uuid::from_u128(1));
+        let parsed = parse_mapping_overrides([input.as_str()]).unwrap();
+        assert_eq!(parsed[0].target, LocalTarget::Unix(PathBuf::from("/run/game.sock")));
+        assert_eq!(parsed[0].proxy_protocol, Some(Some(ProxyProtocol::ProxyProtocolV2)));
+
+        assert!(parse_mapping_overrides([format!("{}=udp:unix:/run/game.sock", Uuid::from_u128(1)).as_str()]).is_err());
+        assert!(parse_mapping_overrides([format!("{}=unix:", Uuid::from_u128(1)).as_str()]).is_err());
+    }
+}
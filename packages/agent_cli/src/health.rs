@@ -0,0 +1,116 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use playit_agent_core::agent_control::health::ControlHealth;
+use tokio::net::TcpListener;
+
+use crate::local_http::{request_target, serve, HttpResponse};
+
+/*
+ * Optional HTTP listener (--health_listen) for container healthchecks.
+ *
+ *   GET /healthz  200 while the process is up (liveness)
+ *   GET /readyz   200 once the control channel is registered and a keepalive
+ *                 succeeded, 503 otherwise (readiness, see ControlHealth)
+ *
+ * The listener binds before the agent connects so liveness answers during
+ * startup. Only the request line is inspected. On Linux a socket passed by
+ * systemd socket activation is used instead of binding (see systemd.rs).
+ */
+
+#[derive(Clone)]
+pub struct HealthServer {
+    control: Arc<Mutex<Option<ControlHealth>>>,
+}
+
+impl HealthServer {
+    pub async fn start(addr: SocketAddr) -> std::io::Result<Self> {
+        Self::from_listener(TcpListener::bind(addr).await?)
+    }
+
+    /* for sockets bound by someone else, ex. systemd socket activation */
+    pub fn from_std(listener: std::net::TcpListener) -> std::io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Self::from_listener(TcpListener::from_std(listener)?)
+    }
+
+    fn from_listener(listener: TcpListener) -> std::io::Result<Self> {
+        tracing::info!(addr = %listener.local_addr()?, "health listener started");
+
+        let server = HealthServer {
+            control: Arc::new(Mutex::new(None)),
+        };
+
+        let handler = server.clone();
+        serve(listener, "health", move |request| handler.route(request));
+        Ok(server)
+    }
+
+    /* replaced when the agent is recreated */
+    pub fn attach(&self, health: ControlHealth) {
+        self.control.lock().unwrap().replace(health);
+    }
+
+    fn is_ready(&self) -> bool {
+        self.control.lock().unwrap().as_ref().map(|health| health.is_ready()).unwrap_or(false)
+    }
+
+    fn route(&self, request: &str) -> HttpResponse {
+        let Some((method, path)) = request_target(request) else {
+            return HttpResponse::text("400 Bad Request", "bad request\n");
+        };
+
+        if method != "GET" {
+            return HttpResponse::text("405 Method Not Allowed", "method not allowed\n");
+        }
+
+        match path {
+            "/healthz" => HttpResponse::text("200 OK", "ok\n"),
+            "/readyz" if self.is_ready() => HttpResponse::text("200 OK", "ready\n"),
+            "/readyz" => HttpResponse::text("503 Service Unavailable", "not ready\n"),
+            _ => HttpResponse::text("404 Not Found", "not found\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_core::agent_control::health::ControlHealth;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::HealthServer;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoints() {
+        /* find a free port, the server does not expose its bound address */
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = HealthServer::start(addr).await.unwrap();
+
+        assert_eq!(get(addr, "/healthz").await, "HTTP/1.1 200 OK");
+        assert_eq!(get(addr, "/readyz").await, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(get(addr, "/metrics").await, "HTTP/1.1 404 Not Found");
+
+        let health = ControlHealth::default();
+        server.attach(health.clone());
+
+        health.set_registered();
+        assert_eq!(get(addr, "/readyz").await, "HTTP/1.1 503 Service Unavailable");
+
+        health.keep_alive_succeeded();
+        assert_eq!(get(addr, "/readyz").await, "HTTP/1.1 200 OK");
+        assert_eq!(get(addr, "/readyz?verbose").await, "HTTP/1.1 200 OK");
+
+        health.set_disconnected();
+        assert_eq!(get(addr, "/readyz").await, "HTTP/1.1 503 Service Unavailable");
+    }
+}
@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+
+use playit_agent_core::network::tcp_pipe::pipe_with_buffer;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+/*
+ * `playit listen-local`, a testing / development tool (listen-local feature).
+ *
+ * Reverses the normal direction: accepts connections on a local port and
+ * forwards them out to a tunnel's public address, copying with the same pipe
+ * the agent uses. With an agent running for that tunnel this gives a loopback
+ * of the full path (local client -> tunnel server -> agent -> local server)
+ * without an external client. Not meant to expose anything, only to test.
+ */
+
+pub struct ListenLocalSettings {
+    pub bind: SocketAddr,
+    /* tunnel's public address, "<host>:<port>" */
+    pub target: String,
+    pub buffer_size: usize,
+}
+
+pub async fn run_listen_local(settings: ListenLocalSettings) -> std::io::Result<()> {
+    let listener = TcpListener::bind(settings.bind).await?;
+    tracing::warn!(bind = %listener.local_addr()?, target = %settings.target, "listen-local is a testing feature, forwarding local connections out to tunnel");
+    serve(listener, settings.target, settings.buffer_size).await
+}
+
+async fn serve(listener: TcpListener, target: String, buffer_size: usize) -> std::io::Result<()> {
+    loop {
+        let (local_conn, peer_addr) = listener.accept().await?;
+        let target = target.clone();
+
+        tokio::spawn(async move {
+            if let Err(error) = forward(local_conn, &target, buffer_size).await {
+                tracing::error!(?error, %peer_addr, %target, "listen-local connection failed");
+            }
+        });
+    }
+}
+
+async fn forward(local_conn: TcpStream, target: &str, buffer_size: usize) -> std::io::Result<()> {
+    /* resolved per connection so tunnel address changes are picked up */
+    let tunnel_conn = TcpStream::connect(target).await?;
+    let _ = local_conn.set_nodelay(true);
+    let _ = tunnel_conn.set_nodelay(true);
+
+    tracing::info!(local = ?local_conn.peer_addr(), tunnel = ?tunnel_conn.peer_addr(), "listen-local forwarding connection");
+
+    let (local_read, mut local_write) = local_conn.into_split();
+    let (tunnel_read, mut tunnel_write) = tunnel_conn.into_split();
+
+    let outbound = tokio::spawn(async move {
+        let res = pipe_with_buffer(local_read, &mut tunnel_write, buffer_size).await;
+        let _ = tunnel_write.shutdown().await;
+        res
+    });
+
+    let inbound = pipe_with_buffer(tunnel_read, &mut local_write, buffer_size).await;
+    let _ = local_write.shutdown().await;
+
+    outbound.await.map_err(std::io::Error::other)??;
+    inbound
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use playit_agent_core::network::tcp_pipe::DEFAULT_PIPE_BUFFER_SIZE;
+
+    use super::serve;
+
+    #[tokio::test]
+    async fn test_forwards_to_target() {
+        /* stands in for the tunnel endpoint, echoes one message */
+        let tunnel = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = tunnel.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut conn, _) = tunnel.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, target, DEFAULT_PIPE_BUFFER_SIZE));
+
+        let mut client = TcpStream::connect(local_addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+    }
+}
@@ -0,0 +1,106 @@
+use std::net::SocketAddr;
+
+use playit_api_client::api::PortType;
+use uuid::Uuid;
+
+use crate::CliError;
+
+/*
+ * Two tunnels forwarding to the same local address and protocol send their
+ * clients to one backend, which is almost always a misconfiguration. Ports
+ * are compared as ranges since multi port tunnels map from local_port
+ * upwards. TCP and UDP tunnels may share a port.
+ */
+
+#[derive(Debug, Clone)]
+pub struct LocalBinding {
+    pub tunnel_id: Uuid,
+    pub proto: PortType,
+    pub local_addr: SocketAddr,
+    pub port_count: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalAddrConflict {
+    /* first local address both tunnels forward to */
+    pub local_addr: SocketAddr,
+    pub tunnel_ids: (Uuid, Uuid),
+}
+
+pub fn find_local_addr_conflicts(bindings: &[LocalBinding]) -> Vec<LocalAddrConflict> {
+    let mut conflicts = Vec::new();
+
+    for (i, a) in bindings.iter().enumerate() {
+        for b in &bindings[i + 1..] {
+            if a.tunnel_id == b.tunnel_id || a.local_addr.ip() != b.local_addr.ip() {
+                continue;
+            }
+            if !a.proto.matches(b.proto) && !b.proto.matches(a.proto) {
+                continue;
+            }
+
+            let a_end = a.local_addr.port() as u32 + a.port_count.max(1) as u32;
+            let b_end = b.local_addr.port() as u32 + b.port_count.max(1) as u32;
+            let start = a.local_addr.port().max(b.local_addr.port());
+
+            if (start as u32) < a_end.min(b_end) {
+                conflicts.push(LocalAddrConflict {
+                    local_addr: SocketAddr::new(a.local_addr.ip(), start),
+                    tunnel_ids: (a.tunnel_id, b.tunnel_id),
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+/* warns about every conflict, with --deny_local_addr_conflicts the first one is an error */
+pub fn report_local_addr_conflicts(conflicts: &[LocalAddrConflict], deny: bool) -> Result<(), CliError> {
+    for conflict in conflicts {
+        let (first, second) = conflict.tunnel_ids;
+        tracing::warn!(local_addr = %conflict.local_addr, tunnel_a = %first, tunnel_b = %second, "tunnels forward to the same local address");
+    }
+
+    match conflicts.first() {
+        Some(conflict) if deny => Err(CliError::LocalAddrConflict(conflict.local_addr, conflict.tunnel_ids.0, conflict.tunnel_ids.1)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::PortType;
+    use uuid::Uuid;
+
+    use super::{find_local_addr_conflicts, LocalAddrConflict, LocalBinding};
+
+    fn binding(id: u128, proto: PortType, local_addr: &str, port_count: u16) -> LocalBinding {
+        LocalBinding {
+            tunnel_id: Uuid::from_u128(id),
+            proto,
+            local_addr: local_addr.parse().unwrap(),
+            port_count,
+        }
+    }
+
+    #[test]
+    fn test_local_addr_conflicts() {
+        let conflicts = find_local_addr_conflicts(&[
+            binding(1, PortType::Tcp, "127.0.0.1:25565", 1),
+            /* same port over udp is fine */
+            binding(2, PortType::Udp, "127.0.0.1:25565", 1),
+            /* different host */
+            binding(3, PortType::Tcp, "192.168.1.5:25565", 1),
+            /* range 25560..25570 overlaps tunnel 1 */
+            binding(4, PortType::Both, "127.0.0.1:25560", 10),
+            /* adjacent, no overlap */
+            binding(5, PortType::Tcp, "127.0.0.1:25570", 1),
+        ]);
+
+        assert_eq!(conflicts, vec![
+            LocalAddrConflict { local_addr: "127.0.0.1:25565".parse().unwrap(), tunnel_ids: (Uuid::from_u128(1), Uuid::from_u128(4)) },
+            LocalAddrConflict { local_addr: "127.0.0.1:25565".parse().unwrap(), tunnel_ids: (Uuid::from_u128(2), Uuid::from_u128(4)) },
+        ]);
+    }
+}
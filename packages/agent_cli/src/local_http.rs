@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/*
+ * Tiny HTTP/1.1 server behind the local listeners (health.rs, admin_api.rs).
+ * Requests have no body, the request line and headers are read (up to
+ * MAX_REQUEST_SIZE) and handed to the handler as text. Every response closes
+ * the connection, a request that takes longer than REQUEST_TIMEOUT is dropped.
+ */
+
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct HttpResponse {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn text(status: &'static str, body: &str) -> Self {
+        HttpResponse { status, content_type: "text/plain", body: body.to_string() }
+    }
+
+    pub fn json(status: &'static str, body: serde_json::Value) -> Self {
+        HttpResponse { status, content_type: "application/json", body: serde_json::to_string(&body).unwrap() }
+    }
+}
+
+/* name is only used in logs */
+pub fn serve<H>(listener: TcpListener, name: &'static str, handler: H)
+    where H: Fn(&str) -> HttpResponse + Send + Sync + 'static
+{
+    tokio::spawn(accept_loop(listener, name, Arc::new(handler)));
+}
+
+async fn accept_loop<H>(listener: TcpListener, name: &'static str, handler: Arc<H>)
+    where H: Fn(&str) -> HttpResponse + Send + Sync + 'static
+{
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::error!(?error, listener = name, "failed to accept connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(error) = tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, &*handler)).await {
+                tracing::debug!(?error, listener = name, "request timed out");
+            }
+        });
+    }
+}
+
+async fn handle<H: Fn(&str) -> HttpResponse>(mut stream: TcpStream, handler: &H) {
+    let mut buffer = vec![0u8; MAX_REQUEST_SIZE];
+    let mut len = 0;
+
+    while !buffer[..len].windows(4).any(|w| w == b"\r\n\r\n") && len < buffer.len() {
+        match stream.read(&mut buffer[len..]).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => len += read,
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buffer[..len]);
+    let response = handler(&request);
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body,
+    );
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/* value of the first header called name (case insensitive) */
+pub fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().skip(1).take_while(|line| !line.is_empty()).find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/* (method, path without query), None for a malformed request line */
+pub fn request_target(request: &str) -> Option<(&str, &str)> {
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (parts.next()?, parts.next()?);
+    Some((method, path.split('?').next().unwrap_or(path)))
+}
@@ -0,0 +1,229 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/*
+ * Size based rotation for --log_path (--log_max_size, --log_keep).
+ *
+ * When a write would grow the log past max_size, playit.log is renamed to
+ * playit.log.1, existing playit.log.N shift up by one and anything past
+ * keep is deleted. Without --log_max_size the file is never rotated, for
+ * setups that rotate externally (ex. logrotate with copytruncate).
+ *
+ * With --log_compress rotated files are zstd compressed (playit.log.N.zst),
+ * playit.log itself stays plain text so `playit logs --follow` can tail it.
+ * If compressing fails the rotated file is kept uncompressed as playit.log.1
+ * and shifts up like the compressed ones.
+ */
+
+pub const DEFAULT_LOG_KEEP: usize = 5;
+pub const MIN_LOG_MAX_SIZE: u64 = 64 * 1024;
+const COMPRESSION_LEVEL: i32 = 3;
+
+pub struct SizeRotatingWriter {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    compress: bool,
+    file: File,
+    size: u64,
+}
+
+impl SizeRotatingWriter {
+    pub fn open<P: Into<PathBuf>>(path: P, max_size: u64, keep: usize, compress: bool) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(SizeRotatingWriter {
+            path,
+            max_size,
+            keep,
+            compress,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn compressed_path(&self, index: usize) -> PathBuf {
+        let mut name = self.rotated_path(index).into_os_string();
+        name.push(".zst");
+        PathBuf::from(name)
+    }
+
+    /* with compression a rotated file can also be left plain when compressing it failed */
+    fn archived_paths(&self, index: usize) -> Vec<PathBuf> {
+        if self.compress {
+            vec![self.compressed_path(index), self.rotated_path(index)]
+        } else {
+            vec![self.rotated_path(index)]
+        }
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep == 0 {
+            remove_if_exists(&self.path)?;
+        } else {
+            for path in self.archived_paths(self.keep) {
+                remove_if_exists(&path)?;
+            }
+            for index in (1..self.keep).rev() {
+                for (from, to) in self.archived_paths(index).into_iter().zip(self.archived_paths(index + 1)) {
+                    rename_if_exists(&from, &to)?;
+                }
+            }
+            rename_if_exists(&self.path, &self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+
+        if self.compress && 0 < self.keep {
+            let rotated = self.rotated_path(1);
+            match compress_file(&rotated, &self.compressed_path(1)) {
+                Ok(()) => remove_if_exists(&rotated)?,
+                Err(error) => {
+                    let _ = remove_if_exists(&self.compressed_path(1));
+                    /* tracing can't be used from inside the log writer, note it in the new file */
+                    let line = format!("failed to compress rotated log {}: {:?}\n", rotated.display(), error);
+                    self.file.write_all(line.as_bytes())?;
+                    self.size += line.len() as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        /* a single line larger than max_size still gets written to a fresh file */
+        if 0 < self.size && self.max_size < self.size + buf.len() as u64 {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn compress_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = zstd::Encoder::new(File::create(to)?, COMPRESSION_LEVEL)?;
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::SizeRotatingWriter;
+
+    #[test]
+    fn test_rotation_keeps_count() {
+        let dir = std::env::temp_dir().join(format!("playit-log-rotation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("playit.log");
+
+        let mut writer = SizeRotatingWriter::open(&path, 20, 2, false).unwrap();
+        for line in ["line 1 ........\n", "line 2 ........\n", "line 3 ........\n", "line 4 ........\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+        assert_eq!(read("playit.log").as_deref(), Some("line 4 ........\n"));
+        assert_eq!(read("playit.log.1").as_deref(), Some("line 3 ........\n"));
+        assert_eq!(read("playit.log.2").as_deref(), Some("line 2 ........\n"));
+        assert_eq!(read("playit.log.3"), None);
+
+        /* reopening appends and counts the existing size */
+        let mut writer = SizeRotatingWriter::open(&path, 20, 2, false).unwrap();
+        writer.write_all(b"line 5 ........\n").unwrap();
+        assert_eq!(read("playit.log.1").as_deref(), Some("line 4 ........\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation_compresses_old_files() {
+        let dir = std::env::temp_dir().join(format!("playit-log-compress-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("playit.log");
+
+        let mut writer = SizeRotatingWriter::open(&path, 20, 2, true).unwrap();
+        for line in ["line 1 ........\n", "line 2 ........\n", "line 3 ........\n", "line 4 ........\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let unzip = |name: &str| std::fs::read(dir.join(name)).ok().map(|data| String::from_utf8(zstd::decode_all(&data[..]).unwrap()).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 4 ........\n");
+        assert_eq!(unzip("playit.log.1.zst").as_deref(), Some("line 3 ........\n"));
+        assert_eq!(unzip("playit.log.2.zst").as_deref(), Some("line 2 ........\n"));
+        assert_eq!(unzip("playit.log.3.zst"), None);
+        assert!(!dir.join("playit.log.1").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation_keeps_uncompressed_leftover() {
+        let dir = std::env::temp_dir().join(format!("playit-log-leftover-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("playit.log");
+
+        /* compressing the previous rotation failed and left it plain */
+        std::fs::write(dir.join("playit.log.1"), "line 1 ........\n").unwrap();
+
+        let mut writer = SizeRotatingWriter::open(&path, 20, 3, true).unwrap();
+        for line in ["line 2 ........\n", "line 3 ........\n", "line 4 ........\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let unzip = |name: &str| std::fs::read(dir.join(name)).ok().map(|data| String::from_utf8(zstd::decode_all(&data[..]).unwrap()).unwrap());
+        assert_eq!(unzip("playit.log.1.zst").as_deref(), Some("line 3 ........\n"));
+        assert_eq!(unzip("playit.log.2.zst").as_deref(), Some("line 2 ........\n"));
+        assert_eq!(std::fs::read_to_string(dir.join("playit.log.3")).unwrap(), "line 1 ........\n");
+
+        /* past keep it is deleted like the compressed files */
+        writer.write_all(b"line 5 ........\n").unwrap();
+        assert!(!dir.join("playit.log.3").exists());
+        assert!(!dir.join("playit.log.4").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
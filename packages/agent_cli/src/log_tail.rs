@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::Level;
+
+/*
+ * playit logs: prints the end of the agent's --log_path file and with
+ * --follow keeps printing new lines, also across --log_max_size rotations
+ * (the file shrinks) and external rotation (the file is replaced).
+ *
+ * Level filtering is done on the text, lines without a level (ex. a multi
+ * line message) keep the level of the line they continue.
+ */
+
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/* how far back from the end to look for the last lines */
+const MAX_TAIL_READ: u64 = 1024 * 1024;
+
+pub struct LogTailSettings {
+    pub lines: usize,
+    pub follow: bool,
+    pub max_level: Level,
+}
+
+struct LevelFilter {
+    max_level: Level,
+    current: Option<Level>,
+}
+
+impl LevelFilter {
+    fn keep(&mut self, line: &str) -> bool {
+        if let Some(level) = line_level(line) {
+            self.current = Some(level);
+        }
+        match self.current {
+            Some(level) => level <= self.max_level,
+            None => true,
+        }
+    }
+}
+
+/* level of a tracing_subscriber fmt line ("<timestamp>  INFO target: message") */
+fn line_level(line: &str) -> Option<Level> {
+    let mut parts = line.split_whitespace();
+    parts.next()?;
+    match parts.next()? {
+        "ERROR" => Some(Level::ERROR),
+        "WARN" => Some(Level::WARN),
+        "INFO" => Some(Level::INFO),
+        "DEBUG" => Some(Level::DEBUG),
+        "TRACE" => Some(Level::TRACE),
+        _ => None,
+    }
+}
+
+fn last_lines(text: &str, count: usize) -> &str {
+    if count == 0 {
+        return "";
+    }
+
+    let trimmed = text.strip_suffix('\n').unwrap_or(text);
+    match trimmed.rmatch_indices('\n').nth(count - 1) {
+        Some((pos, _)) => &text[pos + 1..],
+        None => text,
+    }
+}
+
+fn write_filtered<W: Write>(out: &mut W, text: &str, filter: &mut LevelFilter) -> std::io::Result<()> {
+    for line in text.lines() {
+        if filter.keep(line) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    out.flush()
+}
+
+pub async fn tail_logs<W: Write>(path: &Path, settings: &LogTailSettings, out: &mut W) -> std::io::Result<()> {
+    let mut filter = LevelFilter { max_level: settings.max_level, current: None };
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(MAX_TAIL_READ);
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let mut pos = start + data.len() as u64;
+
+    let mut text = String::from_utf8_lossy(&data).into_owned();
+    if 0 < start {
+        /* first line is most likely cut */
+        text = text.split_once('\n').map(|(_, rest)| rest.to_string()).unwrap_or_default();
+    }
+
+    /* a partial last line is printed once it is complete */
+    let complete = text.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    pos -= (text.len() - complete) as u64;
+    write_filtered(out, last_lines(&text[..complete], settings.lines), &mut filter)?;
+
+    if !settings.follow {
+        return Ok(());
+    }
+
+    let mut pending = Vec::new();
+    loop {
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+
+        let replaced = match (File::open(path), file.metadata()) {
+            (Ok(current), Ok(open_meta)) => match current.metadata() {
+                Ok(meta) if meta.len() < pos || is_replaced(&open_meta, &meta) => Some(current),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(current) = replaced {
+            /* finish the rotated file before switching */
+            let mut rest = Vec::new();
+            file.read_to_end(&mut rest)?;
+            pending.extend_from_slice(&rest);
+
+            file = current;
+            pos = 0;
+        }
+
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_to_end(&mut data)?;
+        pos += data.len() as u64;
+        pending.extend_from_slice(&data);
+
+        if let Some(end) = pending.iter().rposition(|b| *b == b'\n') {
+            let lines: Vec<u8> = pending.drain(..=end).collect();
+            write_filtered(out, &String::from_utf8_lossy(&lines), &mut filter)?;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_replaced(open: &std::fs::Metadata, current: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    open.ino() != current.ino() || open.dev() != current.dev()
+}
+
+#[cfg(not(unix))]
+fn is_replaced(_open: &std::fs::Metadata, _current: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use tracing::Level;
+
+    use super::{tail_logs, LogTailSettings};
+
+    #[tokio::test]
+    async fn test_tail_filters_levels() {
+        let path = std::env::temp_dir().join(format!("playit-log-tail-{}.log", std::process::id()));
+        std::fs::write(&path, concat!(
+            "2024-05-01T10:00:00.000000Z  INFO playit_cli: starting\n",
+            "2024-05-01T10:00:01.000000Z DEBUG playit_agent_core: ping\n",
+            "2024-05-01T10:00:02.000000Z ERROR playit_cli: failed\n",
+            "details of the failure\n",
+            "2024-05-01T10:00:03.000000Z  WARN playit_cli: slow\n",
+            "2024-05-01T10:00:04.000000Z  INFO playit_cli: partial",
+        )).unwrap();
+
+        let mut out = Vec::new();
+        tail_logs(&path, &LogTailSettings { lines: 4, follow: false, max_level: Level::WARN }, &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "2024-05-01T10:00:02.000000Z ERROR playit_cli: failed\n",
+            "details of the failure\n",
+            "2024-05-01T10:00:03.000000Z  WARN playit_cli: slow\n",
+        ));
+
+        let mut out = Vec::new();
+        tail_logs(&path, &LogTailSettings { lines: 100, follow: false, max_level: Level::TRACE }, &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 5);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
@@ -6,26 +6,44 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
-use clap::{arg, Command};
+use clap::{arg, ArgAction, ArgMatches, Command};
 use playit_agent_core::agent_control::platform::get_platform;
 use playit_agent_core::agent_control::version::register_version;
 use rand::Rng;
 use uuid::Uuid;
 
-use autorun::autorun;
+use autorun::{autorun, check_has_tunnels, control_server_ips};
 use playit_api_client::{api::*, PlayitApi};
 use playit_api_client::http_client::HttpClientError;
-use playit_agent_core::network::address_lookup::{AddressLookup, AddressValue};
+use playit_api_client::ip_resource::PlayitRegion;
+use playit_agent_core::network::access_log::{AccessLog, AccessLogFormat};
+use playit_agent_core::network::address_lookup::{AddressLookup, AddressValue, HostOrigin};
+use playit_agent_core::network::tcp_pipe::DEFAULT_PIPE_BUFFER_SIZE;
 use playit_agent_core::agent_control::errors::SetupError;
+use playit_agent_core::agent_control::{AuthApi, AuthResource};
 use playit_agent_core::playit_agent::PlayitAgent;
 use playit_agent_core::utils::now_milli;
+use playit_agent_core::utils::redact::set_redact_client_ips;
 use playit_secret::PlayitSecret;
 
 use crate::match_ip::MatchIp;
+use crate::pidfile::{acquire_pidfile, PidFileError};
 use crate::signal_handle::get_signal_handle;
+use crate::tunnel_export::{export_env, export_hosts, export_json, export_pending_comments, ExportFormat, ExportedTunnel};
 use crate::ui::{UI, UISettings};
+use crate::admin_api::AdminServer;
+use crate::health::HealthServer;
+use crate::log_tail::{tail_logs, LogTailSettings};
+use crate::udp_probe::{run_udp_probe, PROBE_ATTEMPTS, PROBE_TIMEOUT};
+use crate::local_addr_check::{find_local_addr_conflicts, report_local_addr_conflicts, LocalBinding};
+use crate::benchmark::{run_benchmark, BenchmarkSettings};
+use crate::log_rotation::{SizeRotatingWriter, DEFAULT_LOG_KEEP, MIN_LOG_MAX_SIZE};
+use crate::util::parse_byte_size;
+use crate::webhook::WebhookNotifier;
+use crate::flags::{autorun_settings, parse_admin_listen, parse_claim_poll_intervals, parse_mapping_overrides, LocalTarget};
 
 pub const API_BASE: &'static str = "https://api.playit.gg";
+const QUOTA_SAVE_INTERVAL: Duration = Duration::from_secs(10);
 
 pub mod util;
 pub mod autorun;
@@ -33,19 +51,44 @@ pub mod playit_secret;
 pub mod match_ip;
 pub mod ui;
 pub mod signal_handle;
+pub mod tunnel_filter;
+pub mod tunnel_state;
+pub mod config_check;
+pub mod print_config;
+pub mod webhook;
+pub mod tunnel_export;
+pub mod pidfile;
+pub mod health;
+pub mod admin_api;
+pub mod local_http;
+pub mod local_addr_check;
+pub mod log_rotation;
+pub mod benchmark;
+pub mod udp_probe;
+pub mod log_tail;
+pub mod backoff;
+pub mod flags;
+#[cfg(feature = "listen-local")]
+pub mod listen_local;
+#[cfg(target_os = "linux")]
+pub mod systemd;
 
 #[tokio::main]
 async fn main() -> Result<std::process::ExitCode, CliError> {
+    server_error_exit_code(run_cli().await)
+}
+
+async fn run_cli() -> Result<std::process::ExitCode, CliError> {
     let matches = cli().get_matches();
 
+    let platform = if matches.get_flag("platform_docker") {
+        Platform::Docker
+    } else {
+        get_platform()
+    };
+
     /* register docker */
     {
-        let platform = if matches.get_flag("platform_docker") {
-            Platform::Docker
-        } else {
-            get_platform()
-        };
-
         register_version(PlayitAgentVersion {
             version: AgentVersion {
                 platform,
@@ -57,22 +100,54 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
         });
     }
 
+    let claim_poll = parse_claim_poll_intervals(
+        matches.get_one::<String>("claim_setup_interval"),
+        matches.get_one::<String>("claim_exchange_interval"),
+    )?;
+
     let mut secret = PlayitSecret::from_args(&matches).await;
+    secret.set_claim_poll(claim_poll);
     let _ = secret.with_default_path().await;
 
-    let log_only = matches.get_flag("stdout");
-    let log_path = matches.get_one::<String>("log_path");
+    /* headless is for supervised processes (ex. systemd Type=simple), only logs and never prompts */
+    let headless = matches.subcommand_matches("start")
+        .map(|m| m.get_flag("headless"))
+        .unwrap_or(false);
+
+    /* playit logs reads --log_path, it must not write its own logs into it */
+    let log_path = matches.get_one::<String>("log_path").filter(|_| matches.subcommand_name() != Some("logs"));
+    let log_only = matches.get_flag("stdout") || headless;
+    let log_stdout = matches.get_flag("stdout") || (headless && log_path.is_none());
+
+    let log_max_size = match matches.get_one::<String>("log_max_size") {
+        Some(size) => match parse_byte_size(size) {
+            Some(size) if MIN_LOG_MAX_SIZE <= size => Some(size),
+            _ => return Err(CliError::InvalidLogMaxSize),
+        },
+        None => None,
+    };
+    let log_keep = match matches.get_one::<String>("log_keep") {
+        Some(keep) => keep.parse::<usize>().map_err(|_| CliError::InvalidLogKeep)?,
+        None => DEFAULT_LOG_KEEP,
+    };
 
     /* setup logging */
-    let _guard = match (log_only, log_path) {
+    let _guard = match (log_stdout, log_path) {
         (true, Some(_)) => panic!("try to use -s and -l at the same time"),
         (false, Some(path)) => {
-            let write_path = match path.rsplit_once("/") {
-                Some((dir, file)) => tracing_appender::rolling::never(dir, file),
-                None => tracing_appender::rolling::never(".", path),
+            let (non_blocking, guard) = match log_max_size {
+                Some(max_size) => {
+                    let writer = SizeRotatingWriter::open(path, max_size, log_keep, matches.get_flag("log_compress")).map_err(CliError::LogFileOpenError)?;
+                    tracing_appender::non_blocking(writer)
+                }
+                None => {
+                    let write_path = match path.rsplit_once("/") {
+                        Some((dir, file)) => tracing_appender::rolling::never(dir, file),
+                        None => tracing_appender::rolling::never(".", path),
+                    };
+                    tracing_appender::non_blocking(write_path)
+                }
             };
-
-            let (non_blocking, guard) = tracing_appender::non_blocking(write_path);
             tracing_subscriber::fmt()
                 .with_ansi(false)
                 .with_writer(non_blocking)
@@ -82,7 +157,7 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
         (true, None) => {
             let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
             tracing_subscriber::fmt()
-                .with_ansi(get_platform() == Platform::Linux)
+                .with_ansi(!headless && get_platform() == Platform::Linux)
                 .with_writer(non_blocking)
                 .init();
             Some(guard)
@@ -90,24 +165,97 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
         _ => None,
     };
 
+    set_redact_client_ips(matches.get_flag("redact_client_ips"));
+
     let mut ui = UI::new(UISettings {
-        auto_answer: None,
+        auto_answer: prompt_auto_answer(matches.get_flag("yes"), matches.get_flag("assume_no"), headless),
         log_only,
+        quiet: matches.get_flag("quiet"),
     });
 
+    let mut autorun_settings = autorun_settings(&matches)?;
+
+    /* fail fast with a clear message instead of the server rejecting the register signature */
+    if let None | Some("start") | Some("run") = matches.subcommand_name() {
+        if let Err(error) = playit_agent_proto::hmac::self_test() {
+            tracing::error!(error, "HMAC signing self-test failed, this build cannot register with playit");
+            return Err(CliError::HmacSelfTestFailed(error));
+        }
+    }
+
+    /* only commands that keep the agent running claim the pidfile */
+    let _pidfile = match (matches.get_one::<String>("pidfile"), matches.subcommand_name()) {
+        (Some(path), None | Some("start") | Some("run")) => {
+            match acquire_pidfile(std::path::Path::new(path), matches.get_flag("pidfile_takeover")) {
+                Ok(guard) => Some(guard),
+                Err(PidFileError::AlreadyRunning(pid)) => return Err(CliError::AlreadyRunning(pid)),
+                Err(PidFileError::Io(error)) => return Err(CliError::PidFileWriteError(error)),
+            }
+        }
+        _ => None,
+    };
+
+    /* bound up front so liveness answers while the agent is still connecting */
+    let health_listen = match matches.get_one::<String>("health_listen") {
+        Some(addr) => Some(addr.parse::<SocketAddr>().map_err(|_| CliError::InvalidHealthListenAddr)?),
+        None => None,
+    };
+    autorun_settings.health = match (health_listen, matches.subcommand_name()) {
+        (health_listen, None | Some("start") | Some("run")) => {
+            #[cfg(target_os = "linux")]
+            let activated = systemd::take_activation_listener();
+            #[cfg(not(target_os = "linux"))]
+            let activated: Option<std::net::TcpListener> = None;
+
+            match (activated, health_listen) {
+                (Some(listener), _) => Some(HealthServer::from_std(listener).map_err(CliError::HealthListenError)?),
+                (None, Some(addr)) => Some(HealthServer::start(addr).await.map_err(CliError::HealthListenError)?),
+                (None, None) => None,
+            }
+        }
+        _ => None,
+    };
+
+    if let (Some(listen), None | Some("start") | Some("run")) = (matches.get_one::<String>("admin_listen"), matches.subcommand_name()) {
+        let addr = parse_admin_listen(listen)?;
+        let token = matches.get_one::<String>("admin_token").cloned();
+        autorun_settings.admin = Some(AdminServer::start(addr, token).await.map_err(CliError::AdminListenError)?);
+    }
+
+    if let (Some(path), None | Some("start") | Some("run")) = (matches.get_one::<String>("access_log"), matches.subcommand_name()) {
+        let format = match matches.get_one::<String>("access_log_format").map(|v| v.as_str()) {
+            Some("combined") => AccessLogFormat::Combined,
+            _ => AccessLogFormat::Common,
+        };
+
+        autorun_settings.access_log = if path == "-" {
+            AccessLog::new(format, std::io::stdout())
+        } else {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path).map_err(CliError::AccessLogOpenError)?;
+            AccessLog::new(format, file)
+        };
+    }
+
+    if let (Some(path), None | Some("start") | Some("run")) = (matches.get_one::<String>("quota_state_path"), matches.subcommand_name()) {
+        let path = std::path::PathBuf::from(path);
+        autorun_settings.quotas.load(&path).map_err(CliError::QuotaStateError)?;
+        autorun_settings.quotas.persist_to(path, QUOTA_SAVE_INTERVAL);
+    }
+
     match matches.subcommand() {
         None => {
             ui.write_screen("no command provided, doing auto run").await;
             tokio::time::sleep(Duration::from_secs(1)).await;
-            autorun(&mut ui, secret).await?;
+            return no_tunnels_exit_code(autorun(&mut ui, secret, autorun_settings).await);
         }
         Some(("start", _)) => {
-            autorun(&mut ui, secret).await?;
+            return no_tunnels_exit_code(autorun(&mut ui, secret, autorun_settings).await);
         }
         Some(("version", _)) => println!("{}", env!("CARGO_PKG_VERSION")),
         #[cfg(target_os = "linux")]
         Some(("setup", _)) => {
             let mut secret = PlayitSecret::linux_service();
+            secret.set_claim_poll(claim_poll);
             let key = secret
                 .ensure_valid(&mut ui).await?
                 .get_or_setup(&mut ui).await?;
@@ -120,6 +268,11 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
 
             ui.write_screen("Playit setup, secret written to /etc/playit/playit.toml").await;
         }
+        #[cfg(not(target_os = "linux"))]
+        Some(("setup", _)) => {
+            eprintln!("{}", setup_unsupported_message(get_platform()));
+            return Ok(std::process::ExitCode::from(2));
+        }
         Some(("reset", _)) => {
             loop {
                 let mut secerts = PlayitSecret::from_args(&matches).await;
@@ -140,6 +293,101 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
             let path = secerts.get_path().unwrap();
             println!("{}", path);
         }
+        Some(("benchmark", m)) => {
+            let count = |name: &str| m.get_one::<String>(name)
+                .expect("has default")
+                .parse::<usize>()
+                .ok()
+                .filter(|v| 0 < *v)
+                .ok_or(CliError::InvalidBenchmarkSetting);
+
+            let settings = BenchmarkSettings {
+                tcp_bytes: count("size")?.checked_mul(1024 * 1024).ok_or(CliError::InvalidBenchmarkSetting)?,
+                tcp_buffer_size: autorun_settings.tcp_buffer_size.unwrap_or(DEFAULT_PIPE_BUFFER_SIZE),
+                udp_packets: count("udp_packets")?,
+                udp_packet_size: count("udp_packet_size")?.min(1400),
+                round_trips: count("round_trips")?,
+            };
+
+            println!("running benchmark on localhost, this takes a few seconds");
+            let report = run_benchmark(&settings).await.map_err(CliError::BenchmarkFailed)?;
+            print!("{}", report.format());
+        }
+        #[cfg(feature = "listen-local")]
+        Some(("listen-local", m)) => {
+            let settings = listen_local::ListenLocalSettings {
+                bind: m.get_one::<String>("bind").expect("has default").parse().map_err(|_| CliError::InvalidListenLocalAddr)?,
+                target: m.get_one::<String>("to").expect("required").clone(),
+                buffer_size: autorun_settings.tcp_buffer_size.unwrap_or(DEFAULT_PIPE_BUFFER_SIZE),
+            };
+
+            listen_local::run_listen_local(settings).await.map_err(CliError::ListenLocalFailed)?;
+        }
+        Some(("probe-udp", m)) => {
+            let addresses = match m.get_many::<String>("addr") {
+                Some(values) => values
+                    .map(|v| v.parse::<SocketAddr>().map_err(|_| CliError::InvalidProbeAddress))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => {
+                    let secret_key = secret.get().await?;
+                    AuthApi::new(API_BASE.to_string(), secret_key).get_control_addresses().await?
+                }
+            };
+
+            let report = run_udp_probe(&addresses, PROBE_ATTEMPTS, PROBE_TIMEOUT).await.map_err(CliError::UdpProbeFailed)?;
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+            if !report.udp_ok {
+                return Ok(std::process::ExitCode::from(2));
+            }
+        }
+        Some(("logs", m)) => {
+            let Some(path) = m.get_one::<String>("file").or(matches.get_one::<String>("log_path")) else {
+                return Err(CliError::MissingLogPath);
+            };
+
+            let settings = LogTailSettings {
+                lines: m.get_one::<String>("lines").expect("has default").parse().map_err(|_| CliError::InvalidLogTailSetting)?,
+                follow: m.get_flag("follow"),
+                max_level: m.get_one::<String>("level").expect("has default").parse().map_err(|_| CliError::InvalidLogTailSetting)?,
+            };
+
+            tail_logs(std::path::Path::new(path), &settings, &mut std::io::stdout()).await.map_err(CliError::LogTailError)?;
+        }
+        Some(("config", m)) => match m.subcommand() {
+            Some(("check", m)) => {
+                let secret_key = match m.get_one::<String>("config") {
+                    Some(path) => PlayitSecret::read_secret_file(path, secret.get_format()).await,
+                    None => secret.get().await,
+                };
+
+                let (tunnels, server_ips) = match (&secret_key, m.get_flag("offline")) {
+                    (Ok(secret_key), false) => {
+                        let api = PlayitApi::create(API_BASE.to_string(), Some(secret_key.clone()));
+                        let auth = AuthApi::new(API_BASE.to_string(), secret_key.clone()).with_server_override(autorun_settings.control_server);
+                        (
+                            Some(api.agents_rundata().await.map(|data| data.tunnels).map_err(CliError::from)),
+                            control_server_ips(&auth).await,
+                        )
+                    }
+                    _ => (None, vec![]),
+                };
+
+                let mappings: Vec<&str> = m.get_many::<String>("MAPPING").into_iter().flatten().map(|v| v.as_str()).collect();
+                let report = config_check::check_config(secret_key.map(|_| ()), &mappings, tunnels, &server_ips).await;
+                print!("{}", report.format());
+
+                if !report.is_ok() {
+                    return Ok(std::process::ExitCode::from(2));
+                }
+            }
+            _ => return Err(CliError::NotImplemented),
+        }
+        Some(("print-config", m)) => {
+            let config = print_config::resolve_config(&matches, &secret, &autorun_settings, platform).await;
+            let format = m.get_one::<String>("format").expect("has default");
+            println!("{}", print_config::format_config(&config, format)?);
+        }
         Some(("account", m)) => match m.subcommand() {
             Some(("login-url", _)) => {
                 let api = secret.create_api().await?;
@@ -159,23 +407,30 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
             Some(("exchange", m)) => {
                 let claim_code = m.get_one::<String>("CLAIM_CODE").expect("required");
                 let wait: u32 = m.get_one::<String>("wait").expect("required").parse().expect("invalid wait value");
+                let max_attempts: u32 = m.get_one::<String>("max_attempts").expect("required").parse().expect("invalid max attempts value");
 
-                let secret_key = claim_exchange(&mut ui, claim_code, AgentType::SelfManaged, wait).await?;
+                let secret_key = claim_exchange(&mut ui, claim_code, AgentType::SelfManaged, wait, max_attempts, claim_poll).await?;
                 ui.write_screen(secret_key).await;
             }
+            Some(("details", m)) => {
+                let claim_code = m.get_one::<String>("CLAIM_CODE").expect("required");
+
+                let api = secret.create_api().await?;
+                return claim_details(&api, claim_code).await;
+            }
             _ => return Err(CliError::NotImplemented.into()),
         },
         Some(("tunnels", m)) => match m.subcommand() {
             Some(("prepare", m)) => {
                 let api = secret.create_api().await?;
+                ensure_not_guest(&api).await?;
 
                 let name = m.get_one::<String>("NAME").cloned();
                 let tunnel_type: Option<TunnelType> = m.get_one::<String>("TUNNEL_TYPE")
                     .and_then(|v| serde_json::from_str(&format!("{:?}", v)).ok());
                 let port_type = serde_json::from_str::<PortType>(&format!("{:?}", m.get_one::<String>("PORT_TYPE").expect("required")))
                     .map_err(|_| CliError::InvalidPortType)?;
-                let port_count = m.get_one::<String>("PORT_COUNT").expect("required")
-                    .parse::<u16>().map_err(|_| CliError::InvalidPortCount)?;
+                let port_count = parse_port_count(m.get_one::<String>("PORT_COUNT").expect("required"))?;
                 let exact = m.get_flag("exact");
                 let ignore_name = m.get_flag("ignore_name");
 
@@ -186,74 +441,146 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
 
                 println!("{}", tunnel_id);
             }
+            Some(("create", m)) => {
+                let req = parse_tunnel_create(m)?;
+                let api = secret.create_api().await?;
+                return tunnels_create(&api, req).await;
+            }
             Some(("list", _)) => {
                 let api = secret.create_api().await?;
-                let response = api.tunnels_list_json(ReqTunnelsList { tunnel_id: None, agent_id: None }).await?;
+                let mut response = api.tunnels_list_json(ReqTunnelsList { tunnel_id: None, agent_id: None }).await?;
+                sort_tunnel_list(&mut response);
                 println!("{}", serde_json::to_string_pretty(&response).unwrap());
             }
+            Some(("export", m)) => {
+                let format = ExportFormat::parse(m.get_one::<String>("format").expect("has default"))
+                    .ok_or(CliError::InvalidExportFormat)?;
+
+                let api = secret.create_api().await?;
+                print!("{}", tunnels_export(&api, format).await?);
+            }
             _ => return Err(CliError::NotImplemented.into())
         }
+        Some(("agents", m)) => match m.subcommand() {
+            Some(("routing", m)) => match m.subcommand() {
+                Some(("list-pops", _)) => print!("{}", list_regions()),
+                _ => return Err(CliError::NotImplemented),
+            }
+            _ => return Err(CliError::NotImplemented),
+        }
         Some(("run", m)) => {
             tracing::error!("run is depreciated and will be removed in a future version of the CLI");
 
             let secret_key = secret.get().await?;
             let api = PlayitApi::create(API_BASE.to_string(), Some(secret_key.clone()));
             let tunnels = api.agents_rundata().await?;
+            if let Err(error) = check_has_tunnels(&tunnels, autorun_settings.exit_on_no_tunnels) {
+                return no_tunnels_exit_code(Err(error));
+            }
+            if let Some(admin) = &autorun_settings.admin {
+                admin.set_tunnels(tunnels.tunnels.clone());
+            }
+            let agent_id = tunnels.agent_id;
             let mut tunnel_lookup = HashMap::new();
-            let mut tunnel_found = HashSet::new();
 
             for tunnel in tunnels.tunnels {
-                tunnel_found.insert(tunnel.id);
                 tunnel_lookup.insert(tunnel.id, tunnel);
             }
 
-            let mapping_override_strings: Vec<String> = match m.get_many::<String>("MAPPING_OVERRIDE") {
-                Some(v) => v.into_iter().map(|v| v.to_string()).collect(),
-                None => vec![],
-            };
+            let mapping_override_args = parse_mapping_overrides(
+                m.get_many::<String>("MAPPING_OVERRIDE").into_iter().flatten().map(|v| v.as_str())
+            )?;
 
-            let mut mapping_overrides = Vec::new();
-            for override_str in mapping_override_strings {
-                let mut parts = override_str.split("=");
+            let mut mapping_overrides = Vec::<MappingOverride>::new();
+            for arg in mapping_override_args {
+                let Some(tunnel) = tunnel_lookup.get(&arg.tunnel_id) else {
+                    return Err(CliError::TunnelNotFound(arg.tunnel_id));
+                };
 
-                let tunnel_id: Uuid = parts.next().ok_or(CliError::InvalidMappingOverride)?
-                    .parse().map_err(|_| CliError::InvalidMappingOverride)?;
+                /* a "both" tunnel can be split into separate tcp and udp targets */
+                let mut proto = match arg.proto {
+                    Some(proto) if tunnel.proto.matches(proto) => proto,
+                    Some(_) => return Err(CliError::InvalidMappingOverride),
+                    None => tunnel.proto,
+                };
 
-                let local_addr_str = parts.next().ok_or(CliError::InvalidMappingOverride)?;
-                let local_addr = match SocketAddr::from_str(local_addr_str) {
-                    Ok(addr) => addr,
-                    _ => match u16::from_str(local_addr_str) {
-                        Ok(port) => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
-                        _ => return Err(CliError::InvalidMappingOverride.into()),
+                let local_addr = match arg.target {
+                    LocalTarget::Addr(addr) => addr,
+                    /* connections go to the socket (same as --unix_target), the lookup still needs the tunnel's origin */
+                    LocalTarget::Unix(path) => {
+                        if !proto.matches(PortType::Tcp) {
+                            return Err(CliError::InvalidMappingOverride);
+                        }
+                        if autorun_settings.unix_targets.insert(arg.tunnel_id, path).is_some() {
+                            return Err(CliError::TunnelOverwrittenAlready(arg.tunnel_id));
+                        }
+                        proto = PortType::Tcp;
+                        SocketAddr::new(tunnel.local_ip, tunnel.local_port)
                     }
                 };
 
-                match tunnel_lookup.remove(&tunnel_id) {
-                    Some(tunnel) => {
-                        mapping_overrides.push(MappingOverride {
-                            match_ip: MatchIp { ip_number: tunnel.ip_num, region_id: if tunnel.region_num == 0 { None } else { Some(tunnel.region_num) } },
-                            port: tunnel.port,
-                            proto: tunnel.proto,
-                            local_addr,
-                        });
-                    }
-                    None => {
-                        return if tunnel_found.contains(&tunnel_id) {
-                            Err(CliError::TunnelOverwrittenAlready(tunnel_id).into())
-                        } else {
-                            Err(CliError::TunnelNotFound(tunnel_id).into())
-                        };
+                /* a second target of the other IP family is used with --match_client_family or --local_ipv6_first */
+                let unix_target = proto == PortType::Tcp && autorun_settings.unix_targets.contains_key(&arg.tunnel_id);
+                let alt_family = mapping_overrides.iter_mut().find(|existing| {
+                    existing.tunnel_id == arg.tunnel_id
+                        && existing.proto == proto
+                        && existing.alt_local_addr.is_none()
+                        && existing.local_addr.is_ipv4() != local_addr.is_ipv4()
+                }).filter(|_| !unix_target);
+
+                if let Some(existing) = alt_family {
+                    /* both families share one origin, they can't disagree on the header */
+                    if arg.proxy_protocol.is_some_and(|proxy| proxy != existing.proxy_protocol) {
+                        return Err(CliError::InvalidMappingOverride);
                     }
+                    existing.alt_local_addr = Some(local_addr);
+                    continue;
+                }
+
+                let overlaps = mapping_overrides.iter().any(|existing| {
+                    existing.tunnel_id == arg.tunnel_id && (existing.proto.matches(proto) || proto.matches(existing.proto))
+                });
+
+                if overlaps {
+                    return Err(CliError::TunnelOverwrittenAlready(arg.tunnel_id));
                 }
+
+                mapping_overrides.push(MappingOverride {
+                    tunnel_id: arg.tunnel_id,
+                    match_ip: MatchIp { ip_number: tunnel.ip_num, region_id: if tunnel.region_num == 0 { None } else { Some(tunnel.region_num) } },
+                    port: tunnel.port.clone(),
+                    proto,
+                    local_addr,
+                    alt_local_addr: None,
+                    proxy_protocol: arg.proxy_protocol.unwrap_or(tunnel.proxy_protocol),
+                });
             }
 
-            let tunnel = PlayitAgent::new(
-                API_BASE.to_string(),
-                secret_key,
+            let bindings: Vec<LocalBinding> = mapping_overrides.iter()
+                .filter(|over| !(over.proto == PortType::Tcp && autorun_settings.unix_targets.contains_key(&over.tunnel_id)))
+                .flat_map(|over| [Some(over.local_addr), over.alt_local_addr].into_iter().flatten().map(|local_addr| LocalBinding {
+                    tunnel_id: over.tunnel_id,
+                    proto: over.proto,
+                    local_addr,
+                    port_count: over.port.to - over.port.from,
+                }))
+                .collect();
+            report_local_addr_conflicts(&find_local_addr_conflicts(&bindings), autorun_settings.deny_local_addr_conflicts)?;
+
+            let mut tunnel = PlayitAgent::with_auth(
+                AuthApi::new(API_BASE.to_string(), secret_key).with_server_override(autorun_settings.control_server),
                 Arc::new(LookupWithOverrides(mapping_overrides)),
             ).await?;
+            autorun_settings.apply(&mut tunnel);
+            /* run doesn't poll tunnel data, only agent events (registered, disconnected) are sent */
+            let webhook = autorun_settings.webhook.url.clone().map(|url| {
+                WebhookNotifier::start(url, autorun_settings.webhook.secret.clone(), Some(agent_id))
+            });
+            if let Some(webhook) = &webhook {
+                tunnel.set_event_sender(webhook.agent_event_sender());
+            }
 
-            tunnel.run().await;
+            tunnel.run().await?;
         }
         _ => return Err(CliError::NotImplemented.into()),
     }
@@ -261,6 +588,48 @@ async fn main() -> Result<std::process::ExitCode, CliError> {
     Ok(std::process::ExitCode::SUCCESS)
 }
 
+/* explicit answers win over --headless which always answers yes */
+pub fn prompt_auto_answer(yes: bool, no: bool, headless: bool) -> Option<bool> {
+    if yes {
+        Some(true)
+    } else if no {
+        Some(false)
+    } else if headless {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/* distinct exit code for --exit_on_no_tunnels so orchestration can tell it from other failures */
+const NO_TUNNELS_EXIT_CODE: u8 = 3;
+
+/* support needs the trace id to find an internal api error, print it instead of a debug dump */
+fn server_error_message(trace_id: &str) -> String {
+    format!("server error, reference: {} when contacting support", trace_id)
+}
+
+fn server_error_exit_code(result: Result<std::process::ExitCode, CliError>) -> Result<std::process::ExitCode, CliError> {
+    match result {
+        Err(CliError::ApiError(ApiResponseError::Internal(Some(internal)))) => {
+            eprintln!("{}", server_error_message(&internal.trace_id));
+            Ok(std::process::ExitCode::from(2))
+        }
+        other => other,
+    }
+}
+
+fn no_tunnels_exit_code(result: Result<(), CliError>) -> Result<std::process::ExitCode, CliError> {
+    match result {
+        Ok(()) => Ok(std::process::ExitCode::SUCCESS),
+        Err(CliError::NoTunnelsConfigured) => {
+            eprintln!("no tunnels configured for this agent, add one at https://playit.gg/account/tunnels");
+            Ok(std::process::ExitCode::from(NO_TUNNELS_EXIT_CODE))
+        }
+        Err(error) => Err(error),
+    }
+}
+
 pub fn claim_generate() -> String {
     let mut buffer = [0u8; 5];
     rand::thread_rng().fill(&mut buffer);
@@ -278,7 +647,33 @@ pub fn claim_url(code: &str) -> Result<String, CliError> {
     ))
 }
 
-pub async fn claim_exchange(ui: &mut UI, claim_code: &str, agent_type: AgentType, wait_sec: u32) -> Result<String, CliError> {
+/* errors that will not change by retrying the exchange */
+fn claim_exchange_terminal_error(error: &ClaimExchangeError) -> Option<CliError> {
+    match error {
+        ClaimExchangeError::NotAccepted | ClaimExchangeError::NotSetup => None,
+        ClaimExchangeError::UserRejected => Some(CliError::AgentClaimRejected),
+        ClaimExchangeError::CodeExpired => Some(CliError::ClaimCodeExpired),
+        ClaimExchangeError::CodeNotFound => Some(CliError::ClaimCodeNotFound),
+    }
+}
+
+/* how often claim setup / exchange ask the api, minimums keep impatient settings from hammering it */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClaimPollIntervals {
+    pub setup: Duration,
+    pub exchange: Duration,
+}
+
+impl Default for ClaimPollIntervals {
+    fn default() -> Self {
+        ClaimPollIntervals {
+            setup: Duration::from_millis(200),
+            exchange: Duration::from_secs(2),
+        }
+    }
+}
+
+pub async fn claim_exchange(ui: &mut UI, claim_code: &str, agent_type: AgentType, wait_sec: u32, max_attempts: u32, poll: ClaimPollIntervals) -> Result<String, CliError> {
     let api = PlayitApi::create(API_BASE.to_string(), None);
 
     let end_at = if wait_sec == 0 {
@@ -327,27 +722,44 @@ pub async fn claim_exchange(ui: &mut UI, claim_code: &str, agent_type: AgentType
             };
 
             ui.write_screen(&last_message).await;
-            tokio::time::sleep(Duration::from_millis(200)).await;
+            tokio::time::sleep(poll.setup).await;
         }
     }
 
+    let mut attempts = 0;
+
     let secret_key = loop {
+        attempts += 1;
+
         match api.claim_exchange(ReqClaimExchange { code: claim_code.to_string() }).await {
             Ok(res) => break res.secret_key,
             Err(ApiError::Fail(status)) => {
+                if let Some(error) = claim_exchange_terminal_error(&status) {
+                    tracing::error!(?status, "claim exchange failed");
+                    ui.write_screen(format!("code \"{}\" cannot be exchanged, {:?}", claim_code, status)).await;
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    return Err(error);
+                }
+
                 let msg = format!("code \"{}\" not ready, {:?}", claim_code, status);
                 ui.write_screen(msg).await;
             }
             Err(error) => return Err(error.into()),
         };
 
+        if max_attempts != 0 && attempts >= max_attempts {
+            ui.write_screen(format!("claim exchange not ready after {} attempts, closing", attempts)).await;
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            return Err(CliError::ClaimExchangeAttemptsExceeded);
+        }
+
         if now_milli() > end_at {
             ui.write_screen("you took too long to approve the program, closing").await;
             tokio::time::sleep(Duration::from_secs(2)).await;
             return Err(CliError::TimedOut);
         }
 
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        tokio::time::sleep(poll.exchange).await;
     };
 
     Ok(secret_key)
@@ -362,6 +774,275 @@ struct TunnelOption {
     public_address: Option<TunnelAlloc>,
 }
 
+pub async fn tunnels_export(api: &PlayitApi, format: ExportFormat) -> Result<String, CliError> {
+    let data = api.agents_rundata().await?;
+    let tunnels: Vec<ExportedTunnel> = data.tunnels.iter().map(ExportedTunnel::from_tunnel).collect();
+
+    Ok(match format {
+        ExportFormat::Env => export_env(&tunnels) + &export_pending_comments(&data.pending),
+        ExportFormat::Json => export_json(&tunnels),
+        ExportFormat::Hosts => {
+            let mut resolved = Vec::with_capacity(tunnels.len());
+
+            for tunnel in tunnels {
+                let ip = match tokio::net::lookup_host((tunnel.domain.as_str(), tunnel.port)).await {
+                    Ok(mut addrs) => addrs.next().map(|addr| addr.ip()),
+                    Err(error) => {
+                        tracing::warn!(?error, domain = %tunnel.domain, "failed to resolve tunnel domain");
+                        None
+                    }
+                };
+                resolved.push((tunnel, ip));
+            }
+
+            export_hosts(&resolved) + &export_pending_comments(&data.pending)
+        }
+    })
+}
+
+/* same order as the status screen, allocated tunnels by displayed address then port and id, the rest last */
+pub fn sort_tunnel_list(response: &mut serde_json::Value) {
+    let Some(tunnels) = response.get_mut("tunnels").and_then(|v| v.as_array_mut()) else { return };
+
+    tunnels.sort_by_cached_key(|tunnel| {
+        let address = listed_tunnel_address(tunnel);
+        (address.is_none(), address, tunnel["alloc"]["data"]["port_start"].as_u64(), tunnel["id"].as_str().map(|v| v.to_string()))
+    });
+}
+
+/* custom domain when one is attached (a playit subdomain is "<name>.<parent>"), otherwise the assigned domain */
+fn listed_tunnel_address(tunnel: &serde_json::Value) -> Option<String> {
+    if tunnel["alloc"]["status"].as_str() != Some("allocated") {
+        return None;
+    }
+
+    let domain = &tunnel["domain"];
+    match (domain["name"].as_str(), domain["parent"].as_str(), domain["is_external"].as_bool()) {
+        (Some(name), Some(parent), Some(false)) => Some(format!("{}.{}", name, parent)),
+        (Some(name), _, _) => Some(name.to_string()),
+        _ => tunnel["alloc"]["data"]["assigned_domain"].as_str().map(|v| v.to_string()),
+    }
+}
+
+pub const ALLOCATION_REGIONS: [AllocationRegion; 7] = [
+    AllocationRegion::SmartGlobal,
+    AllocationRegion::Global,
+    AllocationRegion::NorthAmerica,
+    AllocationRegion::Europe,
+    AllocationRegion::Asia,
+    AllocationRegion::India,
+    AllocationRegion::SouthAmerica,
+];
+
+pub const PLAYIT_REGIONS: [PlayitRegion; 7] = [
+    PlayitRegion::Anycast,
+    PlayitRegion::Global,
+    PlayitRegion::NorthAmerica,
+    PlayitRegion::Europe,
+    PlayitRegion::Asia,
+    PlayitRegion::India,
+    PlayitRegion::SouthAmerica,
+];
+
+/*
+ * Allocation regions use the serde renames the API accepts. PlayitRegion is
+ * decoded from a tunnel's address and has no string form in the API, so it
+ * is listed by region number for reading tunnel addresses, not as arguments.
+ */
+pub fn list_regions() -> String {
+    let mut out = "allocation regions (tunnels create --region):\n".to_string();
+
+    for region in ALLOCATION_REGIONS {
+        let name = serde_json::to_value(region).unwrap();
+        out.push_str(&format!("{}\t{:?}\n", name.as_str().expect("region serializes as string"), region));
+    }
+
+    out.push_str("\ntunnel address regions (region number in a tunnel's ip, not accepted as arguments):\n");
+    for region in PLAYIT_REGIONS {
+        out.push_str(&format!("{}\t{:?}\n", region as u16, region));
+    }
+
+    out
+}
+
+pub async fn guest_account_notice(api: &PlayitApi) -> String {
+    let link = match api.login_guest().await {
+        Ok(session) => format!("https://playit.gg/login/guest-account/{}", session.session_key),
+        Err(error) => {
+            tracing::warn!(?error, "failed to create guest login link");
+            "https://playit.gg/login".to_string()
+        }
+    };
+
+    format!("This agent belongs to a guest account, managing tunnels and agents requires a full account.\nLogin and verify your account at: {}", link)
+}
+
+/* fail early with a clear message instead of letting the request fail with GuestAccountNotAllowed */
+async fn ensure_not_guest(api: &PlayitApi) -> Result<(), CliError> {
+    let data = api.agents_rundata().await?;
+    if data.account_status != AgentAccountStatus::Guest {
+        return Ok(());
+    }
+
+    eprintln!("{}", guest_account_notice(api).await);
+    Err(CliError::GuestAccountNotAllowed)
+}
+
+fn claim_details_error_message(error: ClaimDetailsError) -> &'static str {
+    match error {
+        ClaimDetailsError::AlreadyClaimed => "claim code has already been claimed",
+        ClaimDetailsError::AlreadyRejected => "claim was rejected, generate a new claim code",
+        ClaimDetailsError::ClaimExpired => "claim code has expired, generate a new claim code",
+        ClaimDetailsError::DifferentOwner => "claim belongs to a different account",
+        ClaimDetailsError::WaitingForAgent => "agent has not started the claim yet, make sure it is running with this claim code",
+        ClaimDetailsError::InvalidCode => "invalid claim code",
+    }
+}
+
+/* setup writes the linux service secret, point other platforms at their own flow */
+#[cfg(any(test, not(target_os = "linux")))]
+fn setup_unsupported_message(platform: Platform) -> String {
+    let guide = match platform {
+        Platform::Windows => "install playit with the Windows installer from https://playit.gg/download, it runs the agent as a service",
+        Platform::Macos => "run `playit start` to link the agent, use a launchd job to keep it running in the background",
+        _ => "run `playit start` to link the agent and keep it running",
+    };
+    format!("setup is only supported on Linux (it installs the playit service secret), on this platform {}", guide)
+}
+
+pub async fn claim_details(api: &PlayitApi, code: &str) -> Result<std::process::ExitCode, CliError> {
+    let res = api.claim_details(ReqClaimDetails { code: code.to_string() }).await;
+
+    match res {
+        Ok(details) => {
+            let agent_type = match details.agent_type {
+                AgentType::Default => "default",
+                AgentType::Assignable => "assignable",
+                AgentType::SelfManaged => "self-managed",
+            };
+
+            println!("name: {}", details.name);
+            println!("remote ip: {}", details.remote_ip);
+            println!("agent type: {}", agent_type);
+            println!("version: {}", details.version);
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Err(ApiError::Fail(error)) => {
+            eprintln!("{}", claim_details_error_message(error));
+            Ok(std::process::ExitCode::from(2))
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/* the upper limit depends on the account and allocation, only the api can check it (see PortCountRejected) */
+const MIN_PORT_COUNT: u16 = 1;
+
+fn parse_port_count(value: &str) -> Result<u16, CliError> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CliError::InvalidPortCount);
+    }
+
+    match value.parse::<u16>() {
+        Ok(count) if MIN_PORT_COUNT <= count => Ok(count),
+        _ => Err(CliError::PortCountOutOfRange { count: value.to_string(), min: MIN_PORT_COUNT }),
+    }
+}
+
+/* unlike prepare, always creates a new tunnel */
+fn parse_tunnel_create(m: &ArgMatches) -> Result<ReqTunnelsCreate, CliError> {
+    let tunnel_type = match m.get_one::<String>("type") {
+        Some(v) => Some(serde_json::from_str::<TunnelType>(&format!("{:?}", v)).map_err(|_| CliError::InvalidTunnelType)?),
+        None => None,
+    };
+    let port_type = serde_json::from_str::<PortType>(&format!("{:?}", m.get_one::<String>("port_type").expect("required")))
+        .map_err(|_| CliError::InvalidPortType)?;
+    let port_count = parse_port_count(m.get_one::<String>("port_count").expect("has default"))?;
+
+    let agent_id = match m.get_one::<String>("agent_id") {
+        Some(id) => Some(Uuid::from_str(id).map_err(|_| CliError::InvalidAgentId)?),
+        None => None,
+    };
+    let local_ip = match m.get_one::<String>("local_ip") {
+        Some(ip) => ip.parse::<IpAddr>().map_err(|_| CliError::InvalidTunnelOrigin)?,
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    let local_port = match m.get_one::<String>("local_port") {
+        Some(port) => Some(port.parse::<u16>().map_err(|_| CliError::InvalidTunnelOrigin)?),
+        None => None,
+    };
+
+    let origin = match m.get_one::<String>("origin").expect("has default").as_str() {
+        "managed" => TunnelOriginCreate::Managed(AssignedManagedCreate { agent_id }),
+        "agent" => TunnelOriginCreate::Agent(AssignedAgentCreate {
+            agent_id: agent_id.ok_or(CliError::AgentIdRequired)?,
+            local_ip,
+            local_port,
+        }),
+        "default" => TunnelOriginCreate::Default(AssignedDefaultCreate { local_ip, local_port }),
+        _ => return Err(CliError::InvalidTunnelOrigin),
+    };
+
+    let alloc = if let Some(region) = m.get_one::<String>("region") {
+        /* exact names only, serde maps unknown regions to smart-global */
+        let region = ALLOCATION_REGIONS.into_iter()
+            .find(|known| serde_json::to_value(known).ok().as_ref().and_then(|name| name.as_str()) == Some(region.as_str()))
+            .ok_or(CliError::InvalidAllocationRegion)?;
+        Some(TunnelCreateUseAllocation::Region(UseRegion { region }))
+    } else if let Some(ip_hostname) = m.get_one::<String>("dedicated_ip") {
+        let port = match m.get_one::<String>("dedicated_port") {
+            Some(port) => Some(port.parse::<u16>().map_err(|_| CliError::InvalidPortCount)?),
+            None => None,
+        };
+        Some(TunnelCreateUseAllocation::DedicatedIp(UseAllocDedicatedIp { ip_hostname: ip_hostname.clone(), port }))
+    } else if let Some(alloc_id) = m.get_one::<String>("port_alloc") {
+        let alloc_id = Uuid::from_str(alloc_id).map_err(|_| CliError::InvalidAllocationId)?;
+        Some(TunnelCreateUseAllocation::PortAllocation(UseAllocPortAlloc { alloc_id }))
+    } else {
+        None
+    };
+
+    let firewall_id = match m.get_one::<String>("firewall_id") {
+        Some(id) => Some(Uuid::from_str(id).map_err(|_| CliError::InvalidFirewallId)?),
+        None => None,
+    };
+
+    Ok(ReqTunnelsCreate {
+        name: m.get_one::<String>("name").cloned(),
+        tunnel_type,
+        port_type,
+        port_count,
+        origin,
+        enabled: !m.get_flag("disabled"),
+        alloc,
+        firewall_id,
+    })
+}
+
+pub async fn tunnels_create(api: &PlayitApi, req: ReqTunnelsCreate) -> Result<std::process::ExitCode, CliError> {
+    let message = match api.tunnels_create(req).await {
+        Ok(created) => {
+            println!("{}", created.id);
+            return Ok(std::process::ExitCode::SUCCESS);
+        }
+        Err(ApiError::Fail(error)) => match error {
+            TunnelCreateError::InvalidPortCount => "invalid port count for this port type or allocation",
+            TunnelCreateError::AgentIdRequired | TunnelCreateError::ManagedMissingAgentId => "an agent id is required for this origin, pass --agent_id",
+            TunnelCreateError::AgentNotFound | TunnelCreateError::InvalidAgentId => "agent not found or cannot be assigned",
+            TunnelCreateError::DedicatedIpNotFound => "dedicated ip not found on this account",
+            TunnelCreateError::DedicatedIpPortNotAvailable => "port is already in use on the dedicated ip",
+            TunnelCreateError::DedicatedIpNotEnoughSpace => "not enough free ports left on the dedicated ip",
+            TunnelCreateError::PortAllocNotFound => "port allocation not found",
+            TunnelCreateError::InvalidIpHostname => "invalid dedicated ip hostname",
+        },
+        Err(error) => return Err(error.into()),
+    };
+
+    eprintln!("failed to create tunnel: {}", message);
+    Ok(std::process::ExitCode::from(2))
+}
+
 struct TunnelAlloc {
     address: String,
     port: u16,
@@ -463,28 +1144,37 @@ pub async fn tunnels_prepare(api: &PlayitApi, name: Option<String>, tunnel_type:
         enabled: true,
         alloc: None,
         firewall_id: None,
-    }).await?;
+    }).await;
 
-    Ok(created.id)
+    match created {
+        Ok(created) => Ok(created.id),
+        Err(ApiError::Fail(TunnelCreateError::InvalidPortCount)) => Err(CliError::PortCountRejected { port_type, port_count }),
+        Err(error) => Err(error.into()),
+    }
 }
 
 struct MappingOverride {
+    tunnel_id: Uuid,
     match_ip: MatchIp,
     proto: PortType,
     port: PortRange,
     local_addr: SocketAddr,
+    alt_local_addr: Option<SocketAddr>,
+    proxy_protocol: Option<ProxyProtocol>,
 }
 
 pub struct LookupWithOverrides(Vec<MappingOverride>);
 
 impl AddressLookup for LookupWithOverrides {
-    type Value = SocketAddr;
+    type Value = HostOrigin;
 
-    fn lookup(&self, ip: IpAddr, port: u16, proto: PortType) -> Option<AddressValue<SocketAddr>> {
+    fn lookup(&self, ip: IpAddr, port: u16, proto: PortType) -> Option<AddressValue<HostOrigin>> {
         for over in &self.0 {
             if over.proto.matches(proto) && over.match_ip.matches(ip) && over.port.contains(port) {
                 return Some(AddressValue {
-                    value: over.local_addr,
+                    value: HostOrigin::new(over.tunnel_id, over.local_addr)
+                        .with_alt_host_addr(over.alt_local_addr)
+                        .with_proxy_protocol(over.proxy_protocol),
                     from_port: over.port.from,
                     to_port: over.port.to,
                 });
@@ -492,7 +1182,7 @@ impl AddressLookup for LookupWithOverrides {
         }
 
         Some(AddressValue {
-            value: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)),
+            value: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).into(),
             from_port: port,
             to_port: port + 1,
         })
@@ -512,8 +1202,15 @@ pub enum CliError {
     SecretFilePathMissing,
     InvalidPortType,
     InvalidPortCount,
+    /* below min, or more ports than a port range can hold */
+    PortCountOutOfRange { count: String, min: u16 },
+    /* the api does not allow this many ports for the port type */
+    PortCountRejected { port_type: PortType, port_count: u16 },
     InvalidMappingOverride,
     AgentClaimRejected,
+    ClaimCodeExpired,
+    ClaimCodeNotFound,
+    ClaimExchangeAttemptsExceeded,
     InvalidConfigFile,
     TunnelNotFound(Uuid),
     TimedOut,
@@ -524,6 +1221,59 @@ pub enum CliError {
     ApiError(ApiResponseError),
     ApiFail(String),
     TunnelSetupError(SetupError),
+    InvalidAgentId,
+    AgentIdRequired,
+    GuestAccountNotAllowed,
+    InvalidConfigFormat,
+    InvalidConnectionHook,
+    InvalidTcpBufferSize,
+    InvalidConnectTimeout,
+    InvalidTcpSetupLimit,
+    InvalidSourcePort,
+    InvalidClaimPollInterval { min: Duration },
+    NoTunnelsConfigured,
+    InvalidConnectionLifetime,
+    InvalidUnixTarget,
+    UnixTargetUnsupported,
+    InvalidExportFormat,
+    InvalidUdpRecvBatchSize,
+    InvalidUdpProxyResend,
+    AlreadyRunning(u32),
+    #[cfg(feature = "client-geo")]
+    InvalidClientGeoDb(String),
+    #[cfg(feature = "listen-local")]
+    InvalidListenLocalAddr,
+    #[cfg(feature = "listen-local")]
+    ListenLocalFailed(std::io::Error),
+    PidFileWriteError(std::io::Error),
+    InvalidHealthListenAddr,
+    InvalidTunnelRefreshInterval,
+    InvalidIdleSleep,
+    LocalAddrConflict(SocketAddr, Uuid, Uuid),
+    InvalidTunnelType,
+    InvalidTunnelOrigin,
+    InvalidAllocationRegion,
+    InvalidAllocationId,
+    InvalidFirewallId,
+    InvalidHostRoute,
+    HealthListenError(std::io::Error),
+    InvalidAdminListenAddr,
+    AdminListenError(std::io::Error),
+    InvalidLogMaxSize,
+    InvalidLogKeep,
+    LogFileOpenError(std::io::Error),
+    AccessLogOpenError(std::io::Error),
+    InvalidBenchmarkSetting,
+    InvalidTunnelQuota,
+    QuotaStateError(std::io::Error),
+    BenchmarkFailed(std::io::Error),
+    InvalidProbeAddress,
+    UdpProbeFailed(std::io::Error),
+    InvalidControlServer,
+    MissingLogPath,
+    InvalidLogTailSetting,
+    LogTailError(std::io::Error),
+    HmacSelfTestFailed(&'static str),
 }
 
 impl Error for CliError {
@@ -532,13 +1282,17 @@ impl Error for CliError {
 
 impl Display for CliError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            CliError::ApiError(ApiResponseError::Internal(Some(internal))) => write!(f, "{}", server_error_message(&internal.trace_id)),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
 impl<F: serde::Serialize> From<ApiError<F, HttpClientError>> for CliError {
     fn from(e: ApiError<F, HttpClientError>) -> Self {
         match e {
+            ApiError::ApiError(ApiResponseError::Auth(AuthError::GuestAccountNotAllowed)) => CliError::GuestAccountNotAllowed,
             ApiError::ApiError(e) => CliError::ApiError(e),
             ApiError::ClientError(e) => CliError::RequestError(e),
             ApiError::Fail(fail) => CliError::ApiFail(serde_json::to_string(&fail).unwrap())
@@ -549,6 +1303,7 @@ impl<F: serde::Serialize> From<ApiError<F, HttpClientError>> for CliError {
 impl From<ApiErrorNoFail<HttpClientError>> for CliError {
     fn from(e: ApiErrorNoFail<HttpClientError>) -> Self {
         match e {
+            ApiErrorNoFail::ApiError(ApiResponseError::Auth(AuthError::GuestAccountNotAllowed)) => CliError::GuestAccountNotAllowed,
             ApiErrorNoFail::ApiError(e) => CliError::ApiError(e),
             ApiErrorNoFail::ClientError(e) => CliError::RequestError(e),
         }
@@ -565,10 +1320,56 @@ fn cli() -> Command {
     let mut cmd = Command::new("playit-cli")
         .arg(arg!(--secret <SECRET> "secret code for the agent").required(false))
         .arg(arg!(--secret_path <PATH> "path to file containing secret").required(false))
+        .arg(arg!(--config_format <FORMAT> "format of the secret file: toml, json or yaml (default from the file extension, otherwise toml)").required(false).value_parser(["toml", "json", "yaml"]))
         .arg(arg!(-w --secret_wait "wait for secret_path file to read secret").required(false))
         .arg(arg!(-s --stdout "prints logs to stdout").required(false))
+        .arg(arg!(-q --quiet "with --stdout or --headless, only log status changes instead of repeating the status screen").required(false))
         .arg(arg!(-l --log_path <PATH> "path to write logs to").required(false))
+        .arg(arg!(--log_max_size <SIZE> "rotate the --log_path file when it reaches this size, ex. 10M (minimum 64K, default never rotates)").required(false).requires("log_path"))
+        .arg(arg!(--log_keep <COUNT> "number of rotated log files kept with --log_max_size (default 5)").required(false).requires("log_max_size"))
+        .arg(arg!(--log_compress "zstd compress log files rotated by --log_max_size (playit.log.N.zst), the active log stays uncompressed").required(false).requires("log_max_size"))
+        .arg(arg!(-y --yes "answer yes to every prompt instead of waiting for input").required(false).visible_alias("assume_yes").conflicts_with("assume_no"))
+        .arg(arg!(--assume_no "answer no to every prompt instead of waiting for input").required(false))
         .arg(arg!(--platform_docker "overrides platform in version to be docker").required(false))
+        .arg(arg!(--tunnel_filter <FILTER> "only serve tunnels matching ids or name globs (format \"<tunnel-id|name-glob>[, ..]\")").required(false).value_delimiter(','))
+        .arg(arg!(--on_connect <HOOK> "command to run when a tunnel gets its first connection (format \"<tunnel-id>=<command>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--on_disconnect <HOOK> "command to run when a tunnel's last connection closes (format \"<tunnel-id>=<command>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--webhook_url <URL> "POST agent lifecycle events (agent-registered, control-disconnected, tunnel-disabled, tunnel-state-changed, account-banned) as JSON").required(false))
+        .arg(arg!(--webhook_secret <SECRET> "sign webhook bodies with HMAC-SHA256 in the X-Playit-Signature header").required(false))
+        .arg(arg!(--admin_listen <ADDR> "serve the local admin api (tunnels, stats, control latency, reload, stop) as HTTP + JSON on this port of 127.0.0.1 or on an address").required(false))
+        .arg(arg!(--admin_token <TOKEN> "require \"Authorization: Bearer <TOKEN>\" on every admin api request, required when --admin_listen is not a loopback address").required(false).requires("admin_listen"))
+        .arg(arg!(--health_listen <ADDR> "serve GET /healthz (liveness) and /readyz (control channel registered) over HTTP on this address, e.g. 127.0.0.1:9100, a socket passed by systemd socket activation is used instead when present").required(false))
+        .arg(arg!(--ip4_only "only accept clients connecting over IPv4").required(false).conflicts_with("ip6_only"))
+        .arg(arg!(--ip6_only "only accept clients connecting over IPv6").required(false))
+        .arg(arg!(--match_client_family "when a tunnel has both IPv4 and IPv6 local addresses, forward to the one matching the client's IP family").required(false))
+        .arg(arg!(--local_ipv6_first "when a tunnel has both IPv4 and IPv6 local addresses, forward to the IPv6 one, --match_client_family takes precedence").required(false))
+        .arg(arg!(--display_family <FAMILY> "show the tunnel's public address of this IP family (ipv4 or ipv6) first on the status screen, the domain and other family are listed after").required(false).value_parser(["ipv4", "ipv6"]))
+        .arg(arg!(--redact_client_ips "mask client addresses in logs (203.0.113.x), forwarding and proxy protocol still use the real address").required(false))
+        .arg(arg!(--tunnel_refresh_interval <SECONDS> "how often tunnel settings are fetched from the api while running (minimum 1, default 3)").required(false))
+        .arg(arg!(--idle_sleep <SECONDS> "after this long without connections, ping and poll less often to save resources until the next connection; reconnecting after a network change takes a few seconds longer while asleep (minimum 10, default off)").required(false))
+        .arg(arg!(--exit_on_no_tunnels "exit with code 3 when the agent has no tunnels after loading them the first time, instead of waiting for tunnels to be added").required(false))
+        .arg(arg!(--deny_local_addr_conflicts "refuse to start when multiple tunnels forward to the same local address and protocol instead of only warning").required(false))
+        .arg(arg!(--claim_setup_interval <SECONDS> "how often to check if the claim link was visited and approved (default 0.2, min 0.1)").required(false))
+        .arg(arg!(--claim_exchange_interval <SECONDS> "how often to retry exchanging an approved claim for the secret (default 2, min 0.5)").required(false))
+        .arg(arg!(--tcp_setup_limit <COUNT> "maximum new TCP clients being set up at once (claiming the connection and connecting to the local server), more are rejected (default 512)").required(false))
+        .arg(arg!(--source_port <PORT> "connect to local servers from this source port or the first free port of a range (format \"<port>\" or \"<from>-<to>\"), each UDP client uses its own port").required(false))
+        .arg(arg!(--connect_timeout <SECONDS> "timeout for connecting to the local server before closing the client connection (default 5)").required(false))
+        .arg(arg!(--control_server <SERVER> "debugging: only connect to this tunnel server, by address from the routing api (<ip>[:<port>]) or server id, fails instead of falling back").required(false).hide(true))
+        .arg(arg!(--reconnect_on_auth_error "keep retrying when registration is rejected (banned, disabled or unsupported version) instead of exiting").required(false))
+        .arg(arg!(--host_route <ROUTE> "send a TCP tunnel's connections to another local server by TLS SNI, HTTP Host or Minecraft Java handshake address (format \"<tunnel-id>=<hostname|*.domain>=[<local-ip>:]<local-port>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--max_connection_lifetime <LIFETIME> "close a tunnel's TCP connections after they have been open this long (format \"<tunnel-id>=<seconds>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--unix_target <TARGET> "forward a TCP tunnel to a UNIX domain socket instead of its local address (format \"<tunnel-id>=unix:<path>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--quota_bytes_per_day <QUOTA> "reject new clients of a tunnel once it transferred this much today (UTC), both directions count (format \"<tunnel-id>=<bytes, ex. 10G>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--quota_connections_per_hour <QUOTA> "reject new clients of a tunnel once this many connected in the current hour (format \"<tunnel-id>=<count>\")").required(false).action(ArgAction::Append))
+        .arg(arg!(--access_log <PATH> "append a Common Log Format line per closed TCP connection to this file, \"-\" for stdout").required(false))
+        .arg(arg!(--access_log_format <FORMAT> "format of --access_log lines, combined adds bytes received and duration in ms (default common)").required(false).value_parser(["common", "combined"]).requires("access_log"))
+        .arg(arg!(--quota_state_path <PATH> "save quota usage to this file so it survives restarts").required(false))
+        .arg(arg!(--pidfile <PATH> "write the agent's pid to this file while running, refuses to start if the recorded process is still alive").required(false))
+        .arg(arg!(--pidfile_takeover "with --pidfile, overwrite the pid of a running process instead of refusing to start").required(false).requires("pidfile"))
+        .arg(arg!(--udp_proxy_host_idle <SECONDS> "UDP tunnels with PROXY protocol v2 resend the header once the local server has not replied for this long (default 15)").required(false))
+        .arg(arg!(--udp_proxy_min_resend <SECONDS> "minimum time between PROXY protocol v2 headers sent for a UDP client (default 2)").required(false))
+        .arg(arg!(--udp_recv_batch_size <PACKETS> "UDP packets read from the tunnel per syscall, batching (recvmmsg) only applies on Linux (1 to 64, default 1)").required(false))
+        .arg(arg!(--tcp_buffer_size <BYTES> "buffer size used to copy TCP data, larger helps high throughput tunnels (512 to 1048576, default 2048)").required(false))
         .subcommand_required(false)
         .subcommand(Command::new("version"))
         .subcommand(
@@ -601,11 +1402,18 @@ fn cli() -> Command {
                         .about("Exchanges the claim for the secret key")
                         .arg(arg!(<CLAIM_CODE> "claim code (see \"claim generate\")"))
                         .arg(arg!(--wait <WAIT_SEC> "number of seconds to wait 0=infinite").default_value("0"))
+                        .arg(arg!(--max_attempts <COUNT> "number of exchange attempts before giving up 0=infinite").default_value("0"))
+                )
+                .subcommand(
+                    Command::new("details")
+                        .about("Show the name, IP, type and version of the agent behind a pending claim before accepting it")
+                        .arg(arg!(<CLAIM_CODE> "claim code"))
                 )
         )
         .subcommand(
             Command::new("start")
                 .about("Start the playit agent")
+                .arg(arg!(--headless "run in the foreground without the terminal UI, logs to stdout (or --log_path) and answers prompts automatically").required(false))
         )
         .subcommand(
             Command::new("tunnels")
@@ -621,15 +1429,52 @@ fn cli() -> Command {
                         .arg(arg!(--exact))
                         .arg(arg!(--ignore_name))
                 )
+                .subcommand(
+                    Command::new("create")
+                        .about("Create a new tunnel even if a matching one exists, prints the tunnel id")
+                        .arg(arg!(--port_type <PORT_TYPE> "either \"tcp\", \"udp\", or \"both\""))
+                        .arg(arg!(--port_count [PORT_COUNT] "number of ports in a series to allocate").default_value("1"))
+                        .arg(arg!(--type [TUNNEL_TYPE] "the tunnel type, ex. \"minecraft-java\""))
+                        .arg(arg!(--name [NAME] "name of the tunnel"))
+                        .arg(arg!(--origin [ORIGIN] "either \"managed\" (the agent decides the local address), \"agent\" or \"default\"").default_value("managed"))
+                        .arg(arg!(--agent_id [AGENT_ID] "agent to assign the tunnel to, required for the \"agent\" origin"))
+                        .arg(arg!(--local_ip [IP] "local ip for the \"agent\" and \"default\" origins (default 127.0.0.1)"))
+                        .arg(arg!(--local_port [PORT] "local port for the \"agent\" and \"default\" origins"))
+                        .arg(arg!(--region [REGION] "allocate in a region, see \"agents routing list-pops\"").conflicts_with_all(["dedicated_ip", "port_alloc"]))
+                        .arg(arg!(--dedicated_ip [HOSTNAME] "allocate on a dedicated ip").conflicts_with("port_alloc"))
+                        .arg(arg!(--dedicated_port [PORT] "port to use on the dedicated ip").requires("dedicated_ip"))
+                        .arg(arg!(--port_alloc [ALLOC_ID] "use an existing port allocation"))
+                        .arg(arg!(--firewall_id [FIREWALL_ID] "firewall to attach to the tunnel"))
+                        .arg(arg!(--disabled "create the tunnel disabled"))
+                )
                 .subcommand(
                     Command::new("list")
                         .about("List tunnels (format \"[tunnel-id] [port-type] [port-count] [public-address]\")")
                 )
+                .subcommand(
+                    Command::new("export")
+                        .about("Print the agent's tunnel addresses as env lines (TUNNEL_<NAME>=addr:port), hosts file entries or json")
+                        .arg(arg!(--format <FORMAT> "either \"env\", \"hosts\", or \"json\"").required(false).default_value("env"))
+                )
+        )
+        .subcommand(
+            Command::new("agents")
+                .subcommand_required(true)
+                .about("Manage agents")
+                .subcommand(
+                    Command::new("routing")
+                        .subcommand_required(true)
+                        .about("Agent routing")
+                        .subcommand(
+                            Command::new("list-pops")
+                                .about("List the allocation region names accepted by the API (canonical name, then enum name) and the regions encoded in tunnel addresses")
+                        )
+                )
         )
         .subcommand(
             Command::new("run")
                 .about("(depreciated will be removed) Run the playit agent with manual port mappings")
-                .arg(arg!([MAPPING_OVERRIDE] "(format \"<tunnel-id>=[<local-ip>:]<local-port> [, ..]\")").required(false).value_delimiter(','))
+                .arg(arg!([MAPPING_OVERRIDE] "(format \"<tunnel-id>=[tcp:|udp:][<local-ip>:]<local-port>[/proxy=none|v1|v2][,udp:..] [, ..]\", TCP can also target \"unix:<path>\")").required(false).value_delimiter(','))
         )
         .subcommand(
             Command::new("reset")
@@ -639,11 +1484,312 @@ fn cli() -> Command {
             Command::new("secret-path")
                 .about("shows the file path where the playit secret can be found")
         )
+        .subcommand(
+            Command::new("benchmark")
+                .about("measures TCP and UDP throughput and added latency of the agent's forwarding against a built-in server on localhost, use with --tcp_buffer_size to compare buffer sizes")
+                .arg(arg!(--size [MIB] "MiB sent over TCP").default_value("256"))
+                .arg(arg!(--udp_packets [COUNT] "UDP packets sent").default_value("50000"))
+                .arg(arg!(--udp_packet_size [BYTES] "size of each UDP packet (max 1400)").default_value("1200"))
+                .arg(arg!(--round_trips [COUNT] "round trips used to measure latency").default_value("1000"))
+        )
+        .subcommand(
+            Command::new("probe-udp")
+                .about("checks if UDP reaches playit's tunnel servers, prints a JSON report and exits non-zero if no server answered")
+                .arg(arg!(--addr [ADDRESS] "probe this <ip>:<port> instead of the agent's control addresses (repeatable)").action(ArgAction::Append))
+        )
+        .subcommand(
+            Command::new("logs")
+                .about("prints the end of the log file of an agent running with --log_path")
+                .arg(arg!(--file [PATH] "log file to read, defaults to --log_path"))
+                .arg(arg!(-f --follow "keep printing new lines as they are written").required(false))
+                .arg(arg!(--level [LEVEL] "only show lines at this level or more severe (error, warn, info, debug, trace)").default_value("trace"))
+                .arg(arg!(-n --lines [COUNT] "lines to print from the end of the file").default_value("50"))
+        )
+        .subcommand(
+            Command::new("config")
+                .subcommand_required(true)
+                .about("Validate agent configuration")
+                .subcommand(
+                    Command::new("check")
+                        .about("checks the config file and run mappings without running the agent, reports every problem and exits non-zero if any was found")
+                        .arg(arg!(--config [PATH] "config (secret) file to check, defaults to --secret_path or the default location"))
+                        .arg(arg!(--offline "only check the files and mappings, don't load the account's tunnels from the api").required(false))
+                        .arg(arg!([MAPPING] "run mappings to check (same format as \"run\")").required(false).value_delimiter(','))
+                )
+        )
+        .subcommand(
+            Command::new("print-config")
+                .about("prints the resolved configuration the agent would run with (secrets redacted)")
+                .arg(arg!(--format [FORMAT] "either \"json\" or \"toml\"").default_value("json"))
+        )
         ;
 
     #[cfg(target_os = "linux")] {
         cmd = cmd.subcommand(Command::new("setup"));
     }
 
+    #[cfg(not(target_os = "linux"))] {
+        cmd = cmd.subcommand(Command::new("setup").about("only supported on Linux, prints how to set up playit on this platform"));
+    }
+
+    #[cfg(feature = "client-geo")] {
+        cmd = cmd.arg(arg!(--client_geo_db <PATH> "log a country / ASN hint for new clients using a local ip2asn TSV file, no network lookups are made").required(false));
+    }
+
+    #[cfg(feature = "listen-local")] {
+        cmd = cmd.subcommand(
+            Command::new("listen-local")
+                .about("[testing only] accepts local TCP connections and forwards them out to a tunnel's public address, for loopback tests of the full path")
+                .arg(arg!(--bind [ADDRESS] "local <ip>:<port> to accept connections on").default_value("127.0.0.1:25565"))
+                .arg(arg!(--to <ADDRESS> "tunnel's public address, <host>:<port>").required(true))
+        );
+    }
+
     cmd
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use playit_agent_core::network::address_lookup::{AddressLookup, LocalAddrSelection};
+    use playit_api_client::api::{AllocationRegion, ApiInternalError, ApiResponseError, AssignedManagedCreate, ClaimDetailsError, ClaimExchangeError, Platform, PortRange, PortType, ProxyProtocol, TunnelCreateUseAllocation, TunnelOriginCreate, TunnelType, UseRegion};
+    use playit_api_client::ip_resource::PlayitRegion;
+    use uuid::Uuid;
+
+    use crate::match_ip::MatchIp;
+
+    use super::{claim_details_error_message, claim_exchange_terminal_error, list_regions, parse_tunnel_create, prompt_auto_answer, setup_unsupported_message, sort_tunnel_list, CliError, LookupWithOverrides, MappingOverride, ALLOCATION_REGIONS, PLAYIT_REGIONS};
+
+    #[test]
+    fn test_parse_tunnel_create() {
+        let parse = |args: &[&str]| {
+            let matches = super::cli().try_get_matches_from(["playit", "tunnels", "create"].iter().chain(args)).unwrap();
+            let (_, tunnels) = matches.subcommand().unwrap();
+            let (_, create) = tunnels.subcommand().unwrap();
+            parse_tunnel_create(create)
+        };
+
+        let req = parse(&["--port_type", "both", "--port_count", "2", "--type", "minecraft-bedrock", "--region", "europe", "--disabled"]).unwrap();
+        assert_eq!(req.port_type, PortType::Both);
+        assert_eq!(req.port_count, 2);
+        assert_eq!(req.tunnel_type, Some(TunnelType::MinecraftBedrock));
+        assert!(!req.enabled);
+        assert!(matches!(req.origin, TunnelOriginCreate::Managed(AssignedManagedCreate { agent_id: None })));
+        assert!(matches!(req.alloc, Some(TunnelCreateUseAllocation::Region(UseRegion { region: AllocationRegion::Europe }))));
+
+        let agent_id = Uuid::from_u128(5).to_string();
+        let req = parse(&["--port_type", "tcp", "--origin", "agent", "--agent_id", &agent_id, "--local_port", "8080"]).unwrap();
+        match req.origin {
+            TunnelOriginCreate::Agent(origin) => {
+                assert_eq!(origin.agent_id, Uuid::from_u128(5));
+                assert_eq!(origin.local_ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+                assert_eq!(origin.local_port, Some(8080));
+            }
+            other => panic!("unexpected origin {:?}", other),
+        }
+        assert!(req.alloc.is_none());
+
+        assert!(matches!(parse(&["--port_type", "tcp", "--origin", "agent"]), Err(CliError::AgentIdRequired)));
+        assert!(matches!(parse(&["--port_type", "tcp", "--port_count", "0"]), Err(CliError::PortCountOutOfRange { min: 1, .. })));
+        assert!(matches!(parse(&["--port_type", "tcp", "--port_count", "70000"]), Err(CliError::PortCountOutOfRange { .. })));
+        assert!(matches!(parse(&["--port_type", "tcp", "--port_count", "ten"]), Err(CliError::InvalidPortCount)));
+        assert_eq!(parse(&["--port_type", "tcp", "--port_count", "64"]).unwrap().port_count, 64);
+        assert!(matches!(parse(&["--port_type", "tcp", "--region", "mars"]), Err(CliError::InvalidAllocationRegion)));
+    }
+
+    #[test]
+    fn test_server_error_shows_trace_id() {
+        let error = CliError::ApiError(ApiResponseError::Internal(Some(ApiInternalError { trace_id: "3f2a9c".to_string() })));
+        assert_eq!(error.to_string(), "server error, reference: 3f2a9c when contacting support");
+        assert_eq!(CliError::ApiError(ApiResponseError::Internal(None)).to_string(), "ApiError(Internal(None))");
+        assert_eq!(CliError::NotImplemented.to_string(), "NotImplemented");
+    }
+
+    #[test]
+    fn test_setup_unsupported_message() {
+        assert!(setup_unsupported_message(Platform::Windows).contains("Windows installer"));
+        assert!(setup_unsupported_message(Platform::Macos).contains("launchd"));
+        assert!(setup_unsupported_message(Platform::Freebsd).starts_with("setup is only supported on Linux"));
+    }
+
+    #[test]
+    fn test_prompt_auto_answer() {
+        assert_eq!(prompt_auto_answer(false, false, false), None);
+        assert_eq!(prompt_auto_answer(false, false, true), Some(true));
+        assert_eq!(prompt_auto_answer(false, true, true), Some(false));
+        assert_eq!(prompt_auto_answer(true, false, false), Some(true));
+
+        let matches = super::cli().try_get_matches_from(["playit", "--assume_yes", "start"]).unwrap();
+        assert!(matches.get_flag("yes"));
+        assert!(super::cli().try_get_matches_from(["playit", "-y", "--assume_no"]).is_err());
+    }
+
+    /* trimmed /tunnels/list response, fields the sort doesn't read are kept so the shape stays recognizable */
+    const TUNNELS_LIST_FIXTURE: &str = r#"{
+        "tcp_alloc": { "allowed": 4, "claimed": 3, "desired": 0 },
+        "udp_alloc": { "allowed": 4, "claimed": 1, "desired": 0 },
+        "tunnels": [
+            {
+                "id": "00000000-0000-0000-0000-000000000004",
+                "tunnel_type": null, "created_at": "2024-03-01T10:00:00Z", "name": "new",
+                "port_type": "tcp", "port_count": 1,
+                "alloc": { "status": "pending" },
+                "origin": { "type": "default", "data": { "local_ip": "127.0.0.1", "local_port": 8080, "agent_id": "00000000-0000-0000-0000-0000000000aa" } },
+                "domain": null, "firewall_id": null, "ratelimit": { "bytes_per_second": null, "packets_per_second": null },
+                "active": true, "disabled_reason": null, "region": null, "expire_notice": null, "proxy_protocol": null
+            },
+            {
+                "id": "00000000-0000-0000-0000-000000000003",
+                "tunnel_type": "minecraft-java", "created_at": "2024-01-01T10:00:00Z", "name": "survival",
+                "port_type": "tcp", "port_count": 1,
+                "alloc": { "status": "allocated", "data": {
+                    "id": "00000000-0000-0000-0000-0000000000b3", "ip_hostname": "147.185.221.17", "static_ip4": "147.185.221.17",
+                    "static_ip6": "2602:fbaf:0:1::11", "assigned_domain": "zzz.gl.joinmc.link", "assigned_srv": "zzz.gl.joinmc.link",
+                    "tunnel_ip": "147.185.221.17", "port_start": 25565, "port_end": 25566,
+                    "assignment": { "type": "shared-ip" }, "ip_type": "both", "region": "global"
+                } },
+                "origin": { "type": "default", "data": { "local_ip": "127.0.0.1", "local_port": 25565, "agent_id": "00000000-0000-0000-0000-0000000000aa" } },
+                "domain": { "id": "00000000-0000-0000-0000-0000000000d3", "name": "mc", "is_external": false, "parent": "example.playit.plus", "source": "from-tunnel" },
+                "firewall_id": null, "ratelimit": { "bytes_per_second": null, "packets_per_second": null },
+                "active": true, "disabled_reason": null, "region": "global", "expire_notice": null, "proxy_protocol": null
+            },
+            {
+                "id": "00000000-0000-0000-0000-000000000002",
+                "tunnel_type": null, "created_at": "2024-01-02T10:00:00Z", "name": "web",
+                "port_type": "tcp", "port_count": 1,
+                "alloc": { "status": "allocated", "data": {
+                    "id": "00000000-0000-0000-0000-0000000000b2", "ip_hostname": "147.185.221.18", "static_ip4": "147.185.221.18",
+                    "static_ip6": "2602:fbaf:0:1::12", "assigned_domain": "nine-web.gl.at.ply.gg", "assigned_srv": null,
+                    "tunnel_ip": "147.185.221.18", "port_start": 4021, "port_end": 4022,
+                    "assignment": { "type": "shared-ip" }, "ip_type": "both", "region": "global"
+                } },
+                "origin": { "type": "default", "data": { "local_ip": "127.0.0.1", "local_port": 80, "agent_id": "00000000-0000-0000-0000-0000000000aa" } },
+                "domain": null, "firewall_id": null, "ratelimit": { "bytes_per_second": null, "packets_per_second": null },
+                "active": true, "disabled_reason": null, "region": "global", "expire_notice": null, "proxy_protocol": null
+            },
+            {
+                "id": "00000000-0000-0000-0000-000000000001",
+                "tunnel_type": null, "created_at": "2024-01-03T10:00:00Z", "name": "api",
+                "port_type": "tcp", "port_count": 1,
+                "alloc": { "status": "allocated", "data": {
+                    "id": "00000000-0000-0000-0000-0000000000b1", "ip_hostname": "147.185.221.19", "static_ip4": "147.185.221.19",
+                    "static_ip6": "2602:fbaf:0:1::13", "assigned_domain": "aaa.gl.at.ply.gg", "assigned_srv": null,
+                    "tunnel_ip": "147.185.221.19", "port_start": 5000, "port_end": 5001,
+                    "assignment": { "type": "shared-ip" }, "ip_type": "both", "region": "global"
+                } },
+                "origin": { "type": "default", "data": { "local_ip": "127.0.0.1", "local_port": 3000, "agent_id": "00000000-0000-0000-0000-0000000000aa" } },
+                "domain": { "id": "00000000-0000-0000-0000-0000000000d1", "name": "play.example.com", "is_external": true, "parent": null, "source": "from-tunnel" },
+                "firewall_id": null, "ratelimit": { "bytes_per_second": null, "packets_per_second": null },
+                "active": true, "disabled_reason": null, "region": "global", "expire_notice": null, "proxy_protocol": null
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_sort_tunnel_list() {
+        let mut response: serde_json::Value = serde_json::from_str(TUNNELS_LIST_FIXTURE).unwrap();
+
+        sort_tunnel_list(&mut response);
+        let names: Vec<&str> = response["tunnels"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        /* mc.example.playit.plus, nine-web.gl.at.ply.gg, play.example.com, then the pending tunnel */
+        assert_eq!(names, vec!["survival", "web", "api", "new"]);
+        assert_eq!(response["tcp_alloc"]["claimed"], 3);
+    }
+
+    #[test]
+    fn test_list_regions() {
+        /* fails to compile when a region is added, keep ALLOCATION_REGIONS and PLAYIT_REGIONS in sync */
+        for region in ALLOCATION_REGIONS {
+            match region {
+                AllocationRegion::SmartGlobal | AllocationRegion::Global | AllocationRegion::NorthAmerica
+                | AllocationRegion::Europe | AllocationRegion::Asia | AllocationRegion::India
+                | AllocationRegion::SouthAmerica => {}
+            }
+        }
+
+        for region in PLAYIT_REGIONS {
+            match region {
+                PlayitRegion::Anycast | PlayitRegion::Global | PlayitRegion::NorthAmerica
+                | PlayitRegion::Europe | PlayitRegion::Asia | PlayitRegion::India
+                | PlayitRegion::SouthAmerica => {}
+            }
+        }
+
+        let listed = list_regions();
+        let (allocation, tunnel) = listed.split_once("\n\n").unwrap();
+        assert_eq!(allocation.lines().count(), ALLOCATION_REGIONS.len() + 1);
+        assert!(allocation.contains("\nsmart-global\tSmartGlobal\n"));
+        assert!(allocation.contains("\nnorth-america\tNorthAmerica\n"));
+        assert_eq!(tunnel.lines().count(), PLAYIT_REGIONS.len() + 1);
+        assert!(tunnel.contains("\n0\tAnycast\n"));
+        assert!(tunnel.ends_with("\n6\tSouthAmerica\n"));
+    }
+
+    #[test]
+    fn test_split_mapping_override_routing() {
+        let tunnel_ip = IpAddr::V4(Ipv4Addr::new(147, 185, 221, 1));
+        let match_ip = || MatchIp { ip_number: 1, region_id: None };
+        let port = PortRange { from: 5000, to: 5001 };
+
+        let lookup = LookupWithOverrides(vec![
+            MappingOverride {
+                tunnel_id: Uuid::from_u128(1),
+                match_ip: match_ip(),
+                proto: PortType::Tcp,
+                port: port.clone(),
+                local_addr: "127.0.0.1:7777".parse().unwrap(),
+                alt_local_addr: Some("[::1]:7777".parse().unwrap()),
+                proxy_protocol: Some(ProxyProtocol::ProxyProtocolV1),
+            },
+            MappingOverride {
+                tunnel_id: Uuid::from_u128(1),
+                match_ip: match_ip(),
+                proto: PortType::Udp,
+                port: port.clone(),
+                local_addr: "127.0.0.1:7778".parse().unwrap(),
+                alt_local_addr: None,
+                proxy_protocol: None,
+            },
+        ]);
+
+        let tcp = lookup.lookup(tunnel_ip, 5000, PortType::Tcp).unwrap().value;
+        let udp = lookup.lookup(tunnel_ip, 5000, PortType::Udp).unwrap().value;
+
+        assert_eq!(tcp.host_addr.port(), 7777);
+        assert_eq!(udp.host_addr.port(), 7778);
+        assert_eq!(tcp.proxy_protocol, Some(ProxyProtocol::ProxyProtocolV1));
+        assert_eq!(udp.proxy_protocol, None);
+
+        let ip6_client: IpAddr = "2001:db8::1".parse().unwrap();
+        let matching = LocalAddrSelection { match_client_family: true, ipv6_first: false };
+        assert_eq!(tcp.select_host_addr(ip6_client, matching), "[::1]:7777".parse::<SocketAddr>().unwrap());
+        assert_eq!(udp.select_host_addr(ip6_client, matching), "127.0.0.1:7778".parse::<SocketAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_claim_exchange_terminal_errors() {
+        assert!(claim_exchange_terminal_error(&ClaimExchangeError::NotAccepted).is_none());
+        assert!(claim_exchange_terminal_error(&ClaimExchangeError::NotSetup).is_none());
+        assert!(matches!(claim_exchange_terminal_error(&ClaimExchangeError::UserRejected), Some(CliError::AgentClaimRejected)));
+        assert!(matches!(claim_exchange_terminal_error(&ClaimExchangeError::CodeExpired), Some(CliError::ClaimCodeExpired)));
+        assert!(matches!(claim_exchange_terminal_error(&ClaimExchangeError::CodeNotFound), Some(CliError::ClaimCodeNotFound)));
+    }
+
+    #[test]
+    fn test_claim_details_error_messages() {
+        let errors = [
+            ClaimDetailsError::AlreadyClaimed,
+            ClaimDetailsError::AlreadyRejected,
+            ClaimDetailsError::ClaimExpired,
+            ClaimDetailsError::DifferentOwner,
+            ClaimDetailsError::WaitingForAgent,
+            ClaimDetailsError::InvalidCode,
+        ];
+
+        let messages: HashSet<&str> = errors.iter().map(|error| claim_details_error_message(*error)).collect();
+        assert_eq!(messages.len(), errors.len());
+        assert!(claim_details_error_message(ClaimDetailsError::ClaimExpired).contains("expired"));
+    }
+}
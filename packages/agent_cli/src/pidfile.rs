@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/*
+ * --pidfile support for running under init scripts and supervisors.
+ *
+ * The pid is written to a temp file next to the target and renamed into place
+ * so readers never see a partial pid. Startup is refused when the recorded
+ * process is still alive unless --pidfile_takeover is set. The file is removed
+ * on clean shutdown, including the Ctrl+C exit paths which don't run Drop,
+ * and only if it still holds our pid.
+ */
+
+static ACTIVE_PIDFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum PidFileError {
+    AlreadyRunning(u32),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for PidFileError {
+    fn from(e: std::io::Error) -> Self {
+        PidFileError::Io(e)
+    }
+}
+
+pub struct PidFileGuard {
+    _private: (),
+}
+
+impl Drop for PidFileGuard {
+    fn drop(&mut self) {
+        release_pidfile();
+    }
+}
+
+pub fn acquire_pidfile(path: &Path, takeover: bool) -> Result<PidFileGuard, PidFileError> {
+    let pid = std::process::id();
+
+    if let Some(recorded) = read_pid(path)? {
+        if recorded != pid && process_alive(recorded) {
+            if !takeover {
+                return Err(PidFileError::AlreadyRunning(recorded));
+            }
+            tracing::warn!(pid = recorded, path = %path.display(), "taking over pidfile of running process");
+        }
+    }
+
+    write_pid(path, pid)?;
+    ACTIVE_PIDFILE.lock().unwrap().replace(path.to_path_buf());
+
+    Ok(PidFileGuard { _private: () })
+}
+
+/* safe to call more than once */
+pub fn release_pidfile() {
+    let Some(path) = ACTIVE_PIDFILE.lock().unwrap().take() else { return };
+
+    /* another process took over, leave their pid in place */
+    match read_pid(&path) {
+        Ok(Some(pid)) if pid == std::process::id() => {}
+        _ => return,
+    }
+
+    if let Err(error) = std::fs::remove_file(&path) {
+        tracing::error!(?error, path = %path.display(), "failed to remove pidfile");
+    }
+}
+
+fn read_pid(path: &Path) -> std::io::Result<Option<u32>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim().parse::<u32>().ok()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn write_pid(path: &Path, pid: u32) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "pidfile path has no file name"))?
+        .to_os_string();
+    tmp_name.push(format!(".{}.tmp", pid));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    std::fs::write(&tmp_path, format!("{}\n", pid))?;
+    if let Err(error) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+    if pid <= 0 {
+        return false;
+    }
+
+    /* signal 0 only checks the process exists, EPERM means it exists under another user */
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/* no cheap check without extra dependencies, treat recorded pids as stale */
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::{acquire_pidfile, read_pid, release_pidfile};
+
+    #[test]
+    fn test_pidfile_lifecycle() {
+        let path = std::env::temp_dir().join(format!("playit-test-{}.pid", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        /* stale pid from a process that no longer exists */
+        std::fs::write(&path, "999999999\n").unwrap();
+        let guard = acquire_pidfile(&path, false).unwrap();
+        assert_eq!(read_pid(&path).unwrap(), Some(std::process::id()));
+        drop(guard);
+        assert!(!path.exists());
+
+        /* pid 1 is always alive */
+        #[cfg(unix)]
+        {
+            std::fs::write(&path, "1\n").unwrap();
+            assert!(matches!(acquire_pidfile(&path, false), Err(super::PidFileError::AlreadyRunning(1))));
+            assert_eq!(read_pid(&path).unwrap(), Some(1));
+
+            let _guard = acquire_pidfile(&path, true).unwrap();
+            assert_eq!(read_pid(&path).unwrap(), Some(std::process::id()));
+            release_pidfile();
+            assert!(!path.exists());
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use clap::ArgMatches;
@@ -6,15 +7,19 @@ use playit_api_client::{
     PlayitApi,
 };
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 
-use crate::{claim_exchange, claim_generate, ui::UI, CliError, API_BASE};
+use crate::{claim_exchange, claim_generate, ClaimPollIntervals, ui::UI, util::ConfigFormat, CliError, API_BASE};
 
 pub struct PlayitSecret {
     secret: RwLock<Option<String>>,
     path: Option<String>,
+    /* --config_format, otherwise detected from the path's extension */
+    format: Option<ConfigFormat>,
     allow_path_read: bool,
     wait_for_path: bool,
+    claim_poll: ClaimPollIntervals,
 }
 
 impl PlayitSecret {
@@ -56,11 +61,23 @@ impl PlayitSecret {
         self
     }
 
+    pub fn reads_from_path(&self) -> bool {
+        self.allow_path_read
+    }
+
     pub fn get_path(&self) -> Option<&str> {
         let path = self.path.as_ref()?;
         Some(path.as_str())
     }
 
+    pub fn get_format(&self) -> Option<ConfigFormat> {
+        self.format
+    }
+
+    pub fn set_claim_poll(&mut self, claim_poll: ClaimPollIntervals) {
+        self.claim_poll = claim_poll;
+    }
+
     pub async fn ensure_valid(&mut self, ui: &mut UI) -> Result<&mut Self, CliError> {
         let api = match self.create_api().await {
             Ok(v) => v,
@@ -140,7 +157,7 @@ impl PlayitSecret {
         }
 
         let claim_code = claim_generate();
-        let secret = claim_exchange(ui, &claim_code, AgentType::Assignable, 0).await?;
+        let secret = claim_exchange(ui, &claim_code, AgentType::Assignable, 0, 0, self.claim_poll).await?;
 
         {
             let mut lock = self.secret.write().await;
@@ -158,13 +175,13 @@ impl PlayitSecret {
             .ok_or(CliError::SecretFilePathMissing)?
             .trim();
 
-        let content = if path.ends_with(".toml") {
-            toml::to_string(&OldConfig { secret_key: secret }).unwrap()
-        } else {
-            secret
+        /* without a known format the file only holds the secret */
+        let content = match self.format.or_else(|| ConfigFormat::from_path(path)) {
+            Some(format) => format.serialize(&OldConfig { secret_key: secret }),
+            None => secret,
         };
 
-        if let Err(error) = tokio::fs::write(path, &content).await {
+        if let Err(error) = write_secret_file(path, &content).await {
             ui.write_error(format!("failed to save secret, path: {}", path), &error).await;
             tokio::time::sleep(Duration::from_secs(5)).await;
             return Err(CliError::SecretFileWriteError(error));
@@ -195,24 +212,17 @@ impl PlayitSecret {
 
         let mut lock = self.secret.write().await;
 
+        let secret = Self::read_secret_file(file_path, self.format).await?;
+        lock.replace(secret.clone());
+        Ok(secret)
+    }
+
+    pub async fn read_secret_file(file_path: &str, format: Option<ConfigFormat>) -> Result<String, CliError> {
         let content = tokio::fs::read_to_string(file_path)
             .await
             .map_err(|_| CliError::SecretFileLoadError)?;
-        let trimmed = content.trim();
-
-        if hex::decode(trimmed).is_err() {
-            let config =
-                toml::from_str::<OldConfig>(&content).map_err(|_| CliError::MalformedSecret)?;
-            let trimmed = config.secret_key.trim();
 
-            hex::decode(trimmed).map_err(|_| CliError::MalformedSecret)?;
-
-            lock.replace(trimmed.to_string());
-            Ok(trimmed.to_string())
-        } else {
-            lock.replace(trimmed.to_string());
-            Ok(trimmed.to_string())
-        }
+        parse_secret_file(&content, format.or_else(|| ConfigFormat::from_path(file_path)))
     }
 
     pub async fn from_args(matches: &ArgMatches) -> Self {
@@ -236,8 +246,10 @@ impl PlayitSecret {
         PlayitSecret {
             secret: RwLock::new(secret),
             path,
+            format: matches.get_one::<String>("config_format").and_then(|format| format.parse().ok()),
             allow_path_read,
             wait_for_path: matches.get_flag("secret_wait"),
+            claim_poll: ClaimPollIntervals::default(),
         }
     }
 
@@ -246,8 +258,10 @@ impl PlayitSecret {
         PlayitSecret {
             secret: RwLock::new(None),
             path: Some("/etc/playit/playit.toml".to_string()),
+            format: None,
             allow_path_read: true,
             wait_for_path: false,
+            claim_poll: ClaimPollIntervals::default(),
         }
     }
 }
@@ -256,3 +270,157 @@ impl PlayitSecret {
 struct OldConfig {
     secret_key: String,
 }
+
+/* either only the hex secret or a config with secret_key, TOML unless another format is known */
+fn parse_secret_file(content: &str, format: Option<ConfigFormat>) -> Result<String, CliError> {
+    let trimmed = content.trim();
+    if hex::decode(trimmed).is_ok() {
+        return Ok(trimmed.to_string());
+    }
+
+    let config = format.unwrap_or(ConfigFormat::Toml)
+        .deserialize::<OldConfig>(content)
+        .ok_or(CliError::MalformedSecret)?;
+    let trimmed = config.secret_key.trim();
+
+    hex::decode(trimmed).map_err(|_| CliError::MalformedSecret)?;
+    Ok(trimmed.to_string())
+}
+
+/*
+ * The secret is written to a temp file in the same directory, fsynced and
+ * renamed over the target. A crash at any point leaves either the old file
+ * or the new one, never a truncated playit.toml. A temp file left behind by
+ * a crash is replaced by the next write. The new file keeps the permissions
+ * of the one it replaces, a new secret file is only readable by its owner
+ * (unix).
+ */
+async fn write_secret_file(path: &str, content: &str) -> std::io::Result<()> {
+    let path = Path::new(path);
+    let tmp_path = write_secret_tmp(path, content).await?;
+
+    if let Err(error) = tokio::fs::rename(&tmp_path, path).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(error);
+    }
+
+    /* persist the rename, not supported on every platform so best effort */
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(dir) = tokio::fs::File::open(dir).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+
+    Ok(())
+}
+
+fn secret_tmp_path(path: &Path) -> std::io::Result<PathBuf> {
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "secret path has no file name"))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    Ok(path.with_file_name(tmp_name))
+}
+
+async fn write_secret_tmp(path: &Path, content: &str) -> std::io::Result<PathBuf> {
+    let tmp_path = secret_tmp_path(path)?;
+
+    /* a stale temp file would keep its own permissions instead of the mode below */
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+
+    let result = async {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&tmp_path).await?;
+        if let Ok(existing) = tokio::fs::metadata(path).await {
+            file.set_permissions(existing.permissions()).await?;
+        }
+
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await
+    }.await;
+
+    if let Err(error) = result {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(error);
+    }
+
+    Ok(tmp_path)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::ConfigFormat;
+
+    use super::{parse_secret_file, write_secret_file, write_secret_tmp, OldConfig, PlayitSecret};
+
+    #[tokio::test]
+    async fn test_crash_before_rename_keeps_secret() {
+        let dir = std::env::temp_dir().join(format!("playit-secret-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("playit.toml");
+        let path_str = path.to_str().unwrap();
+
+        let old = "secret_key = \"aabbccdd\"\n";
+        write_secret_file(path_str, old).await.unwrap();
+
+        /* killed after writing the temp file but before the rename */
+        let tmp_path = write_secret_tmp(&path, "secret_key = \"0011").await.unwrap();
+        assert!(tmp_path.exists());
+        assert_eq!(PlayitSecret::read_secret_file(path_str, None).await.unwrap(), "aabbccdd");
+
+        /* next start replaces the stale temp file */
+        write_secret_file(path_str, "secret_key = \"00112233\"\n").await.unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(PlayitSecret::read_secret_file(path_str, None).await.unwrap(), "00112233");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_keeps_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("playit-secret-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("playit.toml");
+        let path_str = path.to_str().unwrap();
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write_secret_file(path_str, "secret_key = \"aabbccdd\"\n").await.unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        /* set by an admin, kept across saves */
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_secret_file(path_str, "secret_key = \"00112233\"\n").await.unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(PlayitSecret::read_secret_file(path_str, None).await.unwrap(), "00112233");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_secret_config_formats() {
+        let secret = "00112233aabbccdd";
+
+        for format in [ConfigFormat::Toml, ConfigFormat::Json, ConfigFormat::Yaml] {
+            let content = format.serialize(&OldConfig { secret_key: secret.to_string() });
+            assert_eq!(parse_secret_file(&content, Some(format)).unwrap(), secret, "{:?}", format);
+        }
+
+        /* plain secret works with any format, TOML is the default */
+        assert_eq!(parse_secret_file("00112233aabbccdd\n", Some(ConfigFormat::Yaml)).unwrap(), secret);
+        assert_eq!(parse_secret_file("secret_key = \"00112233aabbccdd\"", None).unwrap(), secret);
+        assert!(parse_secret_file("{\"secret_key\": \"00112233aabbccdd\"}", Some(ConfigFormat::Toml)).is_err());
+
+        assert_eq!(ConfigFormat::from_path("/etc/playit/playit.yml"), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::from_path("playit.json"), Some(ConfigFormat::Json));
+        assert_eq!(ConfigFormat::from_path("secret.txt"), None);
+    }
+}
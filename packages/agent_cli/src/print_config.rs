@@ -0,0 +1,128 @@
+use clap::ArgMatches;
+use playit_api_client::api::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{autorun::{AutorunSettings, TunnelEntry}, playit_secret::PlayitSecret, CliError, API_BASE};
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Serialize)]
+pub struct EffectiveConfig {
+    pub version: String,
+    pub api_base: String,
+    pub platform: Platform,
+    pub secret: SecretConfig,
+    pub logging: LoggingConfig,
+    pub tunnel_filter: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnels_error: Option<String>,
+    pub tunnels: Vec<TunnelConfig>,
+}
+
+#[derive(Serialize)]
+pub struct SecretConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub source: &'static str,
+    pub wait_for_path: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LoggingConfig {
+    pub stdout: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_max_size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_keep: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TunnelConfig {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub public_address: String,
+    pub port_type: PortType,
+    pub port_count: u16,
+    pub local_address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocol>,
+    pub disabled: bool,
+    pub included_by_filter: bool,
+}
+
+pub async fn resolve_config(matches: &ArgMatches, secret: &PlayitSecret, settings: &AutorunSettings, platform: Platform) -> EffectiveConfig {
+    let secret_res = secret.get().await;
+
+    let mut config = EffectiveConfig {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_base: API_BASE.to_string(),
+        platform,
+        secret: SecretConfig {
+            path: secret.get_path().map(|v| v.to_string()),
+            source: if secret.reads_from_path() { "path" } else { "argument" },
+            wait_for_path: matches.get_flag("secret_wait"),
+            value: secret_res.as_ref().ok().map(|_| REDACTED),
+            error: secret_res.as_ref().err().map(|error| format!("{:?}", error)),
+        },
+        logging: LoggingConfig {
+            stdout: matches.get_flag("stdout"),
+            log_path: matches.get_one::<String>("log_path").cloned(),
+            log_max_size: matches.get_one::<String>("log_max_size").cloned(),
+            log_keep: matches.get_one::<String>("log_keep").cloned(),
+        },
+        tunnel_filter: matches.get_many::<String>("tunnel_filter")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default(),
+        tunnels_error: None,
+        tunnels: vec![],
+    };
+
+    let tunnels = match secret.create_api().await {
+        Ok(api) => api.agents_rundata().await.map_err(CliError::from),
+        Err(error) => Err(error),
+    };
+
+    match tunnels {
+        Ok(data) => {
+            for tunnel in data.tunnels {
+                let name = tunnel.name.clone();
+                let disabled = tunnel.disabled.is_some();
+                let included_by_filter = settings.tunnel_filter.matches(tunnel.id, name.as_deref());
+                let entry = TunnelEntry::new(tunnel);
+
+                config.tunnels.push(TunnelConfig {
+                    id: entry.tunnel_id,
+                    name,
+                    public_address: entry.pub_address,
+                    port_type: entry.port_type,
+                    port_count: entry.to_port - entry.from_port,
+                    local_address: entry.local_start_address.to_string(),
+                    proxy_protocol: entry.proxy_protocol,
+                    disabled,
+                    included_by_filter,
+                });
+            }
+        }
+        Err(error) => {
+            config.tunnels_error = Some(format!("{:?}", error));
+        }
+    }
+
+    config
+}
+
+pub fn format_config(config: &EffectiveConfig, format: &str) -> Result<String, CliError> {
+    match format {
+        "json" => Ok(serde_json::to_string_pretty(config).unwrap()),
+        "toml" => Ok(toml::to_string_pretty(config).unwrap()),
+        _ => Err(CliError::InvalidConfigFormat),
+    }
+}
@@ -1,8 +1,12 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use lazy_static::lazy_static;
+use playit_agent_core::network::connection_stats::StatsReset;
+use playit_agent_core::network::udp::clients::UdpFlowDumpTrigger;
 use tokio::signal::ctrl_c;
 
+use crate::pidfile::release_pidfile;
+
 lazy_static! {
     static ref SIGNAL: SignalHandle = SignalHandle::setup();
 }
@@ -71,11 +75,13 @@ impl SignalHandle {
 
                 if inner.confirm_close.load(Ordering::SeqCst) == 0 {
                     tracing::info!("no Ctrl+C handler set, closing program");
+                    release_pidfile();
                     std::process::exit(0);
                 }
 
                 if inner.close_requested.swap(true, Ordering::SeqCst) {
                     tracing::info!("Close requested twice, exiting program");
+                    release_pidfile();
                     std::process::exit(0);
                 }
             }
@@ -84,3 +90,53 @@ impl SignalHandle {
         signal
     }
 }
+
+/* `kill -USR1 <pid>` logs the UDP flow table, for reports of UDP that stopped working */
+#[cfg(unix)]
+pub fn dump_udp_flows_on_signal(trigger: UdpFlowDumpTrigger) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr1 = match signal(SignalKind::user_defined1()) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::error!(?error, "cannot listen for SIGUSR1");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            tracing::info!("received SIGUSR1, dumping UDP flow table");
+            trigger.request();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn dump_udp_flows_on_signal(_trigger: UdpFlowDumpTrigger) {
+}
+
+/* `kill -USR2 <pid>` zeroes the cumulative connection counters without a restart */
+#[cfg(unix)]
+pub fn reset_stats_on_signal(stats: StatsReset) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::error!(?error, "cannot listen for SIGUSR2");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while usr2.recv().await.is_some() {
+            tracing::info!("received SIGUSR2, resetting connection stats");
+            stats.reset();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reset_stats_on_signal(_stats: StatsReset) {
+}
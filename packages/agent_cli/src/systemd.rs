@@ -0,0 +1,110 @@
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use playit_agent_core::agent_control::health::ControlHealth;
+
+/*
+ * systemd socket activation and readiness notification (Linux only).
+ *
+ * With a .socket unit systemd binds the listener and passes it as fd 3
+ * (LISTEN_FDS / LISTEN_PID), the agent adopts it for the health listener
+ * instead of binding, so the port can be privileged and stays open across
+ * restarts. With Type=notify, READY=1 is sent over NOTIFY_SOCKET once the
+ * control channel registers.
+ */
+
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/* takes the first passed socket, the variables are cleared so children don't adopt it too */
+pub fn take_activation_listener() -> Option<std::net::TcpListener> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let count = std::env::var("LISTEN_FDS").ok()?;
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if pid.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+
+    let count = count.parse::<i32>().ok()?;
+    if count < 1 {
+        return None;
+    }
+    if 1 < count {
+        tracing::warn!(count, "only the first socket passed by systemd is used");
+    }
+
+    let fd = SD_LISTEN_FDS_START;
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 {
+            tracing::error!(error = ?std::io::Error::last_os_error(), "passed socket is not a valid fd");
+            return None;
+        }
+        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+    }
+
+    Some(unsafe { std::net::TcpListener::from_raw_fd(fd) })
+}
+
+pub fn notify(state: &str) -> std::io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return Ok(false) };
+    let path = path.to_string_lossy();
+
+    /* leading @ is an abstract socket */
+    let addr = match path.strip_prefix('@') {
+        Some(name) => UnixSocketAddr::from_abstract_name(name.as_bytes())?,
+        None => UnixSocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/* READY=1 once after the first registration, no-op outside of systemd */
+pub fn notify_ready_when_registered(health: ControlHealth) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        while !health.is_registered() {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        match notify("READY=1\nSTATUS=control channel registered") {
+            Ok(_) => tracing::info!("notified systemd of readiness"),
+            Err(error) => tracing::error!(?error, "failed to notify systemd"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::notify;
+
+    #[test]
+    fn test_notify() {
+        let path = std::env::temp_dir().join(format!("playit-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let rx = UnixDatagram::bind(&path).unwrap();
+
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        assert!(notify("READY=1").unwrap());
+        std::env::remove_var("NOTIFY_SOCKET");
+
+        let mut buf = [0u8; 64];
+        let len = rx.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        assert!(!notify("READY=1").unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+use playit_api_client::api::{AgentPendingTunnel, AgentTunnel, PortType};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Env,
+    Hosts,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "env" => Some(ExportFormat::Env),
+            "hosts" => Some(ExportFormat::Hosts),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedTunnel {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub domain: String,
+    pub port: u16,
+    pub port_count: u16,
+    pub proto: PortType,
+}
+
+impl ExportedTunnel {
+    pub fn from_tunnel(tunnel: &AgentTunnel) -> Self {
+        ExportedTunnel {
+            id: tunnel.id,
+            name: tunnel.name.clone(),
+            domain: tunnel.custom_domain.clone().unwrap_or_else(|| tunnel.assigned_domain.clone()),
+            port: tunnel.port.from,
+            port_count: tunnel.port.to - tunnel.port.from,
+            proto: tunnel.proto,
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.domain, self.port)
+    }
+}
+
+/* "my server #1" => "MY_SERVER_1", unnamed tunnels use their id */
+pub fn env_identifier(tunnel: &ExportedTunnel) -> String {
+    let source = match tunnel.name.as_deref() {
+        Some(name) if name.chars().any(|c| c.is_ascii_alphanumeric()) => name.to_string(),
+        _ => tunnel.id.simple().to_string(),
+    };
+
+    let mut ident = String::from("TUNNEL_");
+    let mut last_underscore = true;
+
+    for c in source.chars() {
+        if c.is_ascii_alphanumeric() {
+            ident.push(c.to_ascii_uppercase());
+            last_underscore = false;
+        } else if !last_underscore {
+            ident.push('_');
+            last_underscore = true;
+        }
+    }
+
+    while ident.ends_with('_') {
+        ident.pop();
+    }
+
+    ident
+}
+
+pub fn export_env(tunnels: &[ExportedTunnel]) -> String {
+    let mut used = HashSet::new();
+    let mut out = String::new();
+
+    for tunnel in tunnels {
+        let base = env_identifier(tunnel);
+
+        /* names can collide after sanitizing, keep every tunnel */
+        let mut ident = base.clone();
+        let mut suffix = 2;
+        while !used.insert(ident.clone()) {
+            ident = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+
+        writeln!(out, "{}={}", ident, tunnel.address()).unwrap();
+    }
+
+    out
+}
+
+/* resolved holds the ip of each tunnel's domain, None if it could not be resolved */
+pub fn export_hosts(tunnels: &[(ExportedTunnel, Option<IpAddr>)]) -> String {
+    let mut out = String::new();
+
+    for (tunnel, ip) in tunnels {
+        match ip {
+            Some(ip) => writeln!(out, "{}\t{}", ip, tunnel.domain).unwrap(),
+            None => writeln!(out, "# {} could not be resolved", tunnel.domain).unwrap(),
+        }
+    }
+
+    out
+}
+
+/* pending tunnels have no address yet, env and hosts output lists them as comments */
+pub fn export_pending_comments(pending: &[AgentPendingTunnel]) -> String {
+    let mut out = String::new();
+
+    for tunnel in pending {
+        let name = tunnel.name.clone().unwrap_or_else(|| tunnel.id.to_string());
+
+        if tunnel.is_disabled {
+            writeln!(out, "# {} pending allocation (disabled)", name).unwrap();
+        } else {
+            writeln!(out, "# {} pending allocation", name).unwrap();
+        }
+    }
+
+    out
+}
+
+pub fn export_json(tunnels: &[ExportedTunnel]) -> String {
+    serde_json::to_string_pretty(tunnels).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::{AgentPendingTunnel, PortType};
+    use uuid::Uuid;
+
+    use super::{env_identifier, export_env, export_hosts, export_pending_comments, ExportedTunnel};
+
+    fn tunnel(id: u128, name: Option<&str>, domain: &str, port: u16) -> ExportedTunnel {
+        ExportedTunnel {
+            id: Uuid::from_u128(id),
+            name: name.map(|v| v.to_string()),
+            domain: domain.to_string(),
+            port,
+            port_count: 1,
+            proto: PortType::Tcp,
+        }
+    }
+
+    #[test]
+    fn test_env_identifier() {
+        assert_eq!(env_identifier(&tunnel(1, Some("minecraft"), "a.gl.joinmc.link", 1)), "TUNNEL_MINECRAFT");
+        assert_eq!(env_identifier(&tunnel(1, Some("my server #1"), "a", 1)), "TUNNEL_MY_SERVER_1");
+        assert_eq!(env_identifier(&tunnel(1, Some("--web-- "), "a", 1)), "TUNNEL_WEB");
+        assert_eq!(env_identifier(&tunnel(1, Some("über"), "a", 1)), "TUNNEL_BER");
+        assert_eq!(env_identifier(&tunnel(0xab, Some("!!"), "a", 1)), "TUNNEL_000000000000000000000000000000AB");
+        assert_eq!(env_identifier(&tunnel(0xab, None, "a", 1)), "TUNNEL_000000000000000000000000000000AB");
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let tunnels = vec![
+            tunnel(1, Some("web"), "a.playit.gg", 443),
+            tunnel(2, Some("web!"), "b.playit.gg", 8080),
+        ];
+
+        assert_eq!(export_env(&tunnels), "TUNNEL_WEB=a.playit.gg:443\nTUNNEL_WEB_2=b.playit.gg:8080\n");
+
+        let resolved = vec![
+            (tunnels[0].clone(), Some("147.185.221.1".parse().unwrap())),
+            (tunnels[1].clone(), None),
+        ];
+        assert_eq!(export_hosts(&resolved), "147.185.221.1\ta.playit.gg\n# b.playit.gg could not be resolved\n");
+    }
+
+    #[test]
+    fn test_export_pending() {
+        let pending = vec![
+            AgentPendingTunnel { id: Uuid::from_u128(1), name: Some("web".to_string()), proto: PortType::Tcp, port_count: 1, tunnel_type: None, is_disabled: false },
+            AgentPendingTunnel { id: Uuid::from_u128(2), name: None, proto: PortType::Udp, port_count: 1, tunnel_type: None, is_disabled: true },
+        ];
+
+        assert_eq!(
+            export_pending_comments(&pending),
+            "# web pending allocation\n# 00000000-0000-0000-0000-000000000002 pending allocation (disabled)\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use uuid::Uuid;
+
+#[derive(Debug, Default, Clone)]
+pub struct TunnelFilter {
+    ids: Vec<Uuid>,
+    name_globs: Vec<String>,
+}
+
+impl TunnelFilter {
+    pub fn new<'a, I: IntoIterator<Item = &'a String>>(values: I) -> Self {
+        let mut filter = TunnelFilter::default();
+
+        for value in values {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            match value.parse::<Uuid>() {
+                Ok(id) => filter.ids.push(id),
+                Err(_) => filter.name_globs.push(value.to_string()),
+            }
+        }
+
+        filter
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.name_globs.is_empty()
+    }
+
+    pub fn matches(&self, tunnel_id: Uuid, name: Option<&str>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        if self.ids.contains(&tunnel_id) {
+            return true;
+        }
+
+        let Some(name) = name else { return false };
+        self.name_globs.iter().any(|glob| glob_matches(glob, name))
+    }
+}
+
+/* supports "*" for any sequence and "?" for a single character */
+fn glob_matches(glob: &str, value: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let mut g = 0;
+    let mut v = 0;
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == value[v]) {
+            g += 1;
+            v += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            backtrack = Some((g, v));
+            g += 1;
+        } else if let Some((star_g, star_v)) = backtrack {
+            g = star_g + 1;
+            v = star_v + 1;
+            backtrack = Some((star_g, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("minecraft", "minecraft"));
+        assert!(!glob_matches("minecraft", "minecraft-2"));
+        assert!(glob_matches("minecraft*", "minecraft-2"));
+        assert!(glob_matches("*-2", "minecraft-2"));
+        assert!(glob_matches("mc-?", "mc-1"));
+        assert!(!glob_matches("mc-?", "mc-10"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXXbYYc"));
+        assert!(!glob_matches("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let id = Uuid::from_u128(1);
+        let other = Uuid::from_u128(2);
+        let filter = TunnelFilter::new(&[id.to_string(), "web-*".to_string()]);
+
+        assert!(filter.matches(id, None));
+        assert!(filter.matches(other, Some("web-api")));
+        assert!(!filter.matches(other, Some("minecraft")));
+        assert!(!filter.matches(other, None));
+
+        let empty = TunnelFilter::new(&[]);
+        assert!(empty.matches(other, None));
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use playit_api_client::api::{AgentRunData, AgentTunnelDisabled};
+use serde::Serialize;
+use uuid::Uuid;
+
+/*
+ * Tunnel allocation state as seen in successive agents_rundata results.
+ * Diffing two results gives discrete transitions (pending -> allocated,
+ * allocated -> disabled-by-user, ...) so webhook subscribers can react to
+ * changes without keeping and comparing full snapshots themselves.
+ */
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TunnelState {
+    Pending,
+    PendingDisabled,
+    Allocated,
+    DisabledByUser,
+    DisabledBySystem,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TunnelStateChange {
+    pub tunnel_id: Uuid,
+    /* None for a tunnel created since the last result */
+    pub from: Option<TunnelState>,
+    pub to: TunnelState,
+}
+
+#[derive(Debug, Default)]
+pub struct TunnelStates {
+    states: HashMap<Uuid, TunnelState>,
+}
+
+impl TunnelStates {
+    /* starting point, changes are only reported relative to this */
+    pub fn new(data: &AgentRunData) -> Self {
+        TunnelStates { states: current_states(data) }
+    }
+
+    pub fn update(&mut self, data: &AgentRunData) -> Vec<TunnelStateChange> {
+        let current = current_states(data);
+        let mut changes = Vec::new();
+
+        for (&tunnel_id, &to) in &current {
+            let from = self.states.get(&tunnel_id).copied();
+            if from != Some(to) {
+                changes.push(TunnelStateChange { tunnel_id, from, to });
+            }
+        }
+
+        for (&tunnel_id, &from) in &self.states {
+            if !current.contains_key(&tunnel_id) {
+                changes.push(TunnelStateChange { tunnel_id, from: Some(from), to: TunnelState::Removed });
+            }
+        }
+
+        changes.sort_by_key(|change| change.tunnel_id);
+        self.states = current;
+        changes
+    }
+}
+
+fn current_states(data: &AgentRunData) -> HashMap<Uuid, TunnelState> {
+    let pending = data.pending.iter().map(|tunnel| (tunnel.id, match tunnel.is_disabled {
+        true => TunnelState::PendingDisabled,
+        false => TunnelState::Pending,
+    }));
+
+    let allocated = data.tunnels.iter().map(|tunnel| (tunnel.id, match tunnel.disabled {
+        None => TunnelState::Allocated,
+        Some(AgentTunnelDisabled::ByUser) => TunnelState::DisabledByUser,
+        Some(AgentTunnelDisabled::BySystem) => TunnelState::DisabledBySystem,
+    }));
+
+    pending.chain(allocated).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::{AgentAccountStatus, AgentPendingTunnel, AgentRunData, AgentTunnel, AgentTunnelDisabled, AgentType, PortRange, PortType};
+    use uuid::Uuid;
+
+    use super::{TunnelState, TunnelStateChange, TunnelStates};
+
+    fn pending(id: u128) -> AgentPendingTunnel {
+        AgentPendingTunnel {
+            id: Uuid::from_u128(id),
+            name: None,
+            proto: PortType::Tcp,
+            port_count: 1,
+            tunnel_type: None,
+            is_disabled: false,
+        }
+    }
+
+    fn tunnel(id: u128, disabled: Option<AgentTunnelDisabled>) -> AgentTunnel {
+        AgentTunnel {
+            id: Uuid::from_u128(id),
+            name: None,
+            ip_num: 0,
+            region_num: 0,
+            port: PortRange { from: 25565, to: 25566 },
+            proto: PortType::Tcp,
+            local_ip: "127.0.0.1".parse().unwrap(),
+            local_port: 25565,
+            tunnel_type: None,
+            assigned_domain: "example.playit.gg".to_string(),
+            custom_domain: None,
+            disabled,
+            proxy_protocol: None,
+        }
+    }
+
+    fn data(tunnels: Vec<AgentTunnel>, pending: Vec<AgentPendingTunnel>) -> AgentRunData {
+        AgentRunData {
+            agent_id: Uuid::nil(),
+            agent_type: AgentType::Default,
+            account_status: AgentAccountStatus::Ready,
+            tunnels,
+            pending,
+        }
+    }
+
+    fn change(id: u128, from: Option<TunnelState>, to: TunnelState) -> TunnelStateChange {
+        TunnelStateChange { tunnel_id: Uuid::from_u128(id), from, to }
+    }
+
+    #[test]
+    fn test_tunnel_state_transitions() {
+        let mut states = TunnelStates::new(&data(vec![tunnel(1, None)], vec![pending(2)]));
+        assert_eq!(states.update(&data(vec![tunnel(1, None)], vec![pending(2)])), vec![]);
+
+        let changes = states.update(&data(vec![tunnel(1, Some(AgentTunnelDisabled::ByUser)), tunnel(2, None)], vec![pending(3)]));
+        assert_eq!(changes, vec![
+            change(1, Some(TunnelState::Allocated), TunnelState::DisabledByUser),
+            change(2, Some(TunnelState::Pending), TunnelState::Allocated),
+            change(3, None, TunnelState::Pending),
+        ]);
+
+        let changes = states.update(&data(vec![tunnel(2, None)], vec![pending(3)]));
+        assert_eq!(changes, vec![change(1, Some(TunnelState::DisabledByUser), TunnelState::Removed)]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use playit_agent_core::agent_control::udp_probe::probe_udp;
+use playit_agent_core::agent_control::DualStackUdpSocket;
+
+/*
+ * probe-udp: pings each tunnel server address over UDP and prints a JSON
+ * report so users (and support) can see if UDP makes it through their NAT /
+ * firewall and on which IP family.
+ */
+
+pub const PROBE_ATTEMPTS: usize = 3;
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Serialize)]
+pub struct UdpProbeReport {
+    pub udp_ok: bool,
+    pub working_families: Vec<&'static str>,
+    pub addresses: Vec<UdpProbeAddress>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UdpProbeAddress {
+    pub addr: SocketAddr,
+    pub family: &'static str,
+    pub ok: bool,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+fn family(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv4() {
+        "ipv4"
+    } else {
+        "ipv6"
+    }
+}
+
+pub async fn run_udp_probe(addresses: &[SocketAddr], attempts: usize, timeout: Duration) -> std::io::Result<UdpProbeReport> {
+    let io = DualStackUdpSocket::new().await?;
+    let mut results = Vec::with_capacity(addresses.len());
+
+    for addr in addresses {
+        let (latency, error) = match probe_udp(&io, *addr, attempts, timeout).await {
+            Ok(result) => (result.latency, None),
+            Err(error) => (None, Some(error.to_string())),
+        };
+
+        results.push(UdpProbeAddress {
+            addr: *addr,
+            family: family(addr),
+            ok: latency.is_some(),
+            latency_ms: latency.map(|v| v.as_secs_f64() * 1000.0),
+            error,
+        });
+    }
+
+    Ok(UdpProbeReport::new(results))
+}
+
+impl UdpProbeReport {
+    pub fn new(addresses: Vec<UdpProbeAddress>) -> Self {
+        let mut working_families = Vec::new();
+        for result in addresses.iter().filter(|v| v.ok) {
+            if !working_families.contains(&result.family) {
+                working_families.push(result.family);
+            }
+        }
+
+        UdpProbeReport {
+            udp_ok: !working_families.is_empty(),
+            working_families,
+            addresses,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UdpProbeAddress, UdpProbeReport};
+
+    #[test]
+    fn test_report_families() {
+        let result = |addr: &str, family, ok| UdpProbeAddress {
+            addr: addr.parse().unwrap(),
+            family,
+            ok,
+            latency_ms: ok.then_some(20.0),
+            error: None,
+        };
+
+        let report = UdpProbeReport::new(vec![
+            result("[2602:fbaf::1]:5525", "ipv6", false),
+            result("147.185.221.1:5525", "ipv4", true),
+            result("147.185.221.2:5525", "ipv4", true),
+        ]);
+        assert!(report.udp_ok);
+        assert_eq!(report.working_families, vec!["ipv4"]);
+
+        let report = UdpProbeReport::new(vec![result("147.185.221.1:5525", "ipv4", false)]);
+        assert!(!report.udp_ok);
+        assert!(report.working_families.is_empty());
+    }
+}
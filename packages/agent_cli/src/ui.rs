@@ -5,17 +5,23 @@ use crossterm::{
     event::{self, Event, KeyCode, KeyEvent},
     ExecutableCommand,
     style::{Print, ResetColor},
-    terminal::Clear,
+    terminal::{self, Clear},
 };
 use playit_agent_core::utils::now_milli;
 
 use crate::CliError;
+use crate::pidfile::release_pidfile;
 use crate::signal_handle::get_signal_handle;
 
+/* below this the screen is replaced with a resize notice instead of wrapping into garbage */
+const MIN_TERMINAL_COLS: u16 = 30;
+const MIN_TERMINAL_ROWS: u16 = 6;
+
 pub struct UI {
     auto_answer: Option<bool>,
     last_display: Option<(u64, String)>,
     log_only: bool,
+    quiet: bool,
     wrote_content: bool,
 }
 
@@ -23,11 +29,23 @@ pub struct UI {
 pub struct UISettings {
     pub auto_answer: Option<bool>,
     pub log_only: bool,
+    pub quiet: bool,
 }
 
 impl UI {
     pub fn new(settings: UISettings) -> Self {
-        UI { auto_answer: settings.auto_answer, log_only: settings.log_only, last_display: None, wrote_content: false }
+        UI {
+            auto_answer: settings.auto_answer,
+            log_only: settings.log_only,
+            /* quiet only applies to logs, the terminal UI redraws in place */
+            quiet: settings.quiet && settings.log_only,
+            last_display: None,
+            wrote_content: false,
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
     }
 
     pub async fn write_screen<T: std::fmt::Display>(&mut self, content: T) {
@@ -38,6 +56,7 @@ impl UI {
             match self.yn_question(format!("{}\nClose requested, close program?", content), Some(true)).await {
                 Ok(close) => {
                     if close {
+                        release_pidfile();
                         std::process::exit(0);
                     } else {
                         signal.decline_close();
@@ -60,12 +79,17 @@ impl UI {
             let content = content.to_string();
 
             if let Some((ts, last_render)) = &self.last_display {
-                if now_milli() - *ts < 10_000 && content.eq(last_render) {
+                /* quiet mode never re-logs an unchanged screen */
+                if content.eq(last_render) && (self.quiet || now_milli() - *ts < 10_000) {
                     return;
                 }
             }
 
-            tracing::info!("{}", content.lines().next().unwrap());
+            if self.quiet {
+                tracing::info!("{}", content);
+            } else {
+                tracing::info!("{}", content.lines().next().unwrap());
+            }
             self.last_display = Some((now_milli(), content));
         }
 
@@ -73,6 +97,11 @@ impl UI {
             return;
         }
 
+        let content = match terminal::size() {
+            Ok((cols, rows)) => fit_to_terminal(&content.to_string(), cols, rows),
+            Err(_) => content.to_string(),
+        };
+
         let content_ref = &content;
         let res: std::io::Result<()> = (|| {
             let cleared = if self.wrote_content {
@@ -105,6 +134,12 @@ impl UI {
     }
 
     pub async fn yn_question<T: std::fmt::Display + Send + 'static>(&mut self, question: T, default_yes: Option<bool>) -> Result<bool, CliError> {
+        /* do not wait on keyboard input when running unattended */
+        if let Some(auto) = self.auto_answer {
+            tracing::info!(%question, answer = auto, "auto answering question");
+            return Ok(auto);
+        }
+
         let mut line = String::new();
         let mut count = 0;
 
@@ -162,10 +197,6 @@ impl UI {
             }
         }
 
-        if let Some(auto) = self.auto_answer {
-            return Ok(auto);
-        }
-
         if let Some(default_yes) = default_yes {
             return Ok(default_yes);
         }
@@ -181,3 +212,51 @@ impl UI {
         self.write_screen(format!("Got Error\nMSG: {}\nError: {:?}\n", msg, error)).await
     }
 }
+
+/* clips lines to the terminal width and drops lines that would scroll the screen */
+fn fit_to_terminal(content: &str, cols: u16, rows: u16) -> String {
+    if cols < MIN_TERMINAL_COLS || rows < MIN_TERMINAL_ROWS {
+        return format!("terminal too small ({}x{}), resize to at least {}x{}", cols, rows, MIN_TERMINAL_COLS, MIN_TERMINAL_ROWS)
+            .chars()
+            .take(cols as usize)
+            .collect();
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let max_lines = rows as usize - 1;
+
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i + 1 == max_lines && max_lines < lines.len() {
+            out.push_str(&format!("... {} more lines", lines.len() - i));
+            break;
+        }
+
+        if line.chars().count() <= cols as usize {
+            out.push_str(line);
+        } else {
+            out.extend(line.chars().take(cols as usize - 1));
+            out.push('~');
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::fit_to_terminal;
+
+    #[test]
+    fn test_fit_to_terminal() {
+        let content = "playit (v0.15)\n\nTUNNELS\nsome-name.gl.joinmc.link => 127.0.0.1:25565 (minecraft-java)\nb\nc\nd\ne\n";
+
+        let fit = fit_to_terminal(content, 40, 6);
+        assert_eq!(fit, "playit (v0.15)\n\nTUNNELS\nsome-name.gl.joinmc.link => 127.0.0.1:2~\n... 4 more lines");
+
+        assert_eq!(fit_to_terminal("a\nb\n", 80, 24), "a\nb\n");
+        assert!(fit_to_terminal(content, 20, 3).starts_with("terminal too small"));
+        assert!(fit_to_terminal(content, 20, 3).chars().count() <= 20);
+    }
+}
@@ -1,19 +1,88 @@
+use std::str::FromStr;
+
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-pub async fn load_config<T: DeserializeOwned>(path: &str) -> Option<T> {
-    let data = tokio::fs::read_to_string(path).await.ok()?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
 
-    if path.ends_with(".json") {
-        return serde_json::from_str(&data).ok();
+impl ConfigFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".toml") {
+            return Some(ConfigFormat::Toml);
+        }
+        if path.ends_with(".json") {
+            return Some(ConfigFormat::Json);
+        }
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            return Some(ConfigFormat::Yaml);
+        }
+        None
     }
 
-    if path.ends_with(".toml") {
-        return Some(toml::from_str(&data).unwrap());
+    pub fn serialize<T: Serialize>(self, value: &T) -> String {
+        match self {
+            ConfigFormat::Toml => toml::to_string(value).unwrap(),
+            ConfigFormat::Json => serde_json::to_string_pretty(value).unwrap(),
+            ConfigFormat::Yaml => serde_yaml::to_string(value).unwrap(),
+        }
     }
 
-    if path.ends_with(".yaml") || path.ends_with(".yml") {
-        return serde_yaml::from_str(&data).ok();
+    pub fn deserialize<T: DeserializeOwned>(self, data: &str) -> Option<T> {
+        match self {
+            ConfigFormat::Toml => toml::from_str(data).ok(),
+            ConfigFormat::Json => serde_json::from_str(data).ok(),
+            ConfigFormat::Yaml => serde_yaml::from_str(data).ok(),
+        }
     }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = ();
 
-    None
-}
\ No newline at end of file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => Err(()),
+        }
+    }
+}
+
+pub async fn load_config<T: DeserializeOwned>(path: &str) -> Option<T> {
+    let data = tokio::fs::read_to_string(path).await.ok()?;
+    ConfigFormat::from_path(path)?.deserialize(&data)
+}
+
+/* bytes with an optional K, M or G suffix (powers of 1024), ex. "10M" */
+pub fn parse_byte_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1024),
+        (i, 'm' | 'M') => (&value[..i], 1024 * 1024),
+        (i, 'g' | 'G') => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_byte_size;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096"), Some(4096));
+        assert_eq!(parse_byte_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_byte_size("10M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1 G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size("M"), None);
+        assert_eq!(parse_byte_size("-1"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use playit_agent_core::{agent_control::maintained_control::DisconnectReason, playit_agent::AgentEvent, utils::now_milli};
+use playit_agent_proto::hmac::HmacSha256;
+use playit_api_client::api::{AgentTunnelDisabled, ProtoRegisterError};
+use serde::Serialize;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use uuid::Uuid;
+
+use crate::tunnel_state::{TunnelState, TunnelStateChange};
+
+const MAX_ATTEMPTS: u32 = 5;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    AgentRegistered,
+    ControlDisconnected { reason: String },
+    TunnelDisabled { tunnel_id: Uuid, by_system: bool },
+    TunnelStateChanged { tunnel_id: Uuid, from: Option<TunnelState>, to: TunnelState },
+    AccountBanned,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent_id: Option<Uuid>,
+}
+
+#[derive(Default)]
+pub struct WebhookSettings {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    tx: Sender<WebhookEvent>,
+}
+
+impl WebhookNotifier {
+    pub fn start(url: String, secret: Option<String>, agent_id: Option<Uuid>) -> Self {
+        let (tx, rx) = channel(64);
+        tokio::spawn(deliver_events(rx, url, secret, agent_id));
+        WebhookNotifier { tx }
+    }
+
+    pub fn notify(&self, event: WebhookEvent) {
+        /* never block the agent on webhook delivery */
+        if let Err(error) = self.tx.try_send(event) {
+            tracing::warn!(?error, "webhook queue full, dropping event");
+        }
+    }
+
+    pub fn agent_event_sender(&self) -> Sender<AgentEvent> {
+        let (tx, mut rx) = channel(16);
+        let notifier = self.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                notifier.notify(match event {
+                    AgentEvent::Registered => WebhookEvent::AgentRegistered,
+                    AgentEvent::ControlDisconnected(DisconnectReason::RegisterRejected(ProtoRegisterError::AccountBanned)) => WebhookEvent::AccountBanned,
+                    AgentEvent::ControlDisconnected(reason) => WebhookEvent::ControlDisconnected {
+                        reason: match reason {
+                            DisconnectReason::Unauthorized => "unauthorized".to_string(),
+                            DisconnectReason::PongTimeout => "pong-timeout".to_string(),
+                            DisconnectReason::RegisterRejected(error) => format!("register-rejected ({})", error),
+                        },
+                    },
+                });
+            }
+        });
+
+        tx
+    }
+}
+
+impl WebhookEvent {
+    pub fn tunnel_disabled(tunnel_id: Uuid, disabled: AgentTunnelDisabled) -> Self {
+        WebhookEvent::TunnelDisabled {
+            tunnel_id,
+            by_system: disabled == AgentTunnelDisabled::BySystem,
+        }
+    }
+
+    pub fn tunnel_state_changed(change: TunnelStateChange) -> Self {
+        WebhookEvent::TunnelStateChanged {
+            tunnel_id: change.tunnel_id,
+            from: change.from,
+            to: change.to,
+        }
+    }
+}
+
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(HmacSha256::create(secret.as_bytes()).sign(body)))
+}
+
+async fn deliver_events(mut rx: Receiver<WebhookEvent>, url: String, secret: Option<String>, agent_id: Option<Uuid>) {
+    let client = reqwest::Client::new();
+
+    while let Some(event) = rx.recv().await {
+        let body = serde_json::to_vec(&WebhookPayload {
+            event: &event,
+            timestamp: now_milli(),
+            agent_id,
+        }).unwrap();
+
+        let mut backoff = MIN_BACKOFF;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut req = client.post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .timeout(Duration::from_secs(10))
+                .body(body.clone());
+
+            if let Some(secret) = &secret {
+                req = req.header("X-Playit-Signature", sign_payload(secret, &body));
+            }
+
+            match req.send().await {
+                Ok(res) if res.status().is_success() => break,
+                Ok(res) => tracing::warn!(status = %res.status(), attempt, ?event, "webhook delivery rejected"),
+                Err(error) => tracing::warn!(?error, attempt, ?event, "webhook delivery failed"),
+            }
+
+            if attempt == MAX_ATTEMPTS {
+                tracing::error!(?event, "giving up on webhook delivery");
+                break;
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::tunnel_state::TunnelState;
+
+    use super::{sign_payload, WebhookEvent, WebhookPayload};
+
+    #[test]
+    fn test_payload_and_signature() {
+        let event = WebhookEvent::TunnelDisabled { tunnel_id: Uuid::from_u128(1), by_system: true };
+        let body = serde_json::to_string(&WebhookPayload { event: &event, timestamp: 10, agent_id: None }).unwrap();
+
+        assert_eq!(body, r#"{"event":"tunnel-disabled","tunnel_id":"00000000-0000-0000-0000-000000000001","by_system":true,"timestamp":10}"#);
+
+        let event = WebhookEvent::TunnelStateChanged { tunnel_id: Uuid::from_u128(1), from: Some(TunnelState::Pending), to: TunnelState::Allocated };
+        let body = serde_json::to_string(&WebhookPayload { event: &event, timestamp: 10, agent_id: None }).unwrap();
+
+        assert_eq!(body, r#"{"event":"tunnel-state-changed","tunnel_id":"00000000-0000-0000-0000-000000000001","from":"pending","to":"allocated","timestamp":10}"#);
+
+        /* RFC 4231 test case 2 */
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+}
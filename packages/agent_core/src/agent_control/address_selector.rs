@@ -5,7 +5,7 @@ use playit_agent_proto::{control_feed::ControlFeed, control_messages::{ControlRe
 
 use crate::utils::now_milli;
 
-use super::{connected_control::ConnectedControl, errors::SetupError, PacketIO};
+use super::{connected_control::ConnectedControl, errors::SetupError, established_control::{estimate_clock_offset, warn_clock_offset, MAX_CLOCK_OFFSET_MS}, PacketIO};
 
 
 pub struct AddressSelector<IO: PacketIO> {
@@ -71,6 +71,12 @@ impl<IO: PacketIO> AddressSelector<IO> {
                                     match msg.content {
                                         ControlResponse::Pong(pong) => {
                                             tracing::info!(?pong, "got initial pong from tunnel server");
+
+                                            let clock_offset = estimate_clock_offset(&pong, now_milli());
+                                            if MAX_CLOCK_OFFSET_MS < clock_offset.abs() {
+                                                warn_clock_offset(clock_offset);
+                                            }
+
                                             return Ok(ConnectedControl::new(addr, self.packet_io, pong));
                                         }
                                         other => {
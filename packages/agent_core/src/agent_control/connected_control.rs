@@ -36,6 +36,7 @@ impl<IO: PacketIO> ConnectedControl<IO> {
             current_ping: None,
             clock_offset: 0,
             force_expired: false,
+            unexpected_session: None,
         }
     }
 
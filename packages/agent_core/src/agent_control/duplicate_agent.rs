@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+/*
+ * Two agents running with the same secret keep replacing each other's
+ * control session: the tunnel server marks ours unauthorized or answers a
+ * keepalive with a session we did not register, and both agents reconnect
+ * over and over. A few of these conflicts within DUPLICATE_WINDOW_MS are
+ * reported as a likely duplicate agent.
+ */
+
+pub const DUPLICATE_THRESHOLD: usize = 3;
+pub const DUPLICATE_WINDOW_MS: u64 = 5 * 60_000;
+
+#[derive(Debug, Default)]
+pub struct DuplicateAgentDetector {
+    conflicts: VecDeque<u64>,
+    suspected: bool,
+}
+
+impl DuplicateAgentDetector {
+    /* true when this conflict crosses the threshold, warn once per suspicion */
+    pub fn conflict(&mut self, now_ms: u64) -> bool {
+        self.expire(now_ms);
+        self.conflicts.push_back(now_ms);
+
+        if self.suspected || self.conflicts.len() < DUPLICATE_THRESHOLD {
+            return false;
+        }
+
+        self.suspected = true;
+        true
+    }
+
+    /* suspicion clears once the window passes with fewer conflicts */
+    pub fn is_suspected(&mut self, now_ms: u64) -> bool {
+        self.expire(now_ms);
+        if self.conflicts.len() < DUPLICATE_THRESHOLD {
+            self.suspected = false;
+        }
+        self.suspected
+    }
+
+    fn expire(&mut self, now_ms: u64) {
+        while self.conflicts.front().is_some_and(|at| DUPLICATE_WINDOW_MS < now_ms.saturating_sub(*at)) {
+            self.conflicts.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DuplicateAgentDetector, DUPLICATE_WINDOW_MS};
+
+    #[test]
+    fn test_repeated_conflicts_suspect_duplicate() {
+        let mut detector = DuplicateAgentDetector::default();
+
+        /* conflicts spread over more than the window are normal reconnects */
+        assert!(!detector.conflict(0));
+        assert!(!detector.conflict(DUPLICATE_WINDOW_MS));
+        assert!(!detector.conflict(2 * DUPLICATE_WINDOW_MS));
+        assert!(!detector.is_suspected(2 * DUPLICATE_WINDOW_MS));
+
+        let start = 10 * DUPLICATE_WINDOW_MS;
+        assert!(!detector.conflict(start));
+        assert!(!detector.conflict(start + 10_000));
+        assert!(detector.conflict(start + 20_000));
+        assert!(!detector.conflict(start + 30_000));
+        assert!(detector.is_suspected(start + 30_000));
+
+        assert!(!detector.is_suspected(start + 30_000 + DUPLICATE_WINDOW_MS + 1));
+    }
+}
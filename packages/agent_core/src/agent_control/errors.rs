@@ -1,6 +1,8 @@
 use std::{error::Error, fmt::{Display, Formatter}, net::SocketAddr};
 
-use playit_api_client::{api::{ApiError, ApiErrorNoFail, ApiResponseError}, http_client::HttpClientError};
+use super::server_override::ControlServerOverride;
+
+use playit_api_client::{api::{ApiError, ApiErrorNoFail, ApiResponseError, ProtoRegisterError}, http_client::HttpClientError};
 
 
 #[derive(Debug)]
@@ -15,6 +17,33 @@ pub enum SetupError {
     NoResponseFromAuthenticate,
     RegisterInvalidSignature,
     RegisterUnauthorized,
+    RegisterRejected(ProtoRegisterError),
+    /* a long lived agent task panicked, contains the panic message */
+    TaskPanicked(String),
+    /* the pinned control server (--control_server) was not available */
+    ControlServerUnavailable(ControlServerOverride),
+}
+
+impl SetupError {
+    /* retrying won't help, the agent should stop and report why */
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            SetupError::RegisterRejected(error) => register_error_is_terminal(*error),
+            _ => false,
+        }
+    }
+}
+
+pub fn register_error_is_terminal(error: ProtoRegisterError) -> bool {
+    match error {
+        /* agent may be re-enabled once another agent is removed or the plan changes */
+        ProtoRegisterError::AgentDisabledOverLimit => false,
+        /* only stop for reasons this version knows, anything else keeps retrying */
+        ProtoRegisterError::Unknown => false,
+        ProtoRegisterError::AccountBanned => true,
+        ProtoRegisterError::DisabledByUser => true,
+        ProtoRegisterError::UnknownPlayitVersion => true,
+    }
 }
 
 impl<F: serde::Serialize> From<ApiError<F, HttpClientError>> for SetupError {
@@ -61,4 +90,46 @@ impl From<std::io::Error> for ControlError {
     fn from(e: std::io::Error) -> Self {
         ControlError::IoError(e)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::{ApiResult, ProtoRegisterError, SignedAgentKey};
+
+    use super::{register_error_is_terminal, SetupError};
+
+    fn register_fail(body: &str) -> ProtoRegisterError {
+        match serde_json::from_str::<ApiResult<SignedAgentKey, ProtoRegisterError>>(body).unwrap() {
+            ApiResult::Fail(error) => error,
+            other => panic!("expected fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_register_error_classification() {
+        assert!(!register_error_is_terminal(ProtoRegisterError::AgentDisabledOverLimit));
+        assert!(register_error_is_terminal(ProtoRegisterError::AccountBanned));
+        assert!(register_error_is_terminal(ProtoRegisterError::DisabledByUser));
+        assert!(register_error_is_terminal(ProtoRegisterError::UnknownPlayitVersion));
+
+        assert!(SetupError::RegisterRejected(ProtoRegisterError::AccountBanned).is_terminal());
+        assert!(!SetupError::RegisterRejected(ProtoRegisterError::AgentDisabledOverLimit).is_terminal());
+        assert!(!SetupError::FailedToConnect.is_terminal());
+        assert!(!SetupError::RegisterUnauthorized.is_terminal());
+    }
+
+    #[test]
+    fn test_register_fail_body() {
+        let error = register_fail(r#"{"status":"fail","data":"AccountBanned"}"#);
+        assert_eq!(error, ProtoRegisterError::AccountBanned);
+        assert!(register_error_is_terminal(error));
+
+        assert_eq!(register_fail(r#"{"status":"fail","data":"DisabledByUser"}"#), ProtoRegisterError::DisabledByUser);
+        assert_eq!(register_fail(r#"{"status":"fail","data":"UnknownPlayitVersion"}"#), ProtoRegisterError::UnknownPlayitVersion);
+
+        /* a reason this version doesn't know must not stop the agent */
+        let error = register_fail(r#"{"status":"fail","data":"SomeNewReason"}"#);
+        assert_eq!(error, ProtoRegisterError::Unknown);
+        assert!(!SetupError::RegisterRejected(error).is_terminal());
+    }
+}
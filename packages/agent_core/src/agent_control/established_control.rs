@@ -1,12 +1,13 @@
 use playit_agent_proto::control_feed::ControlFeed;
 use playit_agent_proto::control_messages::{AgentRegistered, ControlRequest, ControlResponse, Ping, Pong};
 use playit_agent_proto::rpc::ControlRpcMessage;
+use playit_agent_proto::AgentSessionId;
 
 use crate::utils::now_milli;
 
 use super::connected_control::ConnectedControl;
 use super::errors::{ControlError, SetupError};
-use super::{AuthResource, PacketIO};
+use super::{AuthResource, PacketIO, KEEPALIVE_LOG_TARGET};
 
 pub struct EstablishedControl<A: AuthResource, IO: PacketIO> {
     pub(super) auth: A,
@@ -16,6 +17,8 @@ pub struct EstablishedControl<A: AuthResource, IO: PacketIO> {
     pub(super) current_ping: Option<u32>,
     pub(super) clock_offset: i64,
     pub(super) force_expired: bool,
+    /* session a keepalive was answered with instead of ours, see DuplicateAgentDetector */
+    pub(super) unexpected_session: Option<AgentSessionId>,
 }
 
 impl<A: AuthResource, IO: PacketIO> EstablishedControl<A, IO> {
@@ -96,20 +99,27 @@ impl<A: AuthResource, IO: PacketIO> EstablishedControl<A, IO> {
         if let ControlFeed::Response(res) = &feed {
             match &res.content {
                 ControlResponse::AgentRegistered(registered) => {
-                    tracing::info!(details = ?registered, "agent registered");
+                    /* every keepalive is answered with the same session, only log new sessions at info */
+                    if registered.id == self.registered.id {
+                        tracing::debug!(target: KEEPALIVE_LOG_TARGET, details = ?registered, "agent registration refreshed");
+                    } else {
+                        tracing::info!(details = ?registered, "agent registered");
+                        self.unexpected_session = Some(registered.id.clone());
+                    }
                     self.registered = registered.clone();
                 }
                 ControlResponse::Pong(pong) => {
                     let now = now_milli();
                     let rtt = (now.max(pong.request_now) - pong.request_now) as u32;
 
-                    let server_ts = pong.server_now - (rtt / 2) as u64;
-                    let local_ts = pong.request_now;
-                    self.clock_offset = local_ts as i64 - server_ts as i64;
+                    let clock_offset = estimate_clock_offset(pong, now);
 
-                    if 10_000 < self.clock_offset.abs() {
-                        tracing::warn!(offset = self.clock_offset, "local timestamp if over 10 seconds off");
+                    /* only warn when crossing the threshold, pongs arrive every second */
+                    let was_skewed = MAX_CLOCK_OFFSET_MS < self.clock_offset.abs();
+                    if !was_skewed && MAX_CLOCK_OFFSET_MS < clock_offset.abs() {
+                        warn_clock_offset(clock_offset);
                     }
+                    self.clock_offset = clock_offset;
 
                     self.current_ping = Some(rtt);
 
@@ -127,9 +137,58 @@ impl<A: AuthResource, IO: PacketIO> EstablishedControl<A, IO> {
 }
 
 
+pub const MAX_CLOCK_OFFSET_MS: i64 = 10_000;
+
+/* local clock minus tunnel server clock, server_now is taken about half a round trip after request_now */
+pub fn estimate_clock_offset(pong: &Pong, now: u64) -> i64 {
+    let rtt = now.max(pong.request_now) - pong.request_now;
+    let server_ts = pong.server_now as i64 - (rtt / 2) as i64;
+    pong.request_now as i64 - server_ts
+}
+
+pub fn warn_clock_offset(offset: i64) {
+    tracing::warn!(
+        offset_ms = offset,
+        "local clock is {:.1} seconds {} the tunnel server, this can cause InvalidTimestamp auth errors; sync the system clock (ex. enable NTP)",
+        offset.abs() as f64 / 1000.0,
+        if offset < 0 { "behind" } else { "ahead of" },
+    );
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ExpiredReason {
     Forced,
     SessionNotSetup,
     FlowChanged,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_proto::control_messages::Pong;
+
+    use super::estimate_clock_offset;
+
+    fn pong(request_now: u64, server_now: u64) -> Pong {
+        Pong {
+            request_now,
+            server_now,
+            server_id: 1,
+            data_center_id: 1,
+            client_addr: "1.2.3.4:5000".parse().unwrap(),
+            tunnel_addr: "5.6.7.8:5525".parse().unwrap(),
+            session_expire_at: None,
+        }
+    }
+
+    #[test]
+    fn test_estimate_clock_offset() {
+        /* 100ms round trip, clocks in sync */
+        assert_eq!(estimate_clock_offset(&pong(1_000_000, 1_000_050), 1_000_100), 0);
+
+        /* local clock 30s ahead */
+        assert_eq!(estimate_clock_offset(&pong(1_030_000, 1_000_050), 1_030_100), 30_000);
+
+        /* local clock 30s behind */
+        assert_eq!(estimate_clock_offset(&pong(1_000_000, 1_030_050), 1_000_100), -30_000);
+    }
+}
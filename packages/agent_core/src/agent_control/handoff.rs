@@ -0,0 +1,196 @@
+/*
+ * Socket handoff for zero downtime restarts (unix only)
+ *
+ * Goal: a new agent process adopts the sockets of the running agent so
+ * restarts don't drop the control session or player connections.
+ *
+ * 1. The new agent connects to the old agent over a unix stream socket and
+ *    the old agent stops reading from the sockets it is about to hand off.
+ * 2. Sockets are sent with SCM_RIGHTS along with a small header
+ *    (HANDOFF_MAGIC). The kernel duplicates the fds into the new process, the
+ *    old process closes its copies after the send succeeds.
+ * 3. Control socket (implemented here): the new agent keeps the same local
+ *    UDP addresses, so the tunnel server sees the same client_addr and the
+ *    existing session can be re-authenticated without a flow change.
+ * 4. TCP connections (future): each established tunnel/local stream pair is
+ *    sent with its NewClient details and any unsent proxy protocol header
+ *    state. Bytes already read into userspace buffers must be flushed before
+ *    the handoff or sent along with the fds.
+ * 5. UDP client sockets (future): sent with their flow mappings so origin
+ *    replies keep routing to the right tunnel flow. Listening sockets that
+ *    must exist in both processes during the overlap use SO_REUSEPORT.
+ *
+ * If any step fails the new agent falls back to a normal startup.
+ */
+
+use std::io::{Error, ErrorKind};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use super::DualStackUdpSocket;
+
+pub const HANDOFF_MAGIC: &[u8; 8] = b"PLAYITH1";
+
+pub fn send_fds(stream: &UnixStream, payload: &[u8], fds: &[RawFd]) -> std::io::Result<usize> {
+    if payload.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "payload required to send fds"));
+    }
+
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+
+    let fds_len = std::mem::size_of_val(fds) as u32;
+    let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+
+    /* u64 buffer to keep cmsghdr aligned */
+    let mut cmsg_buffer = vec![0u64; space.div_ceil(8)];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if !fds.is_empty() {
+        msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+    }
+
+    let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+    if sent < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(sent as usize)
+}
+
+pub fn recv_fds(stream: &UnixStream, buffer: &mut [u8], max_fds: usize) -> std::io::Result<(usize, Vec<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+
+    let space = unsafe { libc::CMSG_SPACE((max_fds * std::mem::size_of::<RawFd>()) as u32) } as usize;
+    let mut cmsg_buffer = vec![0u64; space.div_ceil(8)];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+    if received < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(data.add(i))));
+                }
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    /* received fds are closed when dropped */
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "too many fds sent in handoff"));
+    }
+
+    Ok((received as usize, fds))
+}
+
+pub fn send_control_socket(stream: &UnixStream, socket: DualStackUdpSocket) -> std::io::Result<()> {
+    let (ip4, ip6) = socket.into_std()?;
+
+    let mut fds = vec![ip4.as_raw_fd()];
+    if let Some(ip6) = &ip6 {
+        fds.push(ip6.as_raw_fd());
+    }
+
+    send_fds(stream, HANDOFF_MAGIC, &fds)?;
+    tracing::info!(fd_count = fds.len(), "sent control socket handoff");
+
+    Ok(())
+}
+
+/* must be called within a tokio runtime */
+pub fn recv_control_socket(stream: &UnixStream) -> std::io::Result<DualStackUdpSocket> {
+    let mut buffer = [0u8; HANDOFF_MAGIC.len()];
+    let (bytes, fds) = recv_fds(stream, &mut buffer, 2)?;
+
+    if bytes != buffer.len() || buffer.ne(HANDOFF_MAGIC) {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid handoff header"));
+    }
+
+    let mut fds = fds.into_iter().map(std::net::UdpSocket::from);
+    let ip4 = fds.next().ok_or_else(|| Error::new(ErrorKind::InvalidData, "handoff missing ip4 socket"))?;
+    let ip6 = fds.next();
+
+    if !ip4.local_addr()?.is_ipv4() {
+        return Err(Error::new(ErrorKind::InvalidData, "first handoff socket is not ip4"));
+    }
+
+    if let Some(ip6) = &ip6 {
+        if !ip6.local_addr()?.is_ipv6() {
+            return Err(Error::new(ErrorKind::InvalidData, "second handoff socket is not ip6"));
+        }
+    }
+
+    tracing::info!(local_addr = %ip4.local_addr()?, "received control socket handoff");
+    DualStackUdpSocket::from_std(ip4, ip6)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::os::unix::net::UnixStream;
+
+    use tokio::net::UdpSocket;
+
+    use crate::agent_control::{DualStackUdpSocket, PacketIO};
+
+    use super::{recv_control_socket, send_control_socket};
+
+    #[tokio::test]
+    async fn test_control_socket_handoff() {
+        let (old_agent, new_agent) = UnixStream::pair().unwrap();
+
+        let socket = DualStackUdpSocket::new().await.unwrap();
+        let ip4_addr = socket.ip4.local_addr().unwrap();
+        let ip6_addr = socket.ip6.as_ref().map(|v| v.local_addr().unwrap());
+
+        send_control_socket(&old_agent, socket).unwrap();
+        let adopted = recv_control_socket(&new_agent).unwrap();
+
+        assert_eq!(adopted.ip4.local_addr().unwrap(), ip4_addr);
+        assert_eq!(adopted.ip6.as_ref().map(|v| v.local_addr().unwrap()), ip6_addr);
+
+        /* adopted socket still receives on the same address */
+        let sender = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        sender.send_to(b"hello", SocketAddr::new(Ipv4Addr::LOCALHOST.into(), ip4_addr.port())).await.unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (bytes, _) = adopted.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..bytes], b"hello");
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/*
+ * Readiness of the control channel, shared with health checks.
+ *
+ * Ready means the session is registered and the tunnel server has answered
+ * at least one keepalive for it. Both flags reset whenever the session
+ * expires or the control channel disconnects.
+ */
+#[derive(Clone, Default)]
+pub struct ControlHealth {
+    inner: Arc<HealthState>,
+}
+
+#[derive(Default)]
+struct HealthState {
+    registered: AtomicBool,
+    keep_alive_ok: AtomicBool,
+    last_pong: Mutex<Option<ControlPong>>,
+    duplicate_suspected: AtomicBool,
+}
+
+/* tunnel server answering the control channel and the round trip of its last pong */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ControlPong {
+    pub tunnel_server_id: u64,
+    pub data_center_id: u32,
+    pub latency_ms: u64,
+}
+
+impl ControlHealth {
+    pub fn set_registered(&self) {
+        self.inner.registered.store(true, Ordering::SeqCst);
+    }
+
+    pub fn keep_alive_succeeded(&self) {
+        self.inner.keep_alive_ok.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_disconnected(&self) {
+        self.inner.registered.store(false, Ordering::SeqCst);
+        self.inner.keep_alive_ok.store(false, Ordering::SeqCst);
+    }
+
+    pub fn pong_received(&self, pong: ControlPong) {
+        self.inner.last_pong.lock().unwrap().replace(pong);
+    }
+
+    pub fn last_pong(&self) -> Option<ControlPong> {
+        *self.inner.last_pong.lock().unwrap()
+    }
+
+    /* see DuplicateAgentDetector */
+    pub fn set_duplicate_suspected(&self, suspected: bool) {
+        self.inner.duplicate_suspected.store(suspected, Ordering::SeqCst);
+    }
+
+    pub fn duplicate_suspected(&self) -> bool {
+        self.inner.duplicate_suspected.load(Ordering::SeqCst)
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.inner.registered.load(Ordering::SeqCst)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_registered() && self.inner.keep_alive_ok.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlHealth;
+
+    #[test]
+    fn test_ready_after_keep_alive() {
+        let health = ControlHealth::default();
+        let shared = health.clone();
+        assert!(!shared.is_ready());
+
+        health.set_registered();
+        assert!(shared.is_registered());
+        assert!(!shared.is_ready());
+
+        health.keep_alive_succeeded();
+        assert!(shared.is_ready());
+
+        /* a new session needs its own keepalive */
+        health.set_disconnected();
+        health.set_registered();
+        assert!(!shared.is_ready());
+    }
+}
@@ -1,17 +1,24 @@
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
 use playit_agent_proto::control_feed::{ControlFeed, NewClient};
 use playit_agent_proto::control_messages::{ControlResponse, UdpChannelDetails};
+use playit_agent_proto::AgentSessionId;
+use playit_api_client::api::ProtoRegisterError;
 
 use crate::agent_control::established_control::EstablishedControl;
+use crate::network::idle_sleep::{IdleSleep, IDLE_PING_INTERVAL};
 use crate::utils::now_milli;
+use crate::utils::reconnect::reconnect_coordinator;
 
 use super::address_selector::AddressSelector;
 use super::connected_control::ConnectedControl;
+use super::duplicate_agent::DuplicateAgentDetector;
 use super::errors::SetupError;
-use super::{AuthResource, PacketIO};
+use super::health::{ControlHealth, ControlPong};
+use super::udp_setup_retry::{UdpSetupRetry, UDP_SETUP_SEND_TIMEOUT};
+use super::{AuthResource, PacketIO, KEEPALIVE_LOG_TARGET};
 
 
 pub struct MaintainedControl<I: PacketIO, A: AuthResource> {
@@ -19,8 +26,28 @@ pub struct MaintainedControl<I: PacketIO, A: AuthResource> {
     last_keep_alive: u64,
     last_ping: u64,
     last_pong: u64,
-    last_udp_auth: u64,
+    udp_setup: UdpSetupRetry,
     last_control_targets: Vec<SocketAddr>,
+    udp_details: Option<CachedUdpDetails>,
+    health: ControlHealth,
+    idle_sleep: IdleSleep,
+    duplicates: DuplicateAgentDetector,
+}
+
+/* last udp details received, valid as long as the session they were issued for */
+#[derive(Debug, Clone)]
+struct CachedUdpDetails {
+    details: UdpChannelDetails,
+    session_expires_at: u64,
+}
+
+impl CachedUdpDetails {
+    fn get(&self, now_ms: u64) -> Option<UdpChannelDetails> {
+        if self.session_expires_at <= now_ms {
+            return None;
+        }
+        Some(self.details.clone())
+    }
 }
 
 impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
@@ -29,13 +56,20 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
         let setup = AddressSelector::new(addresses.clone(), io).connect_to_first().await?;
         let control_channel = setup.auth_into_established(auth).await?;
 
+        let health = ControlHealth::default();
+        health.set_registered();
+
         Ok(MaintainedControl {
             control: control_channel,
             last_keep_alive: 0,
             last_ping: 0,
             last_pong: 0,
-            last_udp_auth: 0,
+            udp_setup: UdpSetupRetry::default(),
             last_control_targets: addresses,
+            udp_details: None,
+            health,
+            idle_sleep: IdleSleep::default(),
+            duplicates: DuplicateAgentDetector::default(),
         })
     }
 
@@ -58,6 +92,24 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
         Ok(updated)
     }
 
+    /* addresses of playit servers, local targets must never point at these */
+    pub fn server_ips(&self) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = self.last_control_targets.iter().map(|addr| addr.ip()).collect();
+        ips.push(self.control.conn.control_addr.ip());
+        ips.push(self.control.conn.pong_latest.tunnel_addr.ip());
+        ips.dedup();
+        ips
+    }
+
+    /* tunnel server the control channel is connected to */
+    pub fn control_addr(&self) -> SocketAddr {
+        self.control.conn.control_addr
+    }
+
+    pub fn health(&self) -> ControlHealth {
+        self.health.clone()
+    }
+
     pub async fn replace_connection(&mut self, mut connected: ConnectedControl<I>, force: bool) -> Result<bool, SetupError> {
         if !force
             && self.control.conn.pong_latest.client_addr.ip() == connected.pong_latest.client_addr.ip()
@@ -73,32 +125,78 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
         Ok(true)
     }
 
-    pub async fn send_udp_session_auth(&mut self, now_ms: u64, min_wait_ms: u64) -> bool {
-        if now_ms < self.last_udp_auth + min_wait_ms {
+    /* retried on its own schedule, see UdpSetupRetry */
+    pub async fn send_udp_session_auth(&mut self, now_ms: u64) -> bool {
+        if !self.udp_setup.should_send(now_ms) {
             return false;
         }
-        
-        self.last_udp_auth = now_ms;
-        if let Err(error) = self.control.send_setup_udp_channel(1).await {
-            tracing::error!(?error, "failed to send setup udp channel request");
+
+        match tokio::time::timeout(UDP_SETUP_SEND_TIMEOUT, self.control.send_setup_udp_channel(1)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => tracing::error!(?error, "failed to send setup udp channel request"),
+            Err(_) => tracing::warn!("timed out sending setup udp channel request, TCP clients are not affected"),
         }
 
         true
     }
 
+    /* re-sent after reconnecting so udp flows don't stall until fresh details arrive */
+    pub fn cached_udp_details(&self, now_ms: u64) -> Option<UdpChannelDetails> {
+        self.udp_details.as_ref()?.get(now_ms)
+    }
+
+    fn session_conflict(&mut self, conflicting_session: Option<AgentSessionId>) {
+        if self.duplicates.conflict(now_milli()) {
+            tracing::warn!(
+                ?conflicting_session,
+                "control session keeps getting replaced, another agent is probably running with the same secret. \
+                Stop the other agent or give each machine its own agent"
+            );
+            self.health.set_duplicate_suspected(true);
+        }
+    }
+
+    /* ping and keepalive less often while the agent has no connections */
+    pub fn set_idle_sleep(&mut self, idle_sleep: IdleSleep) {
+        self.idle_sleep = idle_sleep;
+    }
+
     pub async fn update(&mut self) -> Option<TunnelControlEvent> {
         if let Some(reason) = self.control.is_expired() {
             tracing::warn!(?reason, "session expired");
+            self.health.set_disconnected();
+
+            /* keepalives may have extended the session since the details were cached */
+            if let Some(cached) = &mut self.udp_details {
+                cached.session_expires_at = self.control.get_expire_at();
+            }
+
+            reconnect_coordinator().wait_turn("control").await;
 
             if let Err(error) = self.control.authenticate().await {
                 tracing::error!(?error, "failed to authenticate");
                 tokio::time::sleep(Duration::from_secs(2)).await;
+
+                if let SetupError::RegisterRejected(error) = error {
+                    return Some(TunnelControlEvent::Disconnected(DisconnectReason::RegisterRejected(error)));
+                }
                 return None;
             }
+
+            self.health.set_registered();
+            self.udp_setup.reset();
+            return Some(TunnelControlEvent::Registered);
         }
 
+        let asleep = self.idle_sleep.is_asleep();
+        let ping_interval = if asleep {
+            IDLE_PING_INTERVAL.as_millis() as u64
+        } else {
+            1_000
+        };
+
         let now = now_milli();
-        if now - self.last_ping > 1_000 {
+        if now - self.last_ping > ping_interval {
             self.last_ping = now;
 
             if let Err(error) = self.control.send_ping(200, now).await {
@@ -109,17 +207,19 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
         let time_till_expire = self.control.get_expire_at().max(now) - now;
         tracing::trace!(time_till_expire, "time till expire");
 
-        /* keep alive every 60s or every 10s if expiring soon */
+        /* keep alive every 60s or every 10s if expiring soon, idle sleep only keeps alive when expiring soon */
         let interval = if time_till_expire < 30_000 {
-            10_000
+            Some(10_000)
+        } else if asleep {
+            None
         } else {
-            60_000
+            Some(60_000)
         };
 
-        if interval < now - self.last_keep_alive {
+        if interval.is_some_and(|interval| interval < now - self.last_keep_alive) {
             self.last_keep_alive = now;
 
-            tracing::info!(time_till_expire, "send KeepAlive");
+            tracing::debug!(target: KEEPALIVE_LOG_TARGET, time_till_expire, "send KeepAlive");
             if let Err(error) = self.control.send_keep_alive(100).await {
                 tracing::error!(?error, "failed to send KeepAlive");
             }
@@ -131,13 +231,37 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
             match tokio::time::timeout(Duration::from_millis(100), self.control.recv_feed_msg()).await {
                 Ok(Ok(ControlFeed::NewClient(new_client))) => return Some(TunnelControlEvent::NewClient(new_client)),
                 Ok(Ok(ControlFeed::Response(msg))) => match msg.content {
-                    ControlResponse::UdpChannelDetails(details) => return Some(TunnelControlEvent::UdpChannelDetails(details)),
+                    ControlResponse::UdpChannelDetails(details) => {
+                        self.udp_setup.succeeded();
+                        self.udp_details = Some(CachedUdpDetails {
+                            details: details.clone(),
+                            session_expires_at: self.control.get_expire_at(),
+                        });
+                        return Some(TunnelControlEvent::UdpChannelDetails(details));
+                    }
+                    /* keepalives are answered with the refreshed registration */
+                    ControlResponse::AgentRegistered(_) => {
+                        self.health.keep_alive_succeeded();
+
+                        if let Some(session) = self.control.unexpected_session.take() {
+                            self.session_conflict(Some(session));
+                        }
+                    }
                     ControlResponse::Unauthorized => {
                         tracing::info!("session no longer authorized");
+                        self.session_conflict(None);
+                        self.health.set_disconnected();
+                        self.udp_details = None;
                         self.control.set_expired();
+                        return Some(TunnelControlEvent::Disconnected(DisconnectReason::Unauthorized));
                     }
                     ControlResponse::Pong(pong) => {
                         self.last_pong = now_milli();
+                        self.health.pong_received(ControlPong {
+                            tunnel_server_id: pong.server_id,
+                            data_center_id: pong.data_center_id,
+                            latency_ms: self.last_pong.saturating_sub(pong.request_now),
+                        });
 
                         if pong.client_addr != self.control.pong_at_auth.client_addr {
                             tracing::info!(
@@ -165,11 +289,15 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
             }
         }
 
-        if self.last_pong != 0 && now_milli() - self.last_pong > 6_000 {
+        self.health.set_duplicate_suspected(self.duplicates.is_suspected(now_milli()));
+
+        if self.last_pong != 0 && now_milli() - self.last_pong > ping_interval + 5_000 {
             tracing::info!("timeout waiting for pong");
 
             self.last_pong = 0;
+            self.health.set_disconnected();
             self.control.set_expired();
+            return Some(TunnelControlEvent::Disconnected(DisconnectReason::PongTimeout));
         }
 
         None
@@ -179,4 +307,42 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
 pub enum TunnelControlEvent {
     NewClient(NewClient),
     UdpChannelDetails(UdpChannelDetails),
-}
\ No newline at end of file
+    Registered,
+    Disconnected(DisconnectReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Unauthorized,
+    PongTimeout,
+    RegisterRejected(ProtoRegisterError),
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use playit_agent_proto::control_messages::UdpChannelDetails;
+
+    use super::CachedUdpDetails;
+
+    #[test]
+    fn test_cached_udp_details_across_control_gap() {
+        let cached = CachedUdpDetails {
+            details: UdpChannelDetails {
+                tunnel_addr: "147.185.221.1:5525".parse().unwrap(),
+                token: Arc::new(vec![1, 2, 3]),
+            },
+            session_expires_at: 10_000,
+        };
+
+        /* control drops at 4s and reconnects at 6s, session is still valid */
+        let details = cached.get(6_000).unwrap();
+        assert_eq!(details.tunnel_addr, cached.details.tunnel_addr);
+        assert_eq!(details.token, cached.details.token);
+
+        /* gap outlived the session, wait for fresh details */
+        assert!(cached.get(10_000).is_none());
+        assert!(cached.get(15_000).is_none());
+    }
+}
@@ -1,46 +1,83 @@
-use std::{future::Future, net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6}, sync::{atomic::AtomicUsize, Arc}, task::Poll};
+use std::{future::Future, net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6}, sync::{atomic::{AtomicBool, Ordering}, Arc}, task::Poll};
 
 use playit_agent_proto::control_messages::Pong;
 use errors::SetupError;
 use tokio::{io::ReadBuf, net::UdpSocket};
 use version::get_version;
 
-use playit_api_client::{api::{ReqAgentsRoutingGet, ReqProtoRegister, SignedAgentKey}, PlayitApi};
+use playit_api_client::{api::{ApiError, ReqAgentsRoutingGet, ReqProtoRegister, SignedAgentKey}, PlayitApi};
 
+use crate::network::lan_address::{is_port_taken, SourcePorts};
 use crate::utils::error_helper::ErrorHelper;
 
+use self::server_override::ControlServerOverride;
+
 pub mod errors;
 
 pub mod address_selector;
 pub mod connected_control;
 pub mod established_control;
 pub mod maintained_control;
+pub mod health;
+pub mod duplicate_agent;
 pub mod version;
 
 pub mod udp_channel;
+pub mod udp_setup_retry;
 pub mod udp_proto;
 pub mod platform;
+#[cfg(unix)]
+pub mod handoff;
+pub mod static_auth;
+pub mod server_override;
+pub mod udp_probe;
+
+/*
+ * Routine keepalive / session refresh events happen every few seconds and
+ * drowned out connections and errors at info. They log at debug under this
+ * target so they can be enabled on their own.
+ */
+pub const KEEPALIVE_LOG_TARGET: &str = "playit_agent_core::keepalive";
 
 pub trait PacketIO: Send + Sync + 'static {
     fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = std::io::Result<usize>> + Sync + Send;
 
     fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<(usize, SocketAddr)>> + Sync + Send;
+
+    /* fills bufs in order, pushing (len, source) onto received for each packet, default receives a single packet into bufs[0] */
+    fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> impl Future<Output = std::io::Result<()>> + Send {
+        async move {
+            let buf = bufs.first_mut().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no buffers to receive into"))?;
+            received.push(self.recv_from(buf).await?);
+            Ok(())
+        }
+    }
 }
 
 pub trait PacketRx {
     fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<(usize, SocketAddr)>> + Sync + Send;
+
+    fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> impl Future<Output = std::io::Result<()>> + Send;
 }
 
 impl<T: PacketIO> PacketRx for T {
     fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<(usize, SocketAddr)>> + Sync + Send {
         T::recv_from(self, buf)
     }
+
+    fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> impl Future<Output = std::io::Result<()>> + Send {
+        T::recv_batch(self, bufs, received)
+    }
 }
 
 impl<T: PacketIO> PacketRx for Arc<T> {
     fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<(usize, SocketAddr)>> + Sync + Send {
         T::recv_from(self, buf)
     }
+
+    fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> impl Future<Output = std::io::Result<()>> + Send {
+        T::recv_batch(self, bufs, received)
+    }
 }
 
 pub trait PacketTx {
@@ -62,7 +99,8 @@ impl<T: PacketIO> PacketTx for Arc<T> {
 pub struct DualStackUdpSocket {
     ip4: UdpSocket,
     ip6: Option<UdpSocket>,
-    next: AtomicUsize,
+    /* family serviced by the last recv, the other family is polled first next time so neither starves */
+    last_ip6: AtomicBool,
 }
 
 impl DualStackUdpSocket {
@@ -73,9 +111,60 @@ impl DualStackUdpSocket {
         Ok(DualStackUdpSocket {
             ip4,
             ip6,
-            next: AtomicUsize::new(0),
+            last_ip6: AtomicBool::new(false),
+        })
+    }
+
+    /* binds the first port of the range free for IPv4, IPv6 uses the same port if it can */
+    pub async fn bind_ports(ports: SourcePorts) -> std::io::Result<Self> {
+        let mut last_error = None;
+
+        for port in ports.iter() {
+            let ip4 = match UdpSocket::bind(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))).await {
+                Ok(v) => v,
+                Err(error) if is_port_taken(&error) => {
+                    last_error = Some(error);
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let ip6 = UdpSocket::bind(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0))).await.ok();
+
+            return Ok(DualStackUdpSocket {
+                ip4,
+                ip6,
+                last_ip6: AtomicBool::new(false),
+            });
+        }
+
+        Err(last_error.unwrap_or_else(|| std::io::Error::from(std::io::ErrorKind::AddrInUse)))
+    }
+
+    /* must be called within a tokio runtime */
+    pub fn from_std(ip4: std::net::UdpSocket, ip6: Option<std::net::UdpSocket>) -> std::io::Result<Self> {
+        ip4.set_nonblocking(true)?;
+        if let Some(ip6) = &ip6 {
+            ip6.set_nonblocking(true)?;
+        }
+
+        Ok(DualStackUdpSocket {
+            ip4: UdpSocket::from_std(ip4)?,
+            ip6: match ip6 {
+                Some(ip6) => Some(UdpSocket::from_std(ip6)?),
+                None => None,
+            },
+            last_ip6: AtomicBool::new(false),
         })
     }
+
+    pub fn into_std(self) -> std::io::Result<(std::net::UdpSocket, Option<std::net::UdpSocket>)> {
+        let ip4 = self.ip4.into_std()?;
+        let ip6 = match self.ip6 {
+            Some(ip6) => Some(ip6.into_std()?),
+            None => None,
+        };
+        Ok((ip4, ip6))
+    }
 }
 
 impl PacketIO for DualStackUdpSocket {
@@ -89,20 +178,55 @@ impl PacketIO for DualStackUdpSocket {
     }
 
     async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
-        let sel = self.next.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
-        
-        if sel % 2 == 0 {
+        let ip6_first = !self.last_ip6.load(Ordering::Acquire);
+
+        let (bytes, source) = if ip6_first {
             PoolBoth {
                 buffer: buf,
                 a: self.ip6.as_ref(),
                 b: Some(&self.ip4),
-            }.await
+            }.await?
         } else {
             PoolBoth {
                 buffer: buf,
                 a: Some(&self.ip4),
                 b: self.ip6.as_ref(),
-            }.await
+            }.await?
+        };
+
+        self.last_ip6.store(source.is_ipv6(), Ordering::Release);
+        Ok((bytes, source))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> std::io::Result<()> {
+        use std::io::ErrorKind;
+        use crate::network::udp::recv_batch::try_recv_batch;
+
+        let Some(ip6) = &self.ip6 else {
+            return crate::network::udp::recv_batch::recv_batch(&self.ip4, bufs, received).await;
+        };
+
+        loop {
+            /* alternate which family is drained first so neither starves */
+            let ip6_first = !self.last_ip6.load(Ordering::Acquire);
+            let (first, second) = if ip6_first { (ip6, &self.ip4) } else { (&self.ip4, ip6) };
+
+            for socket in [first, second] {
+                match try_recv_batch(socket, bufs, received) {
+                    Ok(()) => {
+                        self.last_ip6.store(std::ptr::eq(socket, ip6), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                    Err(error) => return Err(error),
+                }
+            }
+
+            tokio::select! {
+                res = self.ip4.readable() => res?,
+                res = ip6.readable() => res?,
+            }
         }
     }
 }
@@ -155,17 +279,23 @@ impl PacketIO for UdpSocket {
     fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = std::io::Result<(usize, SocketAddr)>> + Sync {
         UdpSocket::recv_from(self, buf)
     }
+
+    #[cfg(target_os = "linux")]
+    fn recv_batch(&self, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> impl Future<Output = std::io::Result<()>> + Send {
+        crate::network::udp::recv_batch::recv_batch(self, bufs, received)
+    }
 }
 
 pub trait AuthResource: Clone {
-    fn authenticate(&self, pong: &Pong) -> impl Future<Output = Result<SignedAgentKey, SetupError>> + Sync;
+    fn authenticate(&self, pong: &Pong) -> impl Future<Output = Result<SignedAgentKey, SetupError>> + Sync + Send;
 
-    fn get_control_addresses(&self) -> impl Future<Output = Result<Vec<SocketAddr>, SetupError>> + Sync;
+    fn get_control_addresses(&self) -> impl Future<Output = Result<Vec<SocketAddr>, SetupError>> + Sync + Send;
 }
 
 #[derive(Clone)]
 pub struct AuthApi {
     client: PlayitApi,
+    server_override: Option<ControlServerOverride>,
 }
 
 impl AuthApi {
@@ -174,17 +304,35 @@ impl AuthApi {
             api_url,
             Some(secret_key)
         );
-        AuthApi { client }
+        AuthApi { client, server_override: None }
+    }
+
+    pub fn with_server_override(mut self, server_override: Option<ControlServerOverride>) -> Self {
+        if let Some(server) = &server_override {
+            tracing::warn!(%server, "control server override in effect, the agent will not fall back to other tunnel servers");
+        }
+        self.server_override = server_override;
+        self
     }
 }
 
 impl AuthResource for AuthApi {
     async fn authenticate(&self, pong: &Pong) -> Result<SignedAgentKey, SetupError> {
+        if let Some(server) = &self.server_override {
+            server.check_pong(pong)?;
+        }
+
         let res = self.client.proto_register(ReqProtoRegister {
             agent_version: get_version(),
             client_addr: pong.client_addr,
             tunnel_addr: pong.tunnel_addr,
-        }).await.with_error(|error| tracing::error!(?error, "failed to sign and register"))?;
+        }).await.with_error(|error| tracing::error!(?error, "failed to sign and register"));
+
+        let res = match res {
+            Ok(res) => res,
+            Err(ApiError::Fail(error)) => return Err(SetupError::RegisterRejected(error)),
+            Err(error) => return Err(error.into()),
+        };
 
         Ok(res)
     }
@@ -200,6 +348,54 @@ impl AuthResource for AuthApi {
             addresses.push(SocketAddr::new(ip4.into(), 5525));
         }
 
-        Ok(addresses)
+        match &self.server_override {
+            Some(server) => server.filter_addresses(addresses),
+            None => Ok(addresses),
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use tokio::net::UdpSocket;
+
+    use super::{DualStackUdpSocket, PacketIO};
+
+    #[tokio::test]
+    async fn test_dual_stack_recv_is_fair() {
+        let socket = DualStackUdpSocket::new().await.unwrap();
+        let Some(ip6) = &socket.ip6 else { return };
+
+        let target4 = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), socket.ip4.local_addr().unwrap().port());
+        let target6 = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), ip6.local_addr().unwrap().port());
+
+        let Ok(sender6) = UdpSocket::bind(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)).await else { return };
+        let sender4 = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+
+        /* queue packets on both families so both sockets are always ready */
+        for i in 0..100u32 {
+            sender4.send_to(&i.to_be_bytes(), target4).await.unwrap();
+            sender6.send_to(&i.to_be_bytes(), target6).await.unwrap();
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let mut buffer = [0u8; 64];
+        let mut ip4_count = 0;
+        let mut ip6_count = 0;
+
+        for _ in 0..100 {
+            let (_, source) = socket.recv_from(&mut buffer).await.unwrap();
+            if source.is_ipv6() {
+                ip6_count += 1;
+            } else {
+                ip4_count += 1;
+            }
+        }
+
+        assert_eq!(ip4_count, 50);
+        assert_eq!(ip6_count, 50);
+    }
+}
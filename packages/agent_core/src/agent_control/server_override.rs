@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use playit_agent_proto::control_messages::Pong;
+
+use super::errors::SetupError;
+
+/*
+ * Debugging aid to pin the control connection to one tunnel server, used to
+ * reproduce issues specific to a POP. An address restricts the addresses
+ * from the routing api to that one, a server id is checked against the
+ * server that answered the initial ping. Either fails setup instead of
+ * falling back to another server.
+ */
+
+const CONTROL_PORT: u16 = 5525;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlServerOverride {
+    Address(SocketAddr),
+    ServerId(u64),
+}
+
+impl FromStr for ControlServerOverride {
+    type Err = ();
+
+    /* "<ip>[:<port>]" or "<server-id>" */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(ControlServerOverride::Address(addr));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(ControlServerOverride::Address(SocketAddr::new(ip, CONTROL_PORT)));
+        }
+        s.parse::<u64>().map(ControlServerOverride::ServerId).map_err(|_| ())
+    }
+}
+
+impl Display for ControlServerOverride {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlServerOverride::Address(addr) => write!(f, "address {}", addr),
+            ControlServerOverride::ServerId(id) => write!(f, "server id {}", id),
+        }
+    }
+}
+
+impl ControlServerOverride {
+    pub fn filter_addresses(&self, addresses: Vec<SocketAddr>) -> Result<Vec<SocketAddr>, SetupError> {
+        match self {
+            ControlServerOverride::Address(addr) => {
+                if !addresses.contains(addr) {
+                    tracing::error!(%addr, ?addresses, "control server override not in routing addresses");
+                    return Err(SetupError::ControlServerUnavailable(*self));
+                }
+                Ok(vec![*addr])
+            }
+            ControlServerOverride::ServerId(_) => Ok(addresses),
+        }
+    }
+
+    pub fn check_pong(&self, pong: &Pong) -> Result<(), SetupError> {
+        match self {
+            ControlServerOverride::ServerId(id) if pong.server_id != *id => {
+                tracing::error!(expected = id, got = pong.server_id, tunnel_addr = %pong.tunnel_addr, "control server override not matched by tunnel server");
+                Err(SetupError::ControlServerUnavailable(*self))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::ControlServerOverride;
+
+    #[test]
+    fn test_parse_and_filter() {
+        let addresses: Vec<SocketAddr> = vec!["[2602:fbaf::1]:5525".parse().unwrap(), "147.185.221.1:5525".parse().unwrap()];
+
+        let by_ip = "147.185.221.1".parse::<ControlServerOverride>().unwrap();
+        assert_eq!(by_ip.filter_addresses(addresses.clone()).unwrap(), vec![addresses[1]]);
+
+        let by_addr = "[2602:fbaf::1]:5525".parse::<ControlServerOverride>().unwrap();
+        assert_eq!(by_addr.filter_addresses(addresses.clone()).unwrap(), vec![addresses[0]]);
+
+        let missing = "147.185.221.2".parse::<ControlServerOverride>().unwrap();
+        assert!(missing.filter_addresses(addresses.clone()).is_err());
+
+        assert_eq!("42".parse::<ControlServerOverride>(), Ok(ControlServerOverride::ServerId(42)));
+        assert!("pop-1".parse::<ControlServerOverride>().is_err());
+    }
+}
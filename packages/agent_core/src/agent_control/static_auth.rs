@@ -0,0 +1,208 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use message_encoding::MessageEncoding;
+use playit_agent_proto::control_messages::{AgentRegister, ControlRequest, Pong};
+use playit_agent_proto::hmac::HmacSha256;
+use playit_api_client::api::SignedAgentKey;
+
+use crate::utils::now_milli;
+
+use super::errors::SetupError;
+use super::AuthResource;
+
+pub type RegisterSigner = dyn Fn(&mut AgentRegister) -> Result<(), SetupError> + Send + Sync;
+
+/* authenticates without the HTTP API, for air-gapped or self-hosted control planes */
+#[derive(Clone)]
+pub struct StaticAuthResource {
+    account_id: u64,
+    agent_id: u64,
+    addresses: Vec<SocketAddr>,
+    signer: Arc<RegisterSigner>,
+}
+
+impl StaticAuthResource {
+    /* signs registrations with the hmac secret shared with the control server */
+    pub fn new(secret: &[u8], account_id: u64, agent_id: u64, addresses: Vec<SocketAddr>) -> Self {
+        let hmac = HmacSha256::create(secret);
+
+        Self::with_signer(account_id, agent_id, addresses, move |register| {
+            register.update_signature(&mut Vec::new(), &hmac);
+            Ok(())
+        })
+    }
+
+    pub fn with_signer<F>(account_id: u64, agent_id: u64, addresses: Vec<SocketAddr>, signer: F) -> Self
+        where F: Fn(&mut AgentRegister) -> Result<(), SetupError> + Send + Sync + 'static
+    {
+        StaticAuthResource {
+            account_id,
+            agent_id,
+            addresses,
+            signer: Arc::new(signer),
+        }
+    }
+
+    fn sign(&self, pong: &Pong) -> Result<SignedAgentKey, SetupError> {
+        let mut register = AgentRegister {
+            account_id: self.account_id,
+            agent_id: self.agent_id,
+            agent_version: 0,
+            timestamp: now_milli(),
+            client_addr: pong.client_addr,
+            tunnel_addr: pong.tunnel_addr,
+            signature: [0u8; 32],
+        };
+
+        (self.signer)(&mut register)?;
+
+        let mut bytes = Vec::new();
+        ControlRequest::AgentRegister(register).write_to(&mut bytes)?;
+
+        Ok(SignedAgentKey { key: hex::encode(bytes) })
+    }
+}
+
+impl AuthResource for StaticAuthResource {
+    async fn authenticate(&self, pong: &Pong) -> Result<SignedAgentKey, SetupError> {
+        self.sign(pong)
+    }
+
+    async fn get_control_addresses(&self) -> Result<Vec<SocketAddr>, SetupError> {
+        if self.addresses.is_empty() {
+            return Err(SetupError::FailedToConnect);
+        }
+        Ok(self.addresses.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use message_encoding::MessageEncoding;
+    use playit_agent_proto::control_feed::{ClaimInstructions, ControlFeed, NewClient};
+    use playit_agent_proto::control_messages::{AgentRegistered, ControlRequest, ControlResponse, Pong};
+    use playit_agent_proto::hmac::HmacSha256;
+    use playit_agent_proto::rpc::ControlRpcMessage;
+    use playit_agent_proto::AgentSessionId;
+    use tokio::net::UdpSocket;
+
+    use crate::agent_control::errors::SetupError;
+    use crate::agent_control::maintained_control::{MaintainedControl, TunnelControlEvent};
+    use crate::agent_control::DualStackUdpSocket;
+    use crate::utils::now_milli;
+
+    use super::StaticAuthResource;
+
+    const SECRET: &[u8] = b"self-hosted-secret";
+
+    async fn mock_control_server() -> SocketAddr {
+        let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let hmac = HmacSha256::create(SECRET);
+            let mut buffer = vec![0u8; 2048];
+            let mut temp = Vec::new();
+
+            loop {
+                let (bytes, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let Ok(request) = ControlRpcMessage::<ControlRequest>::read_from(&mut &buffer[..bytes]) else { continue };
+
+                /* keepalives are answered and followed by a new TCP client, udp channel setup is never answered */
+                let new_client = matches!(request.content, ControlRequest::AgentKeepAlive(_));
+
+                let content = match request.content {
+                    ControlRequest::Ping(ping) => ControlResponse::Pong(Pong {
+                        request_now: ping.now,
+                        server_now: now_milli(),
+                        server_id: 1,
+                        data_center_id: 1,
+                        client_addr: peer,
+                        tunnel_addr: addr,
+                        session_expire_at: Some(now_milli() + 60_000),
+                    }),
+                    ControlRequest::AgentRegister(register) => {
+                        if register.verify_signature(&mut temp, &hmac) && register.client_addr == peer {
+                            ControlResponse::AgentRegistered(AgentRegistered {
+                                id: AgentSessionId {
+                                    session_id: 10,
+                                    account_id: register.account_id,
+                                    agent_id: register.agent_id,
+                                },
+                                expires_at: now_milli() + 60_000,
+                            })
+                        } else {
+                            ControlResponse::InvalidSignature
+                        }
+                    }
+                    ControlRequest::AgentKeepAlive(id) => ControlResponse::AgentRegistered(AgentRegistered {
+                        id,
+                        expires_at: now_milli() + 60_000,
+                    }),
+                    _ => continue,
+                };
+
+                let mut out = Vec::new();
+                ControlFeed::Response(ControlRpcMessage { request_id: request.request_id, content }).write_to(&mut out).unwrap();
+                socket.send_to(&out, peer).await.unwrap();
+
+                if new_client {
+                    let mut out = Vec::new();
+                    ControlFeed::NewClient(NewClient {
+                        connect_addr: "147.185.221.1:25565".parse().unwrap(),
+                        peer_addr: "1.2.3.4:5000".parse().unwrap(),
+                        claim_instructions: ClaimInstructions { address: addr, token: vec![1, 2, 3] },
+                        tunnel_server_id: 1,
+                        data_center_id: 1,
+                    }).write_to(&mut out).unwrap();
+                    socket.send_to(&out, peer).await.unwrap();
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_register_with_static_auth() {
+        let server = mock_control_server().await;
+
+        let auth = StaticAuthResource::new(SECRET, 1, 2, vec![server]);
+        let io = DualStackUdpSocket::new().await.unwrap();
+        assert!(MaintainedControl::setup(io, auth).await.is_ok());
+
+        let auth = StaticAuthResource::new(b"wrong-secret", 1, 2, vec![server]);
+        let io = DualStackUdpSocket::new().await.unwrap();
+        assert!(matches!(MaintainedControl::setup(io, auth).await, Err(SetupError::RegisterInvalidSignature)));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_clients_while_udp_setup_fails() {
+        let server = mock_control_server().await;
+
+        let auth = StaticAuthResource::new(SECRET, 1, 2, vec![server]);
+        let io = DualStackUdpSocket::new().await.unwrap();
+        let mut control = MaintainedControl::setup(io, auth).await.unwrap();
+
+        /* unanswered udp setup backs off on its own */
+        let start = now_milli();
+        assert!(control.send_udp_session_auth(start).await);
+        assert!(!control.send_udp_session_auth(start + 1_000).await);
+
+        let new_client = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                match control.update().await {
+                    Some(TunnelControlEvent::NewClient(new_client)) => break new_client,
+                    Some(TunnelControlEvent::UdpChannelDetails(_)) => panic!("mock never sets up the udp channel"),
+                    _ => {}
+                }
+            }
+        }).await.expect("TCP client not delivered while udp setup is failing");
+
+        assert_eq!(new_client.peer_addr, "1.2.3.4:5000".parse().unwrap());
+        assert!(control.cached_udp_details(now_milli()).is_none());
+    }
+}
@@ -11,7 +11,7 @@ use playit_agent_proto::control_messages::UdpChannelDetails;
 use crate::agent_control::udp_proto::{UDP_CHANNEL_ESTABLISH_ID, UdpFlow};
 use crate::utils::now_sec;
 
-use super::PacketTx;
+use super::{PacketTx, KEEPALIVE_LOG_TARGET};
 
 pub struct UdpChannel {
     inner: Arc<Inner>,
@@ -113,7 +113,7 @@ impl UdpChannel {
 
         io.send_to(&details.token, details.tunnel_addr).await?;
 
-        tracing::info!(token_len = details.token.len(), tunnel_addr = %details.tunnel_addr, "send udp session token");
+        tracing::debug!(target: KEEPALIVE_LOG_TARGET, token_len = details.token.len(), tunnel_addr = %details.tunnel_addr, "send udp session token");
         self.inner.last_send.store(now_sec(), Ordering::SeqCst);
 
         Ok(())
@@ -174,8 +174,8 @@ impl UdpChannel {
 
         let mut lock = self.inner.details.write().await;
         match &lock.udp {
-            Some(current) if buffer[..bytes].eq(&current.token[..]) => {
-                tracing::info!(token_len = bytes, tunnel_addr = %remote, "udp session confirmed");
+            Some(current) if current.tunnel_addr == remote && buffer[..bytes].eq(&current.token[..]) => {
+                tracing::debug!(target: KEEPALIVE_LOG_TARGET, token_len = bytes, tunnel_addr = %remote, "udp session confirmed");
                 Ok(UdpTunnelRx::ConfirmedConnection)
             }
             _ => {
@@ -187,7 +187,7 @@ impl UdpChannel {
 
                 if let Some(old) = old {
                     if old.tunnel_addr != remote {
-                        tracing::info!(tunnel_addr = %old.tunnel_addr, "saving old tunnel address");
+                        tracing::info!(old_tunnel_addr = %old.tunnel_addr, new_tunnel_addr = %remote, "udp flows migrated to new tunnel address");
 
                         lock.addr_history.push_front(old.tunnel_addr);
                         if 8 < lock.addr_history.len() {
@@ -237,3 +237,71 @@ pub enum UdpTunnelRx {
     ConfirmedConnection,
     UpdatedConnection,
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use playit_agent_proto::control_messages::UdpChannelDetails;
+
+    use crate::agent_control::udp_proto::{UdpFlow, UDP_CHANNEL_ESTABLISH_ID};
+    use crate::agent_control::PacketTx;
+
+    use super::{UdpChannel, UdpTunnelRx};
+
+    #[derive(Default)]
+    struct RecordTx {
+        targets: Mutex<Vec<SocketAddr>>,
+    }
+
+    impl PacketTx for RecordTx {
+        async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+            self.targets.lock().unwrap().push(target);
+            Ok(buf.len())
+        }
+    }
+
+    async fn establish(channel: &UdpChannel, io: &RecordTx, tunnel_addr: SocketAddr, token: &[u8]) -> UdpTunnelRx {
+        let details = UdpChannelDetails {
+            tunnel_addr,
+            token: Arc::new(token.to_vec()),
+        };
+        channel.send_token(&details, io).await.unwrap();
+
+        /* tunnel server echos token followed by establish footer */
+        let mut reply = token.to_vec();
+        reply.extend_from_slice(&UDP_CHANNEL_ESTABLISH_ID.to_be_bytes());
+        channel.parse_packet(&reply, reply.len(), tunnel_addr).await.unwrap()
+    }
+
+    async fn send_host(channel: &UdpChannel, io: &RecordTx) -> SocketAddr {
+        let flow = UdpFlow::V4 {
+            src: "1.2.3.4:5000".parse().unwrap(),
+            dst: "5.6.7.8:6000".parse().unwrap(),
+        };
+
+        let mut buffer = [0u8; 64];
+        channel.send_host_pkt(&mut buffer, 4, flow, io).await.unwrap();
+        *io.targets.lock().unwrap().last().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flows_migrate_on_updated_connection() {
+        let channel = UdpChannel::new();
+        let io = RecordTx::default();
+
+        let old_addr: SocketAddr = "10.0.0.1:5525".parse().unwrap();
+        let new_addr: SocketAddr = "10.0.0.2:5525".parse().unwrap();
+
+        assert!(matches!(establish(&channel, &io, old_addr, b"token-a").await, UdpTunnelRx::UpdatedConnection));
+        assert_eq!(send_host(&channel, &io).await, old_addr);
+
+        assert!(matches!(establish(&channel, &io, new_addr, b"token-b").await, UdpTunnelRx::UpdatedConnection));
+        assert_eq!(send_host(&channel, &io).await, new_addr);
+
+        /* same token re-issued for a different tunnel address must still migrate */
+        assert!(matches!(establish(&channel, &io, old_addr, b"token-b").await, UdpTunnelRx::UpdatedConnection));
+        assert_eq!(send_host(&channel, &io).await, old_addr);
+    }
+}
@@ -0,0 +1,206 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use message_encoding::MessageEncoding;
+use playit_agent_proto::{control_feed::ControlFeed, control_messages::{ControlRequest, ControlResponse, Ping}, rpc::ControlRpcMessage};
+
+use crate::utils::now_milli;
+
+use super::udp_proto::UdpFlow;
+use super::PacketIO;
+
+/*
+ * Sends the same unauthenticated ping the control channel starts with and
+ * waits for the pong, used to check if UDP to the tunnel servers works
+ * through the local NAT / firewall. Nothing is registered.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpProbeResult {
+    pub addr: SocketAddr,
+    /* round trip of the first answered ping, None if every attempt timed out */
+    pub latency: Option<Duration>,
+}
+
+pub async fn probe_udp<IO: PacketIO>(io: &IO, addr: SocketAddr, attempts: usize, timeout: Duration) -> std::io::Result<UdpProbeResult> {
+    let mut buffer = Vec::new();
+
+    for attempt in 0..attempts {
+        let latency = ping_once(io, addr, attempt as u64 + 1, 0, timeout, &mut buffer).await?;
+        if latency.is_some() {
+            return Ok(UdpProbeResult { addr, latency });
+        }
+    }
+
+    Ok(UdpProbeResult { addr, latency: None })
+}
+
+/*
+ * Path MTU check: pings padded to increasing sizes, packets over the path MTU
+ * are often dropped without any error so "it connects but nothing works".
+ * Tunneled packets carry a flow footer, REQUIRED_PATH_SIZE leaves room for
+ * 1200 byte game packets (the common upper bound) with the IPv6 footer.
+ */
+
+pub const PATH_PROBE_SIZES: [usize; 6] = [576, 1024, 1248, 1280, 1400, 1472];
+pub const REQUIRED_PATH_SIZE: usize = 1200 + UdpFlow::len_v6();
+
+/* largest of sizes (ascending) that got a pong, None if not even the smallest did */
+pub async fn probe_path_size<IO: PacketIO>(io: &IO, addr: SocketAddr, sizes: &[usize], attempts: usize, timeout: Duration) -> std::io::Result<Option<usize>> {
+    let mut buffer = Vec::new();
+    let mut largest = None;
+    let mut request_id = 0;
+
+    'sizes: for &size in sizes {
+        for _ in 0..attempts {
+            request_id += 1;
+
+            match ping_once(io, addr, request_id, size, timeout, &mut buffer).await {
+                Ok(Some(_)) => {
+                    largest = Some(size);
+                    continue 'sizes;
+                }
+                Ok(None) => {}
+                /* ex. EMSGSIZE when the local interface MTU is smaller */
+                Err(error) => {
+                    tracing::debug!(?error, size, "failed to send path probe");
+                    break;
+                }
+            }
+        }
+
+        break;
+    }
+
+    Ok(largest)
+}
+
+/* logs the result, run once the control connection is up */
+pub async fn log_path_size<IO: PacketIO>(io: &IO, addr: SocketAddr) {
+    match probe_path_size(io, addr, &PATH_PROBE_SIZES, 2, Duration::from_millis(500)).await {
+        Ok(Some(size)) if size < REQUIRED_PATH_SIZE => {
+            tracing::warn!(%addr, safe_size = size, required = REQUIRED_PATH_SIZE, "UDP packets larger than {} bytes do not reach the tunnel server, large packets may be dropped (check MTU of VPN / PPPoE links)", size);
+        }
+        Ok(Some(size)) => tracing::info!(%addr, safe_size = size, "UDP path size to tunnel server"),
+        Ok(None) => tracing::info!(%addr, "tunnel server did not answer path size probes, skipping MTU check"),
+        Err(error) => tracing::warn!(?error, %addr, "failed to probe UDP path size"),
+    }
+}
+
+/* round trip of a ping padded with zeros up to size, None on timeout */
+async fn ping_once<IO: PacketIO>(io: &IO, addr: SocketAddr, request_id: u64, size: usize, timeout: Duration, buffer: &mut Vec<u8>) -> std::io::Result<Option<Duration>> {
+    buffer.clear();
+    ControlRpcMessage {
+        request_id,
+        content: ControlRequest::Ping(Ping {
+            now: now_milli(),
+            current_ping: None,
+            session_id: None,
+        }),
+    }.write_to(buffer)?;
+
+    if buffer.len() < size {
+        buffer.resize(size, 0);
+    }
+
+    let sent_at = Instant::now();
+    io.send_to(buffer, addr).await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    buffer.resize(2048, 0);
+
+    while let Ok(received) = tokio::time::timeout_at(deadline, io.recv_from(buffer)).await {
+        let (bytes, peer) = received?;
+        if peer != addr {
+            continue;
+        }
+
+        let mut reader = &buffer[..bytes];
+        if let Ok(ControlFeed::Response(msg)) = ControlFeed::read_from(&mut reader) {
+            if msg.request_id == request_id && matches!(msg.content, ControlResponse::Pong(_)) {
+                return Ok(Some(sent_at.elapsed()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use message_encoding::MessageEncoding;
+    use playit_agent_proto::{control_feed::ControlFeed, control_messages::{ControlRequest, ControlResponse, Pong}, rpc::ControlRpcMessage};
+    use tokio::net::UdpSocket;
+
+    use super::{probe_path_size, probe_udp, PATH_PROBE_SIZES};
+
+    /* answers pings up to max_size bytes, skips the first skip_first pings */
+    async fn spawn_pong_server(max_size: usize, skip_first: usize) -> SocketAddr {
+        let server = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 2048];
+            let mut pings = 0;
+            loop {
+                let (bytes, from) = server.recv_from(&mut buffer).await.unwrap();
+                let msg = ControlRpcMessage::<ControlRequest>::read_from(&mut &buffer[..bytes]).unwrap();
+                pings += 1;
+                if pings <= skip_first || max_size < bytes {
+                    continue;
+                }
+
+                let mut out = Vec::new();
+                ControlFeed::Response(ControlRpcMessage {
+                    request_id: msg.request_id,
+                    content: ControlResponse::Pong(Pong {
+                        request_now: 0,
+                        server_now: 0,
+                        server_id: 1,
+                        data_center_id: 1,
+                        client_addr: from,
+                        tunnel_addr: server_addr,
+                        session_expire_at: None,
+                    }),
+                }).write_to(&mut out).unwrap();
+                server.send_to(&out, from).await.unwrap();
+            }
+        });
+
+        server_addr
+    }
+
+    #[tokio::test]
+    async fn test_probe_udp() {
+        /* the first ping is "lost" */
+        let server_addr = spawn_pong_server(2048, 1).await;
+
+        let client = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        let result = probe_udp(&client, server_addr, 3, Duration::from_millis(200)).await.unwrap();
+        assert!(result.latency.is_some());
+
+        let silent = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        let result = probe_udp(&client, silent.local_addr().unwrap(), 2, Duration::from_millis(50)).await.unwrap();
+        assert_eq!(result.latency, None);
+    }
+
+    #[tokio::test]
+    async fn test_probe_path_size() {
+        let client = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+
+        let limited = spawn_pong_server(1300, 0).await;
+        let size = probe_path_size(&client, limited, &PATH_PROBE_SIZES, 2, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(size, Some(1280));
+
+        let open = spawn_pong_server(2048, 0).await;
+        let size = probe_path_size(&client, open, &PATH_PROBE_SIZES, 2, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(size, Some(1472));
+
+        let silent = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await.unwrap();
+        let size = probe_path_size(&client, silent.local_addr().unwrap(), &PATH_PROBE_SIZES, 1, Duration::from_millis(50)).await.unwrap();
+        assert_eq!(size, None);
+    }
+}
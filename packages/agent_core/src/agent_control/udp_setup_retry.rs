@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+/*
+ * Retry schedule for SetupUdpChannel, kept apart from everything TCP uses.
+ * An unanswered request is retried with backoff (UDP_SETUP_MIN_RETRY_MS
+ * doubling up to UDP_SETUP_MAX_RETRY_MS) and sending one is bounded by
+ * UDP_SETUP_SEND_TIMEOUT, so a UDP channel that can't be set up never holds
+ * up the control loop handing out new TCP clients.
+ */
+
+pub const UDP_SETUP_MIN_RETRY_MS: u64 = 5_000;
+pub const UDP_SETUP_MAX_RETRY_MS: u64 = 60_000;
+pub const UDP_SETUP_SEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct UdpSetupRetry {
+    next_at: u64,
+    interval: u64,
+}
+
+impl Default for UdpSetupRetry {
+    fn default() -> Self {
+        UdpSetupRetry {
+            next_at: 0,
+            interval: UDP_SETUP_MIN_RETRY_MS,
+        }
+    }
+}
+
+impl UdpSetupRetry {
+    /* true when a request should be sent now, each attempt waits longer for the next */
+    pub fn should_send(&mut self, now_ms: u64) -> bool {
+        if now_ms < self.next_at {
+            return false;
+        }
+
+        self.next_at = now_ms + self.interval;
+        self.interval = (self.interval * 2).min(UDP_SETUP_MAX_RETRY_MS);
+        true
+    }
+
+    /* details arrived, the next time the channel needs auth starts from the minimum again */
+    pub fn succeeded(&mut self) {
+        self.interval = UDP_SETUP_MIN_RETRY_MS;
+    }
+
+    /* new session, request the channel right away */
+    pub fn reset(&mut self) {
+        *self = UdpSetupRetry::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UdpSetupRetry, UDP_SETUP_MAX_RETRY_MS, UDP_SETUP_MIN_RETRY_MS};
+
+    #[test]
+    fn test_udp_setup_backoff() {
+        let mut retry = UdpSetupRetry::default();
+        assert!(retry.should_send(1_000));
+        assert!(!retry.should_send(1_000 + UDP_SETUP_MIN_RETRY_MS - 1));
+        assert!(retry.should_send(1_000 + UDP_SETUP_MIN_RETRY_MS));
+
+        /* second retry waits twice as long */
+        let at = 1_000 + UDP_SETUP_MIN_RETRY_MS;
+        assert!(!retry.should_send(at + UDP_SETUP_MIN_RETRY_MS));
+        assert!(retry.should_send(at + 2 * UDP_SETUP_MIN_RETRY_MS));
+
+        let mut now = at + 2 * UDP_SETUP_MIN_RETRY_MS;
+        for _ in 0..10 {
+            now += UDP_SETUP_MAX_RETRY_MS;
+            assert!(retry.should_send(now));
+        }
+
+        retry.succeeded();
+        assert!(retry.should_send(now + UDP_SETUP_MAX_RETRY_MS));
+        assert!(retry.should_send(now + UDP_SETUP_MAX_RETRY_MS + UDP_SETUP_MIN_RETRY_MS));
+
+        retry.reset();
+        assert!(retry.should_send(0));
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWrite;
+use uuid::Uuid;
+
+/*
+ * Optional access log of TCP connections (--access_log) in Common Log
+ * Format so tunnels in front of HTTP services work with existing log
+ * tooling. One line is written when a connection closes:
+ *
+ *   common:   <client ip> - - [<opened at>] "CONNECT <tunnel addr> TCP" 200 <bytes sent to client>
+ *   combined: <common> "-" "-" <bytes received from client> <duration ms>
+ *
+ * The request line is made up, the agent does not parse HTTP. Referer and
+ * user agent are always "-", the two trailing fields extend the Combined
+ * format like most servers' custom formats do.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Common,
+    Combined,
+}
+
+#[derive(Clone, Default)]
+pub struct AccessLog {
+    output: Option<Arc<AccessLogOutput>>,
+}
+
+struct AccessLogOutput {
+    format: AccessLogFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new<W: Write + Send + 'static>(format: AccessLogFormat, writer: W) -> Self {
+        AccessLog {
+            output: Some(Arc::new(AccessLogOutput {
+                format,
+                writer: Mutex::new(Box::new(writer)),
+            })),
+        }
+    }
+
+    /* None when disabled, the entry is written once every clone is dropped */
+    pub fn connected(&self, tunnel_id: Uuid, peer_addr: SocketAddr, tunnel_addr: SocketAddr) -> Option<Arc<AccessLogEntry>> {
+        let output = self.output.clone()?;
+
+        Some(Arc::new(AccessLogEntry {
+            output,
+            tunnel_id,
+            peer_addr,
+            tunnel_addr,
+            opened_at: Utc::now(),
+            started: Instant::now(),
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+            bytes_received: Arc::new(AtomicU64::new(0)),
+        }))
+    }
+}
+
+pub struct AccessLogEntry {
+    output: Arc<AccessLogOutput>,
+    tunnel_id: Uuid,
+    peer_addr: SocketAddr,
+    tunnel_addr: SocketAddr,
+    opened_at: DateTime<Utc>,
+    started: Instant,
+    bytes_sent: Arc<AtomicU64>,
+    bytes_received: Arc<AtomicU64>,
+}
+
+impl AccessLogEntry {
+    /* counter for bytes written to the client */
+    pub fn sent_counter(&self) -> Arc<AtomicU64> {
+        self.bytes_sent.clone()
+    }
+
+    /* counter for bytes written to the local server */
+    pub fn received_counter(&self) -> Arc<AtomicU64> {
+        self.bytes_received.clone()
+    }
+
+    fn line(&self, duration_ms: u128) -> String {
+        let mut line = format!(
+            "{} - - [{}] \"CONNECT {} TCP\" 200 {}",
+            self.peer_addr.ip(),
+            self.opened_at.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.tunnel_addr,
+            self.bytes_sent.load(Ordering::Relaxed),
+        );
+
+        if self.output.format == AccessLogFormat::Combined {
+            line.push_str(&format!(
+                " \"-\" \"-\" {} {}",
+                self.bytes_received.load(Ordering::Relaxed),
+                duration_ms,
+            ));
+        }
+
+        line
+    }
+}
+
+impl Drop for AccessLogEntry {
+    fn drop(&mut self) {
+        let line = self.line(self.started.elapsed().as_millis());
+        let mut writer = self.output.writer.lock().unwrap();
+
+        if let Err(error) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::error!(?error, tunnel_id = %self.tunnel_id, "failed to write access log");
+        }
+    }
+}
+
+/* counts bytes written into an optional per connection counter */
+pub struct CountingWrite<W> {
+    inner: W,
+    counter: Option<Arc<AtomicU64>>,
+}
+
+impl<W> CountingWrite<W> {
+    pub fn new(inner: W, counter: Option<Arc<AtomicU64>>) -> Self {
+        CountingWrite { inner, counter }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWrite<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(written)), Some(counter)) = (&result, &self.counter) {
+            counter.fetch_add(*written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::AsyncWriteExt;
+    use uuid::Uuid;
+
+    use super::{AccessLog, AccessLogFormat, CountingWrite};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_log_lines() {
+        assert!(AccessLog::default().connected(Uuid::nil(), "1.2.3.4:5000".parse().unwrap(), "147.185.221.1:80".parse().unwrap()).is_none());
+
+        for (format, fields) in [(AccessLogFormat::Common, 10), (AccessLogFormat::Combined, 14)] {
+            let buf = SharedBuf::default();
+            let log = AccessLog::new(format, buf.clone());
+
+            let entry = log.connected(Uuid::nil(), "1.2.3.4:5000".parse().unwrap(), "147.185.221.1:80".parse().unwrap()).unwrap();
+            let mut to_client = CountingWrite::new(Vec::new(), Some(entry.sent_counter()));
+            to_client.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+            let mut to_local = CountingWrite::new(Vec::new(), Some(entry.received_counter()));
+            to_local.write_all(b"GET /").await.unwrap();
+
+            let other_pipe = entry.clone();
+            drop(entry);
+            assert!(buf.0.lock().unwrap().is_empty());
+            drop(other_pipe);
+
+            let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+            let parts: Vec<&str> = text.split_whitespace().collect();
+            assert_eq!(parts.len(), fields, "{}", text);
+            assert_eq!(parts[0], "1.2.3.4");
+            assert!(parts[3].starts_with('[') && parts[4] == "+0000]");
+            assert_eq!(&parts[5..10], &["\"CONNECT", "147.185.221.1:80", "TCP\"", "200", "19"]);
+
+            if format == AccessLogFormat::Combined {
+                assert_eq!(&parts[10..13], &["\"-\"", "\"-\"", "5"]);
+            }
+        }
+    }
+}
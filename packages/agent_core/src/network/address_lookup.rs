@@ -1,7 +1,7 @@
 use std::net::{IpAddr, SocketAddr};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use playit_api_client::api::{PortType, ProxyProtocol};
+use playit_api_client::api::{PortRange, PortType, ProxyProtocol};
 use uuid::Uuid;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -29,10 +29,72 @@ impl<T: AddressLookup> AddressLookup for Arc<T> {
 pub struct HostOrigin {
     pub tunnel_id: Uuid,
     pub host_addr: SocketAddr,
+    /* local address of the other IP family, used when matching the client's family */
+    pub alt_host_addr: Option<SocketAddr>,
     pub use_special_lan: Option<bool>,
     pub proxy_protocol: Option<ProxyProtocol>,
 }
 
+impl HostOrigin {
+    pub fn new(tunnel_id: Uuid, host_addr: SocketAddr) -> Self {
+        HostOrigin {
+            tunnel_id,
+            host_addr,
+            alt_host_addr: None,
+            use_special_lan: None,
+            proxy_protocol: None,
+        }
+    }
+
+    pub fn with_proxy_protocol(mut self, proxy_protocol: Option<ProxyProtocol>) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    pub fn with_special_lan(mut self, use_special_lan: bool) -> Self {
+        self.use_special_lan = Some(use_special_lan);
+        self
+    }
+
+    pub fn with_alt_host_addr(mut self, alt_host_addr: Option<SocketAddr>) -> Self {
+        self.alt_host_addr = alt_host_addr;
+        self
+    }
+
+    /*
+     * Picks between host_addr and alt_host_addr (other IP family), falls back
+     * to host_addr. Matching the client's family takes precedence, ipv6_first
+     * only decides when the client's family is not considered.
+     */
+    pub fn select_host_addr(&self, client_ip: IpAddr, selection: LocalAddrSelection) -> SocketAddr {
+        let want_ip4 = if selection.match_client_family {
+            client_ip.to_canonical().is_ipv4()
+        } else if selection.ipv6_first {
+            false
+        } else {
+            return self.host_addr;
+        };
+
+        if self.host_addr.ip().to_canonical().is_ipv4() == want_ip4 {
+            return self.host_addr;
+        }
+
+        match self.alt_host_addr {
+            Some(alt) if alt.ip().to_canonical().is_ipv4() == want_ip4 => alt,
+            _ => self.host_addr,
+        }
+    }
+}
+
+/* how the local address is chosen when a tunnel has both an IPv4 and IPv6 target */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalAddrSelection {
+    /* use the local address with the same IP family as the client */
+    pub match_client_family: bool,
+    /* otherwise prefer the IPv6 local address, ex. when IPv4 goes through NAT64 */
+    pub ipv6_first: bool,
+}
+
 impl std::fmt::Display for HostOrigin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "HostOrigin({}, special: {:?}, proxy: {:?})", self.host_addr, self.use_special_lan, self.proxy_protocol)
@@ -47,11 +109,228 @@ impl Into<SocketAddr> for HostOrigin {
 
 impl From<SocketAddr> for HostOrigin {
     fn from(value: SocketAddr) -> Self {
-        HostOrigin {
-            tunnel_id: Uuid::default(),
-            host_addr: value,
-            use_special_lan: None,
-            proxy_protocol: None,
+        HostOrigin::new(Uuid::default(), value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfLoop {
+    TunnelAddress,
+    ServerAddress,
+}
+
+/* local target pointing back into playit would loop traffic through the agent */
+pub fn check_self_loop(target: SocketAddr, tunnel_addr: SocketAddr, server_ips: &[IpAddr]) -> Option<SelfLoop> {
+    let target_ip = target.ip().to_canonical();
+
+    if target_ip == tunnel_addr.ip().to_canonical() && target.port() == tunnel_addr.port() {
+        return Some(SelfLoop::TunnelAddress);
+    }
+
+    if server_ips.iter().any(|ip| ip.to_canonical() == target_ip) {
+        return Some(SelfLoop::ServerAddress);
+    }
+
+    None
+}
+
+/// Maps tunnel port ranges to local addresses, for programs embedding the agent.
+///
+/// `local_addr` is the address for the first port of the range, a connection to
+/// `port_range.from + n` is forwarded to `local_addr.port() + n`.
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use playit_agent_core::network::address_lookup::StaticAddressLookup;
+/// use playit_agent_core::playit_agent::PlayitAgent;
+/// use playit_api_client::api::{PortRange, PortType};
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let lookup = Arc::new(StaticAddressLookup::default());
+/// lookup.insert(
+///     "a0ee2dd1-4a0c-4e2d-8c9f-7c1e6b8b4a10".parse()?,
+///     PortType::Both,
+///     PortRange { from: 25565, to: 25566 },
+///     "127.0.0.1:25565".parse()?,
+///     None,
+/// )?;
+///
+/// let agent = PlayitAgent::new("https://api.playit.gg".to_string(), "<secret>".to_string(), lookup).await?;
+/// agent.run().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct StaticAddressLookup {
+    entries: RwLock<Vec<StaticEntry>>,
+}
+
+struct StaticEntry {
+    proto: PortType,
+    from_port: u16,
+    to_port: u16,
+    origin: HostOrigin,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StaticLookupError {
+    EmptyPortRange,
+    Overlapping { tunnel_id: Uuid },
+}
+
+impl std::fmt::Display for StaticLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for StaticLookupError {
+}
+
+impl StaticAddressLookup {
+    pub fn insert(&self, tunnel_id: Uuid, proto: PortType, port_range: PortRange, local_addr: SocketAddr, proxy_protocol: Option<ProxyProtocol>) -> Result<(), StaticLookupError> {
+        self.insert_origin(proto, port_range, HostOrigin::new(tunnel_id, local_addr).with_proxy_protocol(proxy_protocol))
+    }
+
+    pub fn insert_origin(&self, proto: PortType, port_range: PortRange, origin: HostOrigin) -> Result<(), StaticLookupError> {
+        if port_range.to <= port_range.from {
+            return Err(StaticLookupError::EmptyPortRange);
         }
+
+        let mut entries = self.entries.write().unwrap();
+
+        let overlapping = entries.iter().find(|entry| {
+            (entry.proto.matches(proto) || proto.matches(entry.proto))
+                && entry.from_port < port_range.to
+                && port_range.from < entry.to_port
+        });
+
+        if let Some(entry) = overlapping {
+            return Err(StaticLookupError::Overlapping { tunnel_id: entry.origin.tunnel_id });
+        }
+
+        entries.push(StaticEntry {
+            proto,
+            from_port: port_range.from,
+            to_port: port_range.to,
+            origin,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove(&self, tunnel_id: Uuid) {
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|entry| entry.origin.tunnel_id != tunnel_id);
+    }
+}
+
+impl AddressLookup for StaticAddressLookup {
+    type Value = HostOrigin;
+
+    fn lookup(&self, _ip: IpAddr, port: u16, proto: PortType) -> Option<AddressValue<HostOrigin>> {
+        let entries = self.entries.read().unwrap();
+
+        let entry = entries.iter().find(|entry| {
+            entry.proto.matches(proto) && entry.from_port <= port && port < entry.to_port
+        })?;
+
+        Some(AddressValue {
+            value: entry.origin.clone(),
+            from_port: entry.from_port,
+            to_port: entry.to_port,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use playit_api_client::api::{PortRange, PortType};
+    use uuid::Uuid;
+
+    use super::{check_self_loop, AddressLookup, HostOrigin, LocalAddrSelection, SelfLoop, StaticAddressLookup, StaticLookupError};
+
+    #[test]
+    fn test_static_lookup() {
+        let lookup = StaticAddressLookup::default();
+        let tunnel_ip = IpAddr::V4(Ipv4Addr::new(147, 185, 221, 1));
+
+        lookup.insert(Uuid::from_u128(1), PortType::Tcp, PortRange { from: 100, to: 110 }, "127.0.0.1:8000".parse().unwrap(), None).unwrap();
+        lookup.insert(Uuid::from_u128(2), PortType::Udp, PortRange { from: 100, to: 110 }, "127.0.0.1:9000".parse().unwrap(), None).unwrap();
+
+        assert_eq!(
+            lookup.insert(Uuid::from_u128(3), PortType::Both, PortRange { from: 105, to: 120 }, "127.0.0.1:7000".parse().unwrap(), None),
+            Err(StaticLookupError::Overlapping { tunnel_id: Uuid::from_u128(1) }),
+        );
+        assert_eq!(
+            lookup.insert(Uuid::from_u128(3), PortType::Both, PortRange { from: 120, to: 120 }, "127.0.0.1:7000".parse().unwrap(), None),
+            Err(StaticLookupError::EmptyPortRange),
+        );
+
+        let tcp = lookup.lookup(tunnel_ip, 105, PortType::Tcp).unwrap();
+        assert_eq!(tcp.value.tunnel_id, Uuid::from_u128(1));
+        assert_eq!((tcp.from_port, tcp.to_port), (100, 110));
+
+        let udp = lookup.lookup(tunnel_ip, 109, PortType::Udp).unwrap();
+        assert_eq!(udp.value.tunnel_id, Uuid::from_u128(2));
+
+        assert!(lookup.lookup(tunnel_ip, 110, PortType::Tcp).is_none());
+
+        lookup.remove(Uuid::from_u128(1));
+        assert!(lookup.lookup(tunnel_ip, 105, PortType::Tcp).is_none());
+    }
+
+    #[test]
+    fn test_check_self_loop() {
+        let tunnel_addr: SocketAddr = "147.185.221.1:25565".parse().unwrap();
+        let server_ips: Vec<IpAddr> = vec!["209.25.140.1".parse().unwrap()];
+
+        assert_eq!(check_self_loop("127.0.0.1:25565".parse().unwrap(), tunnel_addr, &server_ips), None);
+        assert_eq!(check_self_loop("147.185.221.1:25566".parse().unwrap(), tunnel_addr, &server_ips), None);
+        assert_eq!(check_self_loop(tunnel_addr, tunnel_addr, &server_ips), Some(SelfLoop::TunnelAddress));
+        assert_eq!(check_self_loop("[::ffff:147.185.221.1]:25565".parse().unwrap(), tunnel_addr, &server_ips), Some(SelfLoop::TunnelAddress));
+        assert_eq!(check_self_loop("209.25.140.1:80".parse().unwrap(), tunnel_addr, &server_ips), Some(SelfLoop::ServerAddress));
+    }
+
+    #[test]
+    fn test_select_host_addr() {
+        let ip4_client: IpAddr = "1.2.3.4".parse().unwrap();
+        let ip6_client: IpAddr = "2001:db8::1".parse().unwrap();
+        let local4: SocketAddr = "127.0.0.1:25565".parse().unwrap();
+        let local6: SocketAddr = "[::1]:25565".parse().unwrap();
+        let matching = LocalAddrSelection { match_client_family: true, ipv6_first: false };
+        let first_addr = LocalAddrSelection::default();
+        let ipv6_first = LocalAddrSelection { match_client_family: false, ipv6_first: true };
+
+        let both = HostOrigin::new(Uuid::default(), local4).with_alt_host_addr(Some(local6));
+        assert_eq!(both.select_host_addr(ip4_client, matching), local4);
+        assert_eq!(both.select_host_addr(ip6_client, matching), local6);
+        assert_eq!(both.select_host_addr(ip6_client, first_addr), local4);
+
+        /* v4 mapped clients are treated as v4 */
+        assert_eq!(both.select_host_addr("::ffff:1.2.3.4".parse().unwrap(), matching), local4);
+
+        let both = HostOrigin::new(Uuid::default(), local6).with_alt_host_addr(Some(local4));
+        assert_eq!(both.select_host_addr(ip4_client, matching), local4);
+        assert_eq!(both.select_host_addr(ip6_client, matching), local6);
+        assert_eq!(both.select_host_addr(ip4_client, first_addr), local6);
+
+        /* v6 first regardless of the client, unless matching the client's family */
+        let both = HostOrigin::new(Uuid::default(), local4).with_alt_host_addr(Some(local6));
+        assert_eq!(both.select_host_addr(ip4_client, ipv6_first), local6);
+        assert_eq!(both.select_host_addr(ip6_client, ipv6_first), local6);
+        assert_eq!(both.select_host_addr(ip4_client, LocalAddrSelection { match_client_family: true, ipv6_first: true }), local4);
+
+        let only4 = HostOrigin::new(Uuid::default(), local4);
+        assert_eq!(only4.select_host_addr(ip4_client, matching), local4);
+        assert_eq!(only4.select_host_addr(ip6_client, matching), local4);
+        assert_eq!(only4.select_host_addr(ip4_client, ipv6_first), local4);
+
+        let only6 = HostOrigin::new(Uuid::default(), local6);
+        assert_eq!(only6.select_host_addr(ip4_client, matching), local6);
+        assert_eq!(only6.select_host_addr(ip6_client, matching), local6);
     }
 }
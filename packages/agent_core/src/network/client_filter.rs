@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::utils::redact::ClientAddr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientIpFilter {
+    #[default]
+    Any,
+    Ip4Only,
+    Ip6Only,
+}
+
+impl ClientIpFilter {
+    pub fn allows(&self, peer_addr: SocketAddr) -> bool {
+        match self {
+            ClientIpFilter::Any => true,
+            ClientIpFilter::Ip4Only => peer_addr.is_ipv4(),
+            ClientIpFilter::Ip6Only => peer_addr.is_ipv6(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ClientFilter {
+    pub ip_filter: ClientIpFilter,
+    dropped: Arc<AtomicU64>,
+}
+
+impl ClientFilter {
+    pub fn new(ip_filter: ClientIpFilter) -> Self {
+        ClientFilter {
+            ip_filter,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /* returns false and counts the client if it should be dropped */
+    pub fn check(&self, peer_addr: SocketAddr) -> bool {
+        if self.ip_filter.allows(peer_addr) {
+            return true;
+        }
+
+        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!(peer_addr = %ClientAddr(peer_addr), filter = ?self.ip_filter, dropped, "dropping client rejected by ip filter");
+        false
+    }
+
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /* returns the count before the reset, drops counted concurrently land on one side of it */
+    pub fn reset_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClientFilter, ClientIpFilter};
+
+    #[test]
+    fn test_client_ip_filter() {
+        let ip4 = "1.2.3.4:5000".parse().unwrap();
+        let ip6 = "[2602:fbaf::1]:5000".parse().unwrap();
+
+        let any = ClientFilter::default();
+        assert!(any.check(ip4));
+        assert!(any.check(ip6));
+        assert_eq!(any.dropped_count(), 0);
+
+        let ip6_only = ClientFilter::new(ClientIpFilter::Ip6Only);
+        assert!(!ip6_only.check(ip4));
+        assert!(ip6_only.check(ip6));
+        assert!(!ip6_only.check(ip4));
+        assert_eq!(ip6_only.dropped_count(), 2);
+
+        let ip4_only = ClientFilter::new(ClientIpFilter::Ip4Only);
+        assert!(ip4_only.check(ip4));
+        assert!(!ip4_only.check(ip6));
+        assert_eq!(ip4_only.dropped_count(), 1);
+    }
+}
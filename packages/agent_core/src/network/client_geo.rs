@@ -0,0 +1,178 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::utils::redact::ClientIp;
+
+/*
+ * Optional country / ASN hint logged when a client connects (feature "client-geo").
+ *
+ * Lookups only use a local database file supplied by the user, nothing is sent
+ * over the network. The file uses the ip2asn TSV layout (as published by
+ * iptoasn.com, GeoLite2 ASN/country CSVs convert to it easily):
+ *
+ *   range_start <TAB> range_end <TAB> as_number <TAB> country_code <TAB> as_description
+ *
+ * IPv4 and IPv6 ranges may be mixed in one file. Without the feature, or
+ * without a database, ClientGeo is disabled and logs nothing.
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoHint {
+    pub asn: u32,
+    pub country: String,
+    pub as_name: String,
+}
+
+#[derive(Debug)]
+struct GeoRange {
+    start: u128,
+    end: u128,
+    hint: GeoHint,
+}
+
+#[derive(Debug)]
+pub struct GeoDatabase {
+    /* sorted by start, non overlapping */
+    ranges: Vec<GeoRange>,
+}
+
+#[derive(Debug)]
+pub enum GeoDatabaseError {
+    Io(std::io::Error),
+    InvalidLine(usize),
+}
+
+impl GeoDatabase {
+    pub fn parse(data: &str) -> Result<Self, GeoDatabaseError> {
+        let mut ranges = Vec::new();
+
+        for (idx, line) in data.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(5, '\t');
+            let mut next = || parts.next().ok_or(GeoDatabaseError::InvalidLine(idx + 1));
+
+            let start = ip_key(next()?.parse().map_err(|_| GeoDatabaseError::InvalidLine(idx + 1))?);
+            let end = ip_key(next()?.parse().map_err(|_| GeoDatabaseError::InvalidLine(idx + 1))?);
+            let asn = next()?.parse::<u32>().map_err(|_| GeoDatabaseError::InvalidLine(idx + 1))?;
+            let country = next()?.to_string();
+            let as_name = next().unwrap_or("").to_string();
+
+            if end < start {
+                return Err(GeoDatabaseError::InvalidLine(idx + 1));
+            }
+
+            /* unrouted space carries no useful hint */
+            if asn == 0 {
+                continue;
+            }
+
+            ranges.push(GeoRange { start, end, hint: GeoHint { asn, country, as_name } });
+        }
+
+        ranges.sort_by_key(|range| range.start);
+        Ok(GeoDatabase { ranges })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&GeoHint> {
+        let key = ip_key(ip);
+        let idx = self.ranges.partition_point(|range| range.start <= key);
+        let range = &self.ranges[idx.checked_sub(1)?];
+
+        if key <= range.end {
+            Some(&range.hint)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/* v4 as v4-mapped v6 so both families sort in one list */
+fn ip_key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ClientGeo {
+    db: Option<Arc<GeoDatabase>>,
+}
+
+impl ClientGeo {
+    #[cfg(feature = "client-geo")]
+    pub fn load(path: &std::path::Path) -> Result<Self, GeoDatabaseError> {
+        let data = std::fs::read_to_string(path).map_err(GeoDatabaseError::Io)?;
+        let db = GeoDatabase::parse(&data)?;
+        tracing::info!(path = %path.display(), ranges = db.len(), "loaded client geo database");
+        Ok(ClientGeo { db: Some(Arc::new(db)) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.db.is_some()
+    }
+
+    /* called once per new TCP connection or UDP flow */
+    pub fn log_connection(&self, tunnel_id: Uuid, peer_addr: SocketAddr, proto: &'static str) {
+        let Some(db) = &self.db else { return };
+
+        match db.lookup(peer_addr.ip()) {
+            Some(hint) => tracing::info!(
+                %tunnel_id,
+                client_ip = %ClientIp(peer_addr.ip()),
+                proto,
+                country = %hint.country,
+                asn = hint.asn,
+                as_name = %hint.as_name,
+                "client connected"
+            ),
+            None => tracing::info!(%tunnel_id, client_ip = %ClientIp(peer_addr.ip()), proto, "client connected, no geo data"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GeoDatabase, GeoDatabaseError};
+
+    #[test]
+    fn test_geo_lookup() {
+        let db = GeoDatabase::parse(concat!(
+            "# comment\n",
+            "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n",
+            "1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n",
+            "2001:db8::\t2001:db8::ffff\t64500\tDE\tEXAMPLE-AS\n",
+            "8.8.8.0\t8.8.8.255\t15169\tUS\tGOOGLE\n",
+        )).unwrap();
+
+        assert_eq!(db.len(), 3);
+
+        let hint = db.lookup("1.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!((hint.asn, hint.country.as_str(), hint.as_name.as_str()), (13335, "US", "CLOUDFLARENET"));
+
+        assert_eq!(db.lookup("8.8.8.8".parse().unwrap()).unwrap().asn, 15169);
+        assert_eq!(db.lookup("2001:db8::42".parse().unwrap()).unwrap().country, "DE");
+
+        /* unrouted, between ranges, before the first and after the last */
+        assert!(db.lookup("1.0.2.1".parse().unwrap()).is_none());
+        assert!(db.lookup("4.4.4.4".parse().unwrap()).is_none());
+        assert!(db.lookup("0.0.0.1".parse().unwrap()).is_none());
+        assert!(db.lookup("2001:db9::1".parse().unwrap()).is_none());
+
+        assert!(matches!(GeoDatabase::parse("1.0.0.0\tnope\t1\tUS\tX\n"), Err(GeoDatabaseError::InvalidLine(1))));
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::process::Command;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Default)]
+pub struct TunnelHooks {
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    Connect,
+    Disconnect,
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Connect => "connect",
+            HookEvent::Disconnect => "disconnect",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionHooks {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    hooks: HashMap<Uuid, TunnelHooks>,
+    min_interval: Duration,
+    state: Mutex<HashMap<Uuid, TunnelState>>,
+}
+
+#[derive(Default)]
+struct TunnelState {
+    active: usize,
+    last_connect: Option<Instant>,
+    last_disconnect: Option<Instant>,
+}
+
+impl Default for ConnectionHooks {
+    fn default() -> Self {
+        ConnectionHooks::new(HashMap::new(), Duration::from_secs(5))
+    }
+}
+
+impl ConnectionHooks {
+    pub fn new(hooks: HashMap<Uuid, TunnelHooks>, min_interval: Duration) -> Self {
+        ConnectionHooks {
+            inner: Arc::new(Inner {
+                hooks,
+                min_interval,
+                state: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.hooks.is_empty()
+    }
+
+    /* tracks a new connection, the returned guard must be held until the connection closes */
+    pub fn connected(&self, tunnel_id: Uuid, client_ip: IpAddr) -> Option<ConnectionHookGuard> {
+        if !self.inner.hooks.contains_key(&tunnel_id) {
+            return None;
+        }
+
+        if self.record(tunnel_id, HookEvent::Connect, Instant::now()) {
+            self.run(tunnel_id, HookEvent::Connect, client_ip);
+        }
+
+        Some(ConnectionHookGuard {
+            hooks: self.clone(),
+            tunnel_id,
+            client_ip,
+        })
+    }
+
+    /* updates active count and returns true if the hook should run */
+    fn record(&self, tunnel_id: Uuid, event: HookEvent, now: Instant) -> bool {
+        let mut lock = self.inner.state.lock().unwrap();
+        let state = lock.entry(tunnel_id).or_default();
+
+        let (transition, last_run) = match event {
+            HookEvent::Connect => {
+                state.active += 1;
+                (state.active == 1, &mut state.last_connect)
+            }
+            HookEvent::Disconnect => {
+                state.active = state.active.saturating_sub(1);
+                (state.active == 0, &mut state.last_disconnect)
+            }
+        };
+
+        if !transition {
+            return false;
+        }
+
+        /* rate limit to avoid hook storms from clients rapidly reconnecting */
+        if let Some(last) = last_run {
+            if now.duration_since(*last) < self.inner.min_interval {
+                tracing::warn!(%tunnel_id, event = event.name(), "skipping connection hook, rate limited");
+                return false;
+            }
+        }
+
+        last_run.replace(now);
+        true
+    }
+
+    fn run(&self, tunnel_id: Uuid, event: HookEvent, client_ip: IpAddr) {
+        let Some(hooks) = self.inner.hooks.get(&tunnel_id) else { return };
+        let command = match event {
+            HookEvent::Connect => hooks.on_connect.clone(),
+            HookEvent::Disconnect => hooks.on_disconnect.clone(),
+        };
+        let Some(command) = command else { return };
+
+        /* may be called from drop, only run if we're inside a runtime */
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(%tunnel_id, event = event.name(), "no runtime to run connection hook");
+            return;
+        };
+
+        handle.spawn(async move {
+            #[cfg(windows)]
+            let mut cmd = {
+                let mut cmd = Command::new("cmd");
+                cmd.arg("/C").arg(&command);
+                cmd
+            };
+
+            #[cfg(not(windows))]
+            let mut cmd = {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(&command);
+                cmd
+            };
+
+            cmd.env("PLAYIT_HOOK_EVENT", event.name())
+                .env("PLAYIT_TUNNEL_ID", tunnel_id.to_string())
+                .env("PLAYIT_CLIENT_IP", client_ip.to_string());
+
+            tracing::info!(%tunnel_id, event = event.name(), %command, "running connection hook");
+
+            match cmd.status().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => tracing::warn!(%tunnel_id, event = event.name(), ?status, "connection hook exited with error"),
+                Err(error) => tracing::error!(?error, %tunnel_id, event = event.name(), "failed to run connection hook"),
+            }
+        });
+    }
+}
+
+pub struct ConnectionHookGuard {
+    hooks: ConnectionHooks,
+    tunnel_id: Uuid,
+    client_ip: IpAddr,
+}
+
+impl std::fmt::Debug for ConnectionHookGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ConnectionHookGuard({}, {})", self.tunnel_id, self.client_ip)
+    }
+}
+
+impl Drop for ConnectionHookGuard {
+    fn drop(&mut self) {
+        if self.hooks.record(self.tunnel_id, HookEvent::Disconnect, Instant::now()) {
+            self.hooks.run(self.tunnel_id, HookEvent::Disconnect, self.client_ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use uuid::Uuid;
+
+    use super::{ConnectionHooks, HookEvent, TunnelHooks};
+
+    #[test]
+    fn test_first_and_last_connection_with_rate_limit() {
+        let tunnel_id = Uuid::from_u128(1);
+        let hooks = ConnectionHooks::new(
+            HashMap::from([(tunnel_id, TunnelHooks::default())]),
+            Duration::from_secs(5),
+        );
+
+        let start = Instant::now();
+
+        assert!(hooks.record(tunnel_id, HookEvent::Connect, start));
+        assert!(!hooks.record(tunnel_id, HookEvent::Connect, start));
+        assert!(!hooks.record(tunnel_id, HookEvent::Disconnect, start));
+        assert!(hooks.record(tunnel_id, HookEvent::Disconnect, start));
+
+        /* reconnect within interval is rate limited */
+        assert!(!hooks.record(tunnel_id, HookEvent::Connect, start + Duration::from_secs(1)));
+        assert!(!hooks.record(tunnel_id, HookEvent::Disconnect, start + Duration::from_secs(2)));
+
+        assert!(hooks.record(tunnel_id, HookEvent::Connect, start + Duration::from_secs(6)));
+        assert!(hooks.record(tunnel_id, HookEvent::Disconnect, start + Duration::from_secs(7)));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use playit_agent_proto::control_feed::NewClient;
+use playit_api_client::api::PortType;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::client_filter::ClientFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct TunnelServer {
+    pub data_center_id: u32,
+    pub tunnel_server_id: u64,
+}
+
+/* counts accepted TCP clients per tunnel server to show how anycast spreads traffic across POPs */
+#[derive(Clone, Default)]
+pub struct ServerConnectionCounts {
+    counts: Arc<Mutex<BTreeMap<TunnelServer, u64>>>,
+}
+
+impl ServerConnectionCounts {
+    pub fn record(&self, client: &NewClient) {
+        let server = TunnelServer {
+            data_center_id: client.data_center_id,
+            tunnel_server_id: client.tunnel_server_id,
+        };
+
+        let mut lock = self.counts.lock().unwrap();
+        *lock.entry(server).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> Vec<(TunnelServer, u64)> {
+        let lock = self.counts.lock().unwrap();
+        lock.iter().map(|(server, count)| (*server, *count)).collect()
+    }
+
+    pub fn reset(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+}
+
+/*
+ * Zeroes the cumulative counters (connections per tunnel server, clients
+ * dropped by the ip filter), ex. at the start of a monitoring window.
+ * Active connection counts are live state and are not touched.
+ */
+#[derive(Clone)]
+pub struct StatsReset {
+    pub(crate) server_counts: ServerConnectionCounts,
+    pub(crate) client_filter: ClientFilter,
+}
+
+impl StatsReset {
+    pub fn reset(&self) {
+        self.server_counts.reset();
+        let dropped = self.client_filter.reset_dropped();
+        tracing::info!(dropped_by_ip_filter = dropped, "connection stats reset");
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TunnelConnectionCount {
+    pub tcp: usize,
+    pub udp: usize,
+}
+
+impl TunnelConnectionCount {
+    pub fn total(&self) -> usize {
+        self.tcp + self.udp
+    }
+}
+
+/* currently open TCP and UDP clients per tunnel */
+#[derive(Clone, Default, Debug)]
+pub struct ActiveConnectionCounts {
+    counts: Arc<Mutex<HashMap<Uuid, TunnelConnectionCount>>>,
+}
+
+impl ActiveConnectionCounts {
+    /* proto must be Tcp or Udp, the returned guard must be held until the client closes */
+    pub fn connected(&self, tunnel_id: Uuid, proto: PortType) -> ActiveConnectionGuard {
+        assert_ne!(proto, PortType::Both);
+
+        let mut lock = self.counts.lock().unwrap();
+        let count = lock.entry(tunnel_id).or_default();
+        match proto {
+            PortType::Tcp => count.tcp += 1,
+            _ => count.udp += 1,
+        }
+
+        ActiveConnectionGuard {
+            counts: self.clone(),
+            tunnel_id,
+            proto,
+        }
+    }
+
+    pub fn get(&self, tunnel_id: Uuid) -> TunnelConnectionCount {
+        let lock = self.counts.lock().unwrap();
+        lock.get(&tunnel_id).copied().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> TunnelConnectionCount {
+        let lock = self.counts.lock().unwrap();
+        lock.values().fold(TunnelConnectionCount::default(), |total, count| TunnelConnectionCount {
+            tcp: total.tcp + count.tcp,
+            udp: total.udp + count.udp,
+        })
+    }
+
+    pub fn snapshot(&self) -> HashMap<Uuid, TunnelConnectionCount> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    fn disconnected(&self, tunnel_id: Uuid, proto: PortType) {
+        let mut lock = self.counts.lock().unwrap();
+        let Some(count) = lock.get_mut(&tunnel_id) else { return };
+
+        match proto {
+            PortType::Tcp => count.tcp = count.tcp.saturating_sub(1),
+            _ => count.udp = count.udp.saturating_sub(1),
+        }
+
+        if count.total() == 0 {
+            lock.remove(&tunnel_id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ActiveConnectionGuard {
+    counts: ActiveConnectionCounts,
+    tunnel_id: Uuid,
+    proto: PortType,
+}
+
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        self.counts.disconnected(self.tunnel_id, self.proto);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_proto::control_feed::{ClaimInstructions, NewClient};
+    use playit_api_client::api::PortType;
+    use uuid::Uuid;
+
+    use crate::network::client_filter::{ClientFilter, ClientIpFilter};
+
+    use super::{ActiveConnectionCounts, ServerConnectionCounts, StatsReset, TunnelConnectionCount, TunnelServer};
+
+    fn client(data_center_id: u32, tunnel_server_id: u64) -> NewClient {
+        NewClient {
+            connect_addr: "147.185.221.1:25565".parse().unwrap(),
+            peer_addr: "1.2.3.4:5000".parse().unwrap(),
+            claim_instructions: ClaimInstructions {
+                address: "147.185.221.1:5525".parse().unwrap(),
+                token: vec![],
+            },
+            tunnel_server_id,
+            data_center_id,
+        }
+    }
+
+    #[test]
+    fn test_counts_per_server() {
+        let counts = ServerConnectionCounts::default();
+        counts.record(&client(2, 10));
+        counts.record(&client(1, 5));
+        counts.clone().record(&client(2, 10));
+
+        assert_eq!(counts.snapshot(), vec![
+            (TunnelServer { data_center_id: 1, tunnel_server_id: 5 }, 1),
+            (TunnelServer { data_center_id: 2, tunnel_server_id: 10 }, 2),
+        ]);
+    }
+
+    #[test]
+    fn test_stats_reset() {
+        let server_counts = ServerConnectionCounts::default();
+        let client_filter = ClientFilter::new(ClientIpFilter::Ip4Only);
+        let reset = StatsReset { server_counts: server_counts.clone(), client_filter: client_filter.clone() };
+
+        server_counts.record(&client(1, 5));
+        assert!(!client_filter.check("[2001:db8::1]:5000".parse().unwrap()));
+
+        reset.reset();
+        assert!(server_counts.snapshot().is_empty());
+        assert_eq!(client_filter.dropped_count(), 0);
+
+        /* counting continues from zero */
+        server_counts.record(&client(1, 5));
+        assert_eq!(server_counts.snapshot(), vec![(TunnelServer { data_center_id: 1, tunnel_server_id: 5 }, 1)]);
+    }
+
+    #[test]
+    fn test_active_counts_per_tunnel() {
+        let counts = ActiveConnectionCounts::default();
+        let busy = Uuid::from_u128(1);
+        let quiet = Uuid::from_u128(2);
+
+        let tcp_a = counts.connected(busy, PortType::Tcp);
+        let _tcp_b = counts.connected(busy, PortType::Tcp);
+        let udp = counts.connected(busy, PortType::Udp);
+        let quiet_udp = counts.connected(quiet, PortType::Udp);
+
+        assert_eq!(counts.get(busy), TunnelConnectionCount { tcp: 2, udp: 1 });
+        assert_eq!(counts.get(quiet), TunnelConnectionCount { tcp: 0, udp: 1 });
+        assert_eq!(counts.total(), TunnelConnectionCount { tcp: 2, udp: 2 });
+
+        drop(tcp_a);
+        drop(udp);
+        drop(quiet_udp);
+
+        assert_eq!(counts.get(busy), TunnelConnectionCount { tcp: 1, udp: 0 });
+        assert!(!counts.snapshot().contains_key(&quiet));
+        assert_eq!(counts.total(), TunnelConnectionCount { tcp: 1, udp: 0 });
+    }
+}
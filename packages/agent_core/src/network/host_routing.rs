@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use uuid::Uuid;
+
+/*
+ * Hostname based routing for TCP tunnels (like mc-router / a SNI proxy).
+ *
+ * For tunnels with routes the first bytes from the client are read before
+ * connecting locally. The hostname comes from the TLS ClientHello SNI (TLS
+ * is not terminated), the HTTP Host header or the server address of the
+ * Minecraft Java handshake. The bytes read are replayed to
+ * the chosen backend. When no hostname is found or nothing matches, the
+ * tunnel's own local address is used.
+ *
+ * Only use this for protocols where the client speaks first, otherwise each
+ * connection waits SNIFF_TIMEOUT before falling back.
+ */
+
+pub const MAX_SNIFF_BYTES: usize = 16 * 1024;
+pub const SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sniffed {
+    Hostname(String),
+    NeedMore,
+    NoHostname,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRoute {
+    /* lowercase, "*.example.com" matches any subdomain */
+    pub hostname: String,
+    pub local_addr: SocketAddr,
+}
+
+#[derive(Clone, Default)]
+pub struct HostRouting {
+    routes: Arc<HashMap<Uuid, Vec<HostRoute>>>,
+}
+
+impl HostRouting {
+    pub fn new(routes: HashMap<Uuid, Vec<HostRoute>>) -> Self {
+        HostRouting { routes: Arc::new(routes) }
+    }
+
+    pub fn has_routes(&self, tunnel_id: Uuid) -> bool {
+        self.routes.contains_key(&tunnel_id)
+    }
+
+    /* exact names win over wildcards, longer wildcards over shorter ones */
+    pub fn route(&self, tunnel_id: Uuid, hostname: &str) -> Option<SocketAddr> {
+        let routes = self.routes.get(&tunnel_id)?;
+        let hostname = normalize_hostname(hostname);
+
+        if let Some(route) = routes.iter().find(|route| route.hostname == hostname) {
+            return Some(route.local_addr);
+        }
+
+        routes.iter()
+            .filter_map(|route| {
+                let suffix = route.hostname.strip_prefix('*')?;
+                (hostname.ends_with(suffix) && suffix.len() < hostname.len()).then_some((suffix.len(), route.local_addr))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, addr)| addr)
+    }
+}
+
+pub fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/* returns the hostname (if any) and every byte read, which must be sent to the backend first */
+pub async fn read_hostname<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<(Option<String>, Vec<u8>)> {
+    let mut data = Vec::with_capacity(1024);
+    let mut buffer = [0u8; 2048];
+
+    let sniff = async {
+        loop {
+            match sniff_hostname(&data) {
+                Sniffed::Hostname(name) => return Ok::<_, std::io::Error>(Some(name)),
+                Sniffed::NoHostname => return Ok(None),
+                Sniffed::NeedMore if MAX_SNIFF_BYTES <= data.len() => return Ok(None),
+                Sniffed::NeedMore => {}
+            }
+
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+    };
+
+    let hostname = match tokio::time::timeout(SNIFF_TIMEOUT, sniff).await {
+        Ok(res) => res?,
+        Err(_) => None,
+    };
+
+    Ok((hostname, data))
+}
+
+pub fn sniff_hostname(data: &[u8]) -> Sniffed {
+    /* a minecraft handshake of length 0x16 starts like a TLS record, the second byte tells them apart */
+    match data {
+        [] | [TLS_RECORD_HANDSHAKE] => Sniffed::NeedMore,
+        [TLS_RECORD_HANDSHAKE, 0x03, ..] => sniff_tls_sni(data),
+        _ if is_http_request(data) => sniff_http_host(data),
+        _ if HTTP_METHODS.iter().any(|method| method.as_bytes().starts_with(data)) => Sniffed::NeedMore,
+        _ => sniff_minecraft_handshake(data),
+    }
+}
+
+const TLS_RECORD_HANDSHAKE: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 0x01;
+const TLS_EXT_SERVER_NAME: u16 = 0x0000;
+
+/* a ClientHello may span multiple records, the handshake message is reassembled first */
+fn sniff_tls_sni(data: &[u8]) -> Sniffed {
+    let mut handshake = Vec::new();
+    let mut rest = data;
+
+    loop {
+        if rest.len() < 5 {
+            return Sniffed::NeedMore;
+        }
+        if rest[0] != TLS_RECORD_HANDSHAKE {
+            return Sniffed::NoHostname;
+        }
+
+        let record_len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        let Some(record) = rest.get(5..5 + record_len) else { return Sniffed::NeedMore };
+        handshake.extend_from_slice(record);
+        rest = &rest[5 + record_len..];
+
+        if handshake.len() < 4 {
+            continue;
+        }
+        if handshake[0] != TLS_CLIENT_HELLO {
+            return Sniffed::NoHostname;
+        }
+
+        let hello_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+        if 4 + hello_len <= handshake.len() {
+            return match parse_client_hello_sni(&handshake[4..4 + hello_len]) {
+                Some(name) => Sniffed::Hostname(name),
+                None => Sniffed::NoHostname,
+            };
+        }
+        if MAX_SNIFF_BYTES < hello_len {
+            return Sniffed::NoHostname;
+        }
+    }
+}
+
+fn parse_client_hello_sni(hello: &[u8]) -> Option<String> {
+    let mut reader = SliceReader(hello);
+
+    /* client_version, random */
+    reader.skip(2 + 32)?;
+    /* session_id, cipher_suites, compression_methods */
+    let len = reader.u8()? as usize;
+    reader.skip(len)?;
+    let len = reader.u16()? as usize;
+    reader.skip(len)?;
+    let len = reader.u8()? as usize;
+    reader.skip(len)?;
+
+    let len = reader.u16()? as usize;
+    let mut extensions = SliceReader(reader.take(len)?);
+
+    while !extensions.0.is_empty() {
+        let ext_type = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let ext = extensions.take(len)?;
+
+        if ext_type != TLS_EXT_SERVER_NAME {
+            continue;
+        }
+
+        let mut list = SliceReader(ext);
+        let len = list.u16()? as usize;
+        let mut names = SliceReader(list.take(len)?);
+
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let len = names.u16()? as usize;
+            let name = names.take(len)?;
+
+            /* 0 is host_name, the only defined type */
+            if name_type == 0 {
+                return valid_hostname(std::str::from_utf8(name).ok()?);
+            }
+        }
+    }
+
+    None
+}
+
+const HTTP_METHODS: [&str; 9] = ["GET ", "POST ", "HEAD ", "PUT ", "DELETE ", "OPTIONS ", "PATCH ", "CONNECT ", "TRACE "];
+
+fn is_http_request(data: &[u8]) -> bool {
+    HTTP_METHODS.iter().any(|method| data.starts_with(method.as_bytes()))
+}
+
+fn sniff_http_host(data: &[u8]) -> Sniffed {
+    let Some(header_end) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Sniffed::NeedMore;
+    };
+    let Ok(head) = std::str::from_utf8(&data[..header_end]) else {
+        return Sniffed::NoHostname;
+    };
+
+    for line in head.split("\r\n").skip(1) {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        if !name.trim().eq_ignore_ascii_case("host") {
+            continue;
+        }
+
+        let value = value.trim();
+        /* strip the port, "[::1]:8080" or "example.com:8080" */
+        let host = match value.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or(rest),
+            None => value.split(':').next().unwrap_or(value),
+        };
+
+        return match valid_hostname(host) {
+            Some(host) => Sniffed::Hostname(host),
+            None => Sniffed::NoHostname,
+        };
+    }
+
+    Sniffed::NoHostname
+}
+
+const MC_HANDSHAKE_ID: i32 = 0x00;
+/* packet id, protocol version, 255 char address, port and next state */
+const MC_MAX_HANDSHAKE_LEN: usize = 1 + 5 + 3 + 255 * 4 + 2 + 5;
+
+/*
+ * Minecraft Java handshake: VarInt packet length, VarInt packet id (0),
+ * VarInt protocol version, String server address, u16 port, VarInt next state.
+ * Forge and BungeeCord append "\0" separated data to the address.
+ */
+fn sniff_minecraft_handshake(data: &[u8]) -> Sniffed {
+    let mut reader = SliceReader(data);
+
+    let packet_len = match reader.var_int() {
+        VarInt::Value(len) => len,
+        VarInt::NeedMore => return Sniffed::NeedMore,
+        VarInt::Invalid => return Sniffed::NoHostname,
+    };
+    let Ok(packet_len) = usize::try_from(packet_len) else { return Sniffed::NoHostname };
+    if packet_len == 0 || MC_MAX_HANDSHAKE_LEN < packet_len {
+        return Sniffed::NoHostname;
+    }
+
+    /* fail early on other protocols instead of waiting for packet_len bytes */
+    if reader.0.first().is_some_and(|id| *id as i32 != MC_HANDSHAKE_ID) {
+        return Sniffed::NoHostname;
+    }
+
+    let Some(packet) = reader.take(packet_len) else { return Sniffed::NeedMore };
+    match parse_minecraft_handshake(packet) {
+        Some(name) => Sniffed::Hostname(name),
+        None => Sniffed::NoHostname,
+    }
+}
+
+fn parse_minecraft_handshake(packet: &[u8]) -> Option<String> {
+    let mut reader = SliceReader(packet);
+
+    if reader.var_int().value()? != MC_HANDSHAKE_ID {
+        return None;
+    }
+    let _protocol_version = reader.var_int().value()?;
+
+    let address_len = usize::try_from(reader.var_int().value()?).ok()?;
+    let address = std::str::from_utf8(reader.take(address_len)?).ok()?;
+    let _port = reader.u16()?;
+
+    /* status, login or transfer */
+    let next_state = reader.var_int().value()?;
+    if !(1..=3).contains(&next_state) || !reader.0.is_empty() {
+        return None;
+    }
+
+    valid_hostname(address.split('\0').next().unwrap_or(address))
+}
+
+fn valid_hostname(name: &str) -> Option<String> {
+    let name = normalize_hostname(name);
+    if name.is_empty() || 253 < name.len() {
+        return None;
+    }
+    if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b':') {
+        return None;
+    }
+    Some(name)
+}
+
+struct SliceReader<'a>(&'a [u8]);
+
+impl<'a> SliceReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /* minecraft VarInt, at most 5 bytes */
+    fn var_int(&mut self) -> VarInt {
+        let mut value: u32 = 0;
+
+        for i in 0..5 {
+            let Some(byte) = self.u8() else { return VarInt::NeedMore };
+            value |= ((byte & 0x7f) as u32) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return VarInt::Value(value as i32);
+            }
+        }
+
+        VarInt::Invalid
+    }
+}
+
+enum VarInt {
+    Value(i32),
+    NeedMore,
+    Invalid,
+}
+
+impl VarInt {
+    fn value(self) -> Option<i32> {
+        match self {
+            VarInt::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use super::{read_hostname, sniff_hostname, HostRoute, HostRouting, Sniffed};
+
+    /* minimal TLS 1.3 style ClientHello with a server_name extension after another extension */
+    fn client_hello(sni: &str) -> Vec<u8> {
+        let mut sni_ext = Vec::new();
+        sni_ext.extend_from_slice(&((sni.len() + 3) as u16).to_be_bytes());
+        sni_ext.push(0);
+        sni_ext.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        sni_ext.extend_from_slice(sni.as_bytes());
+
+        let mut extensions = Vec::new();
+        /* supported_groups before server_name */
+        extensions.extend_from_slice(&[0x00, 0x0a, 0x00, 0x04, 0x00, 0x02, 0x00, 0x1d]);
+        extensions.extend_from_slice(&[0x00, 0x00]);
+        extensions.extend_from_slice(&(sni_ext.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni_ext);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[7u8; 32]);
+        hello.extend_from_slice(&[32]);
+        hello.extend_from_slice(&[9u8; 32]);
+        hello.extend_from_slice(&[0x00, 0x04, 0x13, 0x01, 0x13, 0x02]);
+        hello.extend_from_slice(&[0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![0x01];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn test_sniff_tls_sni() {
+        let hello = client_hello("Play.Example.com");
+        assert_eq!(sniff_hostname(&hello), Sniffed::Hostname("play.example.com".to_string()));
+
+        for split in [1, 5, 40, hello.len() - 1] {
+            assert_eq!(sniff_hostname(&hello[..split]), Sniffed::NeedMore, "split at {}", split);
+        }
+
+        /* same handshake split over two records */
+        let handshake = &hello[5..];
+        let mut fragmented = Vec::new();
+        for part in [&handshake[..30], &handshake[30..]] {
+            fragmented.extend_from_slice(&[0x16, 0x03, 0x01]);
+            fragmented.extend_from_slice(&(part.len() as u16).to_be_bytes());
+            fragmented.extend_from_slice(part);
+        }
+        assert_eq!(sniff_hostname(&fragmented), Sniffed::Hostname("play.example.com".to_string()));
+
+        /* corrupt extension lengths must not panic */
+        let mut corrupt = hello.clone();
+        let len = corrupt.len();
+        corrupt[len - 20] = 0xff;
+        let _ = sniff_hostname(&corrupt);
+    }
+
+    #[test]
+    fn test_sniff_http_host() {
+        let request = b"GET / HTTP/1.1\r\nUser-Agent: test\r\nhost: Web.Example.com:8080\r\n\r\n";
+        assert_eq!(sniff_hostname(request), Sniffed::Hostname("web.example.com".to_string()));
+        assert_eq!(sniff_hostname(&request[..20]), Sniffed::NeedMore);
+        assert_eq!(sniff_hostname(b"GE"), Sniffed::NeedMore);
+        assert_eq!(sniff_hostname(b"GET / HTTP/1.0\r\n\r\n"), Sniffed::NoHostname);
+        assert_eq!(sniff_hostname(b"SSH-2.0-OpenSSH\r\n"), Sniffed::NoHostname);
+    }
+
+    /* 1.20.4 client (protocol 765) joining play.example.gg:25565, followed by its login start packet */
+    const MC_HANDSHAKE: [u8; 24] = [
+        0x16, 0x00, 0xfd, 0x05, 0x0f, b'p', b'l', b'a', b'y', b'.', b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'g', b'g',
+        0x63, 0xdd, 0x02,
+        0x0b,
+    ];
+
+    #[test]
+    fn test_sniff_minecraft_handshake() {
+        /* length 0x16 looks like a TLS record until the second byte */
+        assert_eq!(sniff_hostname(&MC_HANDSHAKE), Sniffed::Hostname("play.example.gg".to_string()));
+        assert_eq!(sniff_hostname(&MC_HANDSHAKE[..23]), Sniffed::Hostname("play.example.gg".to_string()));
+        for split in [1, 2, 10, 22] {
+            assert_eq!(sniff_hostname(&MC_HANDSHAKE[..split]), Sniffed::NeedMore, "split at {}", split);
+        }
+
+        /* forge marker, status request, 3 byte protocol varint */
+        let forge = [
+            0x19, 0x00, 0xf4, 0x05, 0x12, b'm', b'c', b'.', b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm', 0x00, b'F', b'M', b'L',
+            0x63, 0xdd, 0x01,
+        ];
+        assert_eq!(sniff_hostname(&forge[..]), Sniffed::Hostname("mc.example.com".to_string()));
+
+        /* legacy server list ping */
+        assert_eq!(sniff_hostname(&[0xfe, 0x01, 0xfa]), Sniffed::NoHostname);
+        /* wrong packet id, bad next state, address longer than the packet, oversized varint */
+        let mut wrong_id = MC_HANDSHAKE;
+        wrong_id[1] = 0x01;
+        assert_eq!(sniff_hostname(&wrong_id), Sniffed::NoHostname);
+        let mut bad_state = MC_HANDSHAKE;
+        bad_state[22] = 0x07;
+        assert_eq!(sniff_hostname(&bad_state), Sniffed::NoHostname);
+        let mut long_address = MC_HANDSHAKE;
+        long_address[4] = 0x30;
+        assert_eq!(sniff_hostname(&long_address), Sniffed::NoHostname);
+        assert_eq!(sniff_hostname(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]), Sniffed::NoHostname);
+    }
+
+    #[test]
+    fn test_route_selection() {
+        let tunnel_id = Uuid::from_u128(1);
+        let route = |hostname: &str, port: u16| HostRoute { hostname: hostname.to_string(), local_addr: format!("127.0.0.1:{}", port).parse().unwrap() };
+
+        let routing = HostRouting::new(HashMap::from([(tunnel_id, vec![
+            route("*.example.com", 1),
+            route("api.example.com", 2),
+            route("*.dev.example.com", 3),
+        ])]));
+
+        assert!(routing.has_routes(tunnel_id));
+        assert!(!routing.has_routes(Uuid::from_u128(2)));
+        assert_eq!(routing.route(tunnel_id, "API.example.com.").unwrap().port(), 2);
+        assert_eq!(routing.route(tunnel_id, "www.example.com").unwrap().port(), 1);
+        assert_eq!(routing.route(tunnel_id, "a.dev.example.com").unwrap().port(), 3);
+        assert_eq!(routing.route(tunnel_id, "example.com"), None);
+        assert_eq!(routing.route(tunnel_id, "other.org"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_hostname_replays_bytes() {
+        let hello = client_hello("example.com");
+        let mut trailing = hello.clone();
+        trailing.extend_from_slice(b"early data");
+
+        let (hostname, data) = read_hostname(&mut &trailing[..]).await.unwrap();
+        assert_eq!(hostname.as_deref(), Some("example.com"));
+        assert!(trailing.starts_with(&data));
+        assert!(hello.len() <= data.len());
+
+        /* closed before a hostname arrived */
+        let (hostname, data) = read_hostname(&mut &hello[..10]).await.unwrap();
+        assert_eq!(hostname, None);
+        assert_eq!(data, &hello[..10]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::connection_stats::ActiveConnectionCounts;
+
+/*
+ * Optional idle sleep for low traffic agents (--idle_sleep). Once no TCP or
+ * UDP client has been open for `after`, the agent pings the control server
+ * every IDLE_PING_INTERVAL instead of every second, only sends keepalives
+ * when the session is about to expire, refreshes the control address and
+ * tunnel data less often and closes empty UDP client sockets right away.
+ * The first new client wakes it back to full cadence.
+ *
+ * Tradeoff: while asleep a dropped control connection is noticed later (up
+ * to IDLE_PING_INTERVAL plus the pong timeout), so the first client after a
+ * network change can wait several seconds longer for the agent to reconnect.
+ * Clients arriving on a healthy session are not delayed. Tunnel changes take
+ * up to IDLE_TUNNEL_REFRESH_INTERVAL to apply.
+ */
+
+pub const IDLE_PING_INTERVAL: Duration = Duration::from_secs(5);
+pub const IDLE_CONTROL_ADDR_REFRESH: Duration = Duration::from_secs(300);
+pub const IDLE_TUNNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+pub const IDLE_UDP_RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/* disabled (never asleep) by default */
+#[derive(Clone, Debug, Default)]
+pub struct IdleSleep {
+    inner: Option<Arc<IdleSleepInner>>,
+}
+
+#[derive(Debug)]
+struct IdleSleepInner {
+    after: Duration,
+    counts: ActiveConnectionCounts,
+    state: Mutex<IdleState>,
+}
+
+#[derive(Debug)]
+struct IdleState {
+    busy_at: Instant,
+    asleep: bool,
+}
+
+impl IdleSleep {
+    pub fn new(after: Duration, counts: ActiveConnectionCounts) -> Self {
+        IdleSleep {
+            inner: Some(Arc::new(IdleSleepInner {
+                after,
+                counts,
+                state: Mutex::new(IdleState {
+                    busy_at: Instant::now(),
+                    asleep: false,
+                }),
+            })),
+        }
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.check_at(Instant::now())
+    }
+
+    /* a new client is on its way, back to full cadence before it is counted */
+    pub fn wake(&self) {
+        let Some(inner) = &self.inner else { return };
+        inner.state.lock().unwrap().busy(Instant::now());
+    }
+
+    fn check_at(&self, now: Instant) -> bool {
+        let Some(inner) = &self.inner else { return false };
+
+        let active = inner.counts.total().total() != 0;
+        let mut state = inner.state.lock().unwrap();
+        if active {
+            state.busy(now);
+            return false;
+        }
+
+        let asleep = inner.after <= now.saturating_duration_since(state.busy_at);
+        state.set_asleep(asleep);
+        asleep
+    }
+}
+
+impl IdleState {
+    fn busy(&mut self, now: Instant) {
+        self.busy_at = now;
+        self.set_asleep(false);
+    }
+
+    fn set_asleep(&mut self, asleep: bool) {
+        if asleep != self.asleep {
+            self.asleep = asleep;
+
+            if asleep {
+                tracing::info!("no open connections, entering idle sleep");
+            } else {
+                tracing::info!("waking from idle sleep");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use playit_api_client::api::PortType;
+    use uuid::Uuid;
+
+    use crate::network::connection_stats::ActiveConnectionCounts;
+
+    use super::IdleSleep;
+
+    #[test]
+    fn test_sleeps_when_idle_and_wakes_on_connection() {
+        assert!(!IdleSleep::default().check_at(Instant::now() + Duration::from_secs(3600)));
+
+        let counts = ActiveConnectionCounts::default();
+        let idle = IdleSleep::new(Duration::from_secs(60), counts.clone());
+        let start = Instant::now();
+
+        assert!(!idle.check_at(start + Duration::from_secs(30)));
+        assert!(idle.check_at(start + Duration::from_secs(61)));
+
+        /* an open connection wakes it and restarts the idle period */
+        let guard = counts.connected(Uuid::nil(), PortType::Udp);
+        assert!(!idle.check_at(start + Duration::from_secs(62)));
+        assert!(!idle.check_at(start + Duration::from_secs(200)));
+        drop(guard);
+
+        assert!(!idle.check_at(start + Duration::from_secs(230)));
+        assert!(idle.check_at(start + Duration::from_secs(260)));
+    }
+}
@@ -1,14 +1,32 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
 use byteorder::{BigEndian, ByteOrder};
 use tokio::net::{TcpSocket, TcpStream, UdpSocket};
 
+use crate::utils::redact::ClientAddr;
 use crate::utils::shuffle::shuffle;
 
 pub struct LanAddress;
 
 impl LanAddress {
-    pub async fn tcp_socket(special_lan_ip: bool, peer: SocketAddr, host: SocketAddr) -> std::io::Result<TcpStream> {
+    pub async fn tcp_socket(special_lan_ip: bool, source_ports: Option<SourcePorts>, peer: SocketAddr, host: SocketAddr) -> std::io::Result<TcpStream> {
+        if let Some(ports) = source_ports {
+            let local_ip = match host.ip() {
+                IpAddr::V4(ip) if ip.is_loopback() && special_lan_ip => IpAddr::V4(map_to_local_ip4(peer.ip())),
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+
+            return match Self::tcp_socket_from_ports(ports, local_ip, host).await {
+                Err(e) => {
+                    tracing::error!("Failed to connect from source port {} for flow ({}, {}) {:?}", ports, ClientAddr(peer), host, e);
+                    Err(e)
+                }
+                v => v,
+            };
+        }
+
         let is_loopback = host.ip().is_loopback();
         if is_loopback && special_lan_ip {
             let local_ip = map_to_local_ip4(peer.ip());
@@ -21,7 +39,7 @@ impl LanAddress {
                 Ok(_) => {
                     match socket.connect(host).await {
                         Err(e) => {
-                            tracing::warn!("Failed to establish connection using special lan {} for flow {:?} {:?}", local_ip, (peer, host), e);
+                            tracing::warn!("Failed to establish connection using special lan {} for flow ({}, {}) {:?}", local_ip, ClientAddr(peer), host, e);
                         }
                         v => return v,
                     };
@@ -32,13 +50,43 @@ impl LanAddress {
         tracing::warn!(is_loopback, host_ip = %host.ip(), special_lan_ip, "not using special lan address");
         match TcpStream::connect(host).await {
             Err(e) => {
-                tracing::error!("Failed to establish connection for flow {:?} {:?}. Is your server running?", (peer, host), e);
+                tracing::error!("Failed to establish connection for flow ({}, {}) {:?}. Is your server running?", ClientAddr(peer), host, e);
                 Err(e)
             }
             v => v,
         }
     }
 
+    /* first port of the range that is free, errors once every port is in use */
+    async fn tcp_socket_from_ports(ports: SourcePorts, local_ip: IpAddr, host: SocketAddr) -> std::io::Result<TcpStream> {
+        let mut last_error = None;
+
+        for port in ports.iter() {
+            let socket = match local_ip {
+                IpAddr::V4(_) => TcpSocket::new_v4()?,
+                IpAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+
+            /* a pinned port is reused for every connection, don't wait out TIME_WAIT */
+            socket.set_reuseaddr(true)?;
+
+            if let Err(error) = socket.bind(SocketAddr::new(local_ip, port)) {
+                if !is_port_taken(&error) {
+                    return Err(error);
+                }
+                last_error = Some(error);
+                continue;
+            }
+
+            match socket.connect(host).await {
+                Err(error) if is_port_taken(&error) => last_error = Some(error),
+                v => return v,
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| std::io::Error::from(ErrorKind::AddrInUse)))
+    }
+
     pub async fn udp_socket(special_lan_ip: bool, peer: SocketAddr, host: SocketAddr) -> std::io::Result<UdpSocket> {
         if host.ip().is_loopback() && special_lan_ip {
             let local_ip = map_to_local_ip4(peer.ip());
@@ -64,6 +112,97 @@ impl LanAddress {
     }
 }
 
+/*
+ * Local source port(s) for connections to the local server (--source_port),
+ * for backends behind a firewall that only accepts known source ports. A
+ * single port allows one TCP connection at a time, each UDP client needs its
+ * own port so a range also caps concurrent UDP clients.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePorts {
+    first: u16,
+    last: u16,
+}
+
+impl SourcePorts {
+    pub fn new(first: u16, last: u16) -> Option<Self> {
+        if first == 0 || last < first {
+            return None;
+        }
+        Some(SourcePorts { first, last })
+    }
+
+    /* "PORT" or "FROM-TO" */
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once('-') {
+            Some((first, last)) => Self::new(first.trim().parse().ok()?, last.trim().parse().ok()?),
+            None => {
+                let port = value.trim().parse().ok()?;
+                Self::new(port, port)
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> {
+        self.first..=self.last
+    }
+}
+
+impl std::fmt::Display for SourcePorts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+pub fn is_port_taken(error: &std::io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable)
+}
+
+/* parses a local target, link-local ipv6 may include a zone ("[fe80::1%eth0]:25565" or "[fe80::1%2]:25565") */
+pub fn parse_local_addr(value: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+
+    let (host, port) = value.strip_prefix('[')?.split_once("]:")?;
+    let (ip, zone) = host.split_once('%')?;
+
+    let ip = ip.parse::<Ipv6Addr>().ok()?;
+    let port = port.parse::<u16>().ok()?;
+    let scope_id = match zone.parse::<u32>() {
+        Ok(id) => id,
+        Err(_) => interface_index(zone)?,
+    };
+
+    Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+/* local address for a port offset into the tunnel's range, keeps the ipv6 scope id */
+pub fn local_addr_with_offset(host: SocketAddr, port_offset: u16) -> SocketAddr {
+    let mut addr = host;
+    addr.set_port(host.port() + port_offset);
+    addr
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+/* interface names are only resolved on unix, use the numeric zone id elsewhere */
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
 fn as_local_masked(mut ip: u32) -> u32 {
     ip = shuffle(ip) & 0x00FFFFFFu32;
     if ip == 0 {
@@ -84,4 +223,86 @@ fn map_to_local_ip4(ip: IpAddr) -> Ipv4Addr {
                 ^ shuffle(BigEndian::read_u32(&bytes[4..8]))
         }
     }))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{SocketAddr, SocketAddrV6};
+
+    use crate::agent_control::{DualStackUdpSocket, PacketIO};
+
+    use super::{local_addr_with_offset, parse_local_addr, LanAddress, SourcePorts};
+
+    #[test]
+    fn test_parse_scoped_local_addr() {
+        assert_eq!(parse_local_addr("127.0.0.1:25565"), Some("127.0.0.1:25565".parse().unwrap()));
+        assert_eq!(parse_local_addr("[::1]:25565"), Some("[::1]:25565".parse().unwrap()));
+
+        let scoped = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 25565, 0, 3));
+        assert_eq!(parse_local_addr("[fe80::1%3]:25565"), Some(scoped));
+
+        #[cfg(unix)]
+        {
+            let lo = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 25565, 0, 1));
+            assert_eq!(parse_local_addr("[fe80::1%lo]:25565"), Some(lo));
+        }
+
+        assert_eq!(parse_local_addr("[fe80::1%not-an-interface0]:25565"), None);
+        assert_eq!(parse_local_addr("[fe80::1%3]"), None);
+        assert_eq!(parse_local_addr("fe80::1%3:25565"), None);
+
+        let offset = local_addr_with_offset(scoped, 2);
+        assert_eq!(offset, SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 25567, 0, 3)));
+    }
+
+    #[test]
+    fn test_parse_source_ports() {
+        assert_eq!(SourcePorts::parse("5000"), SourcePorts::new(5000, 5000));
+        assert_eq!(SourcePorts::parse("5000-5010"), SourcePorts::new(5000, 5010));
+        assert_eq!(SourcePorts::parse("5000-5010").unwrap().to_string(), "5000-5010");
+        assert_eq!(SourcePorts::parse("5010-5000"), None);
+        assert_eq!(SourcePorts::parse("0"), None);
+        assert_eq!(SourcePorts::parse("70000"), None);
+    }
+
+    #[tokio::test]
+    async fn test_connects_from_source_port() {
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap();
+        let peer: SocketAddr = "1.2.3.4:5000".parse().unwrap();
+
+        /* a listener keeps the first port of the range in use */
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let ports = SourcePorts::new(port, port + 1).unwrap();
+
+        let _conn = LanAddress::tcp_socket(false, Some(ports), peer, backend_addr).await.unwrap();
+        let (_, source) = backend.accept().await.unwrap();
+        assert_eq!(source.port(), port + 1);
+
+        let single = SourcePorts::new(port, port).unwrap();
+        let error = LanAddress::tcp_socket(false, Some(single), peer, backend_addr).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+        drop(taken);
+        let _conn = LanAddress::tcp_socket(false, Some(single), peer, backend_addr).await.unwrap();
+        let (_, source) = backend.accept().await.unwrap();
+        assert_eq!(source.port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_udp_binds_next_source_port() {
+        let backend = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap();
+
+        let first = DualStackUdpSocket::bind_ports(SourcePorts::new(backend_addr.port(), backend_addr.port() + 1).unwrap()).await.unwrap();
+        first.send_to(b"hello", backend_addr).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (_, source) = backend.recv_from(&mut buf).await.unwrap();
+        assert_eq!(source.port(), backend_addr.port() + 1);
+
+        let full = DualStackUdpSocket::bind_ports(SourcePorts::new(backend_addr.port(), backend_addr.port() + 1).unwrap()).await;
+        assert!(full.is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{tcp, TcpStream};
+
+/*
+ * Connection to the local server of a TCP tunnel, either TCP or a UNIX
+ * domain socket (--unix_target) for backends that only listen on a socket
+ * file. Split into enum halves so the pipes stay monomorphic.
+ */
+
+pub enum LocalStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+pub enum LocalReadHalf {
+    Tcp(tcp::OwnedReadHalf),
+    #[cfg(unix)]
+    Unix(tokio::net::unix::OwnedReadHalf),
+}
+
+pub enum LocalWriteHalf {
+    Tcp(tcp::OwnedWriteHalf),
+    #[cfg(unix)]
+    Unix(tokio::net::unix::OwnedWriteHalf),
+}
+
+impl LocalStream {
+    #[cfg(unix)]
+    pub async fn connect_unix(path: &Path) -> std::io::Result<Self> {
+        Ok(LocalStream::Unix(tokio::net::UnixStream::connect(path).await?))
+    }
+
+    #[cfg(not(unix))]
+    pub async fn connect_unix(path: &Path) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("cannot connect to {}, UNIX socket targets are not supported on this platform", path.display()),
+        ))
+    }
+
+    /* for logs, the bound address or socket path */
+    pub fn describe_local(&self) -> Option<String> {
+        match self {
+            LocalStream::Tcp(stream) => stream.local_addr().ok().map(|addr| addr.to_string()),
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => stream.peer_addr().ok()
+                .and_then(|addr| addr.as_pathname().map(|path| format!("unix:{}", path.display()))),
+        }
+    }
+
+    pub fn into_split(self) -> (LocalReadHalf, LocalWriteHalf) {
+        match self {
+            LocalStream::Tcp(stream) => {
+                let (read, write) = stream.into_split();
+                (LocalReadHalf::Tcp(read), LocalWriteHalf::Tcp(write))
+            }
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => {
+                let (read, write) = stream.into_split();
+                (LocalReadHalf::Unix(read), LocalWriteHalf::Unix(write))
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for LocalStream {
+    fn from(stream: TcpStream) -> Self {
+        LocalStream::Tcp(stream)
+    }
+}
+
+impl AsyncRead for LocalReadHalf {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            LocalReadHalf::Tcp(read) => Pin::new(read).poll_read(cx, buf),
+            #[cfg(unix)]
+            LocalReadHalf::Unix(read) => Pin::new(read).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for LocalWriteHalf {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            LocalWriteHalf::Tcp(write) => Pin::new(write).poll_write(cx, buf),
+            #[cfg(unix)]
+            LocalWriteHalf::Unix(write) => Pin::new(write).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            LocalWriteHalf::Tcp(write) => Pin::new(write).poll_flush(cx),
+            #[cfg(unix)]
+            LocalWriteHalf::Unix(write) => Pin::new(write).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            LocalWriteHalf::Tcp(write) => Pin::new(write).poll_shutdown(cx),
+            #[cfg(unix)]
+            LocalWriteHalf::Unix(write) => Pin::new(write).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    use super::LocalStream;
+
+    #[tokio::test]
+    async fn test_unix_target_round_trip() {
+        let path = std::env::temp_dir().join(format!("playit-local-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        });
+
+        let stream = LocalStream::connect_unix(&path).await.unwrap();
+        assert_eq!(stream.describe_local(), Some(format!("unix:{}", path.display())));
+
+        let (mut read, mut write) = stream.into_split();
+        write.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        read.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let _ = std::fs::remove_file(&path);
+        assert!(LocalStream::connect_unix(&path).await.is_err());
+    }
+}
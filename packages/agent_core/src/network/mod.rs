@@ -5,4 +5,13 @@ pub mod lan_address;
 pub mod tcp_pipe;
 pub mod tcp_tunnel;
 pub mod proxy_protocol;
-pub mod udp;
\ No newline at end of file
+pub mod udp;
+pub mod connection_hooks;
+pub mod client_filter;
+pub mod connection_stats;
+pub mod access_log;
+pub mod idle_sleep;
+pub mod client_geo;
+pub mod host_routing;
+pub mod tunnel_quota;
+pub mod local_stream;
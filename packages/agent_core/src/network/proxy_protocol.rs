@@ -1,4 +1,4 @@
-use std::{io::Write, net::{Ipv4Addr, Ipv6Addr}};
+use std::{io::Write, net::{Ipv4Addr, Ipv6Addr, SocketAddr}};
 
 use byteorder::{BigEndian, ReadBytesExt};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
@@ -7,6 +7,16 @@ use crate::{agent_control::udp_proto::UdpFlow, utils::ip_bytes::ReadIpBytesExt};
 
 /*
  DOCS: https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
+
+ client_ip / client_port are always the original client's public source
+ address as seen by the tunnel server, never the tunnel server's or the
+ agent's own address. Backends use these for ban lists so the port matters
+ for clients sharing an IP behind NAT.
+
+ TCP: taken from NewClient.peer_addr, proxy address is NewClient.connect_addr
+      (the tunnel's public ip:port the client connected to).
+ UDP: taken from the src of the flow footer the tunnel server attaches to
+      each packet, dst is the tunnel's public ip:port.
 */
 
 
@@ -27,6 +37,25 @@ pub enum ProxyProtocolHeader {
 }
 
 impl ProxyProtocolHeader {
+    /* None if peer and connect address are different IP versions */
+    pub fn from_tcp_client(peer_addr: SocketAddr, connect_addr: SocketAddr) -> Option<Self> {
+        match (peer_addr, connect_addr) {
+            (SocketAddr::V4(client_addr), SocketAddr::V4(proxy_addr)) => Some(ProxyProtocolHeader::AfInet {
+                client_ip: *client_addr.ip(),
+                proxy_ip: *proxy_addr.ip(),
+                client_port: client_addr.port(),
+                proxy_port: proxy_addr.port(),
+            }),
+            (SocketAddr::V6(client_addr), SocketAddr::V6(proxy_addr)) => Some(ProxyProtocolHeader::AfInet6 {
+                client_ip: *client_addr.ip(),
+                proxy_ip: *proxy_addr.ip(),
+                client_port: client_addr.port(),
+                proxy_port: proxy_addr.port(),
+            }),
+            _ => None,
+        }
+    }
+
     pub fn from_udp_flow(flow: &UdpFlow) -> Self {
         match flow {
             UdpFlow::V4 { src, dst } => {
@@ -188,6 +217,12 @@ impl ProxyProtocolHeader {
 
 #[cfg(test)]
 mod test {
+    use std::net::SocketAddr;
+
+    use playit_agent_proto::control_feed::{ClaimInstructions, NewClient};
+
+    use crate::agent_control::udp_proto::UdpFlow;
+
     use super::ProxyProtocolHeader;
 
     #[test]
@@ -206,4 +241,66 @@ mod test {
         let parsed = ProxyProtocolHeader::parse_v2_udp(&mut reader).unwrap();
         assert_eq!(header, parsed);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_udp_header_has_original_client_addr() {
+        let client: SocketAddr = "203.0.113.7:61034".parse().unwrap();
+        let tunnel: SocketAddr = "147.185.221.1:25565".parse().unwrap();
+
+        /* packet from the tunnel server: payload followed by the flow footer */
+        let flow = UdpFlow::V4 {
+            src: match client { SocketAddr::V4(addr) => addr, _ => unreachable!() },
+            dst: match tunnel { SocketAddr::V4(addr) => addr, _ => unreachable!() },
+        };
+        let mut packet = b"payload".to_vec();
+        let payload_len = packet.len();
+        packet.resize(payload_len + flow.len(), 0);
+        assert!(flow.write_to(&mut packet[payload_len..]));
+
+        let received = UdpFlow::from_tail(&packet).unwrap();
+
+        let mut buffer = Vec::new();
+        ProxyProtocolHeader::from_udp_flow(&received).write_v2_udp(&mut buffer).unwrap();
+        let parsed = ProxyProtocolHeader::parse_v2_udp(&mut &buffer[..]).unwrap();
+
+        assert_eq!(parsed, ProxyProtocolHeader::AfInet {
+            client_ip: "203.0.113.7".parse().unwrap(),
+            proxy_ip: "147.185.221.1".parse().unwrap(),
+            client_port: 61034,
+            proxy_port: 25565,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_tcp_header_has_original_client_addr() {
+        let new_client = NewClient {
+            connect_addr: "[2602:fbaf::1]:443".parse().unwrap(),
+            peer_addr: "[2001:db8::42]:50123".parse().unwrap(),
+            claim_instructions: ClaimInstructions {
+                address: "147.185.221.1:5525".parse().unwrap(),
+                token: vec![],
+            },
+            tunnel_server_id: 1,
+            data_center_id: 1,
+        };
+
+        let header = ProxyProtocolHeader::from_tcp_client(new_client.peer_addr, new_client.connect_addr).unwrap();
+
+        let mut buffer = Vec::new();
+        header.write_v2_tcp(&mut buffer).await.unwrap();
+
+        /* 12 byte signature, version, family, u16 length */
+        assert_eq!(buffer[13], 0x21);
+        let addrs = &buffer[16..];
+        assert_eq!(&addrs[..16], &"2001:db8::42".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        assert_eq!(&addrs[32..34], &50123u16.to_be_bytes());
+        assert_eq!(&addrs[34..36], &443u16.to_be_bytes());
+
+        let mut v1 = Vec::new();
+        header.write_v1_tcp(&mut v1).await.unwrap();
+        assert_eq!(v1, b"PROXY TCP6 2001:db8::42 2602:fbaf::1 50123 443\r\n");
+
+        /* mixed families can't be expressed in one header */
+        assert_eq!(ProxyProtocolHeader::from_tcp_client("1.2.3.4:5".parse().unwrap(), new_client.connect_addr), None);
+    }
+}
@@ -2,23 +2,93 @@ use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::io::Error;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use uuid::Uuid;
 
 use playit_agent_proto::control_feed::NewClient;
 
+use super::address_lookup::LocalAddrSelection;
+use super::host_routing::HostRouting;
+use super::lan_address::SourcePorts;
+use super::tcp_pipe::DEFAULT_PIPE_BUFFER_SIZE;
 use super::tcp_tunnel::TcpTunnel;
 
+pub const DEFAULT_LOCAL_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_TCP_SETUP_LIMIT: usize = 512;
+
 #[derive(Clone)]
 pub struct TcpClients {
     active: ActiveClients,
     pub use_special_lan: bool,
+    pub source_ports: Option<SourcePorts>,
+    pub pipe_buffer_size: usize,
+    pub local_connect_timeout: Duration,
+    pub local_addr_selection: LocalAddrSelection,
+    /* tunnels without an entry keep connections open indefinitely */
+    pub max_connection_lifetimes: Arc<HashMap<Uuid, Duration>>,
+    /* tunnels forwarded to a UNIX domain socket instead of their local address */
+    pub unix_targets: Arc<HashMap<Uuid, PathBuf>>,
+    pub host_routing: HostRouting,
+    pub setup_limit: SetupLimit,
+}
+
+/*
+ * Caps TCP clients that are still being set up (claiming the tunnel
+ * connection and connecting to the local server) so a flood of NewClient
+ * messages can't spawn unbounded work. Clients over the limit are rejected
+ * and counted. Established connections don't hold a permit.
+ */
+#[derive(Clone)]
+pub struct SetupLimit {
+    limit: usize,
+    permits: Arc<Semaphore>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl SetupLimit {
+    pub fn new(limit: usize) -> Self {
+        SetupLimit {
+            limit,
+            permits: Arc::new(Semaphore::new(limit)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /* hold the permit until the client is connected to its local server */
+    pub fn try_start(&self) -> Option<OwnedSemaphorePermit> {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /* clients rejected since start */
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for SetupLimit {
+    fn default() -> Self {
+        SetupLimit::new(DEFAULT_TCP_SETUP_LIMIT)
+    }
 }
 
 #[derive(Clone)]
@@ -66,7 +136,15 @@ impl TcpClients {
     pub fn new() -> Self {
         TcpClients {
             active: ActiveClients::default(),
-            use_special_lan: true
+            use_special_lan: true,
+            source_ports: None,
+            pipe_buffer_size: DEFAULT_PIPE_BUFFER_SIZE,
+            local_connect_timeout: DEFAULT_LOCAL_CONNECT_TIMEOUT,
+            local_addr_selection: LocalAddrSelection::default(),
+            max_connection_lifetimes: Arc::new(HashMap::new()),
+            unix_targets: Arc::new(HashMap::new()),
+            host_routing: HostRouting::default(),
+            setup_limit: SetupLimit::default(),
         }
     }
 
@@ -176,4 +254,25 @@ impl Drop for Dropper {
             lock.remove(&key);
         });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::SetupLimit;
+
+    #[test]
+    fn test_setup_limit_enforced() {
+        let limit = SetupLimit::new(2);
+        let first = limit.try_start().unwrap();
+        let _second = limit.clone().try_start().unwrap();
+
+        assert!(limit.try_start().is_none());
+        assert!(limit.try_start().is_none());
+        assert_eq!(limit.rejected(), 2);
+
+        /* a finished setup frees its slot */
+        drop(first);
+        assert!(limit.try_start().is_some());
+        assert_eq!(limit.rejected(), 2);
+    }
+}
@@ -1,13 +1,29 @@
 use std::time::Duration;
 
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+pub const DEFAULT_PIPE_BUFFER_SIZE: usize = 2048;
+pub const MIN_PIPE_BUFFER_SIZE: usize = 512;
+pub const MAX_PIPE_BUFFER_SIZE: usize = 1024 * 1024;
+
+pub fn is_valid_buffer_size(size: usize) -> bool {
+    (MIN_PIPE_BUFFER_SIZE..=MAX_PIPE_BUFFER_SIZE).contains(&size)
+}
 
 pub async fn pipe<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    from: R,
+    to: W,
+) -> std::io::Result<()> {
+    pipe_with_buffer(from, to, DEFAULT_PIPE_BUFFER_SIZE).await
+}
+
+pub async fn pipe_with_buffer<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
     mut from: R,
     mut to: W,
+    buffer_size: usize,
 ) -> std::io::Result<()> {
-    let mut buffer = Vec::new();
-    buffer.resize(2048, 0u8);
+    let mut buffer = vec![0u8; buffer_size.clamp(MIN_PIPE_BUFFER_SIZE, MAX_PIPE_BUFFER_SIZE)];
 
     loop {
         tokio::task::yield_now().await;
@@ -39,3 +55,92 @@ pub async fn pipe<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
 
     Ok(())
 }
+
+/* like pipe_with_buffer but stops at deadline and shuts down the write half so the peer sees a clean close */
+pub async fn pipe_until<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    from: R,
+    mut to: W,
+    buffer_size: usize,
+    deadline: Option<Instant>,
+) -> std::io::Result<()> {
+    let Some(deadline) = deadline else {
+        return pipe_with_buffer(from, to, buffer_size).await;
+    };
+
+    tokio::select! {
+        res = pipe_with_buffer(from, &mut to, buffer_size) => res,
+        _ = tokio::time::sleep_until(deadline) => {
+            tracing::info!("max connection lifetime reached, closing pipe");
+            to.shutdown().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{pipe_until, pipe_with_buffer};
+
+    /* cargo test -p playit-agent-core --release --lib bench_pipe_buffer_sizes -- --ignored --nocapture */
+    #[tokio::test]
+    #[ignore]
+    async fn bench_pipe_buffer_sizes() {
+        const TOTAL: usize = 8 * 1024 * 1024;
+
+        for buffer_size in [2048, 16 * 1024, 64 * 1024] {
+            let (mut src_write, src_read) = tokio::io::duplex(256 * 1024);
+            let (dst_write, mut dst_read) = tokio::io::duplex(256 * 1024);
+
+            let start = Instant::now();
+            let pipe_task = tokio::spawn(pipe_with_buffer(src_read, dst_write, buffer_size));
+
+            let writer = tokio::spawn(async move {
+                let chunk = vec![7u8; 64 * 1024];
+                for _ in 0..(TOTAL / chunk.len()) {
+                    src_write.write_all(&chunk).await.unwrap();
+                }
+            });
+
+            let mut received = 0;
+            let mut buffer = vec![0u8; 64 * 1024];
+            while received < TOTAL {
+                let bytes = dst_read.read(&mut buffer).await.unwrap();
+                assert_ne!(bytes, 0);
+                received += bytes;
+            }
+
+            writer.await.unwrap();
+            pipe_task.await.unwrap().unwrap();
+
+            println!("buffer {} bytes: {:?} for {} MiB", buffer_size, start.elapsed(), TOTAL / (1024 * 1024));
+            assert_eq!(received, TOTAL);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipe_closed_after_lifetime() {
+        let (mut src_write, src_read) = tokio::io::duplex(1024);
+        let (dst_write, mut dst_read) = tokio::io::duplex(1024);
+
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(200);
+        let pipe_task = tokio::spawn(pipe_until(src_read, dst_write, 1024, Some(deadline)));
+
+        src_write.write_all(b"hello").await.unwrap();
+
+        let mut buffer = [0u8; 16];
+        let bytes = dst_read.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..bytes], b"hello");
+
+        /* source stays open, only the lifetime can end the pipe */
+        let bytes = tokio::time::timeout(Duration::from_secs(5), dst_read.read(&mut buffer)).await.unwrap().unwrap();
+        assert_eq!(bytes, 0);
+        assert!(Duration::from_millis(200) <= start.elapsed());
+
+        pipe_task.await.unwrap().unwrap();
+        drop(src_write);
+    }
+}
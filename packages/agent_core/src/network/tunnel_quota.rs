@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+use uuid::Uuid;
+
+use crate::utils::now_sec;
+
+/*
+ * Optional per tunnel quotas tracked by the agent (--quota_bytes_per_day,
+ * --quota_connections_per_hour). Once a tunnel is over either limit new
+ * TCP clients and UDP flows are rejected until the window resets, clients
+ * that are already connected keep working. Windows are fixed UTC days and
+ * hours. Bytes count both directions.
+ *
+ * Counters live in memory and can be saved to a state file so restarts
+ * within the same window don't reset usage.
+ */
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const HOUR_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    pub bytes_per_day: Option<u64>,
+    pub connections_per_hour: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct QuotaCounters {
+    day_start: u64,
+    bytes: u64,
+    hour_start: u64,
+    connections: u64,
+    /* set once the exceeded notice is logged for the current windows */
+    #[serde(skip)]
+    notified: bool,
+}
+
+impl QuotaCounters {
+    fn roll_windows(&mut self, now: u64) {
+        let day_start = now - now % DAY_SECS;
+        if self.day_start != day_start {
+            self.day_start = day_start;
+            self.bytes = 0;
+            self.notified = false;
+        }
+
+        let hour_start = now - now % HOUR_SECS;
+        if self.hour_start != hour_start {
+            self.hour_start = hour_start;
+            self.connections = 0;
+            self.notified = false;
+        }
+    }
+
+    fn exceeded(&self, limits: &QuotaLimits) -> Option<QuotaExceeded> {
+        if let Some(limit) = limits.bytes_per_day {
+            if limit <= self.bytes {
+                return Some(QuotaExceeded::BytesPerDay { limit, resets_at: self.day_start + DAY_SECS });
+            }
+        }
+        if let Some(limit) = limits.connections_per_hour {
+            if limit <= self.connections {
+                return Some(QuotaExceeded::ConnectionsPerHour { limit, resets_at: self.hour_start + HOUR_SECS });
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    BytesPerDay { limit: u64, resets_at: u64 },
+    ConnectionsPerHour { limit: u64, resets_at: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub limits: QuotaLimits,
+    pub bytes_today: u64,
+    pub connections_this_hour: u64,
+    pub exceeded: Option<QuotaExceeded>,
+}
+
+#[derive(Clone, Default)]
+pub struct TunnelQuotas {
+    limits: Arc<HashMap<Uuid, QuotaLimits>>,
+    counters: Arc<Mutex<HashMap<Uuid, QuotaCounters>>>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl TunnelQuotas {
+    pub fn new(limits: HashMap<Uuid, QuotaLimits>) -> Self {
+        TunnelQuotas {
+            limits: Arc::new(limits),
+            counters: Default::default(),
+            dirty: Default::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    pub fn has_quota(&self, tunnel_id: Uuid) -> bool {
+        self.limits.contains_key(&tunnel_id)
+    }
+
+    /* counts a new client, Err if the tunnel is paused. The notice is logged once per window */
+    pub fn try_connect(&self, tunnel_id: Uuid) -> Result<(), QuotaExceeded> {
+        self.try_connect_at(tunnel_id, now_sec() as u64)
+    }
+
+    fn try_connect_at(&self, tunnel_id: Uuid, now: u64) -> Result<(), QuotaExceeded> {
+        let Some(limits) = self.limits.get(&tunnel_id) else { return Ok(()) };
+
+        let mut lock = self.counters.lock().unwrap();
+        let counters = lock.entry(tunnel_id).or_default();
+        counters.roll_windows(now);
+
+        if let Some(exceeded) = counters.exceeded(limits) {
+            if !counters.notified {
+                counters.notified = true;
+                tracing::warn!(%tunnel_id, ?exceeded, "tunnel quota exceeded, rejecting new clients until the quota resets");
+            }
+            return Err(exceeded);
+        }
+
+        counters.connections += 1;
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn add_bytes(&self, tunnel_id: Uuid, bytes: u64) {
+        self.add_bytes_at(tunnel_id, bytes, now_sec() as u64);
+    }
+
+    fn add_bytes_at(&self, tunnel_id: Uuid, bytes: u64, now: u64) {
+        if bytes == 0 || !self.has_quota(tunnel_id) {
+            return;
+        }
+
+        let mut lock = self.counters.lock().unwrap();
+        let counters = lock.entry(tunnel_id).or_default();
+        counters.roll_windows(now);
+        counters.bytes += bytes;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn usage(&self, tunnel_id: Uuid) -> Option<QuotaUsage> {
+        self.usage_at(tunnel_id, now_sec() as u64)
+    }
+
+    fn usage_at(&self, tunnel_id: Uuid, now: u64) -> Option<QuotaUsage> {
+        let limits = *self.limits.get(&tunnel_id)?;
+
+        let mut counters = self.counters.lock().unwrap().get(&tunnel_id).copied().unwrap_or_default();
+        counters.roll_windows(now);
+
+        Some(QuotaUsage {
+            limits,
+            bytes_today: counters.bytes,
+            connections_this_hour: counters.connections,
+            exceeded: counters.exceeded(&limits),
+        })
+    }
+
+    /* counters for tunnels without a quota are dropped, a missing file is not an error */
+    pub fn load(&self, path: &Path) -> std::io::Result<()> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        let saved: HashMap<Uuid, QuotaCounters> = serde_json::from_str(&data)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+
+        let mut lock = self.counters.lock().unwrap();
+        for (tunnel_id, counters) in saved {
+            if self.has_quota(tunnel_id) {
+                lock.insert(tunnel_id, counters);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = {
+            let lock = self.counters.lock().unwrap();
+            serde_json::to_string(&*lock).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?
+        };
+
+        let mut tmp_name = path.as_os_str().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /* saves the counters every interval when they changed */
+    pub fn persist_to(&self, path: PathBuf, interval: Duration) {
+        let quotas = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if !quotas.dirty.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+
+                if let Err(error) = quotas.save(&path) {
+                    tracing::error!(?error, path = %path.display(), "failed to save quota state");
+                }
+            }
+        });
+    }
+}
+
+/* adds written bytes to the tunnel's quota */
+pub struct QuotaCountingWrite<W> {
+    inner: W,
+    quotas: TunnelQuotas,
+    tunnel_id: Uuid,
+}
+
+impl<W> QuotaCountingWrite<W> {
+    pub fn new(inner: W, quotas: TunnelQuotas, tunnel_id: Uuid) -> Self {
+        QuotaCountingWrite { inner, quotas, tunnel_id }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for QuotaCountingWrite<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &result {
+            self.quotas.add_bytes(self.tunnel_id, *written as u64);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use super::{QuotaExceeded, QuotaLimits, TunnelQuotas, DAY_SECS, HOUR_SECS};
+
+    #[test]
+    fn test_quota_pause_and_reset() {
+        let limited = Uuid::from_u128(1);
+        let quotas = TunnelQuotas::new(HashMap::from([
+            (limited, QuotaLimits { bytes_per_day: Some(1000), connections_per_hour: Some(2) }),
+        ]));
+
+        let now = 100 * DAY_SECS + 10;
+        assert_eq!(quotas.try_connect_at(limited, now), Ok(()));
+        assert_eq!(quotas.try_connect_at(limited, now), Ok(()));
+        assert_eq!(
+            quotas.try_connect_at(limited, now),
+            Err(QuotaExceeded::ConnectionsPerHour { limit: 2, resets_at: 100 * DAY_SECS + HOUR_SECS })
+        );
+
+        /* next hour resets connections, bytes keep counting for the day */
+        let next_hour = now + HOUR_SECS;
+        quotas.add_bytes_at(limited, 1200, next_hour);
+        assert_eq!(
+            quotas.try_connect_at(limited, next_hour),
+            Err(QuotaExceeded::BytesPerDay { limit: 1000, resets_at: 101 * DAY_SECS })
+        );
+
+        let usage = quotas.usage_at(limited, next_hour).unwrap();
+        assert_eq!((usage.bytes_today, usage.connections_this_hour), (1200, 0));
+
+        assert_eq!(quotas.try_connect_at(limited, now + DAY_SECS), Ok(()));
+
+        /* tunnels without a quota are never paused or tracked */
+        let other = Uuid::from_u128(2);
+        assert_eq!(quotas.try_connect_at(other, now), Ok(()));
+        assert_eq!(quotas.usage_at(other, now), None);
+    }
+
+    #[test]
+    fn test_quota_state_persisted() {
+        let tunnel_id = Uuid::from_u128(1);
+        let limits = HashMap::from([(tunnel_id, QuotaLimits { bytes_per_day: Some(1000), connections_per_hour: None })]);
+        let path = std::env::temp_dir().join(format!("playit-quota-{}.json", std::process::id()));
+
+        let quotas = TunnelQuotas::new(limits.clone());
+        quotas.add_bytes(tunnel_id, 600);
+        quotas.save(&path).unwrap();
+
+        let restarted = TunnelQuotas::new(limits);
+        restarted.load(&path).unwrap();
+        assert_eq!(restarted.usage(tunnel_id).unwrap().bytes_today, 600);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
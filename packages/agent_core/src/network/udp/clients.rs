@@ -1,4 +1,4 @@
-use std::{collections::{btree_map, hash_map, BTreeMap, HashMap}, future::Future, net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+use std::{collections::{btree_map, hash_map, BTreeMap, HashMap}, future::Future, net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
 
 use playit_agent_proto::control_messages::UdpChannelDetails;
 use playit_api_client::api::{PortType, ProxyProtocol};
@@ -6,9 +6,9 @@ use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::{agent_control::{udp_channel::{UdpChannel, UdpTunnelRx}, udp_proto::UdpFlow, DualStackUdpSocket, PacketIO}, network::{address_lookup::{AddressLookup, HostOrigin}, proxy_protocol::ProxyProtocolHeader, udp::receive_task::UdpReceiverTask}, utils::{error_helper::MaxErrorInterval, id_slab::IdSlab, now_sec}};
+use crate::{agent_control::{udp_channel::{UdpChannel, UdpTunnelRx}, udp_proto::UdpFlow, DualStackUdpSocket, PacketIO}, network::{address_lookup::{check_self_loop, AddressLookup, HostOrigin, LocalAddrSelection}, client_geo::ClientGeo, connection_hooks::{ConnectionHookGuard, ConnectionHooks}, connection_stats::{ActiveConnectionCounts, ActiveConnectionGuard}, idle_sleep::IdleSleep, tunnel_quota::TunnelQuotas, lan_address::{local_addr_with_offset, SourcePorts}, proxy_protocol::ProxyProtocolHeader, udp::{receive_task::{UdpReceiverTask, MAX_RECV_BATCH_SIZE}, send_task::{try_queue, QueueError, QueuedPacket, UdpSenderTask, SEND_QUEUE_SIZE}}}, utils::{error_helper::MaxErrorInterval, id_slab::IdSlab, now_sec, redact::ClientAddr, supervise::spawn_restarting}};
 
-use super::{packets::Packets, receive_task::SocketPacket};
+use super::{packets::{Packet, Packets}, receive_task::SocketPacket};
 
 pub struct UdpClients<I: UdpTunnelProvider> {
     packets: Packets,
@@ -22,6 +22,106 @@ pub struct UdpClients<I: UdpTunnelProvider> {
     flow_to_socket_id: BTreeMap<UdpFlow, u64>,
     udp_details: UdpDetailsSenderInner,
     last_clear_old: Instant,
+    connection_hooks: ConnectionHooks,
+    active_counts: ActiveConnectionCounts,
+    timeouts: UdpClientTimeouts,
+    proxy_resend: UdpProxyResend,
+    local_addr_selection: LocalAddrSelection,
+    client_geo: ClientGeo,
+    recv_batch_size: Arc<AtomicUsize>,
+    send_queue_drops: u64,
+    flow_dump_requested: Arc<AtomicBool>,
+    quotas: TunnelQuotas,
+    idle_sleep: IdleSleep,
+}
+
+/* asks the UDP task to log its flow table on the next receive loop */
+#[derive(Clone)]
+pub struct UdpFlowDumpTrigger {
+    requested: Arc<AtomicBool>,
+}
+
+impl UdpFlowDumpTrigger {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpClientTimeouts {
+    /* grace period for a client whose local host has not sent anything yet, measured from the last tunnel packet */
+    pub no_host_activity: Duration,
+    /* client is removed when neither side has sent anything for this long */
+    pub both_idle: Duration,
+    /* client is removed when either side has sent nothing for this long, even if the other side is active */
+    pub one_side_idle: Duration,
+}
+
+impl Default for UdpClientTimeouts {
+    fn default() -> Self {
+        UdpClientTimeouts {
+            no_host_activity: Duration::from_secs(15),
+            both_idle: Duration::from_secs(60),
+            one_side_idle: Duration::from_secs(300),
+        }
+    }
+}
+
+impl UdpClientTimeouts {
+    fn keep_client(&self, since_tunnel_activity: Duration, since_host_activity: Option<Duration>) -> bool {
+        let Some(since_host_activity) = since_host_activity else {
+            return since_tunnel_activity < self.no_host_activity;
+        };
+
+        if self.both_idle < since_host_activity.min(since_tunnel_activity) {
+            return false;
+        }
+
+        if self.one_side_idle < since_host_activity.max(since_tunnel_activity) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/*
+ * UDP has no connection so the PROXY protocol v2 header is sent ahead of a
+ * client's packets and resent while the origin might have lost it (ex. it
+ * restarted or expired the client). Once the origin replies it has the
+ * header, so resends stop until the origin has been quiet for host_idle.
+ * min_resend caps how often the header is sent while the client keeps
+ * sending, a min_resend above host_idle delays the first resend past it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpProxyResend {
+    pub host_idle: Duration,
+    pub min_resend: Duration,
+}
+
+impl Default for UdpProxyResend {
+    fn default() -> Self {
+        UdpProxyResend {
+            host_idle: Duration::from_secs(15),
+            min_resend: Duration::from_secs(2),
+        }
+    }
+}
+
+impl UdpProxyResend {
+    fn should_send(&self, since_host_activity: Option<Duration>, since_last_send: Option<Duration>) -> bool {
+        /* have recent packets from host, they must be okay with client so no need to resend proxy protocol */
+        if since_host_activity.is_some_and(|since| since < self.host_idle) {
+            return false;
+        }
+
+        /* don't send proxy protocol packet more than once every min_resend */
+        if since_last_send.is_some_and(|since| since < self.min_resend) {
+            return false;
+        }
+
+        true
+    }
 }
 
 pub struct UdpDetailsSender {
@@ -55,11 +155,17 @@ pub trait UdpTunnelProvider: AddressLookup {
 
 pub struct DualSocketTunnelProvider<L: AddressLookup> {
     lookup: L,
+    source_ports: Option<SourcePorts>,
 }
 
 impl<L: AddressLookup> DualSocketTunnelProvider<L> {
     pub fn new(lookup: L) -> Self {
-        DualSocketTunnelProvider { lookup }
+        DualSocketTunnelProvider { lookup, source_ports: None }
+    }
+
+    /* client sockets bind the next free port of the range */
+    pub fn set_source_ports(&mut self, ports: Option<SourcePorts>) {
+        self.source_ports = ports;
     }
 }
 
@@ -75,24 +181,31 @@ impl<L: AddressLookup> UdpTunnelProvider for DualSocketTunnelProvider<L> {
     type PacketIO = DualStackUdpSocket;
 
     async fn alloc_socket(&self) -> std::io::Result<Self::PacketIO> {
-        DualStackUdpSocket::new().await
+        match self.source_ports {
+            Some(ports) => DualStackUdpSocket::bind_ports(ports).await,
+            None => DualStackUdpSocket::new().await,
+        }
     }
 }
 
 struct ErrorLogs {
     channel: MaxErrorInterval,
     tunnel_missing: MaxErrorInterval,
+    self_loop: MaxErrorInterval,
     max_sockets: MaxErrorInterval,
     send: MaxErrorInterval,
     unexpected_origin: MaxErrorInterval,
     session_send_fail: MaxErrorInterval,
     pkt_send: MaxErrorInterval,
     out_of_packets: MaxErrorInterval,
+    send_queue_full: MaxErrorInterval,
 }
 
 struct Socket<I: PacketIO> {
     id: u64,
     packet_io: Arc<I>,
+    /* sends to origins go through a queue so a slow origin can't stall other clients, None for the tunnel socket */
+    send_queue: Option<Sender<QueuedPacket>>,
     run_receiver: Arc<AtomicBool>,
     clients: SocketClients,
     socket_type: SocketType,
@@ -112,7 +225,7 @@ impl<I: PacketIO> Drop for Socket<I> {
 }
 
 impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
-    pub fn new(provider: I, tunnel_socket: Arc<I::PacketIO>, packet_count: usize) -> Self {
+    pub fn new(provider: I, tunnel_socket: Arc<I::PacketIO>, packet_count: usize, timeouts: UdpClientTimeouts) -> Self {
         assert!(2 < packet_count);
 
         let packets = Packets::new(packet_count);
@@ -121,24 +234,35 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
         let mut sockets = IdSlab::with_capacity(1024);
         let entry = sockets.vacant_entry().expect("first alloc of slab failed");
         let tunnel_socket_id = entry.id();
+        let recv_batch_size = Arc::new(AtomicUsize::new(1));
 
         let tunnel_socket = Socket {
             id: tunnel_socket_id,
             packet_io: tunnel_socket,
+            send_queue: None,
             run_receiver: Arc::new(AtomicBool::new(true)),
             clients: SocketClients::default(),
             socket_type: SocketType::Tunnel,
             empty_at: Instant::now(),
         };
 
-        tokio::spawn(UdpReceiverTask {
-            id: tunnel_socket_id,
-            rx: tunnel_socket.packet_io.clone(),
-            run: tunnel_socket.run_receiver.clone(),
-            packets: packets.clone(),
-            tx: rx_packets_sender.clone(),
-            rx_offset: 0,
-        }.start());
+        {
+            let rx = tunnel_socket.packet_io.clone();
+            let run = tunnel_socket.run_receiver.clone();
+            let packets = packets.clone();
+            let tx = rx_packets_sender.clone();
+            let batch_size = recv_batch_size.clone();
+
+            spawn_restarting("udp_tunnel_receiver", run.clone(), move || UdpReceiverTask {
+                id: tunnel_socket_id,
+                rx: rx.clone(),
+                run: run.clone(),
+                packets: packets.clone(),
+                tx: tx.clone(),
+                rx_offset: 0,
+                batch_size: Some(batch_size.clone()),
+            }.start());
+        }
 
         entry.insert(tunnel_socket);
 
@@ -153,34 +277,145 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
             errors: ErrorLogs {
                 channel: MaxErrorInterval::new(Duration::from_secs(2)),
                 tunnel_missing: MaxErrorInterval::new(Duration::from_secs(2)),
+                self_loop: MaxErrorInterval::new(Duration::from_secs(2)),
                 max_sockets: MaxErrorInterval::new(Duration::from_secs(2)),
                 send: MaxErrorInterval::new(Duration::from_secs(2)),
                 unexpected_origin: MaxErrorInterval::new(Duration::from_secs(2)),
                 session_send_fail: MaxErrorInterval::new(Duration::from_secs(2)),
                 pkt_send: MaxErrorInterval::new(Duration::from_secs(2)),
                 out_of_packets: MaxErrorInterval::new(Duration::from_secs(2)),
+                send_queue_full: MaxErrorInterval::new(Duration::from_secs(2)),
             },
             flow_to_socket_id: Default::default(),
             udp_details: UdpDetailsSenderInner {
                 value: Arc::new(Mutex::new(None)),
             },
             last_clear_old: Instant::now(),
+            connection_hooks: ConnectionHooks::default(),
+            active_counts: ActiveConnectionCounts::default(),
+            timeouts,
+            proxy_resend: UdpProxyResend::default(),
+            local_addr_selection: LocalAddrSelection::default(),
+            client_geo: ClientGeo::default(),
+            recv_batch_size,
+            send_queue_drops: 0,
+            flow_dump_requested: Arc::new(AtomicBool::new(false)),
+            quotas: TunnelQuotas::default(),
+            idle_sleep: IdleSleep::default(),
         }
     }
 
+    /* empty client sockets are closed right away while asleep */
+    pub fn set_idle_sleep(&mut self, idle_sleep: IdleSleep) {
+        self.idle_sleep = idle_sleep;
+    }
+
+    pub fn provider_mut(&mut self) -> &mut I {
+        &mut self.provider
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: UdpClientTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub fn set_proxy_resend(&mut self, proxy_resend: UdpProxyResend) {
+        self.proxy_resend = proxy_resend;
+    }
+
+    pub fn set_connection_hooks(&mut self, hooks: ConnectionHooks) {
+        self.connection_hooks = hooks;
+    }
+
+    pub fn set_active_connection_counts(&mut self, counts: ActiveConnectionCounts) {
+        self.active_counts = counts;
+    }
+
+    pub fn set_local_addr_selection(&mut self, selection: LocalAddrSelection) {
+        self.local_addr_selection = selection;
+    }
+
+    pub fn set_tunnel_quotas(&mut self, quotas: TunnelQuotas) {
+        self.quotas = quotas;
+    }
+
+    pub fn set_client_geo(&mut self, geo: ClientGeo) {
+        self.client_geo = geo;
+    }
+
+    /*
+     * Only the tunnel socket receives in batches, it carries every client's
+     * traffic. Origin sockets stay at one packet so idle clients don't each
+     * hold a batch worth of packets from the shared pool.
+     */
+    pub fn set_recv_batch_size(&mut self, batch_size: usize) {
+        self.recv_batch_size.store(batch_size.clamp(1, MAX_RECV_BATCH_SIZE), Ordering::Relaxed);
+    }
+
     pub fn udp_channel(&self) -> UdpChannel {
         self.udp_channel.clone()
     }
 
+    pub fn flow_dump_trigger(&self) -> UdpFlowDumpTrigger {
+        UdpFlowDumpTrigger {
+            requested: self.flow_dump_requested.clone(),
+        }
+    }
+
     pub fn udp_details_sender(&self) -> UdpDetailsSender {
         UdpDetailsSender {
             inner: self.udp_details.clone(),
         }
     }
 
+    /*
+     * Snapshot for debugging stuck UDP (see UdpFlowDumpTrigger). One line per
+     * flow with the client, the tunnel address it arrived on, the local
+     * address it is forwarded to and how long each side has been quiet.
+     */
+    fn log_flow_table(&self) {
+        tracing::info!(
+            flows = self.flow_to_socket_id.len(),
+            sockets = self.sockets.len(),
+            send_queue_drops = self.send_queue_drops,
+            "UDP flow table"
+        );
+
+        for (flow, socket_id) in &self.flow_to_socket_id {
+            let client = self.sockets.get(*socket_id).and_then(|socket| socket.clients.get_client(flow));
+            let Some(client) = client else {
+                tracing::info!(
+                    socket_id,
+                    client = %ClientAddr(flow.src()),
+                    tunnel_addr = %flow.dst(),
+                    "UDP flow without client"
+                );
+                continue;
+            };
+
+            let port_offset = flow.dst().port() - client.resource.tunn_from_port;
+            tracing::info!(
+                socket_id,
+                tunnel_id = %client.tunnel_id,
+                client = %ClientAddr(flow.src()),
+                tunnel_addr = %flow.dst(),
+                local_addr = %local_addr_with_offset(client.resource.host_origin, port_offset),
+                since_tunnel_activity = ?client.last_tunnel_activity.elapsed(),
+                since_host_activity = ?client.last_host_activity.map(|at| at.elapsed()),
+                uses_proxy_protocol = client.uses_proxy_protocol,
+                "UDP flow"
+            );
+        }
+    }
+
     fn clear_old(&mut self) {
         let mut sockets_to_remove = Vec::<u64>::new();
         let mut flows_to_remove = Vec::<UdpFlow>::new();
+        let timeouts = self.timeouts;
+        let empty_linger = if self.idle_sleep.is_asleep() {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(60)
+        };
 
         for socket in self.sockets.iter_mut() {
             if socket.socket_type == SocketType::Tunnel {
@@ -188,7 +423,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
             }
 
             if socket.clients.clients.len() == 0 {
-                if Duration::from_secs(60) < socket.empty_at.elapsed() {
+                if empty_linger < socket.empty_at.elapsed() {
                     tracing::info!(socket_id = socket.id, "removing empty socket with no recent activity");
                     sockets_to_remove.push(socket.id);
                 }
@@ -196,28 +431,10 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
             }
 
             socket.clients.clients.retain(|client| {
-                let keep = 'keep: {
-                    let since_tunnel_activity = client.last_tunnel_activity.elapsed();
-
-                    /* no data from host and 15s since tunnel data */
-                    if client.last_host_activity.is_none() {
-                        break 'keep since_tunnel_activity < Duration::from_secs(15);
-                    }
-
-                    let since_host_activity = client.last_host_activity.unwrap().elapsed();
-
-                    /* most recent acitivty was within that last minute */
-                    if Duration::from_secs(60) < since_host_activity.min(since_tunnel_activity) {
-                        break 'keep false;
-                    }
-
-                    /* one side of the connection has no activity in last 5m */
-                    if Duration::from_secs(300) < since_host_activity.max(since_tunnel_activity) {
-                        break 'keep false;
-                    }
-
-                    true
-                };
+                let keep = timeouts.keep_client(
+                    client.last_tunnel_activity.elapsed(),
+                    client.last_host_activity.map(|at| at.elapsed()),
+                );
 
                 if keep {
                     return true;
@@ -254,7 +471,14 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
 
                 assert!(flow_rm_count != 0, "client should have at least 1 flow");
 
-                tracing::info!(socket_id = socket.id, ?client, flow_rm_count, "removing old client from socket");
+                tracing::info!(
+                    socket_id = socket.id,
+                    tunnel_id = %client.tunnel_id,
+                    client = %ClientAddr(client.tunnel_flow.client_addr()),
+                    host_origin = %client.resource.host_origin,
+                    flow_rm_count,
+                    "removing old client from socket"
+                );
                 false
             });
 
@@ -283,6 +507,10 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
             self.clear_old();
         }
 
+        if self.flow_dump_requested.swap(false, Ordering::AcqRel) {
+            self.log_flow_table();
+        }
+
         /* send UDP session details */
         {
             if let Some(udp_details) = self.udp_details.take() {
@@ -340,6 +568,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
         };
 
         client.last_host_activity = Some(Instant::now());
+        self.quotas.add_bytes(client.tunnel_id, packet_data_len as u64);
 
         let client_flow = {
             let port_offset = packet.address.port() - client.resource.host_origin.port();
@@ -376,7 +605,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
         }
     }
 
-    async fn handle_tunnel_packet(&mut self, mut packet: SocketPacket) {
+    async fn handle_tunnel_packet(&mut self, packet: SocketPacket) {
         let parse_res = self.udp_channel.parse_packet(
             &packet.packet.full_slice()[packet.data_offset..],
             packet.packet.len() - packet.data_offset,
@@ -395,7 +624,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
 
         match rx {
             UdpTunnelRx::ReceivedPacket { bytes, flow } => {
-                self.forward_packet_to_origin(flow, packet.packet.full_slice_mut(), packet.data_offset, bytes).await;
+                self.forward_packet_to_origin(flow, packet.address, packet.packet, packet.data_offset, bytes).await;
             }
             UdpTunnelRx::ConfirmedConnection => {
                 tracing::info!("UDP session confirmed");
@@ -406,7 +635,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
         }
     }
 
-    async fn forward_packet_to_origin(&mut self, flow_path: UdpFlow, buffer: &mut [u8], data_start: usize, data_len: usize) {
+    async fn forward_packet_to_origin(&mut self, flow_path: UdpFlow, tunnel_server: SocketAddr, packet: Packet, data_start: usize, data_len: usize) {
         let mut now = Instant::now();
 
         let socket_id = match self.flow_to_socket_id.entry(flow_path) {
@@ -415,7 +644,7 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
                 let path = v.key();
                 let Some(found) = self.provider.lookup(path.dst().ip(), path.dst().port(), PortType::Udp) else {
                     if self.errors.tunnel_missing.check() {
-                        tracing::error!(?path, "could not find tunnel for new flow");
+                        tracing::error!(client = %ClientAddr(path.src()), tunnel_addr = %path.dst(), "could not find tunnel for new flow");
                     }
                     return;
                 };
@@ -424,13 +653,34 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
                 assert!(found.from_port <= flow_path.dst().port());
                 assert!(flow_path.dst().port() < found.to_port);
 
+                let host_addr = host_origin.select_host_addr(flow_path.src().ip(), self.local_addr_selection);
+                let target_addr = local_addr_with_offset(host_addr, flow_path.dst().port() - found.from_port);
+
+                if let Some(self_loop) = check_self_loop(target_addr, flow_path.dst(), &[tunnel_server.ip()]) {
+                    if self.errors.self_loop.check() {
+                        tracing::error!(
+                            ?self_loop,
+                            tunnel_id = %host_origin.tunnel_id,
+                            local_addr = %target_addr,
+                            "refusing UDP flow, tunnel's local address points back to playit. Update the tunnel's local address to your server"
+                        );
+                    }
+                    return;
+                }
+
+                /* over quota, new flows are dropped until the window resets */
+                if self.quotas.try_connect(host_origin.tunnel_id).is_err() {
+                    return;
+                }
+
                 let uses_proxy_protocol = host_origin.proxy_protocol == Some(ProxyProtocol::ProxyProtocolV2);
-                tracing::info!(uses_proxy_protocol, tunnel_id = %host_origin.tunnel_id, ?flow_path, "new UDP client");
+                tracing::info!(uses_proxy_protocol, tunnel_id = %host_origin.tunnel_id, client = %ClientAddr(flow_path.src()), tunnel_addr = %flow_path.dst(), "new UDP client");
+                self.client_geo.log_connection(host_origin.tunnel_id, flow_path.src(), "udp");
 
                 let socket_client = SocketClient {
                     tunnel_id: host_origin.tunnel_id,
                     resource: HostResource {
-                        host_origin: host_origin.host_addr,
+                        host_origin: host_addr,
                         tunn_from_port: found.from_port,
                         tunn_to_port: found.to_port,
                     },
@@ -442,6 +692,8 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
                     last_host_activity: None,
                     uses_proxy_protocol,
                     last_proxy_packet: None,
+                    hook_guard: self.connection_hooks.connected(host_origin.tunnel_id, flow_path.src().ip()),
+                    active_guard: self.active_counts.connected(host_origin.tunnel_id, PortType::Udp),
                 };
 
                 let socket = self.sockets.iter_mut().find(|socket| {
@@ -469,23 +721,42 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
                             }
                         };
 
+                        let packet_io = Arc::new(new_io);
+                        let (send_queue, queue_rx) = channel(SEND_QUEUE_SIZE);
+
+                        tokio::spawn(UdpSenderTask {
+                            id: socket_entry.id(),
+                            tx: packet_io.clone(),
+                            queue: queue_rx,
+                        }.start());
+
                         let socket = Socket {
                             id: socket_entry.id(),
-                            packet_io: Arc::new(new_io),
+                            packet_io,
+                            send_queue: Some(send_queue),
                             run_receiver: Arc::new(AtomicBool::new(true)),
                             clients: SocketClients { clients: vec![socket_client] },
                             socket_type: SocketType::Client,
                             empty_at: Instant::now(),
                         };
 
-                        tokio::spawn(UdpReceiverTask {
-                            id: socket.id,
-                            rx: socket.packet_io.clone(),
-                            run: socket.run_receiver.clone(),
-                            packets: self.packets.clone(),
-                            tx: self.rx_packets_sender.clone(),
-                            rx_offset: 0,
-                        }.start());
+                        {
+                            let id = socket.id;
+                            let rx = socket.packet_io.clone();
+                            let run = socket.run_receiver.clone();
+                            let packets = self.packets.clone();
+                            let tx = self.rx_packets_sender.clone();
+
+                            spawn_restarting("udp_origin_receiver", run.clone(), move || UdpReceiverTask {
+                                id,
+                                rx: rx.clone(),
+                                run: run.clone(),
+                                packets: packets.clone(),
+                                tx: tx.clone(),
+                                rx_offset: 0,
+                                batch_size: None,
+                            }.start());
+                        }
 
                         socket_entry.insert(socket)
                     }
@@ -501,10 +772,11 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
 
         let client = socket.clients.get_client_mut(&flow_path).expect("could not find client");
         client.last_tunnel_activity = now;
+        self.quotas.add_bytes(client.tunnel_id, data_len as u64);
 
-        let target_addr = SocketAddr::new(
-            client.resource.host_origin.ip(),
-            client.resource.host_origin.port() + flow_path.dst().port() - client.resource.tunn_from_port
+        let target_addr = local_addr_with_offset(
+            client.resource.host_origin,
+            flow_path.dst().port() - client.resource.tunn_from_port
         );
 
         'send_proxy_packet: {
@@ -512,18 +784,10 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
                 break 'send_proxy_packet;
             }
 
-            /* have recent packets from host, they must be okay with client so need to resend proxy protocol */
-            if let Some(host_ts) = &client.last_host_activity {
-                if host_ts.elapsed() < Duration::from_secs(15) {
-                    break 'send_proxy_packet;
-                }
-            }
-
-            /* don't send proxy protocol packet more than once ever 2s */
-            if let Some(send_ts) = &client.last_proxy_packet {
-                if send_ts.elapsed() < Duration::from_secs(2) {
-                    break 'send_proxy_packet;
-                }
+            let since_host_activity = client.last_host_activity.map(|ts| ts.elapsed());
+            let since_last_send = client.last_proxy_packet.map(|ts| ts.elapsed());
+            if !self.proxy_resend.should_send(since_host_activity, since_last_send) {
+                break 'send_proxy_packet;
             }
 
             /* Send proxy protocol header to establish true origin IP */
@@ -538,11 +802,15 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
 
                 packet.set_len(len).expect("len should be within bounds");
 
-                /* send proxy protocol packet */
+                /* send proxy protocol packet, queued ahead of the data so ordering is kept */
                 {
-                    if let Err(error) = socket.packet_io.send_to(packet.as_ref(), target_addr).await {
+                    let send_queue = socket.send_queue.as_ref().expect("client socket missing send queue");
+
+                    if let Err(error) = try_queue(send_queue, QueuedPacket { packet, data_start: 0, data_len: len, target: target_addr }) {
+                        self.send_queue_drops += 1;
+
                         if self.errors.send.check() {
-                            tracing::error!(?error, "failed to send PROXY PROTOCOL V2 packet");
+                            tracing::error!(?error, "failed to queue PROXY PROTOCOL V2 packet");
                         }
                     }
                 }
@@ -556,12 +824,31 @@ impl<I: UdpTunnelProvider> UdpClients<I> where I::Value: Into<HostOrigin> {
             }
         }
 
-        if let Err(error) = socket.packet_io.send_to(&buffer[data_start..(data_start + data_len)], target_addr).await {
-            if self.errors.send.check() {
-                tracing::error!(?error, "failed to send packet");
+        let send_queue = socket.send_queue.as_ref().expect("client socket missing send queue");
+
+        match try_queue(send_queue, QueuedPacket { packet, data_start, data_len, target: target_addr }) {
+            Ok(()) => {}
+            Err(QueueError::Full) => {
+                self.send_queue_drops += 1;
+
+                if self.errors.send_queue_full.check() {
+                    tracing::warn!(%target_addr, dropped = self.send_queue_drops, "origin not keeping up, dropping packet");
+                }
+            }
+            Err(QueueError::Closed) => {
+                self.send_queue_drops += 1;
+
+                if self.errors.send.check() {
+                    tracing::error!(%target_addr, "send queue closed, dropping packet");
+                }
             }
         }
     }
+
+    /* packets dropped because an origin's send queue was full */
+    pub fn send_queue_drops(&self) -> u64 {
+        self.send_queue_drops
+    }
 }
 
 #[derive(Default)]
@@ -576,20 +863,12 @@ impl SocketClients {
             .is_some()
     }
     
-    fn get_client_mut(&mut self, flow: &UdpFlow) -> Option<&mut SocketClient> {
-        self.clients.iter_mut().find(|client| {
-            let (same_tunnel_ip, dst_port) = match (&client.tunnel_flow, flow) {
-                (TunnelFlow::V4Client { tunnel_ip, .. }, UdpFlow::V4 { dst, .. }) => (tunnel_ip.eq(dst.ip()), dst.port()),
-                (TunnelFlow::V6Client { tunnel_ip, .. }, UdpFlow::V6 { dst, .. }) => (tunnel_ip.eq(&dst.0), dst.1),
-                _ => (false, 0),
-            };
-
-            if !same_tunnel_ip {
-                return false;
-            }
+    fn get_client(&self, flow: &UdpFlow) -> Option<&SocketClient> {
+        self.clients.iter().find(|client| client.matches_flow(flow))
+    }
 
-            client.resource.tunn_from_port <= dst_port && dst_port < client.resource.tunn_to_port
-        })
+    fn get_client_mut(&mut self, flow: &UdpFlow) -> Option<&mut SocketClient> {
+        self.clients.iter_mut().find(|client| client.matches_flow(flow))
     }
 }
 
@@ -605,6 +884,11 @@ pub struct SocketClient {
 
     pub uses_proxy_protocol: bool,
     pub last_proxy_packet: Option<Instant>,
+
+    /* fires disconnect hook when client is removed */
+    pub hook_guard: Option<ConnectionHookGuard>,
+    /* counts the client as active until removed */
+    pub active_guard: ActiveConnectionGuard,
 }
 
 #[derive(Debug)]
@@ -628,6 +912,31 @@ pub enum TunnelFlow {
     },
 }
 
+impl SocketClient {
+    fn matches_flow(&self, flow: &UdpFlow) -> bool {
+        let (same_tunnel_ip, dst_port) = match (&self.tunnel_flow, flow) {
+            (TunnelFlow::V4Client { tunnel_ip, .. }, UdpFlow::V4 { dst, .. }) => (tunnel_ip.eq(dst.ip()), dst.port()),
+            (TunnelFlow::V6Client { tunnel_ip, .. }, UdpFlow::V6 { dst, .. }) => (tunnel_ip.eq(&dst.0), dst.1),
+            _ => (false, 0),
+        };
+
+        if !same_tunnel_ip {
+            return false;
+        }
+
+        self.resource.tunn_from_port <= dst_port && dst_port < self.resource.tunn_to_port
+    }
+}
+
+impl TunnelFlow {
+    fn client_addr(&self) -> SocketAddr {
+        match self {
+            TunnelFlow::V4Client { client_ip, client_port, .. } => SocketAddr::new((*client_ip).into(), *client_port),
+            TunnelFlow::V6Client { client_ip, client_port, .. } => SocketAddr::new((*client_ip).into(), *client_port),
+        }
+    }
+}
+
 impl HostResource {
     fn contains_addr(&self, addr: &SocketAddr) -> bool {
         if self.host_origin.ip() != addr.ip() {
@@ -656,3 +965,58 @@ impl HostResource {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{UdpClientTimeouts, UdpProxyResend};
+
+    #[test]
+    fn test_raised_idle_timeouts() {
+        let defaults = UdpClientTimeouts::default();
+        let raised = UdpClientTimeouts {
+            no_host_activity: Duration::from_secs(60),
+            both_idle: Duration::from_secs(600),
+            one_side_idle: Duration::from_secs(3600),
+        };
+
+        let secs = Duration::from_secs;
+
+        assert!(defaults.keep_client(secs(10), None));
+        assert!(!defaults.keep_client(secs(20), None));
+        assert!(raised.keep_client(secs(20), None));
+
+        /* turn based game idle for a few minutes */
+        assert!(!defaults.keep_client(secs(120), Some(secs(90))));
+        assert!(raised.keep_client(secs(120), Some(secs(90))));
+
+        /* host still sending but client gone */
+        assert!(!defaults.keep_client(secs(400), Some(secs(1))));
+        assert!(raised.keep_client(secs(400), Some(secs(1))));
+        assert!(!raised.keep_client(secs(4000), Some(secs(1))));
+    }
+
+    #[test]
+    fn test_proxy_resend_intervals() {
+        let secs = Duration::from_secs;
+        let resend = UdpProxyResend { host_idle: secs(30), min_resend: secs(5) };
+
+        /* first packet of a flow always sends the header */
+        assert!(resend.should_send(None, None));
+
+        /* origin never answered, throttled by min_resend */
+        assert!(!resend.should_send(None, Some(secs(4))));
+        assert!(resend.should_send(None, Some(secs(5))));
+
+        /* origin active, no resend until it is idle for host_idle */
+        assert!(!resend.should_send(Some(secs(29)), Some(secs(60))));
+        assert!(resend.should_send(Some(secs(30)), Some(secs(60))));
+
+        /* idle origin still throttled */
+        assert!(!resend.should_send(Some(secs(45)), Some(secs(1))));
+
+        let defaults = UdpProxyResend::default();
+        assert!(!defaults.should_send(Some(secs(14)), None));
+        assert!(defaults.should_send(Some(secs(15)), Some(secs(2))));
+    }
+}
@@ -1,3 +1,6 @@
 pub mod receive_task;
 pub mod packets;
-pub mod clients;
\ No newline at end of file
+pub mod clients;
+pub mod send_task;
+#[cfg(target_os = "linux")]
+pub mod recv_batch;
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+use std::{net::SocketAddr, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}, time::Duration};
 
 use tokio::sync::mpsc::{error::SendTimeoutError, Sender};
 
@@ -6,6 +6,9 @@ use crate::{agent_control::PacketRx, utils::error_helper::MaxErrorInterval};
 
 use super::packets::{Packet, Packets};
 
+/* upper bound for the configurable receive batch, batches only help on linux (recvmmsg) */
+pub const MAX_RECV_BATCH_SIZE: usize = 64;
+
 pub struct UdpReceiverTask<I: PacketRx> {
     pub id: u64,
     pub rx: I,
@@ -13,6 +16,8 @@ pub struct UdpReceiverTask<I: PacketRx> {
     pub packets: Packets,
     pub tx: Sender<SocketPacket>,
     pub rx_offset: usize,
+    /* read every loop so changes apply to a running receiver, None or 1 receives one packet per recv_from */
+    pub batch_size: Option<Arc<AtomicUsize>>,
 }
 
 pub struct SocketPacket {
@@ -24,29 +29,57 @@ pub struct SocketPacket {
 
 impl<I: PacketRx> UdpReceiverTask<I> {
     pub async fn start(self) {
-        let mut rx_packet = None;
+        let mut rx_packets: Vec<Packet> = Vec::new();
+        let mut received = Vec::new();
 
         let mut out_of_packets = MaxErrorInterval::new(Duration::from_secs(5));
         let mut io_error = MaxErrorInterval::new(Duration::from_secs(3));
 
         while self.run.load(Ordering::Relaxed) {
-            if rx_packet.is_none() {
-                rx_packet = self.packets.allocate();
+            let batch_size = match &self.batch_size {
+                Some(size) => size.load(Ordering::Relaxed).clamp(1, MAX_RECV_BATCH_SIZE),
+                None => 1,
+            };
 
-                if rx_packet.is_none() {
-                    if out_of_packets.check() {
-                        tracing::warn!("out of free packets to receive from UDP socket");
-                    }
+            while rx_packets.len() < batch_size {
+                match self.packets.allocate() {
+                    Some(packet) => rx_packets.push(packet),
+                    None => break,
+                }
+            }
 
-                    tokio::time::sleep(Duration::from_millis(50)).await;
-                    continue;
+            if rx_packets.is_empty() {
+                if out_of_packets.check() {
+                    tracing::warn!("out of free packets to receive from UDP socket");
                 }
+
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
             }
 
-            let packet = rx_packet.as_mut().expect("rx packet should be allocated");
-            let rx_buffer = &mut packet.full_slice_mut()[self.rx_offset..];
+            received.clear();
 
-            match tokio::time::timeout(Duration::from_secs(5), self.rx.recv_from(rx_buffer)).await {
+            let result = if batch_size == 1 {
+                let rx_buffer = &mut rx_packets[0].full_slice_mut()[self.rx_offset..];
+
+                match tokio::time::timeout(Duration::from_secs(5), self.rx.recv_from(rx_buffer)).await {
+                    Ok(Ok(res)) => {
+                        received.push(res);
+                        Ok(Ok(()))
+                    }
+                    Ok(Err(error)) => Ok(Err(error)),
+                    Err(timeout) => Err(timeout),
+                }
+            } else {
+                let mut rx_buffers: Vec<&mut [u8]> = rx_packets.iter_mut()
+                    .take(batch_size)
+                    .map(|packet| &mut packet.full_slice_mut()[self.rx_offset..])
+                    .collect();
+
+                tokio::time::timeout(Duration::from_secs(5), self.rx.recv_batch(&mut rx_buffers, &mut received)).await
+            };
+
+            match result {
                 Err(_) => {
                     continue;
                 }
@@ -55,25 +88,28 @@ impl<I: PacketRx> UdpReceiverTask<I> {
                         tracing::error!(?error, "failed to receive UDP packet");
                     }
                 }
-                Ok(Ok((bytes, source))) => {
-                    packet.set_len(bytes + self.rx_offset).expect("receive length too large");
-
-                    let send_res = self.tx.send_timeout(SocketPacket {
-                        socket_id: self.id,
-                        packet: rx_packet.take().expect("missing rx packet"),
-                        address: source,
-                        data_offset: self.rx_offset,
-                    }, Duration::from_secs(1)).await;
-
-                    match send_res {
-                        Ok(()) => {},
-                        Err(SendTimeoutError::Closed(_)) => {
-                            tracing::error!("UDP receive queue closed, closing receiver");
-                            break;
-                        }
-                        Err(SendTimeoutError::Timeout(_)) => {
-                            tracing::error!("timeout sending to UDP receive queue");
-                            continue;
+                Ok(Ok(())) => {
+                    let count = received.len();
+
+                    for (mut packet, (bytes, source)) in rx_packets.drain(..count).zip(received.drain(..)) {
+                        packet.set_len(bytes + self.rx_offset).expect("receive length too large");
+
+                        let send_res = self.tx.send_timeout(SocketPacket {
+                            socket_id: self.id,
+                            packet,
+                            address: source,
+                            data_offset: self.rx_offset,
+                        }, Duration::from_secs(1)).await;
+
+                        match send_res {
+                            Ok(()) => {},
+                            Err(SendTimeoutError::Closed(_)) => {
+                                tracing::error!("UDP receive queue closed, closing receiver");
+                                return;
+                            }
+                            Err(SendTimeoutError::Timeout(_)) => {
+                                tracing::error!("timeout sending to UDP receive queue");
+                            }
                         }
                     }
                 },
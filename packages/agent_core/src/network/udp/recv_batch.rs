@@ -0,0 +1,201 @@
+/*
+ * Batched UDP receive with recvmmsg (linux only)
+ *
+ * One recv_from per datagram costs a syscall and a wakeup per packet which
+ * limits busy relays. recvmmsg drains up to MAX_RECV_BATCH_SIZE queued datagrams
+ * in one call. Other platforms fall back to receiving one packet at a time
+ * (see PacketIO::recv_batch).
+ */
+
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::fd::AsRawFd;
+
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+use super::receive_task::MAX_RECV_BATCH_SIZE;
+
+/* non blocking, WouldBlock if nothing is queued */
+pub fn try_recv_batch(socket: &UdpSocket, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> std::io::Result<()> {
+    socket.try_io(Interest::READABLE, || recvmmsg(socket, bufs, received))
+}
+
+pub async fn recv_batch(socket: &UdpSocket, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> std::io::Result<()> {
+    loop {
+        socket.readable().await?;
+
+        match try_recv_batch(socket, bufs, received) {
+            Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+            other => return other,
+        }
+    }
+}
+
+fn recvmmsg(socket: &UdpSocket, bufs: &mut [&mut [u8]], received: &mut Vec<(usize, SocketAddr)>) -> std::io::Result<()> {
+    let count = bufs.len().min(MAX_RECV_BATCH_SIZE);
+    if count == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "no buffers to receive into"));
+    }
+
+    let mut iovecs: [libc::iovec; MAX_RECV_BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut addrs: [libc::sockaddr_storage; MAX_RECV_BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut msgs: [libc::mmsghdr; MAX_RECV_BATCH_SIZE] = unsafe { std::mem::zeroed() };
+
+    for i in 0..count {
+        iovecs[i].iov_base = bufs[i].as_mut_ptr() as *mut libc::c_void;
+        iovecs[i].iov_len = bufs[i].len();
+
+        let hdr = &mut msgs[i].msg_hdr;
+        hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        hdr.msg_iov = &mut iovecs[i];
+        hdr.msg_iovlen = 1;
+    }
+
+    let res = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            count as _,
+            libc::MSG_DONTWAIT as _,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if res < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    for i in 0..res as usize {
+        let source = sockaddr_to_std(&addrs[i])
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unexpected address family from recvmmsg"))?;
+        received.push((msgs[i].msg_len as usize, source));
+    }
+
+    Ok(())
+}
+
+fn sockaddr_to_std(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(addr as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(addr as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use tokio::net::UdpSocket;
+
+    use super::{recv_batch, MAX_RECV_BATCH_SIZE};
+
+    #[tokio::test]
+    async fn test_recv_batch() {
+        let rx = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tx_addr = tx.local_addr().unwrap();
+
+        for i in 0..10u8 {
+            tx.send_to(&[i; 3], rx.local_addr().unwrap()).await.unwrap();
+        }
+
+        let mut storage = vec![[0u8; 64]; 16];
+        let mut got = Vec::new();
+
+        while got.len() < 10 {
+            let mut bufs: Vec<&mut [u8]> = storage.iter_mut().map(|v| &mut v[..]).collect();
+            let mut received = Vec::new();
+            recv_batch(&rx, &mut bufs, &mut received).await.unwrap();
+
+            for (i, (bytes, source)) in received.into_iter().enumerate() {
+                assert_eq!(source, tx_addr);
+                got.push(storage[i][..bytes].to_vec());
+            }
+        }
+
+        let expected: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 3]).collect();
+        assert_eq!(got, expected);
+    }
+
+    /* cargo test -p playit-agent-core --release --lib bench_recv_batch -- --ignored --nocapture */
+    #[tokio::test]
+    #[ignore]
+    async fn bench_recv_batch() {
+        /* fill the socket buffer then time draining it so only the receive side is measured */
+        const ROUNDS: usize = 50;
+        const PACKETS: usize = 4000;
+
+        for batch in [1, 8, MAX_RECV_BATCH_SIZE] {
+            let rx = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let rx_addr = rx.local_addr().unwrap();
+            set_recv_buffer(&rx);
+
+            let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            let mut storage = vec![[0u8; 2048]; batch];
+            let mut received: Vec<(usize, SocketAddr)> = Vec::new();
+            let mut elapsed = Duration::ZERO;
+            let mut total = 0;
+
+            for _ in 0..ROUNDS {
+                for _ in 0..PACKETS {
+                    tx.send_to(&[7u8; 512], rx_addr).unwrap();
+                }
+
+                let start = Instant::now();
+                let mut round = 0;
+
+                while round < PACKETS {
+                    let mut bufs: Vec<&mut [u8]> = storage.iter_mut().map(|v| &mut v[..]).collect();
+                    received.clear();
+
+                    /* batch of 1 is the per packet recv_from path */
+                    if batch == 1 {
+                        received.push(rx.recv_from(bufs[0]).await.unwrap());
+                    } else {
+                        recv_batch(&rx, &mut bufs, &mut received).await.unwrap();
+                    }
+
+                    round += received.len();
+                }
+
+                elapsed += start.elapsed();
+                total += round;
+            }
+
+            println!("batch {:>2}: {} packets in {:?} ({:.0} pkt/s)", batch, total, elapsed, total as f64 / elapsed.as_secs_f64());
+        }
+    }
+
+    fn set_recv_buffer(socket: &UdpSocket) {
+        use std::os::fd::AsRawFd;
+
+        let size: libc::c_int = 8 * 1024 * 1024;
+        unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &size as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+        }
+    }
+}
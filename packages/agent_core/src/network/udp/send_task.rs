@@ -0,0 +1,148 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+
+use crate::{agent_control::PacketIO, utils::error_helper::MaxErrorInterval};
+
+use super::packets::Packet;
+
+/* small so a slow origin drops packets instead of holding a large share of the packet pool */
+pub const SEND_QUEUE_SIZE: usize = 64;
+
+pub struct QueuedPacket {
+    pub packet: Packet,
+    pub data_start: usize,
+    pub data_len: usize,
+    pub target: SocketAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    Full,
+    Closed,
+}
+
+/* never waits, a full queue means the origin is not keeping up */
+pub fn try_queue(queue: &Sender<QueuedPacket>, packet: QueuedPacket) -> Result<(), QueueError> {
+    match queue.try_send(packet) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => Err(QueueError::Full),
+        Err(TrySendError::Closed(_)) => Err(QueueError::Closed),
+    }
+}
+
+pub struct UdpSenderTask<I: PacketIO> {
+    pub id: u64,
+    pub tx: Arc<I>,
+    pub queue: Receiver<QueuedPacket>,
+}
+
+impl<I: PacketIO> UdpSenderTask<I> {
+    /* runs until the socket drops its queue sender */
+    pub async fn start(mut self) {
+        let mut send_error = MaxErrorInterval::new(Duration::from_secs(2));
+
+        while let Some(queued) = self.queue.recv().await {
+            let data = &queued.packet.full_slice()[queued.data_start..(queued.data_start + queued.data_len)];
+
+            if let Err(error) = self.tx.send_to(data, queued.target).await {
+                if send_error.check() {
+                    tracing::error!(?error, socket_id = self.id, target = %queued.target, "failed to send packet");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::mpsc::channel;
+
+    use crate::agent_control::PacketIO;
+    use crate::network::udp::packets::Packets;
+
+    use super::{try_queue, QueueError, QueuedPacket, UdpSenderTask, SEND_QUEUE_SIZE};
+
+    struct Backend {
+        delay: Duration,
+        sent: AtomicUsize,
+    }
+
+    impl PacketIO for Backend {
+        async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> std::io::Result<usize> {
+            if !self.delay.is_zero() {
+                tokio::time::sleep(self.delay).await;
+            }
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            Ok(buf.len())
+        }
+
+        async fn recv_from(&self, _buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_backend_does_not_stall_fast_backend() {
+        const PACKETS: usize = 2000;
+
+        let packets = Packets::new(1024);
+        let target: SocketAddr = "127.0.0.1:25565".parse().unwrap();
+
+        let slow = Arc::new(Backend { delay: Duration::from_millis(50), sent: AtomicUsize::new(0) });
+        let fast = Arc::new(Backend { delay: Duration::ZERO, sent: AtomicUsize::new(0) });
+
+        let (slow_queue, rx) = channel(SEND_QUEUE_SIZE);
+        tokio::spawn(UdpSenderTask { id: 1, tx: slow.clone(), queue: rx }.start());
+
+        let (fast_queue, rx) = channel(SEND_QUEUE_SIZE);
+        tokio::spawn(UdpSenderTask { id: 2, tx: fast.clone(), queue: rx }.start());
+
+        let mut slow_dropped = 0;
+        let mut fast_queued = 0;
+        let start = Instant::now();
+
+        for _ in 0..PACKETS {
+            for (queue, is_slow) in [(&slow_queue, true), (&fast_queue, false)] {
+                let mut packet = loop {
+                    match packets.allocate() {
+                        Some(packet) => break packet,
+                        None => tokio::task::yield_now().await,
+                    }
+                };
+                packet.full_slice_mut()[..4].copy_from_slice(b"data");
+
+                match try_queue(queue, QueuedPacket { packet, data_start: 0, data_len: 4, target }) {
+                    Ok(()) => {
+                        if !is_slow {
+                            fast_queued += 1;
+                        }
+                    }
+                    Err(QueueError::Full) if is_slow => slow_dropped += 1,
+                    Err(QueueError::Full) => {}
+                    Err(QueueError::Closed) => panic!("sender task closed"),
+                }
+            }
+
+            tokio::task::yield_now().await;
+        }
+
+        /* slow backend would need 100s to send everything */
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(PACKETS - SEND_QUEUE_SIZE * 2 < slow_dropped);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while fast.sent.load(Ordering::SeqCst) < fast_queued {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+
+        assert!(PACKETS / 2 < fast_queued);
+        assert!(slow.sent.load(Ordering::SeqCst) < PACKETS / 10);
+    }
+}
@@ -1,47 +1,93 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Sender;
 use tracing::Instrument;
+use uuid::Uuid;
 
-use crate::agent_control::{AuthApi, DualStackUdpSocket};
+use crate::agent_control::{AuthApi, AuthResource, DualStackUdpSocket, KEEPALIVE_LOG_TARGET};
 use crate::network::proxy_protocol::ProxyProtocolHeader;
-use crate::network::udp::clients::{DualSocketTunnelProvider, UdpClients, UdpDetailsSender};
+use crate::network::udp::clients::{DualSocketTunnelProvider, UdpClientTimeouts, UdpClients, UdpDetailsSender, UdpFlowDumpTrigger, UdpProxyResend};
 use playit_api_client::api::{PortType, ProxyProtocol};
-use crate::network::address_lookup::{AddressLookup, HostOrigin};
-use crate::network::lan_address::LanAddress;
-use crate::network::tcp_clients::TcpClients;
-use crate::network::tcp_pipe::pipe;
-use crate::agent_control::errors::SetupError;
-use crate::agent_control::maintained_control::{MaintainedControl, TunnelControlEvent};
+use crate::network::access_log::{AccessLog, CountingWrite};
+use crate::network::address_lookup::{check_self_loop, AddressLookup, HostOrigin, LocalAddrSelection};
+use crate::network::client_filter::ClientFilter;
+use crate::network::client_geo::ClientGeo;
+use crate::network::connection_hooks::ConnectionHooks;
+use crate::network::host_routing::{read_hostname, HostRouting};
+use crate::network::connection_stats::{ActiveConnectionCounts, ServerConnectionCounts, StatsReset};
+use crate::network::idle_sleep::{IdleSleep, IDLE_CONTROL_ADDR_REFRESH, IDLE_UDP_RECV_TIMEOUT};
+use crate::network::lan_address::{local_addr_with_offset, LanAddress, SourcePorts};
+use crate::network::local_stream::LocalStream;
+use crate::network::tcp_clients::{SetupLimit, TcpClients};
+use crate::network::tcp_pipe::{is_valid_buffer_size, pipe_until, MAX_PIPE_BUFFER_SIZE, MIN_PIPE_BUFFER_SIZE};
+use crate::agent_control::errors::{register_error_is_terminal, SetupError};
+use crate::agent_control::health::ControlHealth;
+use crate::agent_control::maintained_control::{DisconnectReason, MaintainedControl, TunnelControlEvent};
 use crate::agent_control::udp_channel::UdpChannel;
+use crate::agent_control::udp_probe::log_path_size;
+use crate::utils::error_helper::MaxErrorInterval;
 use crate::utils::now_milli;
+use crate::utils::redact::ClientAddr;
+use crate::network::tunnel_quota::{QuotaCountingWrite, TunnelQuotas};
+use crate::utils::supervise::{join_task, StopOnExit};
 
-pub struct PlayitAgent<L: AddressLookup> {
+pub struct PlayitAgent<L: AddressLookup, A: AuthResource = AuthApi> {
     lookup: Arc<L>,
-    control: MaintainedControl<DualStackUdpSocket, AuthApi>,
+    control: MaintainedControl<DualStackUdpSocket, A>,
     udp_clients: UdpClients<DualSocketTunnelProvider<Arc<L>>>,
     udp_channel: UdpChannel,
     udp_details_sender: UdpDetailsSender,
     tcp_clients: TcpClients,
+    connection_hooks: ConnectionHooks,
+    client_filter: ClientFilter,
+    client_geo: ClientGeo,
+    server_counts: ServerConnectionCounts,
+    active_counts: ActiveConnectionCounts,
+    quotas: TunnelQuotas,
+    access_log: AccessLog,
+    idle_sleep: IdleSleep,
+    events: Option<Sender<AgentEvent>>,
+    reconnect_on_auth_error: bool,
     keep_running: Arc<AtomicBool>,
 }
 
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    Registered,
+    ControlDisconnected(DisconnectReason),
+}
+
 impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOrigin> + Into<SocketAddr> {
     pub async fn new(api_url: String, secret_key: String, lookup: Arc<L>) -> Result<Self, SetupError> {
+        Self::with_auth(AuthApi::new(api_url, secret_key), lookup).await
+    }
+}
+
+impl<L: AddressLookup + Sync + Send, A: AuthResource + Send + Sync + 'static> PlayitAgent<L, A> where L::Value: Into<HostOrigin> + Into<SocketAddr> {
+    /* use a custom auth resource, see StaticAuthResource for running without the playit api */
+    pub async fn with_auth(auth: A, lookup: Arc<L>) -> Result<Self, SetupError> {
         let io = DualStackUdpSocket::new().await?;
-        let auth = AuthApi::new(api_url, secret_key);
 
         let udp = DualStackUdpSocket::new().await?;
 
         let tunnel = MaintainedControl::setup(io, auth).await?;
-        let udp_clients = UdpClients::new(
+        let active_counts = ActiveConnectionCounts::default();
+
+        let mut udp_clients = UdpClients::new(
             DualSocketTunnelProvider::new(lookup.clone()),
             Arc::new(udp),
-            1024 * 16
+            1024 * 16,
+            UdpClientTimeouts::default(),
         );
 
+        udp_clients.set_active_connection_counts(active_counts.clone());
+
         let udp_channel = udp_clients.udp_channel();
         let udp_details_sender = udp_clients.udp_details_sender();
 
@@ -52,6 +98,16 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
             udp_channel,
             udp_details_sender,
             tcp_clients: TcpClients::new(),
+            connection_hooks: ConnectionHooks::default(),
+            client_filter: ClientFilter::default(),
+            client_geo: ClientGeo::default(),
+            server_counts: ServerConnectionCounts::default(),
+            active_counts,
+            quotas: TunnelQuotas::default(),
+            access_log: AccessLog::default(),
+            idle_sleep: IdleSleep::default(),
+            events: None,
+            reconnect_on_auth_error: false,
             keep_running: Arc::new(AtomicBool::new(true)),
         })
     }
@@ -60,32 +116,212 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
         self.tcp_clients.use_special_lan = set_use;
     }
 
+    /* connections to the local server are made from these ports, see SourcePorts */
+    pub fn set_source_ports(&mut self, ports: Option<SourcePorts>) {
+        self.tcp_clients.source_ports = ports;
+        self.udp_clients.provider_mut().set_source_ports(ports);
+    }
+
+    pub fn set_tcp_buffer_size(&mut self, size: usize) {
+        if !is_valid_buffer_size(size) {
+            tracing::warn!(size, min = MIN_PIPE_BUFFER_SIZE, max = MAX_PIPE_BUFFER_SIZE, "tcp buffer size out of range, clamping");
+        }
+        self.tcp_clients.pipe_buffer_size = size.clamp(MIN_PIPE_BUFFER_SIZE, MAX_PIPE_BUFFER_SIZE);
+    }
+
+    pub fn set_local_connect_timeout(&mut self, timeout: Duration) {
+        self.tcp_clients.local_connect_timeout = timeout;
+    }
+
+    /* TCP clients being set up at once, see SetupLimit */
+    pub fn set_tcp_setup_limit(&mut self, limit: usize) {
+        self.tcp_clients.setup_limit = SetupLimit::new(limit);
+    }
+
+    pub fn tcp_setup_limit(&self) -> SetupLimit {
+        self.tcp_clients.setup_limit.clone()
+    }
+
+    /* prefer the local address with the same IP family as the client when a tunnel has both */
+    pub fn set_match_client_family(&mut self, match_family: bool) {
+        self.set_local_addr_selection(LocalAddrSelection {
+            match_client_family: match_family,
+            ..self.tcp_clients.local_addr_selection
+        });
+    }
+
+    /* prefer the IPv6 local address when a tunnel has both, --match_client_family takes precedence */
+    pub fn set_local_ipv6_first(&mut self, ipv6_first: bool) {
+        self.set_local_addr_selection(LocalAddrSelection {
+            ipv6_first,
+            ..self.tcp_clients.local_addr_selection
+        });
+    }
+
+    fn set_local_addr_selection(&mut self, selection: LocalAddrSelection) {
+        self.tcp_clients.local_addr_selection = selection;
+        self.udp_clients.set_local_addr_selection(selection);
+    }
+
+    /* picks the local backend of TCP connections by hostname, see HostRouting */
+    pub fn set_host_routing(&mut self, routing: HostRouting) {
+        self.tcp_clients.host_routing = routing;
+    }
+
+    /* closes TCP connections of the listed tunnels once they have been open this long */
+    pub fn set_max_connection_lifetimes(&mut self, lifetimes: HashMap<Uuid, Duration>) {
+        self.tcp_clients.max_connection_lifetimes = Arc::new(lifetimes);
+    }
+
+    /* TCP connections of these tunnels go to a UNIX domain socket, only supported on unix */
+    pub fn set_unix_targets(&mut self, targets: HashMap<Uuid, PathBuf>) {
+        self.tcp_clients.unix_targets = Arc::new(targets);
+    }
+
+    pub fn set_udp_client_timeouts(&mut self, timeouts: UdpClientTimeouts) {
+        self.udp_clients.set_timeouts(timeouts);
+    }
+
+    /* when the PROXY protocol v2 header is resent on UDP tunnels using it, see UdpProxyResend */
+    pub fn set_udp_proxy_resend(&mut self, proxy_resend: UdpProxyResend) {
+        self.udp_clients.set_proxy_resend(proxy_resend);
+    }
+
+    /* packets read from the tunnel socket per syscall, only batches on linux (recvmmsg) */
+    pub fn set_udp_recv_batch_size(&mut self, batch_size: usize) {
+        self.udp_clients.set_recv_batch_size(batch_size);
+    }
+
+    pub fn set_connection_hooks(&mut self, hooks: ConnectionHooks) {
+        self.udp_clients.set_connection_hooks(hooks.clone());
+        self.connection_hooks = hooks;
+    }
+
+    /* logs a country / ASN hint for each new client, see ClientGeo */
+    pub fn set_client_geo(&mut self, geo: ClientGeo) {
+        self.udp_clients.set_client_geo(geo.clone());
+        self.client_geo = geo;
+    }
+
+    /* pauses tunnels that go over their quota, see TunnelQuotas */
+    pub fn set_tunnel_quotas(&mut self, quotas: TunnelQuotas) {
+        self.udp_clients.set_tunnel_quotas(quotas.clone());
+        self.quotas = quotas;
+    }
+
+    /* poll and ping less once there have been no connections for this long, see IdleSleep */
+    pub fn set_idle_sleep(&mut self, after: Duration) {
+        let idle_sleep = IdleSleep::new(after, self.active_counts.clone());
+        self.control.set_idle_sleep(idle_sleep.clone());
+        self.udp_clients.set_idle_sleep(idle_sleep.clone());
+        self.idle_sleep = idle_sleep;
+    }
+
+    /* writes a Common / Combined Log Format line per closed TCP connection, see AccessLog */
+    pub fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = access_log;
+    }
+
+    pub fn set_client_filter(&mut self, filter: ClientFilter) {
+        self.client_filter = filter;
+    }
+
+    pub fn set_event_sender(&mut self, sender: Sender<AgentEvent>) {
+        self.events = Some(sender);
+    }
+
+    /* keep retrying even when the api says registration will never succeed */
+    pub fn set_reconnect_on_auth_error(&mut self, reconnect: bool) {
+        self.reconnect_on_auth_error = reconnect;
+    }
+
+    pub fn server_connection_counts(&self) -> ServerConnectionCounts {
+        self.server_counts.clone()
+    }
+
+    pub fn active_connection_counts(&self) -> ActiveConnectionCounts {
+        self.active_counts.clone()
+    }
+
+    /* set_client_filter must be called before, the reset shares the filter's counter */
+    pub fn stats_reset(&self) -> StatsReset {
+        StatsReset {
+            server_counts: self.server_counts.clone(),
+            client_filter: self.client_filter.clone(),
+        }
+    }
+
+    /* registered + keepalive state for readiness checks */
+    pub fn control_health(&self) -> ControlHealth {
+        self.control.health()
+    }
+
+    /* logs the UDP flow table when requested, for debugging stuck UDP */
+    pub fn udp_flow_dump_trigger(&self) -> UdpFlowDumpTrigger {
+        self.udp_clients.flow_dump_trigger()
+    }
+
+    pub fn idle_sleep(&self) -> IdleSleep {
+        self.idle_sleep.clone()
+    }
+
     pub fn keep_running(&self) -> Arc<AtomicBool> {
         self.keep_running.clone()
     }
 
-    pub async fn run(self) {
+    pub async fn run(self) -> Result<(), SetupError> {
         let mut tunnel = self.control;
+        let reconnect_on_auth_error = self.reconnect_on_auth_error;
 
         let tunnel_run = self.keep_running.clone();
+        let idle_sleep = self.idle_sleep.clone();
         let mut udp_details_sender = self.udp_details_sender;
 
+        let events = self.events;
+        let send_event = move |event: AgentEvent| {
+            /* never block the control loop on event consumers */
+            if let Some(events) = &events {
+                if let Err(error) = events.try_send(event) {
+                    tracing::warn!(?error, "failed to queue agent event");
+                }
+            }
+        };
+
+        send_event(AgentEvent::Registered);
+
+        /* separate socket so probe pongs never reach the control channel */
+        let control_addr = tunnel.control_addr();
+        tokio::spawn(async move {
+            match DualStackUdpSocket::new().await {
+                Ok(io) => log_path_size(&io, control_addr).await,
+                Err(error) => tracing::warn!(?error, "failed to create socket for UDP path size probe"),
+            }
+        });
+
         let tunnel_task = tokio::spawn(async move {
+            let _stop = StopOnExit(tunnel_run.clone());
             let mut last_control_update = now_milli();
+            let mut setup_limit_log = MaxErrorInterval::new(Duration::from_secs(2));
 
             while tunnel_run.load(Ordering::SeqCst) {
                 tokio::task::yield_now().await;
 
                 if self.udp_channel.requires_auth() {
-                    if tunnel.send_udp_session_auth(now_milli(), 5_000).await {
-                        tracing::info!("udp channel requires auth, sent auth request");
+                    if tunnel.send_udp_session_auth(now_milli()).await {
+                        tracing::debug!(target: KEEPALIVE_LOG_TARGET, "udp channel requires auth, sent auth request");
                     }
                 }
 
-                /* refresh control address every half minute */
+                /* refresh control address every half minute, every 5 minutes during idle sleep */
                 {
+                    let refresh_interval = if idle_sleep.is_asleep() {
+                        IDLE_CONTROL_ADDR_REFRESH.as_millis() as u64
+                    } else {
+                        30_000
+                    };
+
                     let now = now_milli();
-                    if 30_000 < now_milli() - last_control_update {
+                    if refresh_interval < now_milli() - last_control_update {
                         last_control_update = now;
 
                         if let Err(error) = tunnel.reload_control_addr(async { DualStackUdpSocket::new().await }).await {
@@ -96,9 +332,28 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
 
                 match tunnel.update().await {
                     Some(TunnelControlEvent::NewClient(new_client)) => {
-                        tracing::info!(?new_client, "New TCP Client");
+                        idle_sleep.wake();
+
+                        tracing::info!(
+                            peer_addr = %ClientAddr(new_client.peer_addr),
+                            tunn_addr = %new_client.connect_addr,
+                            sid = new_client.tunnel_server_id,
+                            did = new_client.data_center_id,
+                            "New TCP Client"
+                        );
+
+                        if !self.client_filter.check(new_client.peer_addr) {
+                            continue;
+                        }
+
+                        self.server_counts.record(&new_client);
 
                         let clients = self.tcp_clients.clone();
+                        let buffer_size = clients.pipe_buffer_size;
+                        let hooks = self.connection_hooks.clone();
+                        let active_counts = self.active_counts.clone();
+                        let client_geo = self.client_geo.clone();
+                        let access_log = self.access_log.clone();
     
                         let host_origin = match self.lookup.lookup(
                             new_client.connect_addr.ip(),
@@ -108,7 +363,8 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                             Some(found) => {
                                 let mut origin: HostOrigin = found.value.into();
                                 let port_offset = new_client.connect_addr.port() - found.from_port;
-                                origin.host_addr = SocketAddr::new(origin.host_addr.ip(), port_offset + origin.host_addr.port());
+                                let host_addr = origin.select_host_addr(new_client.peer_addr.ip(), clients.local_addr_selection);
+                                origin.host_addr = local_addr_with_offset(host_addr, port_offset);
                                 origin
                             },
                             None => {
@@ -121,10 +377,40 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                                 continue;
                             }
                         };
+
+                        let mut server_ips = tunnel.server_ips();
+                        server_ips.push(new_client.claim_instructions.address.ip());
+
+                        if let Some(self_loop) = check_self_loop(host_origin.host_addr, new_client.connect_addr, &server_ips) {
+                            tracing::error!(
+                                ?self_loop,
+                                tunnel_id = %host_origin.tunnel_id,
+                                local_addr = %host_origin.host_addr,
+                                "refusing connection, tunnel's local address points back to playit. Update the tunnel's local address to your server"
+                            );
+
+                            continue;
+                        }
+
+                        let Some(setup_permit) = clients.setup_limit.try_start() else {
+                            if setup_limit_log.check() {
+                                tracing::warn!(
+                                    limit = clients.setup_limit.limit(),
+                                    rejected = clients.setup_limit.rejected(),
+                                    "too many TCP clients being set up, rejecting new client"
+                                );
+                            }
+                            continue;
+                        };
+
+                        if self.quotas.try_connect(host_origin.tunnel_id).is_err() {
+                            continue;
+                        }
+                        let quotas = self.quotas.clone();
     
                         let span = tracing::info_span!(
                             "tcp_tunnel",
-                            peer_addr = %new_client.peer_addr,
+                            peer_addr = %ClientAddr(new_client.peer_addr),
                             tunn_addr = %new_client.connect_addr,
                             %host_origin,
                             sid = new_client.tunnel_server_id,
@@ -134,7 +420,7 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                         tokio::spawn(async move {
                             let peer_addr = new_client.peer_addr;
     
-                            let tunnel_conn = match clients.connect(new_client.clone()).await {
+                            let mut tunnel_conn = match clients.connect(new_client.clone()).await {
                                 Ok(Some(client)) => client,
                                 Ok(None) => {
                                     tracing::warn!("got duplciate NewClient message for connection, ignoring");
@@ -147,19 +433,95 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                             };
     
                             tracing::info!("connected to TCP tunnel");
+                            client_geo.log_connection(host_origin.tunnel_id, peer_addr, "tcp");
+
+                            /* held by both pipes, disconnect hook fires once both directions close */
+                            let hook_guard = hooks.connected(host_origin.tunnel_id, peer_addr.ip()).map(Arc::new);
+                            let local_hook_guard = hook_guard.clone();
+                            let active_guard = Arc::new(active_counts.connected(host_origin.tunnel_id, PortType::Tcp));
+                            let local_active_guard = active_guard.clone();
     
-                            let local_conn = match LanAddress::tcp_socket(self.tcp_clients.use_special_lan, peer_addr, host_origin.host_addr).await {
-                                Ok(v) => v,
-                                Err(error) => {
+                            /* bytes read to find the hostname, replayed to the local server */
+                            let mut initial_data = Vec::new();
+                            let mut host_addr = host_origin.host_addr;
+                            let mut hostname_routed = false;
+
+                            if clients.host_routing.has_routes(host_origin.tunnel_id) {
+                                let hostname = match read_hostname(&mut tunnel_conn).await {
+                                    Ok((hostname, data)) => {
+                                        initial_data = data;
+                                        hostname
+                                    }
+                                    Err(error) => {
+                                        tracing::error!(?error, "failed to read from client while looking for hostname");
+                                        return;
+                                    }
+                                };
+
+                                let routed = hostname.as_deref()
+                                    .and_then(|name| clients.host_routing.route(host_origin.tunnel_id, name).map(|addr| (name, addr)));
+
+                                match routed {
+                                    Some((name, addr)) => {
+                                        if let Some(self_loop) = check_self_loop(addr, new_client.connect_addr, &server_ips) {
+                                            tracing::error!(
+                                                ?self_loop,
+                                                hostname = name,
+                                                local_addr = %addr,
+                                                "refusing connection, hostname route points back to playit. Update the route to your server"
+                                            );
+                                            return;
+                                        }
+
+                                        tracing::info!(hostname = name, local_addr = %addr, "routing connection by hostname");
+                                        host_addr = addr;
+                                        hostname_routed = true;
+                                    }
+                                    None => tracing::info!(?hostname, "no hostname route, using tunnel's local address"),
+                                }
+                            }
+
+                            /* a hostname route picks a TCP address over the tunnel's socket */
+                            let unix_target = clients.unix_targets.get(&host_origin.tunnel_id)
+                                .filter(|_| !hostname_routed);
+
+                            let local_connect = async {
+                                match unix_target {
+                                    Some(path) => LocalStream::connect_unix(path).await,
+                                    None => LanAddress::tcp_socket(clients.use_special_lan, clients.source_ports, peer_addr, host_addr).await.map(LocalStream::from),
+                                }
+                            };
+                            let local_conn = match tokio::time::timeout(clients.local_connect_timeout, local_connect).await {
+                                Ok(Ok(v)) => v,
+                                Ok(Err(error)) => {
                                     tracing::error!(?error, "failed to connect to local server");
                                     return;
                                 }
+                                Err(_) => {
+                                    tracing::error!(
+                                        timeout = ?clients.local_connect_timeout,
+                                        "timed out connecting to local server, closing client connection"
+                                    );
+                                    return;
+                                }
                             };
     
-                            if let Ok(local_addr) = local_conn.local_addr() {
+                            drop(setup_permit);
+
+                            /* written once both pipes are done */
+                            let access_entry = access_log.connected(host_origin.tunnel_id, peer_addr, new_client.connect_addr);
+                            let local_access_entry = access_entry.clone();
+
+                            if let Some(local_addr) = local_conn.describe_local() {
                                 tracing::info!("local TCP connection bound to {}", local_addr);
                             }
     
+                            let deadline = clients.max_connection_lifetimes.get(&host_origin.tunnel_id)
+                                .map(|lifetime| tokio::time::Instant::now() + *lifetime);
+
+                            let local_quotas = quotas.clone();
+                            let tunnel_id = host_origin.tunnel_id;
+
                             let (tunnel_read, tunnel_write) = tunnel_conn.into_split();
                             let (local_read, mut local_write) = local_conn.into_split();
     
@@ -167,26 +529,17 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                             let local_to_tunn_span = tracing::info_span!("local2tunn");
     
                             tokio::spawn(async move {
+                                let _hook_guard = hook_guard;
+                                let _active_guard = active_guard;
+                                let received = access_entry.as_ref().map(|entry| entry.received_counter());
+                                let _access_entry = access_entry;
+
                                 'write_proxy_header: {
                                     let Some(protocol) = host_origin.proxy_protocol else { break 'write_proxy_header };
     
-                                    let header = match (new_client.peer_addr, new_client.connect_addr) {
-                                        (SocketAddr::V4(client_addr), SocketAddr::V4(proxy_addr)) => ProxyProtocolHeader::AfInet {
-                                            client_ip: *client_addr.ip(),
-                                            proxy_ip: *proxy_addr.ip(),
-                                            client_port: client_addr.port(),
-                                            proxy_port: proxy_addr.port(),
-                                        },
-                                        (SocketAddr::V6(client_addr), SocketAddr::V6(proxy_addr)) => ProxyProtocolHeader::AfInet6 {
-                                            client_ip: *client_addr.ip(),
-                                            proxy_ip: *proxy_addr.ip(),
-                                            client_port: client_addr.port(),
-                                            proxy_port: proxy_addr.port(),
-                                        },
-                                        _ => {
-                                            tracing::warn!("peer and connect address have different protocol version");
-                                            break 'write_proxy_header;
-                                        }
+                                    let Some(header) = ProxyProtocolHeader::from_tcp_client(new_client.peer_addr, new_client.connect_addr) else {
+                                        tracing::warn!("peer and connect address have different protocol version");
+                                        break 'write_proxy_header;
                                     };
     
                                     let result = match protocol {
@@ -200,32 +553,91 @@ impl<L: AddressLookup + Sync + Send> PlayitAgent<L> where L::Value: Into<HostOri
                                     }
                                 }
     
-                                pipe(tunnel_read, local_write).await
+                                if !initial_data.is_empty() {
+                                    if let Err(error) = local_write.write_all(&initial_data).await {
+                                        tracing::error!(?error, "failed to forward initial client data to local connection");
+                                        return Err(error);
+                                    }
+                                }
+
+                                quotas.add_bytes(host_origin.tunnel_id, initial_data.len() as u64);
+                                if let Some(received) = &received {
+                                    received.fetch_add(initial_data.len() as u64, Ordering::Relaxed);
+                                }
+
+                                let local_write = QuotaCountingWrite::new(local_write, quotas, host_origin.tunnel_id);
+                                let local_write = CountingWrite::new(local_write, received);
+                                pipe_until(tunnel_read, local_write, buffer_size, deadline).await
                             }.instrument(tunn_to_local_span));
     
-                            tokio::spawn(pipe(local_read, tunnel_write).instrument(local_to_tunn_span));
+                            tokio::spawn(async move {
+                                let _hook_guard = local_hook_guard;
+                                let _active_guard = local_active_guard;
+                                let sent = local_access_entry.as_ref().map(|entry| entry.sent_counter());
+                                let _access_entry = local_access_entry;
+                                let tunnel_write = QuotaCountingWrite::new(tunnel_write, local_quotas, tunnel_id);
+                                let tunnel_write = CountingWrite::new(tunnel_write, sent);
+                                pipe_until(local_read, tunnel_write, buffer_size, deadline).await
+                            }.instrument(local_to_tunn_span));
                         }.instrument(span));
                     }
                     Some(TunnelControlEvent::UdpChannelDetails(udp_details)) => {
                         tracing::info!("udp session details received");
                         udp_details_sender.send(udp_details);
                     }
+                    Some(TunnelControlEvent::Registered) => {
+                        send_event(AgentEvent::Registered);
+
+                        if let Some(udp_details) = tunnel.cached_udp_details(now_milli()) {
+                            tracing::info!("resending cached udp session details after reconnect");
+                            udp_details_sender.send(udp_details);
+                        }
+                    }
+                    Some(TunnelControlEvent::Disconnected(reason)) => {
+                        send_event(AgentEvent::ControlDisconnected(reason));
+
+                        if let DisconnectReason::RegisterRejected(error) = reason {
+                            if register_error_is_terminal(error) && !reconnect_on_auth_error {
+                                tracing::error!(?error, "registration rejected, stopping agent");
+                                tunnel_run.store(false, Ordering::SeqCst);
+                                return Some(error);
+                            }
+                        }
+                    }
                     None => {}
                 }
             }
+
+            None
         });
 
         let mut udp_clients = self.udp_clients;
         let udp_run = self.keep_running.clone();
+        let udp_idle_sleep = self.idle_sleep.clone();
 
         let udp_task = tokio::spawn(async move {
+            let _stop = StopOnExit(udp_run.clone());
             while udp_run.load(Ordering::SeqCst) {
                 tokio::task::yield_now().await;
-                udp_clients.recv_next(Duration::from_millis(100)).await;
+
+                /* packets still return right away, only the empty wakeups get rarer */
+                let recv_timeout = if udp_idle_sleep.is_asleep() {
+                    IDLE_UDP_RECV_TIMEOUT
+                } else {
+                    Duration::from_millis(100)
+                };
+                udp_clients.recv_next(recv_timeout).await;
             }
         }.instrument(tracing::info_span!("udp_session")));
 
-        tunnel_task.await.unwrap();
-        udp_task.await.unwrap();
+        /* either task exiting (or panicking) clears keep_running through StopOnExit, so the other stops too */
+        let rejected = join_task("tunnel_session", tunnel_task).await;
+        let udp_result = join_task("udp_session", udp_task).await;
+
+        match (rejected, udp_result) {
+            (Err(message), _) | (_, Err(message)) => Err(SetupError::TaskPanicked(message)),
+            (Ok(Some(error)), _) => Err(SetupError::RegisterRejected(error)),
+            (Ok(None), _) => Ok(()),
+        }
     }
 }
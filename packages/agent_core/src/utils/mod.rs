@@ -6,6 +6,9 @@ pub mod instance_count;
 pub mod id_slab;
 pub mod non_overlapping;
 pub mod ip_bytes;
+pub mod reconnect;
+pub mod redact;
+pub mod supervise;
 
 pub fn now_milli() -> u64 {
     std::time::SystemTime::now()
@@ -0,0 +1,103 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/*
+ * Staggers reconnects after an outage.
+ *
+ * When the network comes back the control channel, API polling and the ping
+ * monitor would all retry at the same moment. Each subsystem calls wait_turn
+ * before retrying, the coordinator hands out start slots at least min_gap
+ * apart (in call order) and adds up to max_jitter on top so multiple agents
+ * behind the same outage don't line up either. Waiting is cheap when there
+ * is no contention, a lone retry only waits its jitter.
+ */
+
+pub const DEFAULT_MIN_GAP: Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_JITTER: Duration = Duration::from_secs(1);
+
+pub struct ReconnectCoordinator {
+    min_gap: Duration,
+    max_jitter: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl ReconnectCoordinator {
+    pub fn new(min_gap: Duration, max_jitter: Duration) -> Self {
+        ReconnectCoordinator {
+            min_gap,
+            max_jitter,
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /* waits until this subsystem's reconnect slot */
+    pub async fn wait_turn(&self, subsystem: &'static str) {
+        let start = self.reserve_slot(Instant::now());
+
+        let delay = start.saturating_duration_since(Instant::now());
+        if !delay.is_zero() {
+            tracing::info!(subsystem, ?delay, "waiting for reconnect slot");
+        }
+
+        tokio::time::sleep_until(start).await;
+    }
+
+    fn reserve_slot(&self, now: Instant) -> Instant {
+        let jitter = match self.max_jitter.as_millis() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_millis(rand::random::<u64>() % max),
+        };
+
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let start = next_slot.map(|slot| slot.max(now)).unwrap_or(now) + jitter;
+        next_slot.replace(start + self.min_gap);
+
+        start
+    }
+}
+
+pub fn reconnect_coordinator() -> &'static ReconnectCoordinator {
+    static COORDINATOR: OnceLock<ReconnectCoordinator> = OnceLock::new();
+    COORDINATOR.get_or_init(|| ReconnectCoordinator::new(DEFAULT_MIN_GAP, DEFAULT_MAX_JITTER))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::ReconnectCoordinator;
+
+    #[test]
+    fn test_staggered_reconnect_after_outage() {
+        let gap = Duration::from_millis(100);
+        let jitter = Duration::from_millis(50);
+        let coordinator = ReconnectCoordinator::new(gap, jitter);
+
+        /* outage ends, every subsystem (control, api, ping monitor, control addr) retries at once */
+        let outage_end = Instant::now();
+        let slots: Vec<Instant> = (0..4).map(|_| coordinator.reserve_slot(outage_end)).collect();
+
+        assert!(outage_end <= slots[0] && slots[0] < outage_end + jitter);
+        for pair in slots.windows(2) {
+            let spacing = pair[1] - pair[0];
+            assert!(gap <= spacing && spacing < gap + jitter, "reconnects not staggered: {:?}", spacing);
+        }
+    }
+
+    #[test]
+    fn test_slots_reset_after_idle() {
+        let coordinator = ReconnectCoordinator::new(Duration::from_secs(1), Duration::ZERO);
+        let now = Instant::now();
+
+        assert_eq!(coordinator.reserve_slot(now), now);
+        assert_eq!(coordinator.reserve_slot(now), now + Duration::from_secs(1));
+
+        /* long after the last reconnect there is nothing to wait for */
+        let later = now + Duration::from_secs(60);
+        assert_eq!(coordinator.reserve_slot(later), later);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/*
+ * Masks client addresses in logs (--redact_client_ips). Only what gets
+ * printed changes, forwarding, proxy protocol headers and hooks still see
+ * the real address. IPv4 keeps the /24 (203.0.113.x), IPv6 keeps the /48
+ * (2001:db8:1:x). Ports are kept.
+ */
+
+static REDACT_CLIENT_IPS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_redact_client_ips(enabled: bool) {
+    REDACT_CLIENT_IPS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn redact_client_ips() -> bool {
+    REDACT_CLIENT_IPS.load(Ordering::Relaxed)
+}
+
+pub struct ClientIp(pub IpAddr);
+
+pub struct ClientAddr(pub SocketAddr);
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !redact_client_ips() {
+            return write!(f, "{}", self.0);
+        }
+
+        match self.0 {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                write!(f, "{}.{}.{}.x", a, b, c)
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, c, ..] = ip.segments();
+                write!(f, "{:x}:{:x}:{:x}:x", a, b, c)
+            }
+        }
+    }
+}
+
+impl Display for ClientAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            SocketAddr::V4(addr) => write!(f, "{}:{}", ClientIp(IpAddr::V4(*addr.ip())), addr.port()),
+            SocketAddr::V6(addr) => write!(f, "[{}]:{}", ClientIp(IpAddr::V6(*addr.ip())), addr.port()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{set_redact_client_ips, ClientAddr, ClientIp};
+
+    #[test]
+    fn test_redact_client_ips() {
+        let v4 = "203.0.113.57:41000".parse().unwrap();
+        let v6 = "[2001:db8:1:2::5]:41000".parse().unwrap();
+
+        set_redact_client_ips(true);
+        assert_eq!(ClientAddr(v4).to_string(), "203.0.113.x:41000");
+        assert_eq!(ClientAddr(v6).to_string(), "[2001:db8:1:x]:41000");
+        assert_eq!(ClientIp(v4.ip()).to_string(), "203.0.113.x");
+
+        set_redact_client_ips(false);
+        assert_eq!(ClientAddr(v4).to_string(), "203.0.113.57:41000");
+        assert_eq!(ClientAddr(v6).to_string(), "[2001:db8:1:2::5]:41000");
+    }
+}
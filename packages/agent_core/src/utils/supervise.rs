@@ -0,0 +1,174 @@
+use std::any::Any;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::Instant;
+
+/*
+ * Long lived tasks that panic would otherwise die silently and leave the
+ * agent half working. Tasks that can be rebuilt from shared state (ex. a UDP
+ * receiver) are restarted with backoff by spawn_restarting. Tasks that own
+ * state which is lost with the panic are awaited with join_task so the
+ * caller can shut down cleanly instead of unwinding further.
+ */
+
+const MIN_RESTART_DELAY: Duration = Duration::from_millis(100);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10);
+/* a task that ran this long before panicking restarts without backoff */
+const HEALTHY_RUN_TIME: Duration = Duration::from_secs(60);
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&'static str>() {
+        return msg.to_string();
+    }
+    if let Some(msg) = payload.downcast_ref::<String>() {
+        return msg.clone();
+    }
+    "unknown panic".to_string()
+}
+
+/* the panic message, None if the task was cancelled */
+pub fn join_error_message(error: JoinError) -> Option<String> {
+    if error.is_panic() {
+        Some(panic_message(&*error.into_panic()))
+    } else {
+        None
+    }
+}
+
+/* clears run when dropped, also while unwinding from a panic, so sibling tasks polling run stop with it */
+pub struct StopOnExit(pub Arc<AtomicBool>);
+
+impl Drop for StopOnExit {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/* awaits a task, logging and returning the message if it panicked */
+pub async fn join_task<T>(task: &'static str, handle: JoinHandle<T>) -> Result<T, String> {
+    match handle.await {
+        Ok(value) => Ok(value),
+        Err(error) => {
+            let message = join_error_message(error).unwrap_or_else(|| "task cancelled".to_string());
+            tracing::error!(task, %message, "task stopped unexpectedly");
+            Err(message)
+        }
+    }
+}
+
+/*
+ * Runs the task made by create until it returns normally or run is cleared,
+ * restarting it after a panic. Returns the number of restarts.
+ */
+pub fn spawn_restarting<F, Fut>(task: &'static str, run: Arc<AtomicBool>, mut create: F) -> JoinHandle<usize>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut restarts = 0;
+        let mut delay = MIN_RESTART_DELAY;
+
+        loop {
+            let started = Instant::now();
+
+            let error = match tokio::spawn(create()).await {
+                Ok(()) => break,
+                Err(error) => error,
+            };
+
+            let Some(message) = join_error_message(error) else { break };
+
+            if !run.load(Ordering::SeqCst) {
+                tracing::error!(task, %message, "task panicked while stopping");
+                break;
+            }
+
+            if HEALTHY_RUN_TIME < started.elapsed() {
+                delay = MIN_RESTART_DELAY;
+            }
+
+            tracing::error!(task, %message, restart_in = ?delay, "task panicked, restarting");
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+            restarts += 1;
+        }
+
+        restarts
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use std::time::Duration;
+
+    use super::{join_task, spawn_restarting, StopOnExit};
+
+    #[tokio::test]
+    async fn test_panicking_task_restarted() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let run = Arc::new(AtomicBool::new(true));
+
+        let counter = attempts.clone();
+        let restarts = spawn_restarting("test", run, move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    panic!("receiver failed");
+                }
+            }
+        }).await.unwrap();
+
+        assert_eq!(restarts, 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_reported() {
+        let result = join_task("test", tokio::spawn(async {
+            if true {
+                panic!("bad state {}", 5);
+            }
+        })).await;
+
+        assert_eq!(result, Err("bad state 5".to_string()));
+        assert_eq!(join_task("test", tokio::spawn(async { 10 })).await, Ok(10));
+    }
+
+    /* same shape as PlayitAgent::run, the udp side panics while the tunnel side loops */
+    #[tokio::test]
+    async fn test_udp_panic_stops_tunnel_session() {
+        let run = Arc::new(AtomicBool::new(true));
+
+        let tunnel_run = run.clone();
+        let tunnel_task = tokio::spawn(async move {
+            let _stop = StopOnExit(tunnel_run.clone());
+            while tunnel_run.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+
+        let udp_run = run.clone();
+        let udp_task = tokio::spawn(async move {
+            let _stop = StopOnExit(udp_run.clone());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if udp_run.load(Ordering::SeqCst) {
+                panic!("missing tunnel socket");
+            }
+        });
+
+        let tunnel_result = tokio::time::timeout(Duration::from_secs(5), join_task("tunnel_session", tunnel_task)).await
+            .expect("tunnel session kept running after the udp session panicked");
+
+        assert_eq!(tunnel_result, Ok(()));
+        assert_eq!(join_task("udp_session", udp_task).await, Err("missing tunnel socket".to_string()));
+        assert!(!run.load(Ordering::SeqCst));
+    }
+}
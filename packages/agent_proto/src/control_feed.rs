@@ -6,6 +6,7 @@ use byteorder::{BigEndian, ReadBytesExt};
 use message_encoding::MessageEncoding;
 
 use crate::control_messages::ControlResponse;
+use crate::read_token;
 use crate::rpc::ControlRpcMessage;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -95,15 +96,96 @@ impl MessageEncoding for ClaimInstructions {
     fn read_from<T: Read>(read: &mut T) -> std::io::Result<Self> {
         Ok(ClaimInstructions {
             address: SocketAddr::read_from(read)?,
-            token: Vec::read_from(read)?,
+            token: read_token(read)?,
         })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    use rand::{thread_rng, Rng, RngCore};
+
+    use crate::control_messages::Pong;
+
     use super::*;
 
+    fn rng_addr<R: RngCore>(rng: &mut R) -> SocketAddr {
+        if rng.gen_bool(0.5) {
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(rng.next_u32()), rng.gen()))
+        } else {
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(rng.gen::<u128>()), rng.gen(), 0, 0))
+        }
+    }
+
+    fn rng_feed<R: RngCore>(rng: &mut R) -> ControlFeed {
+        if rng.gen_bool(0.2) {
+            return ControlFeed::Response(ControlRpcMessage {
+                request_id: rng.gen(),
+                content: ControlResponse::Pong(Pong {
+                    request_now: rng.gen(),
+                    server_now: rng.gen(),
+                    server_id: rng.gen(),
+                    data_center_id: rng.gen(),
+                    client_addr: rng_addr(rng),
+                    tunnel_addr: rng_addr(rng),
+                    session_expire_at: if rng.gen_bool(0.5) { Some(rng.gen()) } else { None },
+                }),
+            });
+        }
+
+        let mut token = vec![0u8; rng.gen_range(0..128)];
+        rng.fill_bytes(&mut token);
+
+        ControlFeed::NewClient(NewClient {
+            connect_addr: rng_addr(rng),
+            peer_addr: rng_addr(rng),
+            claim_instructions: ClaimInstructions { address: rng_addr(rng), token },
+            tunnel_server_id: rng.gen(),
+            data_center_id: rng.gen(),
+        })
+    }
+
+    #[test]
+    fn fuzzy_test_feed_round_trip_and_corruption() {
+        let mut rng = thread_rng();
+        let mut buffer = Vec::new();
+
+        for _ in 0..50000 {
+            let feed = rng_feed(&mut rng);
+
+            buffer.clear();
+            feed.write_to(&mut buffer).unwrap();
+            assert_eq!(ControlFeed::read_from(&mut &buffer[..]).unwrap(), feed);
+
+            /* corrupt and truncate, decoding may fail but must not panic */
+            for _ in 0..rng.gen_range(1..4) {
+                let pos = rng.gen_range(0..buffer.len());
+                buffer[pos] = rng.gen();
+            }
+            let len = rng.gen_range(0..=buffer.len());
+            let _ = ControlFeed::read_from(&mut &buffer[..len]);
+        }
+    }
+
+    #[test]
+    fn fuzzy_test_feed_random_bytes() {
+        let mut rng = thread_rng();
+        let mut buffer = [0u8; 256];
+
+        for _ in 0..200000 {
+            let len = rng.gen_range(0..buffer.len());
+            rng.fill_bytes(&mut buffer[..len]);
+
+            if 4 <= len {
+                buffer[..4].copy_from_slice(&rng.gen_range(1..=2u32).to_be_bytes());
+            }
+
+            let _ = ControlFeed::read_from(&mut &buffer[..len]);
+        }
+    }
+
     #[test]
     fn parse_control() {
         let data = "0000000204d1198d10046804d053c766cc4904d1198c029306000000000000004c2\
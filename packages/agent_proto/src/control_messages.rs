@@ -7,7 +7,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use message_encoding::{m_max, m_max_list, m_static, MessageEncoding};
 use serde::Serialize;
 
-use crate::{AgentSessionId, PortRange};
+use crate::{read_token, AgentSessionId, PortRange};
 use crate::hmac::HmacSha256;
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize)]
@@ -33,10 +33,16 @@ pub enum ControlRequestId {
 
 impl ControlRequestId {
     pub fn from_num(num: u32) -> Option<Self> {
-        if (Self::END as u32) <= num || num == 0 {
-            return None;
+        /* explicit mapping, new variants must be added here (see test_request_id_round_trip) */
+        match num {
+            1 => Some(ControlRequestId::_PingV1),
+            2 => Some(ControlRequestId::AgentRegisterV1),
+            3 => Some(ControlRequestId::AgentKeepAliveV1),
+            4 => Some(ControlRequestId::SetupUdpChannelV1),
+            5 => Some(ControlRequestId::AgentCheckPortMappingV1),
+            6 => Some(ControlRequestId::PingV2),
+            _ => None,
         }
-        Some(unsafe { std::mem::transmute::<u32, Self>(num) })
     }
 }
 
@@ -376,7 +382,7 @@ impl MessageEncoding for UdpChannelDetails {
     fn read_from<T: Read>(read: &mut T) -> std::io::Result<Self> {
         Ok(UdpChannelDetails {
             tunnel_addr: SocketAddr::read_from(read)?,
-            token: Arc::new(Vec::read_from(read)?),
+            token: Arc::new(read_token(read)?),
         })
     }
 }
@@ -464,6 +470,18 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_request_id_round_trip() {
+        for num in 1..(ControlRequestId::END as u32) {
+            let id = ControlRequestId::from_num(num).expect("every id before END must map to a variant");
+            assert_eq!(id as u32, num);
+        }
+
+        assert!(ControlRequestId::from_num(0).is_none());
+        assert!(ControlRequestId::from_num(ControlRequestId::END as u32).is_none());
+        assert!(ControlRequestId::from_num(u32::MAX).is_none());
+    }
+
     #[test]
     fn fuzzy_test_control_request() {
         let mut rng = thread_rng();
@@ -500,6 +518,130 @@ mod test {
         }
     }
 
+    #[test]
+    fn fuzzy_test_decode_random_bytes() {
+        let mut rng = thread_rng();
+        let mut buffer = [0u8; 256];
+
+        for i in 0..200000 {
+            let len = rng.gen_range(0..buffer.len());
+            rng.fill_bytes(&mut buffer[..len]);
+
+            /* most random ids are invalid, force valid ones to reach the nested decoders */
+            if 12 <= len && i % 2 == 0 {
+                buffer[8..12].copy_from_slice(&rng.gen_range(1..=8u32).to_be_bytes());
+            }
+
+            let _ = ControlRpcMessage::<ControlRequest>::read_from(&mut &buffer[..len]);
+            let _ = ControlRpcMessage::<ControlResponse>::read_from(&mut &buffer[..len]);
+        }
+    }
+
+    #[test]
+    fn test_token_length_bounded() {
+        let mut buffer = Vec::new();
+        ControlResponse::UdpChannelDetails(UdpChannelDetails {
+            tunnel_addr: "147.185.221.1:5525".parse().unwrap(),
+            token: Arc::new(vec![1, 2, 3]),
+        }).write_to(&mut buffer).unwrap();
+
+        /* id (4) + v4 socket addr (1 + 4 + 2), then the u64 token length */
+        assert_eq!(buffer[11..19], 3u64.to_be_bytes());
+        buffer[11..19].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(ControlResponse::read_from(&mut &buffer[..]).is_err());
+    }
+
+    /*
+     * Encoding is hand written big endian (message_encoding), not derived from
+     * a serializer config, so nothing can drift on a dependency update. These
+     * fixtures were captured from the current encoding and must never change,
+     * agents and tunnel servers of different releases have to agree on them.
+     */
+    fn wire_fixtures() -> Vec<(&'static str, ControlRpcMessage<ControlRequest>)> {
+        let session = AgentSessionId { session_id: 0x0102030405060708, account_id: 42, agent_id: 7 };
+
+        vec![
+            ("0000000000000001000000060000018bcfe568000100000023010102030405060708000000000000002a0000000000000007", ControlRpcMessage {
+                request_id: 1,
+                content: ControlRequest::Ping(Ping { now: 1_700_000_000_000, current_ping: Some(35), session_id: Some(session.clone()) }),
+            }),
+            ("000000000000000200000002000000000000002a000000000000000700000000000000120000018bcfe5680004cb0071059c40062602fbaf0000000000000000000000011595abababababababababababababababababababababababababababababababab", ControlRpcMessage {
+                request_id: 2,
+                content: ControlRequest::AgentRegister(AgentRegister {
+                    account_id: 42,
+                    agent_id: 7,
+                    agent_version: 18,
+                    timestamp: 1_700_000_000_000,
+                    client_addr: "203.0.113.5:40000".parse().unwrap(),
+                    tunnel_addr: "[2602:fbaf::1]:5525".parse().unwrap(),
+                    signature: [0xab; 32],
+                }),
+            }),
+            ("0000000000000003000000030102030405060708000000000000002a0000000000000007", ControlRpcMessage { request_id: 3, content: ControlRequest::AgentKeepAlive(session.clone()) }),
+            ("0000000000000004000000040102030405060708000000000000002a0000000000000007", ControlRpcMessage { request_id: 4, content: ControlRequest::SetupUdpChannel(session.clone()) }),
+            ("0000000000000005000000050102030405060708000000000000002a00000000000000070493b9dd0163dd63de03", ControlRpcMessage {
+                request_id: 5,
+                content: ControlRequest::AgentCheckPortMapping(AgentCheckPortMapping {
+                    agent_session_id: session,
+                    port_range: PortRange { ip: IpAddr::V4(Ipv4Addr::new(147, 185, 221, 1)), port_start: 25565, port_end: 25566, port_proto: PortProto::Both },
+                }),
+            }),
+        ]
+    }
+
+    fn response_wire_fixtures() -> Vec<(&'static str, ControlRpcMessage<ControlResponse>)> {
+        vec![
+            ("0000000000000001000000010000018bcfe568000000018bcfe5681400000000000000090000000304cb0071059c400493b9dd011595010000018bcfe65260", ControlRpcMessage {
+                request_id: 1,
+                content: ControlResponse::Pong(Pong {
+                    request_now: 1_700_000_000_000,
+                    server_now: 1_700_000_000_020,
+                    server_id: 9,
+                    data_center_id: 3,
+                    client_addr: "203.0.113.5:40000".parse().unwrap(),
+                    tunnel_addr: "147.185.221.1:5525".parse().unwrap(),
+                    session_expire_at: Some(1_700_000_060_000),
+                }),
+            }),
+            ("0000000000000002000000060102030405060708000000000000002a00000000000000070000018bcfe65260", ControlRpcMessage {
+                request_id: 2,
+                content: ControlResponse::AgentRegistered(AgentRegistered {
+                    id: AgentSessionId { session_id: 0x0102030405060708, account_id: 42, agent_id: 7 },
+                    expires_at: 1_700_000_060_000,
+                }),
+            }),
+            ("0000000000000004000000080493b9dd011595000000000000000401020304", ControlRpcMessage {
+                request_id: 4,
+                content: ControlResponse::UdpChannelDetails(UdpChannelDetails {
+                    tunnel_addr: "147.185.221.1:5525".parse().unwrap(),
+                    token: Arc::new(vec![1, 2, 3, 4]),
+                }),
+            }),
+            ("000000000000000500000003", ControlRpcMessage { request_id: 5, content: ControlResponse::Unauthorized }),
+        ]
+    }
+
+    fn check_wire_fixture<T: MessageEncoding + PartialEq + Debug>(fixture: &str, msg: &T) {
+        let mut buffer = Vec::new();
+        msg.write_to(&mut buffer).unwrap();
+        assert_eq!(hex::encode(&buffer), fixture, "encoding changed for {:?}", msg);
+
+        let bytes = hex::decode(fixture).unwrap();
+        let mut reader = &bytes[..];
+        assert_eq!(&T::read_from(&mut reader).unwrap(), msg);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_wire_fixtures() {
+        for (fixture, msg) in wire_fixtures() {
+            check_wire_fixture(fixture, &msg);
+        }
+        for (fixture, msg) in response_wire_fixtures() {
+            check_wire_fixture(fixture, &msg);
+        }
+    }
+
     fn test_encoding<T: MessageEncoding + PartialEq + Debug>(msg: T, buffer: &mut [u8]) {
         assert_eq!(0, T::_ASSERT);
 
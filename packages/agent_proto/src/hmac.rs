@@ -1,8 +1,11 @@
 use std::fmt::{Debug, Formatter};
+use std::net::{Ipv4Addr, SocketAddr};
 
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 
+use crate::control_messages::AgentRegister;
+
 #[derive(Clone)]
 pub struct HmacSha256(Hmac<Sha256>);
 
@@ -38,4 +41,53 @@ impl HmacSha256 {
     pub fn sign_fixed(&self, data: &[u8]) -> [u8; 32] {
         self.sign(data)
     }
-}
\ No newline at end of file
+}
+
+/*
+ * Checks signing against a known vector (RFC 4231 test case 2) and that an
+ * AgentRegister round trips through update_signature / verify_signature. A
+ * broken build otherwise only shows up as the tunnel server rejecting the
+ * register with an invalid signature.
+ */
+pub fn self_test() -> Result<(), &'static str> {
+    const EXPECTED: [u8; 32] = [
+        0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95, 0x75, 0xc7,
+        0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec, 0x38, 0x43,
+    ];
+
+    let hmac = HmacSha256::create(b"Jefe");
+    if hmac.sign(b"what do ya want for nothing?") != EXPECTED {
+        return Err("HMAC-SHA256 signature does not match the known vector");
+    }
+
+    let mut register = AgentRegister {
+        account_id: 1,
+        agent_id: 2,
+        agent_version: 3,
+        timestamp: 4,
+        client_addr: SocketAddr::new(Ipv4Addr::new(203, 0, 113, 1).into(), 5000),
+        tunnel_addr: SocketAddr::new(Ipv4Addr::new(147, 185, 221, 1).into(), 5525),
+        signature: [0; 32],
+    };
+
+    let mut buffer = Vec::new();
+    register.update_signature(&mut buffer, &hmac);
+    if !register.verify_signature(&mut buffer, &hmac) {
+        return Err("signed AgentRegister failed verification");
+    }
+
+    register.timestamp += 1;
+    if register.verify_signature(&mut buffer, &hmac) {
+        return Err("modified AgentRegister passed verification");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_self_test_passes() {
+        assert_eq!(super::self_test(), Ok(()));
+    }
+}
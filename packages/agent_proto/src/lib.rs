@@ -104,3 +104,18 @@ impl MessageEncoding for PortProto {
         }
     }
 }
+
+/* tokens are far smaller, bounds the allocation a bad length prefix can cause */
+pub const MAX_TOKEN_LEN: u64 = 4096;
+
+/* same wire format as Vec<u8>::read_from (u64 length + bytes) but checks the length before allocating */
+pub(crate) fn read_token<T: Read>(read: &mut T) -> std::io::Result<Vec<u8>> {
+    let len = read.read_u64::<BigEndian>()?;
+    if MAX_TOKEN_LEN < len {
+        return Err(Error::new(ErrorKind::InvalidData, "token length too large"));
+    }
+
+    let mut data = vec![0u8; len as usize];
+    read.read_exact(&mut data)?;
+    Ok(data)
+}
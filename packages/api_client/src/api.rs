@@ -42,8 +42,8 @@ impl<C: PlayitHttpClient> PlayitApiClient<C> {
 	pub async fn claim_reject(&self, req: ReqClaimReject) -> Result<(), ApiError<ClaimRejectError, C::Error>> {
 		Self::unwrap(self.client.call("/claim/reject", req).await)
 	}
-	pub async fn proto_register(&self, req: ReqProtoRegister) -> Result<SignedAgentKey, ApiErrorNoFail<C::Error>> {
-		Self::unwrap_no_fail(self.client.call("/proto/register", req).await)
+	pub async fn proto_register(&self, req: ReqProtoRegister) -> Result<SignedAgentKey, ApiError<ProtoRegisterError, C::Error>> {
+		Self::unwrap(self.client.call("/proto/register", req).await)
 	}
 	pub async fn login_guest(&self) -> Result<WebSession, ApiError<GuestLoginError, C::Error>> {
 		Self::unwrap(self.client.call("/login/guest", ReqLoginGuest {}).await)
@@ -131,10 +131,15 @@ pub enum ApiResponseError {
 	#[serde(rename = "auth")]
 	Auth(AuthError),
 	#[serde(rename = "internal")]
-	Internal,
+	Internal(#[serde(default)] Option<ApiInternalError>),
 }
 
 
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ApiInternalError {
+	pub trace_id: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct PathNotFound {
 	pub path: String,
@@ -270,10 +275,8 @@ pub struct UseRegion {
 	pub region: AllocationRegion,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[derive(serde::Serialize, Debug, PartialEq, Eq, Copy, Clone, Hash)]
 pub enum AllocationRegion {
-	#[serde(rename = "smart-global")]
-	SmartGlobal,
 	#[serde(rename = "global")]
 	Global,
 	#[serde(rename = "north-america")]
@@ -286,6 +289,28 @@ pub enum AllocationRegion {
 	India,
 	#[serde(rename = "south-america")]
 	SouthAmerica,
+	#[serde(rename = "smart-global")]
+	SmartGlobal,
+}
+
+/* regions added after this version parse as smart-global (with a warning) so responses with them still parse */
+impl<'de> serde::Deserialize<'de> for AllocationRegion {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let name = String::deserialize(deserializer)?;
+		Ok(match name.as_str() {
+			"global" => AllocationRegion::Global,
+			"north-america" => AllocationRegion::NorthAmerica,
+			"europe" => AllocationRegion::Europe,
+			"asia" => AllocationRegion::Asia,
+			"india" => AllocationRegion::India,
+			"south-america" => AllocationRegion::SouthAmerica,
+			"smart-global" => AllocationRegion::SmartGlobal,
+			_ => {
+				tracing::warn!(region = %name, "unknown allocation region, using smart-global");
+				AllocationRegion::SmartGlobal
+			}
+		})
+	}
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -526,6 +551,27 @@ pub struct SignedAgentKey {
 	pub key: String,
 }
 
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum ProtoRegisterError {
+	AccountBanned,
+	DisabledByUser,
+	AgentDisabledOverLimit,
+	UnknownPlayitVersion,
+	/* fail reasons added after this version, treated as retryable */
+	#[serde(other)]
+	Unknown,
+}
+
+impl std::fmt::Display for ProtoRegisterError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{:?}", self)
+	}
+}
+
+impl std::error::Error for ProtoRegisterError {
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ReqLoginGuest {
 }
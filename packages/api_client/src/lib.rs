@@ -31,9 +31,42 @@ impl api::PortRange {
 
 #[cfg(test)]
 mod test {
-    use crate::api::{AgentType, ReqClaimSetup};
+    use crate::api::{AgentType, AllocationRegion, ApiResponseError, ReqClaimSetup, UseRegion};
     use crate::PlayitApi;
 
+    #[test]
+    fn test_unknown_region_falls_back_to_smart_global() {
+        let region: UseRegion = serde_json::from_str(r#"{"region":"antarctica"}"#).unwrap();
+        assert_eq!(region.region, AllocationRegion::SmartGlobal);
+
+        let region: UseRegion = serde_json::from_str(r#"{"region":"europe"}"#).unwrap();
+        assert_eq!(region.region, AllocationRegion::Europe);
+        assert_eq!(serde_json::to_string(&AllocationRegion::SmartGlobal).unwrap(), r#""smart-global""#);
+
+        for region in [
+            AllocationRegion::Global, AllocationRegion::NorthAmerica, AllocationRegion::Europe, AllocationRegion::Asia,
+            AllocationRegion::India, AllocationRegion::SouthAmerica, AllocationRegion::SmartGlobal,
+        ] {
+            let name = serde_json::to_string(&region).unwrap();
+            assert_eq!(serde_json::from_str::<AllocationRegion>(&name).unwrap(), region);
+        }
+    }
+
+    #[test]
+    fn test_internal_error_trace_id() {
+        let error: ApiResponseError = serde_json::from_str(r#"{"type":"internal","message":{"trace_id":"3f2a9c"}}"#).unwrap();
+        match error {
+            ApiResponseError::Internal(Some(internal)) => assert_eq!(internal.trace_id, "3f2a9c"),
+            other => panic!("expected internal error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_internal_error_without_trace_id() {
+        let error: ApiResponseError = serde_json::from_str(r#"{"type":"internal"}"#).unwrap();
+        assert!(matches!(error, ApiResponseError::Internal(None)));
+    }
+
     #[tokio::test]
     async fn test() {
         let api = PlayitApi::create("http://localhost:8080".to_string(), None);
@@ -1,4 +1,4 @@
-use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+use std::{collections::{HashMap, HashSet}, future::Future, pin::Pin, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
 
 use ping_tool::PlayitPingTool;
 use playit_api_client::{api::{ApiErrorNoFail, ApiResponseError, PingExperimentDetails, PingExperimentResult, PingSample, PingTarget, ReqPingSubmit}, http_client::HttpClientError, PlayitApi};
@@ -6,11 +6,53 @@ use tokio::sync::Mutex;
 
 pub mod ping_tool;
 
+pub type SecretLoader = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Option<String>> + Send>> + Send + Sync>;
+pub type EventHandler = Arc<dyn Fn(PingMonitorEvent) + Send + Sync>;
+
+const REAUTH_MIN_BACKOFF: Duration = Duration::from_secs(5);
+const REAUTH_MAX_BACKOFF: Duration = Duration::from_secs(600);
+const REAUTH_MAX_ATTEMPTS: u32 = 10;
+
+const MIN_TEST_INTERVAL_MS: u64 = 1_000;
+const MAX_TEST_INTERVAL_MS: u64 = 30_000;
+
 pub struct PingMonitor {
     api_client: PlayitApi,
     tool: Arc<PlayitPingTool>,
     senders: HashMap<u64, Arc<PingSender>>,
     shared: Arc<Shared>,
+    secret_loader: Option<SecretLoader>,
+    reauth: ReAuth,
+    health: PingMonitorHealth,
+    event_handler: Option<EventHandler>,
+}
+
+/* for embedders, refresh results and auth state without parsing logs */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PingMonitorHealth {
+    pub last_success: Option<Instant>,
+    pub consecutive_failures: u32,
+    /* api rejected the secret and re-auth has not succeeded (yet) */
+    pub auth_lost: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingMonitorEvent {
+    /* first successful refresh after failures */
+    Recovered,
+    /* refresh failed, retried on the next refresh */
+    TransientFailure { consecutive_failures: u32 },
+    AuthLost,
+    ReAuthenticated,
+    /* fatal, no secret loader or re-auth attempts exhausted, needs a new secret */
+    ReAuthGaveUp,
+}
+
+#[derive(Default)]
+struct ReAuth {
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    verifying: bool,
 }
 
 struct Shared {
@@ -43,10 +85,132 @@ impl PingMonitor {
             tool,
             senders: HashMap::new(),
             shared,
+            secret_loader: None,
+            reauth: ReAuth::default(),
+            health: PingMonitorHealth::default(),
+            event_handler: None,
         })
     }
 
+    /* used to reload the secret after an auth error, without a loader the monitor continues unauthenticated */
+    pub fn with_secret_loader<F, R>(mut self, loader: F) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: Future<Output = Option<String>> + Send + 'static,
+    {
+        self.secret_loader = Some(Arc::new(move || Box::pin(loader())));
+        self
+    }
+
+    /* called from refresh, must not block */
+    pub fn with_event_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(PingMonitorEvent) + Send + Sync + 'static,
+    {
+        self.event_handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn health(&self) -> PingMonitorHealth {
+        self.health
+    }
+
+    fn emit(&self, event: PingMonitorEvent) {
+        if let Some(handler) = &self.event_handler {
+            handler(event);
+        }
+    }
+
+    async fn handle_auth_error(&mut self) {
+        tracing::warn!("auth failed, removing auth from API client");
+        self.api_client.get_client().remove_auth().await;
+
+        if !self.health.auth_lost {
+            self.health.auth_lost = true;
+            self.emit(PingMonitorEvent::AuthLost);
+        }
+
+        self.reauth.verifying = false;
+        if self.secret_loader.is_none() {
+            tracing::error!("ping monitor lost auth and has no secret loader, continuing unauthenticated");
+            self.emit(PingMonitorEvent::ReAuthGaveUp);
+            return;
+        }
+
+        self.reauth.attempts += 1;
+        if REAUTH_MAX_ATTEMPTS < self.reauth.attempts {
+            tracing::error!(attempts = REAUTH_MAX_ATTEMPTS, "giving up on re-authenticating ping monitor, a new secret is needed");
+            self.emit(PingMonitorEvent::ReAuthGaveUp);
+            return;
+        }
+
+        let backoff = REAUTH_MIN_BACKOFF
+            .saturating_mul(1 << (self.reauth.attempts - 1).min(16))
+            .min(REAUTH_MAX_BACKOFF);
+
+        tracing::info!(attempt = self.reauth.attempts, backoff_ms = backoff.as_millis() as u64, "scheduled ping monitor re-auth");
+        self.reauth.next_attempt = Some(Instant::now() + backoff);
+    }
+
+    async fn try_reauth(&mut self) {
+        let Some(next_attempt) = self.reauth.next_attempt else { return };
+        if Instant::now() < next_attempt {
+            return;
+        }
+
+        self.reauth.next_attempt = None;
+        let Some(loader) = self.secret_loader.clone() else { return };
+
+        let Some(secret) = loader().await else {
+            tracing::warn!("failed to reload secret for ping monitor");
+            self.handle_auth_error().await;
+            return;
+        };
+
+        tracing::info!(attempt = self.reauth.attempts, "re-creating API client with reloaded secret");
+        let api_base = self.api_client.get_client().api_base().to_string();
+        self.api_client = PlayitApi::create(api_base, Some(secret));
+        self.reauth.verifying = true;
+    }
+
+    fn handle_api_success(&mut self) {
+        if !self.reauth.verifying {
+            return;
+        }
+
+        tracing::info!(attempts = self.reauth.attempts, "ping monitor re-authenticated");
+        self.reauth = ReAuth::default();
+        self.health.auth_lost = false;
+        self.emit(PingMonitorEvent::ReAuthenticated);
+    }
+
     pub async fn refresh(&mut self) -> Result<(), PingMonitorError> {
+        let result = self.refresh_experiments().await;
+
+        match &result {
+            Ok(()) => {
+                if 0 < self.health.consecutive_failures {
+                    tracing::info!(failures = self.health.consecutive_failures, "ping monitor refresh recovered");
+                    self.emit(PingMonitorEvent::Recovered);
+                }
+                self.health.consecutive_failures = 0;
+                self.health.last_success = Some(Instant::now());
+            }
+            Err(error) => {
+                self.health.consecutive_failures = self.health.consecutive_failures.saturating_add(1);
+                /* auth errors are reported through AuthLost / ReAuthGaveUp */
+                if !error.is_auth_error() {
+                    self.emit(PingMonitorEvent::TransientFailure { consecutive_failures: self.health.consecutive_failures });
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn refresh_experiments(&mut self) -> Result<(), PingMonitorError> {
+        self.try_reauth().await;
+
         {
             let mut to_send = {
                 let mut lock = self.shared.results.lock().await;
@@ -59,20 +223,33 @@ impl PingMonitor {
                 tracing::info!("submit {} ping results, {} entries", og_send_len, to_send.len());
 
                 for chunk in to_send.chunks(64) {
-                    if let Err(error) = self.api_client.ping_submit(ReqPingSubmit {
+                    match self.api_client.ping_submit(ReqPingSubmit {
                         results: chunk.to_vec(),
                     }).await {
-                        tracing::error!(?error, "failed to submit ping results");
-                        if let ApiErrorNoFail::ApiError(ApiResponseError::Auth(_)) = error {
-                            tracing::warn!("auth failed, removing auth from API client");
-                            self.api_client.get_client().remove_auth().await;
+                        Ok(()) => self.handle_api_success(),
+                        Err(error) => {
+                            tracing::error!(?error, "failed to submit ping results");
+                            if let ApiErrorNoFail::ApiError(ApiResponseError::Auth(_)) = error {
+                                self.handle_auth_error().await;
+                            }
                         }
                     };
                 }
             }
         }
 
-        let pings = self.api_client.ping_get().await?;
+        let pings = match self.api_client.ping_get().await {
+            Ok(pings) => {
+                self.handle_api_success();
+                pings
+            }
+            Err(error) => {
+                if let ApiErrorNoFail::ApiError(ApiResponseError::Auth(_)) = &error {
+                    self.handle_auth_error().await;
+                }
+                return Err(error.into());
+            }
+        };
         let mut keys = self.senders.keys().map(|v| *v).collect::<HashSet<_>>();
 
         for exp in pings.experiments {
@@ -226,9 +403,7 @@ impl PingSender {
         while self.run.load(Ordering::Relaxed) {
             self.run_experiment().await;
 
-            let mut wait_ms = self.experiment.test_interval.min(30_000);
-            wait_ms += rand::random::<u64>() % (wait_ms / 3);
-
+            let wait_ms = experiment_wait_ms(self.experiment.test_interval);
             tokio::time::sleep(Duration::from_millis(wait_ms)).await;
         }
     }
@@ -251,12 +426,24 @@ impl PingSender {
     }
 }
 
+/* clamp before adding jitter so a zero or tiny interval from the API can't spin or divide by zero */
+fn experiment_wait_ms(test_interval: u64) -> u64 {
+    let wait_ms = test_interval.clamp(MIN_TEST_INTERVAL_MS, MAX_TEST_INTERVAL_MS);
+    wait_ms + rand::random::<u64>() % (wait_ms / 3)
+}
 
 #[derive(Debug)]
 pub enum PingMonitorError {
     ApiError(ApiErrorNoFail<HttpClientError>),
 }
 
+impl PingMonitorError {
+    /* the secret was rejected, retrying with the same secret will not help */
+    pub fn is_auth_error(&self) -> bool {
+        matches!(self, PingMonitorError::ApiError(ApiErrorNoFail::ApiError(ApiResponseError::Auth(_))))
+    }
+}
+
 impl From<ApiErrorNoFail<HttpClientError>> for PingMonitorError {
     fn from(value: ApiErrorNoFail<HttpClientError>) -> Self {
         PingMonitorError::ApiError(value)
@@ -271,11 +458,12 @@ fn epoch_milli() -> u64 {
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
+    use std::{sync::Arc, time::{Duration, Instant}};
 
     use playit_api_client::{api::{PingExperimentResult, PingSample, PingTarget}, http_client::HttpClient, PlayitApi};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener, sync::Mutex};
 
-    use crate::{combine_experiments, PingMonitor};
+    use crate::{combine_experiments, experiment_wait_ms, PingMonitor, PingMonitorEvent, MAX_TEST_INTERVAL_MS, MIN_TEST_INTERVAL_MS};
 
     #[tokio::test]
     async fn test_send_pings() {
@@ -292,6 +480,33 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_health_counts_failures() {
+        /* nothing listens on the port, every refresh fails */
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events_handle = events.clone();
+
+        let mut monitor = PingMonitor::new(PlayitApi::create(format!("http://{}", closed), None))
+            .await
+            .unwrap()
+            .with_event_handler(move |event| events_handle.lock().unwrap().push(event));
+
+        for _ in 0..2 {
+            let error = monitor.refresh().await.unwrap_err();
+            assert!(!error.is_auth_error());
+        }
+
+        let health = monitor.health();
+        assert_eq!(health.consecutive_failures, 2);
+        assert_eq!(health.last_success, None);
+        assert_eq!(*events.lock().unwrap(), vec![
+            PingMonitorEvent::TransientFailure { consecutive_failures: 1 },
+            PingMonitorEvent::TransientFailure { consecutive_failures: 2 },
+        ]);
+    }
+
     #[test]
     fn test_combine() {
         let target_1 = PingTarget { ip: "127.0.0.1".parse().unwrap(), port: 1234 };
@@ -310,4 +525,134 @@ mod test {
         assert_eq!(items[0].samples.len(), 3);
         assert_eq!(items[1].samples.len(), 1);
     }
+
+    #[test]
+    fn test_experiment_wait_zero_interval() {
+        for test_interval in [0, 1, 2, 3, MIN_TEST_INTERVAL_MS, 10_000, u64::MAX] {
+            for _ in 0..1000 {
+                let wait_ms = experiment_wait_ms(test_interval);
+                assert!(MIN_TEST_INTERVAL_MS <= wait_ms);
+                assert!(wait_ms < MAX_TEST_INTERVAL_MS + MAX_TEST_INTERVAL_MS / 3);
+            }
+        }
+    }
+
+    type MockRequests = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /* minimal API server, the first ping submit fails with SessionExpired */
+    async fn mock_api(requests: MockRequests) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut submit_count = 0;
+
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut data = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                let header_end = loop {
+                    let bytes = stream.read(&mut buffer).await.unwrap();
+                    data.extend_from_slice(&buffer[..bytes]);
+
+                    if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+
+                let header = String::from_utf8_lossy(&data[..header_end]).to_string();
+                let path = header.split(' ').nth(1).unwrap().to_string();
+
+                let mut content_len = 0;
+                let mut auth = None;
+                for line in header.lines() {
+                    let Some((name, value)) = line.split_once(": ") else { continue };
+                    match name.to_lowercase().as_str() {
+                        "content-length" => content_len = value.parse().unwrap(),
+                        "authorization" => auth = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+
+                while data.len() < header_end + content_len {
+                    let bytes = stream.read(&mut buffer).await.unwrap();
+                    data.extend_from_slice(&buffer[..bytes]);
+                }
+
+                let body = match path.as_str() {
+                    "/ping/submit" => {
+                        submit_count += 1;
+                        if submit_count == 1 {
+                            r#"{"status":"error","data":{"type":"auth","message":"SessionExpired"}}"#
+                        } else {
+                            r#"{"status":"success","data":null}"#
+                        }
+                    }
+                    "/ping/get" => r#"{"status":"success","data":{"experiments":[]}}"#,
+                    _ => r#"{"status":"error","data":{"type":"internal"}}"#,
+                };
+
+                requests.lock().await.push((path, auth));
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_reauth_after_session_expired() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let api_base = mock_api(requests.clone()).await;
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events_handle = events.clone();
+
+        let mut monitor = PingMonitor::new(PlayitApi::create(api_base, Some("abcd".to_string())))
+            .await
+            .unwrap()
+            .with_secret_loader(|| async { Some("ef01".to_string()) })
+            .with_event_handler(move |event| events_handle.lock().unwrap().push(event));
+
+        let result = PingExperimentResult {
+            id: 1,
+            target: PingTarget { ip: "127.0.0.1".parse().unwrap(), port: 1234 },
+            samples: vec![PingSample { tunnel_server_id: 1, dc_id: 2, server_ts: 3, latency: 4, count: 1, num: 0 }],
+        };
+
+        /* submit fails with auth error, auth removed and re-auth scheduled */
+        monitor.shared.results.lock().await.push(result.clone());
+        monitor.refresh().await.unwrap();
+
+        assert_eq!(monitor.reauth.attempts, 1);
+        assert!(monitor.reauth.next_attempt.is_some());
+        assert!(monitor.health().auth_lost);
+        assert_eq!(requests.lock().await.last().unwrap(), &("/ping/get".to_string(), None));
+
+        /* skip backoff, secret is reloaded and submit succeeds with new auth */
+        monitor.reauth.next_attempt = Some(Instant::now());
+        monitor.shared.results.lock().await.push(result);
+        monitor.refresh().await.unwrap();
+
+        assert_eq!(monitor.reauth.attempts, 0);
+        assert!(!monitor.reauth.verifying);
+        assert!(!monitor.health().auth_lost);
+        assert_eq!(*events.lock().unwrap(), vec![PingMonitorEvent::AuthLost, PingMonitorEvent::ReAuthenticated]);
+
+        let requests = requests.lock().await;
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0], ("/ping/submit".to_string(), Some("Agent-Key abcd".to_string())));
+        assert_eq!(requests[2], ("/ping/submit".to_string(), Some("Agent-Key ef01".to_string())));
+        assert_eq!(requests[3], ("/ping/get".to_string(), Some("Agent-Key ef01".to_string())));
+    }
 }
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use playit_api_client::{http_client::HttpClient, PlayitApi};
+use playit_api_client::PlayitApi;
 use playit_ping_monitor::PingMonitor;
 use rand::random;
 use serde::{Deserialize, Serialize};
@@ -10,51 +10,60 @@ async fn main() {
     let (non_blocking, _guard) = tracing_appender::non_blocking(std::io::stdout());
     tracing_subscriber::fmt().with_writer(non_blocking).init();
 
-    let playit_secret = {
-        let content = 'load_secret: {
-            if let Ok(secret) = tokio::fs::read_to_string("playit.toml").await {
-                break 'load_secret Some(secret);
-            }
+    let playit_secret = load_secret().await;
 
-            let config_path = dirs::config_local_dir();
-            if let Some(path) = config_path {
-                let config_root = path.to_string_lossy();
-                let config_file = format!("{}/playit_gg/playit.toml", config_root);
+    let mut ping_monitor = PingMonitor::new(PlayitApi::create(
+        "https://api.playit.gg".to_string(),
+        playit_secret,
+    )).await.unwrap().with_secret_loader(load_secret);
 
-                if let Ok(secret) = tokio::fs::read_to_string(&config_file).await {
-                    break 'load_secret Some(secret);
-                }
+    loop {
+        match ping_monitor.refresh().await {
+            Ok(()) => {}
+            Err(error) if error.is_auth_error() => {
+                tracing::error!(?error, "ping monitor secret rejected, re-authenticating (replace playit.toml if this persists)");
             }
-
-            #[cfg(target_os = "linux")] {
-                let old_path = "/etc/playit/playit.toml";
-                if let Ok(secret) = tokio::fs::read_to_string(old_path).await {
-                    break 'load_secret Some(secret);
-                }
+            Err(error) => {
+                let health = ping_monitor.health();
+                tracing::warn!(?error, failures = health.consecutive_failures, "ping monitor refresh failed, retrying");
             }
+        }
+        tokio::time::sleep(Duration::from_millis(3_000 + (random::<u64>() % 5_000))).await;
+    }
+}
 
-            None
-        };
+async fn load_secret() -> Option<String> {
+    let content = 'load_secret: {
+        if let Ok(secret) = tokio::fs::read_to_string("playit.toml").await {
+            break 'load_secret Some(secret);
+        }
 
-        content
-            .and_then(|s| toml::from_str::<Config>(&s).ok())
-            .and_then(|c| {
-                hex::decode(&c.secret_key).ok()?;
-                Some(c.secret_key)
-            })
-    };
+        let config_path = dirs::config_local_dir();
+        if let Some(path) = config_path {
+            let config_root = path.to_string_lossy();
+            let config_file = format!("{}/playit_gg/playit.toml", config_root);
 
-    let mut ping_monitor = PingMonitor::new(PlayitApi::new(HttpClient::new(
-        "https://api.playit.gg".to_string(),
-        playit_secret,
-    ))).await.unwrap();
+            if let Ok(secret) = tokio::fs::read_to_string(&config_file).await {
+                break 'load_secret Some(secret);
+            }
+        }
 
-    loop {
-        if let Err(error) = ping_monitor.refresh().await {
-            tracing::error!(?error, "error running ping monitor");
+        #[cfg(target_os = "linux")] {
+            let old_path = "/etc/playit/playit.toml";
+            if let Ok(secret) = tokio::fs::read_to_string(old_path).await {
+                break 'load_secret Some(secret);
+            }
         }
-        tokio::time::sleep(Duration::from_millis(3_000 + (random::<u64>() % 5_000))).await;
-    }
+
+        None
+    };
+
+    content
+        .and_then(|s| toml::from_str::<Config>(&s).ok())
+        .and_then(|c| {
+            hex::decode(&c.secret_key).ok()?;
+            Some(c.secret_key)
+        })
 }
 
 #[derive(Deserialize, Serialize)]